@@ -0,0 +1,389 @@
+use axum::extract::{ConnectInfo, Form, Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::login_throttle::ThrottleKey;
+use crate::orm::model::{account, persona};
+use crate::sharedstate::SharedState;
+use crate::utils::auth::account_tokens::{
+    check_account_token, consume_account_token, AccountTokenPurpose,
+};
+use crate::utils::auth::hashing::plain_string_to_hash;
+use crate::utils::auth::tokens::revoke_login_tokens;
+use crate::utils::auth::user::{validate_credentials, CredentialType, UserAuthErr};
+use crate::utils::bans::BanSubject;
+use crate::utils::data_validation::email::email_normalize;
+use crate::utils::data_validation::password::password_validate;
+use crate::mordorwide_errors::MWErr;
+use crate::utils::linking::{link_identity, PendingLinks};
+
+pub struct AccountWebConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    #[serde(default)]
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct PasswordResetForm {
+    token: String,
+    password: String,
+    password_repeat: String,
+}
+
+#[derive(Deserialize)]
+struct LinkQuery {
+    #[serde(default)]
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct LinkForm {
+    code: String,
+    email: String,
+    password: String,
+    // May be left empty if the account has a single persona
+    #[serde(default)]
+    persona: String,
+}
+
+// The tokens are hex strings; anything else is rejected before it reaches a page
+fn is_valid_token(token: &str) -> bool {
+    !token.is_empty() && token.len() <= 128 && token.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn page(status: StatusCode, title: &str, content: &str) -> Response {
+    let html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>MordorWide - {}</title></head>\n\
+        <body><h1>{}</h1>\n{}\n</body></html>\n",
+        title, title, content
+    );
+    (status, Html(html)).into_response()
+}
+
+fn reset_form(token: &str, message: &str) -> Response {
+    let content = format!(
+        "<p>{}</p>\n<form method=\"post\" action=\"/reset-password\">\n\
+        <input type=\"hidden\" name=\"token\" value=\"{}\">\n\
+        <p><label>New password <input type=\"password\" name=\"password\"></label></p>\n\
+        <p><label>Repeat password <input type=\"password\" name=\"password_repeat\"></label></p>\n\
+        <p><button type=\"submit\">Change password</button></p>\n</form>",
+        message, token
+    );
+    page(StatusCode::OK, "Reset password", &content)
+}
+
+// Escapes text that was entered by a player before it is put into a page
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn link_form(status: StatusCode, code: &str, email: &str, persona: &str, message: &str) -> Response {
+    let content = format!(
+        "<p>{}</p>\n<form method=\"post\" action=\"/link\">\n\
+        <p><label>Code <input type=\"text\" name=\"code\" value=\"{}\"></label></p>\n\
+        <p><label>Email <input type=\"email\" name=\"email\" value=\"{}\"></label></p>\n\
+        <p><label>Password <input type=\"password\" name=\"password\"></label></p>\n\
+        <p><label>Persona <input type=\"text\" name=\"persona\" value=\"{}\"></label></p>\n\
+        <p><button type=\"submit\">Link account</button></p>\n</form>",
+        message,
+        escape_html(code),
+        escape_html(email),
+        escape_html(persona)
+    );
+    page(status, "Link console account", &content)
+}
+
+fn invalid_link() -> Response {
+    page(
+        StatusCode::BAD_REQUEST,
+        "Invalid link",
+        "<p>The link is invalid or has expired. Request a new mail in the game.</p>",
+    )
+}
+
+fn server_error() -> Response {
+    page(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error",
+        "<p>Something went wrong. Please try again later.</p>",
+    )
+}
+
+async fn verify_email(
+    State(sstate): State<Arc<SharedState>>,
+    Query(query): Query<TokenQuery>,
+) -> Response {
+    if !is_valid_token(&query.token) {
+        return invalid_link();
+    }
+    let user_id = match consume_account_token(
+        &query.token,
+        AccountTokenPurpose::VerifyEmail,
+        &sstate.database,
+    )
+    .await
+    {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return invalid_link(),
+        Err(_) => return server_error(),
+    };
+
+    let db_account = account::ActiveModel {
+        id: Set(user_id),
+        is_verified: Set(true),
+        ..Default::default()
+    };
+    if db_account.update(&*sstate.database).await.is_err() {
+        return server_error();
+    }
+    info!(target: "auth", "Email address verified for account {}", user_id);
+    page(
+        StatusCode::OK,
+        "Email verified",
+        "<p>Your email address has been verified. You can log in now.</p>",
+    )
+}
+
+async fn show_password_reset(
+    State(sstate): State<Arc<SharedState>>,
+    Query(query): Query<TokenQuery>,
+) -> Response {
+    if !is_valid_token(&query.token) {
+        return invalid_link();
+    }
+    match check_account_token(&query.token, AccountTokenPurpose::ResetPassword, &sstate.database)
+        .await
+    {
+        Ok(Some(_)) => reset_form(&query.token, "Choose a new password (6 to 50 characters)."),
+        Ok(None) => invalid_link(),
+        Err(_) => server_error(),
+    }
+}
+
+async fn reset_password(
+    State(sstate): State<Arc<SharedState>>,
+    Form(form): Form<PasswordResetForm>,
+) -> Response {
+    if !is_valid_token(&form.token) {
+        return invalid_link();
+    }
+    if form.password != form.password_repeat {
+        return reset_form(&form.token, "The passwords do not match.");
+    }
+    if password_validate(&form.password).is_err() {
+        return reset_form(&form.token, "The password must have 6 to 50 characters.");
+    }
+    let user_id = match consume_account_token(
+        &form.token,
+        AccountTokenPurpose::ResetPassword,
+        &sstate.database,
+    )
+    .await
+    {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return invalid_link(),
+        Err(_) => return server_error(),
+    };
+
+    // The mail proved the address, so the account counts as verified as well
+    let db_account = account::ActiveModel {
+        id: Set(user_id),
+        password_hashed: Set(plain_string_to_hash(&form.password)),
+        is_verified: Set(true),
+        ..Default::default()
+    };
+    if db_account.update(&*sstate.database).await.is_err() {
+        return server_error();
+    }
+    // "Remember me" tokens must not outlive the old password
+    if revoke_login_tokens(user_id, &sstate.database).await.is_err() {
+        error!(target: "auth", "Failed to revoke the login tokens of account {}", user_id);
+    }
+    info!(target: "auth", "Password reset for account {}", user_id);
+    page(
+        StatusCode::OK,
+        "Password changed",
+        "<p>Your password has been changed. You can log in with the new password now.</p>",
+    )
+}
+
+async fn show_link(Query(query): Query<LinkQuery>) -> Response {
+    link_form(
+        StatusCode::OK,
+        &PendingLinks::normalize_code(&query.code),
+        "",
+        "",
+        "Enter the code shown in the game and log in with your account. \
+        The persona may be left empty if the account has only one.",
+    )
+}
+
+// Binds a console identity to a persona. The player proves control of the account
+// with its password, and of the console identity with the code shown in the game.
+async fn link_console(
+    State(sstate): State<Arc<SharedState>>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Form(form): Form<LinkForm>,
+) -> Response {
+    let retry_form = |status: StatusCode, message: &str| {
+        link_form(status, &form.code, &form.email, &form.persona, message)
+    };
+
+    let email = email_normalize(&form.email);
+    let client_ip = client_addr.ip().to_string();
+    let throttle_keys = [ThrottleKey::Ip(&client_ip), ThrottleKey::Account(&email)];
+    if sstate.login_throttle.check(&throttle_keys).is_some() {
+        return retry_form(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many failed attempts. Please try again later.",
+        );
+    }
+
+    // Guessed codes count against the IP only
+    let Some(pending_link) = sstate.pending_links.get(&form.code) else {
+        sstate.login_throttle.record_failure(&[ThrottleKey::Ip(&client_ip)]);
+        return retry_form(
+            StatusCode::BAD_REQUEST,
+            "The code is invalid or has expired. Log in on the console to get a new one.",
+        );
+    };
+
+    let ban_subject = BanSubject {
+        ip: Some(client_ip.clone()),
+        ..Default::default()
+    };
+    let credentials = CredentialType::PlainText(email.clone(), form.password.clone());
+    let user_id = match validate_credentials(&credentials, ban_subject, &sstate).await {
+        Ok(user_id) => user_id,
+        Err(MWErr::UserAuthError(UserAuthErr::UserBanned(message))) => {
+            return retry_form(StatusCode::FORBIDDEN, &escape_html(&message));
+        }
+        Err(MWErr::UserAuthError(UserAuthErr::NotVerified)) => {
+            return retry_form(
+                StatusCode::FORBIDDEN,
+                "The email address of the account has not been verified yet.",
+            );
+        }
+        Err(MWErr::DBError) => return server_error(),
+        Err(_) => {
+            sstate.login_throttle.record_failure(&throttle_keys);
+            return retry_form(StatusCode::UNAUTHORIZED, "The email or password is incorrect.");
+        }
+    };
+    sstate.login_throttle.record_success(&throttle_keys);
+
+    let Ok(db_personas) = persona::Entity::find()
+        .filter(persona::Column::UserId.eq(user_id))
+        .all(&*sstate.database)
+        .await
+    else {
+        return server_error();
+    };
+    let persona_name = form.persona.trim();
+    let db_persona = if persona_name.is_empty() && db_personas.len() == 1 {
+        db_personas.first()
+    } else {
+        db_personas.iter().find(|db_persona| db_persona.name == persona_name)
+    };
+    let Some(db_persona) = db_persona else {
+        return retry_form(
+            StatusCode::BAD_REQUEST,
+            "Enter the name of one of the personas of the account.",
+        );
+    };
+
+    // The code may have been used by a parallel request in the meantime
+    let Some(pending_link) = sstate
+        .pending_links
+        .take(&form.code)
+        .filter(|link| link.external_id == pending_link.external_id)
+    else {
+        return invalid_link();
+    };
+    if link_identity(
+        pending_link.platform,
+        &pending_link.external_id,
+        &pending_link.display_name,
+        user_id,
+        db_persona.id,
+        &sstate.database,
+    )
+    .await
+    .is_err()
+    {
+        warn!(target: "auth", "Failed to link {} identity {} to persona {}", pending_link.platform.as_str(), &pending_link.external_id, db_persona.id);
+        return server_error();
+    }
+    info!(target: "auth", "Linked {} identity {} ({}) to persona {} of account {}", pending_link.platform.as_str(), &pending_link.external_id, &pending_link.display_name, &db_persona.name, user_id);
+
+    let mut content = format!(
+        "<p>The {} account {} is now linked to the persona {}. You can log in on the console now.</p>",
+        pending_link.platform.display_name(),
+        escape_html(&pending_link.display_name),
+        escape_html(&db_persona.name)
+    );
+    // The link is no proof of ownership of unverified identities
+    if !pending_link.platform.is_verified() && !db_persona.allow_insecure_login {
+        content.push_str(&format!(
+            "<p>{} logins are not verified, so the persona has to allow insecure logins as well.</p>",
+            pending_link.platform.display_name()
+        ));
+    }
+    page(StatusCode::OK, "Account linked", &content)
+}
+
+pub async fn spawn_account_web(
+    config: AccountWebConfig,
+    shared_state: Arc<SharedState>,
+) -> Option<JoinHandle<()>> {
+    let router = Router::new()
+        .route("/verify", get(verify_email))
+        .route("/reset-password", get(show_password_reset).post(reset_password))
+        .route("/link", get(show_link).post(link_console))
+        .with_state(shared_state);
+
+    let addr = format!("{}:{}", config.host, config.port);
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(target: "account_web", "Failed to bind the account pages to {}: {}", addr, e);
+            return None;
+        }
+    };
+    info!(target: "account_web", "Account pages listening on {}", addr);
+
+    Some(tokio::spawn(async move {
+        if let Err(e) = axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await {
+            error!(target: "account_web", "Account pages stopped: {}", e);
+        }
+    }))
+}
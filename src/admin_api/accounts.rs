@@ -0,0 +1,78 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use serde_json::json;
+
+use crate::orm::model::{account, login_token};
+use crate::utils::auth::tokens::revoke_login_tokens;
+use crate::utils::bans::{email_ban_target, BanScope};
+
+use super::bans::{issue_ban, BanOptions};
+use super::{api_error, AdminState};
+
+
+// Bans the account (by its email hash, as checked on login) and kicks its sessions
+pub async fn ban_account(
+    State(admin_state): State<AdminState>,
+    Path(account_id): Path<i64>,
+    Json(ban_options): Json<BanOptions>,
+) -> Response {
+    let Ok(Some(db_account)) = account::Entity::find_by_id(account_id)
+        .one(&*admin_state.sstate.database)
+        .await
+    else {
+        return api_error(StatusCode::NOT_FOUND, "Account not found");
+    };
+
+    issue_ban(
+        &admin_state,
+        BanScope::Account,
+        email_ban_target(&db_account.email),
+        Some(db_account.id),
+        ban_options,
+    )
+    .await
+}
+
+// "Remember me" tokens of the account
+pub async fn list_tokens(
+    State(admin_state): State<AdminState>,
+    Path(account_id): Path<i64>,
+) -> Response {
+    let Ok(db_tokens) = login_token::Entity::find()
+        .filter(login_token::Column::UserId.eq(account_id))
+        .order_by(login_token::Column::Id, sea_orm::Order::Asc)
+        .all(&*admin_state.sstate.database)
+        .await
+    else {
+        return api_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load tokens");
+    };
+
+    let now = chrono::Utc::now();
+    let tokens: Vec<_> = db_tokens
+        .iter()
+        .map(|db_token| {
+            json!({
+                "token_id": db_token.token_id,
+                "created_at": db_token.created_at,
+                "expires_at": db_token.expires_at,
+                "active": db_token.expires_at > now,
+            })
+        })
+        .collect();
+    Json(json!({ "tokens": tokens })).into_response()
+}
+
+// Revokes all "remember me" tokens of the account
+pub async fn revoke_tokens(
+    State(admin_state): State<AdminState>,
+    Path(account_id): Path<i64>,
+) -> Response {
+    match revoke_login_tokens(account_id, &admin_state.sstate.database).await {
+        Ok(n_revoked) => Json(json!({ "revoked": n_revoked })).into_response(),
+        Err(_) => api_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to revoke tokens"),
+    }
+}
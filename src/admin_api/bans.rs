@@ -0,0 +1,149 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, TimeDelta, Utc};
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::orm::model::{account, ban};
+use crate::utils::bans::{ban_is_active, enforce_ban, normalize_ban_target, BanScope};
+use crate::utils::data_validation::email::email_normalize;
+
+use super::{api_error, AdminState};
+
+
+// Details shared by all kinds of bans
+#[derive(Deserialize)]
+pub struct BanOptions {
+    #[serde(default)]
+    reason: String,
+    // Name of the admin that issues the ban
+    #[serde(default)]
+    issued_by: String,
+    // Either an absolute expiry or a duration. Permanent if neither is set.
+    expires_at: Option<DateTime<Utc>>,
+    duration_secs: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct BanRequest {
+    scope: String,
+    target: String,
+    #[serde(flatten)]
+    options: BanOptions,
+}
+
+pub async fn list_bans(State(admin_state): State<AdminState>) -> Response {
+    let Ok(db_bans) = ban::Entity::find()
+        .order_by(ban::Column::Id, sea_orm::Order::Asc)
+        .all(&*admin_state.sstate.database)
+        .await
+    else {
+        return api_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load bans");
+    };
+
+    let bans: Vec<_> = db_bans.iter().map(ban_to_json).collect();
+    Json(json!({ "bans": bans })).into_response()
+}
+
+pub async fn create_ban(
+    State(admin_state): State<AdminState>,
+    Json(ban_request): Json<BanRequest>,
+) -> Response {
+    let Some(scope) = BanScope::parse(&ban_request.scope) else {
+        return api_error(StatusCode::BAD_REQUEST, "Invalid ban scope");
+    };
+    let Some(target) = normalize_ban_target(scope, &ban_request.target) else {
+        return api_error(StatusCode::BAD_REQUEST, "Invalid ban target");
+    };
+    // Account bans given by email are resolved to the account, so that its tokens can be
+    // revoked. A bare email hash only revokes the tokens of the connected sessions.
+    let mut account_id = None;
+    if scope == BanScope::Account && ban_request.target.contains('@') {
+        let Ok(db_account) = account::Entity::find()
+            .filter(account::Column::Email.eq(email_normalize(&ban_request.target)))
+            .one(&*admin_state.sstate.database)
+            .await
+        else {
+            return api_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load account");
+        };
+        account_id = db_account.map(|db_account| db_account.id);
+    }
+    issue_ban(&admin_state, scope, target, account_id, ban_request.options).await
+}
+
+// Lifts the ban
+pub async fn delete_ban(
+    State(admin_state): State<AdminState>,
+    Path(ban_id): Path<i64>,
+) -> Response {
+    match ban::Entity::delete_by_id(ban_id)
+        .exec(&*admin_state.sstate.database)
+        .await
+    {
+        Ok(result) if result.rows_affected > 0 => Json(json!({ "deleted": ban_id })).into_response(),
+        Ok(_) => api_error(StatusCode::NOT_FOUND, "Ban not found"),
+        Err(_) => api_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete ban"),
+    }
+}
+
+// Stores the ban and disconnects the sessions it applies to
+pub(super) async fn issue_ban(
+    admin_state: &AdminState,
+    scope: BanScope,
+    target: String,
+    // Account the ban was issued for, if known
+    account_id: Option<i64>,
+    options: BanOptions,
+) -> Response {
+    let expires_at = match (options.expires_at, options.duration_secs) {
+        (Some(expires_at), _) => Some(expires_at),
+        (None, Some(duration_secs)) => {
+            // Durations that do not result in a valid point in time are rejected
+            let Some(expires_at) = Some(duration_secs)
+                .filter(|duration_secs| *duration_secs > 0)
+                .and_then(TimeDelta::try_seconds)
+                .and_then(|duration| Utc::now().checked_add_signed(duration))
+            else {
+                return api_error(StatusCode::BAD_REQUEST, "Invalid ban duration");
+            };
+            Some(expires_at)
+        }
+        (None, None) => None,
+    };
+    let issued_by = if options.issued_by.is_empty() {
+        "admin".to_string()
+    } else {
+        options.issued_by
+    };
+
+    let new_ban = ban::ActiveModel {
+        scope: Set(scope.as_str().to_string()),
+        target: Set(target),
+        reason: Set(options.reason),
+        issued_by: Set(issued_by),
+        expires_at: Set(expires_at),
+        ..Default::default()
+    };
+    let Ok(db_ban) = new_ban.insert(&*admin_state.sstate.database).await else {
+        return api_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store ban");
+    };
+
+    let n_kicked = enforce_ban(&db_ban, account_id, &admin_state.sstate).await;
+    Json(json!({ "ban": ban_to_json(&db_ban), "kicked_sessions": n_kicked })).into_response()
+}
+
+fn ban_to_json(db_ban: &ban::Model) -> serde_json::Value {
+    json!({
+        "id": db_ban.id,
+        "scope": db_ban.scope,
+        "target": db_ban.target,
+        "reason": db_ban.reason,
+        "issued_by": db_ban.issued_by,
+        "expires_at": db_ban.expires_at,
+        "active": ban_is_active(db_ban),
+    })
+}
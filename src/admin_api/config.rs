@@ -0,0 +1,132 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use indexmap::IndexMap;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashSet;
+
+use crate::orm::model::config;
+
+use super::{api_error, AdminState};
+
+
+#[derive(Deserialize)]
+pub struct ConfigValue {
+    value: String,
+}
+
+pub async fn list_config(State(admin_state): State<AdminState>) -> Response {
+    let Ok(db_entries) = config::Entity::find()
+        .order_by(config::Column::Key, sea_orm::Order::Asc)
+        .all(&*admin_state.sstate.database)
+        .await
+    else {
+        return api_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load config");
+    };
+
+    let mut entries = serde_json::Map::new();
+    for db_entry in db_entries {
+        entries.insert(db_entry.key, json!(db_entry.value));
+    }
+    Json(json!({ "config": entries })).into_response()
+}
+
+// Creates or updates the config entry. Handlers read the config on use, so the
+// change takes effect immediately. Only known keys with valid values are accepted.
+pub async fn set_config(
+    State(admin_state): State<AdminState>,
+    Path(key): Path<String>,
+    Json(config_value): Json<ConfigValue>,
+) -> Response {
+    if let Err(message) = validate_config_value(&key, &config_value.value) {
+        return api_error(StatusCode::BAD_REQUEST, message);
+    }
+
+    let result = match config::Entity::find()
+        .filter(config::Column::Key.eq(&key))
+        .one(&*admin_state.sstate.database)
+        .await
+    {
+        Ok(Some(db_entry)) => {
+            let mut db_entry = db_entry.into_active_model();
+            db_entry.value = Set(config_value.value.clone());
+            db_entry.update(&*admin_state.sstate.database).await.map(|_| ())
+        }
+        Ok(None) => {
+            let new_entry = config::ActiveModel {
+                key: Set(key.clone()),
+                value: Set(config_value.value.clone()),
+                ..Default::default()
+            };
+            new_entry.insert(&*admin_state.sstate.database).await.map(|_| ())
+        }
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        return api_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store config");
+    }
+
+    Json(json!({ "key": key, "value": config_value.value })).into_response()
+}
+
+// Checks the value against the format the handlers parse for the key
+fn validate_config_value(key: &str, value: &str) -> Result<(), &'static str> {
+    let is_count = |value: &str| value.parse::<i32>().is_ok_and(|count| count >= 0);
+    let is_lifetime = |value: &str| value.parse::<i64>().is_ok_and(|secs| secs > 0);
+    match key {
+        "ENABLE_TOS_CHECK" | "ENABLE_ENTITLEMENT" | "ENABLE_SHARED_ENTITLEMENT" => {
+            if value != "0" && value != "1" {
+                return Err("Value must be 0 or 1");
+            }
+        }
+        "MAX_PERSONAS" | "GetPingSites_minPingSitesToPing" => {
+            if !is_count(value) {
+                return Err("Value must be a non-negative integer");
+            }
+        }
+        "MESSAGE_EXPIRATION_SECONDS" | "MESSAGE_MAX_EXPIRATION_SECONDS" => {
+            if !is_lifetime(value) {
+                return Err("Value must be a positive number of seconds");
+            }
+        }
+        "TOS_VERSION" => {
+            if value.is_empty() || value.chars().any(char::is_whitespace) {
+                return Err("Value must be a version without whitespace");
+            }
+        }
+        "STAT_PERIODS" => {
+            // "period id:length in days" pairs with unique period ids
+            let mut period_ids = HashSet::new();
+            let is_valid = value.split(',').all(|period| {
+                let Some((period_id, length_days)) = period.split_once(':') else {
+                    return false;
+                };
+                length_days.trim().parse::<i64>().is_ok_and(|days| days >= 0)
+                    && period_id.trim().parse::<i32>().is_ok_and(|id| period_ids.insert(id))
+            });
+            if !is_valid {
+                return Err("Value must be a list of period id:length in days pairs");
+            }
+        }
+        "GetPingSites_PingSites" => {
+            let Ok(ping_sites) = serde_json::from_str::<Vec<IndexMap<String, String>>>(value) else {
+                return Err("Value must be a JSON list of ping sites");
+            };
+            let is_complete = |ping_site: &IndexMap<String, String>| {
+                ["addr", "type", "name"].iter().all(|field| ping_site.contains_key(*field))
+            };
+            if !ping_sites.iter().all(is_complete) {
+                return Err("Ping sites must have an addr, type and name");
+            }
+        }
+        _ if key.strip_prefix("TOS_TEXT_").is_some_and(|country_code| {
+            !country_code.is_empty() && country_code.chars().all(|c| c.is_ascii_uppercase())
+        }) => {}
+        _ => return Err("Unknown config key"),
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+
+use crate::handler::fesl::update_presence;
+use crate::plasma_handle::close_connection;
+use crate::presence::{PresenceInfo, PresenceState};
+
+use super::{api_error, AdminState};
+
+
+pub async fn list_games(State(admin_state): State<AdminState>) -> Response {
+    let db_games = admin_state.sstate.registry.get_games();
+
+    let mut games = Vec::new();
+    for db_game in db_games {
+        let n_participants = admin_state
+            .sstate
+            .registry
+            .get_participants_by_game(db_game.id)
+            .len();
+        games.push(json!({
+            "id": db_game.id,
+            "lobby_id": db_game.lobby_id,
+            "name": db_game.name,
+            "host_persona_id": db_game.persona_id,
+            "port": db_game.port,
+            "join_mode": db_game.join_mode,
+            "max_players": db_game.max_players,
+            "participants": n_participants,
+            "ranked": db_game.user_ranked,
+            "friends_only": db_game.user_friends_only,
+            "client_version": db_game.client_version,
+        }));
+    }
+    Json(json!({ "games": games })).into_response()
+}
+
+pub async fn list_participants(
+    State(admin_state): State<AdminState>,
+    Path(game_id): Path<i64>,
+) -> Response {
+    let db_participants = admin_state.sstate.registry.get_participants_by_game(game_id);
+
+    let participants: Vec<_> = db_participants
+        .iter()
+        .map(|db_participant| {
+            json!({
+                "id": db_participant.id,
+                "persona_id": db_participant.persona_id,
+                "queue_pos": db_participant.queue_pos,
+            })
+        })
+        .collect();
+    Json(json!({ "game_id": game_id, "participants": participants })).into_response()
+}
+
+// Removes the game and its participants. The Theater connections of the host and
+// the participants are closed, so that their clients leave the game (or its queue).
+// Their FESL connections and sessions are kept.
+pub async fn close_game(
+    State(admin_state): State<AdminState>,
+    Path(game_id): Path<i64>,
+) -> Response {
+    let Some(db_game) = admin_state.sstate.registry.get_game(game_id) else {
+        return api_error(StatusCode::NOT_FOUND, "Game not found");
+    };
+
+    let db_participants = admin_state
+        .sstate
+        .registry
+        .remove_participants_by_game(db_game.id);
+    admin_state.sstate.registry.remove_game(db_game.id);
+
+    if let Some(db_host_session) = admin_state
+        .sstate
+        .registry
+        .get_session_by_persona(db_game.persona_id)
+    {
+        close_connection(&db_host_session.theater_tcp_handle, &admin_state.sstate).await;
+    }
+
+    // The former players are no longer in a game
+    for db_participant in db_participants {
+        if db_participant.persona_id != db_game.persona_id
+            && let Some(db_client_session) = admin_state
+                .sstate
+                .registry
+                .get_session_by_persona(db_participant.persona_id)
+        {
+            close_connection(&db_client_session.theater_tcp_handle, &admin_state.sstate).await;
+        }
+        if admin_state.sstate.presence.contains_key(&db_participant.persona_id) {
+            update_presence(
+                &admin_state.sstate,
+                db_participant.persona_id,
+                Some(PresenceInfo::new(PresenceState::Online, -1)),
+            )
+            .await;
+        }
+    }
+
+    Json(json!({ "closed": game_id })).into_response()
+}
@@ -0,0 +1,59 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use serde_json::json;
+
+use crate::orm::model::linked_identity;
+
+use super::{api_error, AdminState};
+
+
+// Console identities linked to the personas of the account
+pub async fn list_identities(
+    State(admin_state): State<AdminState>,
+    Path(account_id): Path<i64>,
+) -> Response {
+    let Ok(db_identities) = linked_identity::Entity::find()
+        .filter(linked_identity::Column::UserId.eq(account_id))
+        .order_by(linked_identity::Column::Id, sea_orm::Order::Asc)
+        .all(&*admin_state.sstate.database)
+        .await
+    else {
+        return api_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load identities");
+    };
+
+    let identities: Vec<_> = db_identities
+        .iter()
+        .map(|db_identity| {
+            json!({
+                "id": db_identity.id,
+                "platform": db_identity.platform,
+                "external_id": db_identity.external_id,
+                "display_name": db_identity.display_name,
+                "persona_id": db_identity.persona_id,
+                "linked_at": db_identity.linked_at,
+            })
+        })
+        .collect();
+    Json(json!({ "identities": identities })).into_response()
+}
+
+// Unlinks the identity; its next login hands out a new link code
+pub async fn delete_identity(
+    State(admin_state): State<AdminState>,
+    Path(identity_id): Path<i64>,
+) -> Response {
+    match linked_identity::Entity::delete_by_id(identity_id)
+        .exec(&*admin_state.sstate.database)
+        .await
+    {
+        Ok(result) if result.rows_affected > 0 => {
+            Json(json!({ "deleted": identity_id })).into_response()
+        }
+        Ok(_) => api_error(StatusCode::NOT_FOUND, "Identity not found"),
+        Err(_) => api_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete identity"),
+    }
+}
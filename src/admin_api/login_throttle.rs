@@ -0,0 +1,38 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+
+use super::{api_error, AdminState};
+
+
+// Accounts and IPs with recent failed logins or an active lockout
+pub async fn list_login_throttle(State(admin_state): State<AdminState>) -> Response {
+    let entries: Vec<_> = admin_state
+        .sstate
+        .login_throttle
+        .get_status()
+        .iter()
+        .map(|status| {
+            json!({
+                "key": status.key,
+                "failures": status.n_failures,
+                "lockouts": status.n_lockouts,
+                "locked_for_secs": status.locked_for.map(|locked_for| locked_for.as_secs()),
+            })
+        })
+        .collect();
+    Json(json!({ "entries": entries })).into_response()
+}
+
+// Lifts the lockout and forgets the failures of the key (e.g. "ip:1.2.3.4")
+pub async fn unlock_login_throttle(
+    State(admin_state): State<AdminState>,
+    Path(key): Path<String>,
+) -> Response {
+    if !admin_state.sstate.login_throttle.unlock(&key) {
+        return api_error(StatusCode::NOT_FOUND, "No record for the key");
+    }
+    Json(json!({ "unlocked": key })).into_response()
+}
@@ -0,0 +1,113 @@
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use openssl::memcmp;
+use openssl::sha::sha256;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::sharedstate::SharedState;
+
+mod accounts;
+mod bans;
+mod config;
+mod games;
+mod identities;
+mod login_throttle;
+mod sessions;
+
+pub struct AdminApiConfig {
+    pub host: String,
+    pub port: u16,
+    pub token: String,
+}
+
+#[derive(Clone)]
+struct AdminState {
+    sstate: Arc<SharedState>,
+    token: Arc<String>,
+}
+
+pub struct AdminApi;
+
+impl AdminApi {
+    pub async fn spawn(config: AdminApiConfig, shared_state: Arc<SharedState>) -> Option<JoinHandle<()>> {
+        if config.token.is_empty() {
+            error!(target: "admin", "No admin token configured. The admin API is not started.");
+            return None;
+        }
+
+        let admin_state = AdminState {
+            sstate: shared_state,
+            token: Arc::new(config.token),
+        };
+
+        let router = Router::new()
+            .route("/api/connections", get(sessions::list_connections))
+            .route("/api/sessions", get(sessions::list_sessions))
+            .route("/api/sessions/{session_id}", delete(sessions::kick_session))
+            .route("/api/games", get(games::list_games))
+            .route("/api/games/{game_id}", delete(games::close_game))
+            .route("/api/games/{game_id}/participants", get(games::list_participants))
+            .route("/api/accounts/{account_id}/ban", post(accounts::ban_account))
+            .route(
+                "/api/accounts/{account_id}/tokens",
+                get(accounts::list_tokens).delete(accounts::revoke_tokens),
+            )
+            .route("/api/accounts/{account_id}/identities", get(identities::list_identities))
+            .route("/api/identities/{identity_id}", delete(identities::delete_identity))
+            .route("/api/bans", get(bans::list_bans).post(bans::create_ban))
+            .route("/api/bans/{ban_id}", delete(bans::delete_ban))
+            .route("/api/login-throttle", get(login_throttle::list_login_throttle))
+            .route(
+                "/api/login-throttle/{key}",
+                delete(login_throttle::unlock_login_throttle),
+            )
+            .route("/api/config", get(config::list_config))
+            .route("/api/config/{key}", put(config::set_config))
+            .layer(middleware::from_fn_with_state(admin_state.clone(), check_token))
+            .with_state(admin_state);
+
+        let addr = format!("{}:{}", config.host, config.port);
+        let listener = match tokio::net::TcpListener::bind(&addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!(target: "admin", "Failed to bind the admin API to {}: {}", addr, e);
+                return None;
+            }
+        };
+        info!(target: "admin", "Admin API listening on {}", addr);
+
+        Some(tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                error!(target: "admin", "Admin API stopped: {}", e);
+            }
+        }))
+    }
+}
+
+// Every request has to carry "Authorization: Bearer <token>"
+// The digests of both tokens are compared in constant time, so the comparison
+// neither depends on the position of the first mismatch nor on the token length.
+async fn check_token(State(admin_state): State<AdminState>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| memcmp::eq(&sha256(token.as_bytes()), &sha256(admin_state.token.as_bytes())))
+        .unwrap_or(false);
+    if !authorized {
+        return api_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+    }
+    next.run(request).await
+}
+
+fn api_error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
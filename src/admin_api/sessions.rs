@@ -0,0 +1,88 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+
+use crate::client_connection::{ClientConnectionDescriptor, ProtoType, ServiceType};
+use crate::handler::fesl::update_presence;
+use crate::plasma_handle::close_session;
+
+use super::{api_error, AdminState};
+
+
+// Open client connections, as registered in the shared state
+pub async fn list_connections(State(admin_state): State<AdminState>) -> Response {
+    let connections: Vec<_> = admin_state
+        .sstate
+        .connections
+        .iter()
+        .map(|entry| connection_to_json(entry.key()))
+        .collect();
+    Json(json!({ "connections": connections })).into_response()
+}
+
+pub async fn list_sessions(State(admin_state): State<AdminState>) -> Response {
+    let db_sessions = admin_state.sstate.registry.get_sessions();
+
+    let sessions: Vec<_> = db_sessions
+        .iter()
+        .map(|db_session| {
+            json!({
+                "id": db_session.id,
+                "user_id": db_session.user_id,
+                "persona_id": db_session.persona_id,
+                "fesl_tcp_handle": db_session.fesl_tcp_handle,
+                "theater_tcp_handle": db_session.theater_tcp_handle,
+                "theater_udp_handle": db_session.theater_udp_handle,
+                "nat_type": db_session.nat_type,
+                "connected": is_connected(&admin_state, &db_session.fesl_tcp_handle),
+            })
+        })
+        .collect();
+    Json(json!({ "sessions": sessions })).into_response()
+}
+
+// Kicks the player: Closes the connections and removes the session and hosted games
+pub async fn kick_session(
+    State(admin_state): State<AdminState>,
+    Path(session_id): Path<i64>,
+) -> Response {
+    let Some(db_session) = admin_state.sstate.registry.get_session(session_id) else {
+        return api_error(StatusCode::NOT_FOUND, "Session not found");
+    };
+    let persona_id = db_session.persona_id;
+
+    close_session(db_session, &admin_state.sstate).await;
+    if persona_id != -1 {
+        update_presence(&admin_state.sstate, persona_id, None).await;
+    }
+
+    Json(json!({ "kicked": session_id })).into_response()
+}
+
+fn is_connected(admin_state: &AdminState, handle: &String) -> bool {
+    !handle.is_empty()
+        && admin_state
+            .sstate
+            .connections
+            .contains_key(&ClientConnectionDescriptor::from_string(handle))
+}
+
+fn connection_to_json(con: &ClientConnectionDescriptor) -> serde_json::Value {
+    json!({
+        "handle": con.to_string(),
+        "proto": match con.proto_type {
+            ProtoType::Tcp => "tcp",
+            ProtoType::Udp => "udp",
+            ProtoType::RemoteUdp => "remoteudp",
+        },
+        "service": match con.service_type {
+            ServiceType::Fesl => "fesl",
+            ServiceType::Theater => "theater",
+        },
+        "host_port": con.host_port,
+        "client_ip": con.client_ip,
+        "client_port": con.client_port,
+    })
+}
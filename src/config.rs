@@ -1,19 +1,236 @@
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::utils::lobbies::is_valid_platform;
+
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+const MAX_TOKEN_LIFETIME_SECS: u64 = 10 * 365 * 24 * 3600;
+// The clients send fragments of up to 8 KiB (see fragmentSize in the FSYS Hello)
+const MIN_FRAME_SIZE: usize = 16 * 1024;
 
 #[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Configuration {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub tls: TlsConfig,
+    pub hello: HelloConfig,
+    pub stun: StunConfig,
+    pub turn: TurnConfig,
+    pub admin_api: AdminApiSection,
+    pub metrics: MetricsSection,
+    pub login_throttle: LoginThrottleSection,
+    pub rate_limit: RateLimitSection,
+    pub mail: MailSection,
+    pub account_web: AccountWebSection,
+    pub psn: PsnSection,
+    pub console_linking: ConsoleLinkingSection,
     pub services: Vec<ServiceConfig>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub secret_key: String,
+    pub init_schemas: bool,
+    // Write the in-memory sessions, games and participants to the database
+    pub mirror_state_to_db: bool,
+    // Lifetime of the "remember me" tokens (encryptedLoginInfo)
+    pub remember_token_lifetime_secs: u64,
+    // Largest packet accepted from a client (header included). Bigger packets close the connection.
+    pub max_frame_size: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub proto: String,
+    pub name: String,
+    pub user: String,
+    pub password: String,
+    pub host: String,
+    pub port: String,
+    pub params: String,
+}
+
+// Default key pair of the TLS listeners that do not set their own keys
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub private_key: String,
+    pub public_key: String,
+}
+
+// Endpoints advertised to the clients in the FSYS Hello response
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HelloConfig {
+    pub theater_ip: String,
+    pub theater_port: u16,
+    pub messenger_ip: String,
+    pub messenger_port: u16,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StunConfig {
+    pub enabled: bool,
+    pub relay_host: String,
+    pub relay_port: u16,
+    pub relay_source_port: u16,
+    pub internal_source_port: u16,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TurnConfig {
+    pub enabled: bool,
+    pub relay_internal_host: String,
+    pub relay_port: u16,
+    pub relay_external_ip: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminApiSection {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSection {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+}
+
+// Failed logins within the window lock the account or IP. Each further lockout of
+// the same key doubles the duration, up to max_lockout_secs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginThrottleSection {
+    pub enabled: bool,
+    pub window_secs: u64,
+    pub max_failures_per_account: usize,
+    pub max_failures_per_ip: usize,
+    pub lockout_secs: u64,
+    pub max_lockout_secs: u64,
+}
+
+// Requests per client IP of the routes that declare a rate limit (e.g. NuAddAccount)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSection {
+    pub enabled: bool,
+}
+
+// Mails for the email verification and the account recovery
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailSection {
+    // "stdout" (log the mails), "file" (write them to file_dir) or "smtp"
+    pub transport: String,
+    pub from: String,
+    pub file_dir: String,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_user: String,
+    pub smtp_password: String,
+    // "starttls", "tls" or "none"
+    pub smtp_security: String,
+    // Reject the login of accounts that have not verified their email address
+    pub require_verification: bool,
+    pub verification_token_lifetime_secs: u64,
+    pub reset_token_lifetime_secs: u64,
+    // Per account and kind of mail (verification, password reset, account name)
+    pub max_mails_per_hour: usize,
+}
+
+// Pages that consume the links of the mails (verification, password reset)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountWebSection {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    // Base URL of the links in the mails, as reachable by the players
+    pub public_url: String,
+}
+
+// Checks of the PSN tickets sent with NuPS3Login
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PsnSection {
+    // Accept only tickets signed by one of the issuers (off: trust any ticket)
+    pub verify_tickets: bool,
+    pub max_clock_skew_secs: u64,
+    // Service ids of the game (e.g. "UP0006-BLUS30186_00"); empty accepts any
+    pub allowed_service_ids: Vec<String>,
+    pub issuers: Vec<PsnIssuerConfig>,
+}
+
+// Console identities (PSN account, XUID) are bound to accounts with a link code
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsoleLinkingSection {
+    pub link_code_lifetime_secs: u64,
+    // Link unknown identities to a persona of the same name with allow_insecure_login
+    // on their first login (the behavior before identities were linked)
+    pub allow_legacy_name_login: bool,
+}
+
+// Key that signs tickets (e.g. RPCN or PSN)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PsnIssuerConfig {
+    pub name: String,
+    // Key id in the ticket footer: 4 characters (e.g. "RPCN") or 8 hex digits
+    pub cipher_id: String,
+    // Issuer id of the ticket body, if it should be checked
+    #[serde(default)]
+    pub issuer_id: Option<u32>,
+    // PEM public key, inline or as file
+    #[serde(default)]
+    pub public_key: Option<String>,
+    #[serde(default)]
+    pub public_key_path: Option<String>,
+    // "sha1", "sha224" or "sha256"
+    #[serde(default = "default_psn_digest")]
+    pub digest: String,
+    // Signed part of the ticket: "body" (the body section) or "ticket" (all before the footer)
+    #[serde(default = "default_psn_signed_data")]
+    pub signed_data: String,
+}
+
+fn default_psn_digest() -> String {
+    "sha224".to_string()
+}
+
+fn default_psn_signed_data() -> String {
+    "body".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ServiceConfig {
     pub service_type: String,
     pub handler: String,
+    // "pc", "ps3" or "xbox360"; used to pick the lobbies of FESL clients
+    #[serde(default)]
+    pub platform: Option<String>,
     pub tcp_listeners: Option<Vec<TcpListenerConfig>>,
     pub udp_listeners: Option<Vec<UdpListenerConfig>>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct TcpListenerConfig {
     pub host: String,
     pub port: u16,
@@ -21,14 +238,692 @@ pub struct TcpListenerConfig {
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct UdpListenerConfig {
     pub host: String,
     pub port: u16,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CryptoConfig {
     pub crypto_type: String,
     pub priv_key: Option<String>, // For TLS
     pub pub_key: Option<String>,  // For TLS
 }
+
+#[derive(Debug)]
+pub enum ConfigErr {
+    // The file could not be read
+    Io(String, String),
+    // The file could not be parsed
+    Parse(String, String),
+    // Field (or env variable), value/reason
+    InvalidField(String, String),
+}
+
+impl fmt::Display for ConfigErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigErr::Io(path, reason) => write!(f, "Failed to read config file {}: {}", path, reason),
+            ConfigErr::Parse(path, reason) => write!(f, "Failed to parse config file {}: {}", path, reason),
+            ConfigErr::InvalidField(field, reason) => write!(f, "Invalid config field '{}': {}", field, reason),
+        }
+    }
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            server: ServerConfig::default(),
+            database: DatabaseConfig::default(),
+            tls: TlsConfig::default(),
+            hello: HelloConfig::default(),
+            stun: StunConfig::default(),
+            turn: TurnConfig::default(),
+            admin_api: AdminApiSection::default(),
+            metrics: MetricsSection::default(),
+            login_throttle: LoginThrottleSection::default(),
+            rate_limit: RateLimitSection::default(),
+            mail: MailSection::default(),
+            account_web: AccountWebSection::default(),
+            psn: PsnSection::default(),
+            console_linking: ConsoleLinkingSection::default(),
+            services: default_services(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            secret_key: "UNSAFE_SERVER_SECRET_123456789".to_string(),
+            init_schemas: true,
+            mirror_state_to_db: false,
+            remember_token_lifetime_secs: 30 * 24 * 3600,
+            max_frame_size: 64 * 1024,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            proto: "sqlite".to_string(),
+            name: "db.sqlite".to_string(),
+            user: "".to_string(),
+            password: "".to_string(),
+            host: "".to_string(),
+            port: "".to_string(),
+            params: "".to_string(),
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            private_key: "data/priv.pem".to_string(),
+            public_key: "data/pub.pem".to_string(),
+        }
+    }
+}
+
+impl Default for HelloConfig {
+    fn default() -> Self {
+        Self {
+            theater_ip: "theater.mordorwi.de".to_string(),
+            theater_port: 18885,
+            messenger_ip: "messenger.mordorwi.de".to_string(),
+            messenger_port: 0,
+        }
+    }
+}
+
+impl Default for StunConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            relay_host: "".to_string(),
+            relay_port: 8001,
+            relay_source_port: 39999,
+            internal_source_port: 39999,
+        }
+    }
+}
+
+impl Default for TurnConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            relay_internal_host: "".to_string(),
+            relay_port: 8002,
+            relay_external_ip: "".to_string(),
+        }
+    }
+}
+
+impl Default for AdminApiSection {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            token: "".to_string(),
+        }
+    }
+}
+
+impl Default for MetricsSection {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "127.0.0.1".to_string(),
+            port: 9100,
+        }
+    }
+}
+
+impl Default for LoginThrottleSection {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_secs: 300,
+            max_failures_per_account: 5,
+            max_failures_per_ip: 20,
+            lockout_secs: 60,
+            max_lockout_secs: 3600,
+        }
+    }
+}
+
+impl Default for RateLimitSection {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+impl Default for MailSection {
+    fn default() -> Self {
+        Self {
+            transport: "stdout".to_string(),
+            from: "MordorWide <noreply@localhost>".to_string(),
+            file_dir: "mails".to_string(),
+            smtp_host: "".to_string(),
+            smtp_port: 587,
+            smtp_user: "".to_string(),
+            smtp_password: "".to_string(),
+            smtp_security: "starttls".to_string(),
+            require_verification: false,
+            verification_token_lifetime_secs: 3 * 24 * 3600,
+            reset_token_lifetime_secs: 3600,
+            max_mails_per_hour: 3,
+        }
+    }
+}
+
+impl Default for AccountWebSection {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "0.0.0.0".to_string(),
+            port: 8081,
+            public_url: "http://localhost:8081".to_string(),
+        }
+    }
+}
+
+impl Default for PsnSection {
+    fn default() -> Self {
+        Self {
+            verify_tickets: true,
+            max_clock_skew_secs: 300,
+            allowed_service_ids: Vec::new(),
+            issuers: Vec::new(),
+        }
+    }
+}
+
+impl Default for ConsoleLinkingSection {
+    fn default() -> Self {
+        Self {
+            link_code_lifetime_secs: 900,
+            // The link page is only served if account_web is enabled
+            allow_legacy_name_login: true,
+        }
+    }
+}
+
+fn fesl_service(platform: &str, port: u16, crypto_type: &str) -> ServiceConfig {
+    ServiceConfig {
+        service_type: "Fesl".to_string(),
+        handler: "FeslHandler".to_string(),
+        platform: Some(platform.to_string()),
+        tcp_listeners: Some(vec![TcpListenerConfig {
+            host: "0.0.0.0".to_string(),
+            port,
+            crypto: CryptoConfig {
+                crypto_type: crypto_type.to_string(),
+                priv_key: None,
+                pub_key: None,
+            },
+        }]),
+        udp_listeners: None,
+    }
+}
+
+// The services of the original setup: FESL for PC, PS3 and Xbox 360 and one Theater
+fn default_services() -> Vec<ServiceConfig> {
+    vec![
+        fesl_service("pc", 18880, "tls"),
+        fesl_service("ps3", 18870, "tls"),
+        fesl_service("xbox360", 18860, "plain"),
+        ServiceConfig {
+            service_type: "Theater".to_string(),
+            handler: "TheaterHandler".to_string(),
+            platform: None,
+            tcp_listeners: Some(vec![TcpListenerConfig {
+                host: "0.0.0.0".to_string(),
+                port: 18885,
+                crypto: CryptoConfig {
+                    crypto_type: "plain".to_string(),
+                    priv_key: None,
+                    pub_key: None,
+                },
+            }]),
+            udp_listeners: Some(vec![UdpListenerConfig {
+                host: "0.0.0.0".to_string(),
+                port: 18885,
+            }]),
+        },
+    ]
+}
+
+// Empty variables (e.g. "TURN_RELAY_INTERNAL_HOST=" in an env file) do not override the file
+fn env_override<T: FromStr>(target: &mut T, var: &str) -> Result<(), ConfigErr> {
+    if let Ok(value) = env::var(var)
+        && !value.is_empty()
+    {
+        *target = value
+            .parse::<T>()
+            .map_err(|_| ConfigErr::InvalidField(var.to_string(), format!("cannot parse '{}'", value)))?;
+    }
+    Ok(())
+}
+
+// Flags are set with "1"/"0" (or "true"/"false")
+fn env_override_flag(target: &mut bool, var: &str) -> Result<(), ConfigErr> {
+    if let Ok(value) = env::var(var)
+        && !value.is_empty()
+    {
+        *target = match value.as_str() {
+            "1" | "true" => true,
+            "0" | "false" => false,
+            _ => {
+                return Err(ConfigErr::InvalidField(
+                    var.to_string(),
+                    format!("expected 1 or 0, got '{}'", value),
+                ));
+            }
+        };
+    }
+    Ok(())
+}
+
+fn invalid(field: String, reason: &str) -> Result<(), ConfigErr> {
+    Err(ConfigErr::InvalidField(field, reason.to_string()))
+}
+
+impl Configuration {
+    // Reads the file from MORDORWIDE_CONFIG (default: config.toml, which may be missing),
+    // applies the environment overrides and validates the result.
+    pub fn load() -> Result<Self, ConfigErr> {
+        let (path, required) = match env::var("MORDORWIDE_CONFIG") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_PATH.to_string(), false),
+        };
+
+        let mut configuration = if Path::new(&path).exists() {
+            Self::from_file(&path)?
+        } else if required {
+            return Err(ConfigErr::Io(path, "file not found".to_string()));
+        } else {
+            Self::default()
+        };
+
+        configuration.apply_env_overrides()?;
+        configuration.resolve_tls_keys();
+        configuration.validate()?;
+        Ok(configuration)
+    }
+
+    fn from_file(path: &str) -> Result<Self, ConfigErr> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| ConfigErr::Io(path.to_string(), e.to_string()))?;
+        if path.ends_with(".json") {
+            serde_json::from_str(&content).map_err(|e| ConfigErr::Parse(path.to_string(), e.to_string()))
+        } else {
+            toml::from_str(&content).map_err(|e| ConfigErr::Parse(path.to_string(), e.to_string()))
+        }
+    }
+
+    // The environment variables of the original setup take precedence over the file
+    fn apply_env_overrides(&mut self) -> Result<(), ConfigErr> {
+        env_override(&mut self.server.secret_key, "SECRET_KEY")?;
+        env_override_flag(&mut self.server.init_schemas, "INIT_SCHEMAS")?;
+        env_override_flag(&mut self.server.mirror_state_to_db, "MIRROR_STATE_TO_DB")?;
+        env_override(
+            &mut self.server.remember_token_lifetime_secs,
+            "REMEMBER_TOKEN_LIFETIME_SECS",
+        )?;
+        env_override(&mut self.server.max_frame_size, "MAX_FRAME_SIZE")?;
+
+        env_override(&mut self.database.proto, "DB_PROTO")?;
+        env_override(&mut self.database.name, "DB_NAME")?;
+        env_override(&mut self.database.user, "DB_USER")?;
+        env_override(&mut self.database.password, "DB_PASSWORD")?;
+        env_override(&mut self.database.host, "DB_HOST")?;
+        env_override(&mut self.database.port, "DB_PORT")?;
+        env_override(&mut self.database.params, "DB_PARAMS")?;
+
+        env_override(&mut self.tls.private_key, "PATH_PRIVATE_KEY")?;
+        env_override(&mut self.tls.public_key, "PATH_PUBLIC_KEY")?;
+
+        env_override(&mut self.hello.theater_ip, "HELLO_THEATER_IP")?;
+        env_override(&mut self.hello.theater_port, "HELLO_THEATER_PORT")?;
+        env_override(&mut self.hello.messenger_ip, "HELLO_MESSENGER_IP")?;
+        env_override(&mut self.hello.messenger_port, "HELLO_MESSENGER_PORT")?;
+
+        env_override_flag(&mut self.stun.enabled, "STUN_ENABLED")?;
+        env_override(&mut self.stun.relay_host, "STUN_RELAY_HOST")?;
+        env_override(&mut self.stun.relay_port, "STUN_RELAY_PORT")?;
+        env_override(&mut self.stun.relay_source_port, "STUN_RELAY_SOURCE_PORT")?;
+        env_override(&mut self.stun.internal_source_port, "STUN_INTERNAL_SOURCE_PORT")?;
+
+        env_override_flag(&mut self.turn.enabled, "TURN_ENABLED")?;
+        env_override(&mut self.turn.relay_internal_host, "TURN_RELAY_INTERNAL_HOST")?;
+        env_override(&mut self.turn.relay_port, "TURN_RELAY_PORT")?;
+        env_override(&mut self.turn.relay_external_ip, "TURN_RELAY_EXTERNAL_IP")?;
+
+        env_override_flag(&mut self.admin_api.enabled, "ADMIN_API_ENABLED")?;
+        env_override(&mut self.admin_api.host, "ADMIN_API_HOST")?;
+        env_override(&mut self.admin_api.port, "ADMIN_API_PORT")?;
+        env_override(&mut self.admin_api.token, "ADMIN_API_TOKEN")?;
+
+        env_override_flag(&mut self.metrics.enabled, "METRICS_ENABLED")?;
+        env_override(&mut self.metrics.host, "METRICS_HOST")?;
+        env_override(&mut self.metrics.port, "METRICS_PORT")?;
+
+        env_override_flag(&mut self.login_throttle.enabled, "LOGIN_THROTTLE_ENABLED")?;
+        env_override(&mut self.login_throttle.window_secs, "LOGIN_THROTTLE_WINDOW_SECS")?;
+        env_override(
+            &mut self.login_throttle.max_failures_per_account,
+            "LOGIN_THROTTLE_MAX_FAILURES_PER_ACCOUNT",
+        )?;
+        env_override(
+            &mut self.login_throttle.max_failures_per_ip,
+            "LOGIN_THROTTLE_MAX_FAILURES_PER_IP",
+        )?;
+        env_override(&mut self.login_throttle.lockout_secs, "LOGIN_THROTTLE_LOCKOUT_SECS")?;
+        env_override(
+            &mut self.login_throttle.max_lockout_secs,
+            "LOGIN_THROTTLE_MAX_LOCKOUT_SECS",
+        )?;
+
+        env_override_flag(&mut self.rate_limit.enabled, "RATE_LIMIT_ENABLED")?;
+
+        env_override(&mut self.mail.transport, "MAIL_TRANSPORT")?;
+        env_override(&mut self.mail.from, "MAIL_FROM")?;
+        env_override(&mut self.mail.file_dir, "MAIL_FILE_DIR")?;
+        env_override(&mut self.mail.smtp_host, "MAIL_SMTP_HOST")?;
+        env_override(&mut self.mail.smtp_port, "MAIL_SMTP_PORT")?;
+        env_override(&mut self.mail.smtp_user, "MAIL_SMTP_USER")?;
+        env_override(&mut self.mail.smtp_password, "MAIL_SMTP_PASSWORD")?;
+        env_override(&mut self.mail.smtp_security, "MAIL_SMTP_SECURITY")?;
+        env_override_flag(&mut self.mail.require_verification, "MAIL_REQUIRE_VERIFICATION")?;
+        env_override(
+            &mut self.mail.verification_token_lifetime_secs,
+            "MAIL_VERIFICATION_TOKEN_LIFETIME_SECS",
+        )?;
+        env_override(
+            &mut self.mail.reset_token_lifetime_secs,
+            "MAIL_RESET_TOKEN_LIFETIME_SECS",
+        )?;
+        env_override(&mut self.mail.max_mails_per_hour, "MAIL_MAX_MAILS_PER_HOUR")?;
+
+        env_override_flag(&mut self.account_web.enabled, "ACCOUNT_WEB_ENABLED")?;
+        env_override(&mut self.account_web.host, "ACCOUNT_WEB_HOST")?;
+        env_override(&mut self.account_web.port, "ACCOUNT_WEB_PORT")?;
+        env_override(&mut self.account_web.public_url, "ACCOUNT_WEB_PUBLIC_URL")?;
+
+        env_override_flag(&mut self.psn.verify_tickets, "PSN_VERIFY_TICKETS")?;
+        env_override(&mut self.psn.max_clock_skew_secs, "PSN_MAX_CLOCK_SKEW_SECS")?;
+        if let Ok(value) = env::var("PSN_ALLOWED_SERVICE_IDS")
+            && !value.is_empty()
+        {
+            self.psn.allowed_service_ids = value
+                .split(',')
+                .map(|service_id| service_id.trim().to_string())
+                .filter(|service_id| !service_id.is_empty())
+                .collect();
+        }
+        // A single issuer can be set without a config file
+        if let Ok(path) = env::var("PSN_RPCN_PUBLIC_KEY_PATH")
+            && !path.is_empty()
+        {
+            self.psn.issuers.retain(|issuer| issuer.name != "RPCN");
+            self.psn.issuers.push(PsnIssuerConfig {
+                name: "RPCN".to_string(),
+                cipher_id: "RPCN".to_string(),
+                issuer_id: None,
+                public_key: None,
+                public_key_path: Some(path),
+                digest: default_psn_digest(),
+                signed_data: default_psn_signed_data(),
+            });
+        }
+
+        env_override(
+            &mut self.console_linking.link_code_lifetime_secs,
+            "CONSOLE_LINKING_CODE_LIFETIME_SECS",
+        )?;
+        env_override_flag(
+            &mut self.console_linking.allow_legacy_name_login,
+            "CONSOLE_LINKING_ALLOW_LEGACY_NAME_LOGIN",
+        )?;
+        Ok(())
+    }
+
+    fn resolve_tls_keys(&mut self) {
+        for service in self.services.iter_mut() {
+            for listener in service.tcp_listeners.iter_mut().flatten() {
+                if listener.crypto.crypto_type != "tls" {
+                    continue;
+                }
+                if listener.crypto.priv_key.is_none() {
+                    listener.crypto.priv_key = Some(self.tls.private_key.clone());
+                }
+                if listener.crypto.pub_key.is_none() {
+                    listener.crypto.pub_key = Some(self.tls.public_key.clone());
+                }
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigErr> {
+        if !["sqlite", "postgres"].contains(&self.database.proto.as_str()) {
+            invalid("database.proto".to_string(), "expected 'sqlite' or 'postgres'")?;
+        }
+        if self.database.name.is_empty() {
+            invalid("database.name".to_string(), "must not be empty")?;
+        }
+        if self.server.remember_token_lifetime_secs == 0 {
+            invalid("server.remember_token_lifetime_secs".to_string(), "must be greater than 0")?;
+        }
+        if self.server.remember_token_lifetime_secs > MAX_TOKEN_LIFETIME_SECS {
+            invalid(
+                "server.remember_token_lifetime_secs".to_string(),
+                "must not exceed 10 years",
+            )?;
+        }
+        if self.server.max_frame_size < MIN_FRAME_SIZE {
+            invalid(
+                "server.max_frame_size".to_string(),
+                &format!("must be at least {}", MIN_FRAME_SIZE),
+            )?;
+        }
+        if self.hello.theater_ip.is_empty() {
+            invalid("hello.theater_ip".to_string(), "must not be empty")?;
+        }
+        if self.stun.enabled && self.stun.relay_host.is_empty() {
+            invalid("stun.relay_host".to_string(), "required if STUN is enabled")?;
+        }
+        if self.turn.enabled && self.turn.relay_internal_host.is_empty() {
+            invalid("turn.relay_internal_host".to_string(), "required if TURN is enabled")?;
+        }
+        if self.turn.enabled && self.turn.relay_external_ip.is_empty() {
+            invalid("turn.relay_external_ip".to_string(), "required if TURN is enabled")?;
+        }
+        if self.admin_api.enabled && self.admin_api.token.is_empty() {
+            invalid("admin_api.token".to_string(), "required if the admin API is enabled")?;
+        }
+        if self.login_throttle.enabled {
+            let throttle = &self.login_throttle;
+            for (field, value) in [
+                ("window_secs", throttle.window_secs),
+                ("max_failures_per_account", throttle.max_failures_per_account as u64),
+                ("max_failures_per_ip", throttle.max_failures_per_ip as u64),
+                ("lockout_secs", throttle.lockout_secs),
+            ] {
+                if value == 0 {
+                    invalid(format!("login_throttle.{}", field), "must be greater than 0")?;
+                }
+            }
+            if throttle.max_lockout_secs < throttle.lockout_secs {
+                invalid(
+                    "login_throttle.max_lockout_secs".to_string(),
+                    "must not be less than lockout_secs",
+                )?;
+            }
+        }
+        if !["stdout", "file", "smtp"].contains(&self.mail.transport.as_str()) {
+            invalid("mail.transport".to_string(), "expected 'stdout', 'file' or 'smtp'")?;
+        }
+        if self.mail.from.parse::<lettre::message::Mailbox>().is_err() {
+            invalid("mail.from".to_string(), "not a valid mail address")?;
+        }
+        if self.mail.transport == "file" && self.mail.file_dir.is_empty() {
+            invalid("mail.file_dir".to_string(), "required for the file transport")?;
+        }
+        if self.mail.transport == "smtp" {
+            if self.mail.smtp_host.is_empty() {
+                invalid("mail.smtp_host".to_string(), "required for the smtp transport")?;
+            }
+            if !["starttls", "tls", "none"].contains(&self.mail.smtp_security.as_str()) {
+                invalid(
+                    "mail.smtp_security".to_string(),
+                    "expected 'starttls', 'tls' or 'none'",
+                )?;
+            }
+        }
+        for (field, value) in [
+            ("verification_token_lifetime_secs", self.mail.verification_token_lifetime_secs),
+            ("reset_token_lifetime_secs", self.mail.reset_token_lifetime_secs),
+        ] {
+            if value == 0 || value > MAX_TOKEN_LIFETIME_SECS {
+                invalid(format!("mail.{}", field), "must be between 1 second and 10 years")?;
+            }
+        }
+        if self.account_web.public_url.is_empty() {
+            invalid("account_web.public_url".to_string(), "must not be empty")?;
+        }
+        if self.console_linking.link_code_lifetime_secs == 0
+            || self.console_linking.link_code_lifetime_secs > MAX_TOKEN_LIFETIME_SECS
+        {
+            invalid(
+                "console_linking.link_code_lifetime_secs".to_string(),
+                "must be between 1 second and 10 years",
+            )?;
+        }
+        // Unlinked console identities are sent to the link page of the account web.
+        // Without it and without the login by name, no console could log in.
+        let has_console_services = self.services.iter().any(|service| {
+            service
+                .platform
+                .as_deref()
+                .is_some_and(|platform| platform == "ps3" || platform == "xbox360")
+        });
+        if has_console_services
+            && !self.account_web.enabled
+            && !self.console_linking.allow_legacy_name_login
+        {
+            invalid(
+                "console_linking.allow_legacy_name_login".to_string(),
+                "required for the ps3/xbox360 services if account_web is disabled",
+            )?;
+        }
+        if self.psn.max_clock_skew_secs > MAX_TOKEN_LIFETIME_SECS {
+            invalid("psn.max_clock_skew_secs".to_string(), "must not exceed 10 years")?;
+        }
+        for (i, issuer) in self.psn.issuers.iter().enumerate() {
+            let field = |name: &str| format!("psn.issuers[{}].{}", i, name);
+            if issuer.public_key.is_none() && issuer.public_key_path.is_none() {
+                invalid(field("public_key_path"), "public_key or public_key_path is required")?;
+            }
+            if !["sha1", "sha224", "sha256"].contains(&issuer.digest.as_str()) {
+                invalid(field("digest"), "expected 'sha1', 'sha224' or 'sha256'")?;
+            }
+            if !["body", "ticket"].contains(&issuer.signed_data.as_str()) {
+                invalid(field("signed_data"), "expected 'body' or 'ticket'")?;
+            }
+            let is_hex_id = issuer.cipher_id.len() == 8
+                && issuer.cipher_id.chars().all(|c| c.is_ascii_hexdigit());
+            if !is_hex_id && issuer.cipher_id.len() != 4 {
+                invalid(field("cipher_id"), "expected 4 characters or 8 hex digits")?;
+            }
+        }
+        if self.services.is_empty() {
+            invalid("services".to_string(), "at least one service is required")?;
+        }
+
+        let mut tcp_ports = HashSet::new();
+        let mut udp_ports = HashSet::new();
+        for (service_idx, service) in self.services.iter().enumerate() {
+            let prefix = format!("services[{}]", service_idx);
+            let expected_handler = match service.service_type.as_str() {
+                "Fesl" => "FeslHandler",
+                "Theater" => "TheaterHandler",
+                _ => {
+                    return invalid(format!("{}.service_type", prefix), "expected 'Fesl' or 'Theater'");
+                }
+            };
+            if service.handler != expected_handler {
+                invalid(
+                    format!("{}.handler", prefix),
+                    &format!("expected '{}' for this service type", expected_handler),
+                )?;
+            }
+            if let Some(platform) = &service.platform
+                && !is_valid_platform(platform)
+            {
+                invalid(format!("{}.platform", prefix), "expected 'pc', 'ps3' or 'xbox360'")?;
+            }
+
+            for (listener_idx, listener) in service.tcp_listeners.iter().flatten().enumerate() {
+                let listener_prefix = format!("{}.tcp_listeners[{}]", prefix, listener_idx);
+                if listener.port == 0 {
+                    invalid(format!("{}.port", listener_prefix), "must not be 0")?;
+                }
+                if !tcp_ports.insert(listener.port) {
+                    invalid(format!("{}.port", listener_prefix), "TCP port is used twice")?;
+                }
+                match listener.crypto.crypto_type.as_str() {
+                    "plain" => {}
+                    "tls" => {
+                        for (key_name, key_path) in [
+                            ("priv_key", &listener.crypto.priv_key),
+                            ("pub_key", &listener.crypto.pub_key),
+                        ] {
+                            if !key_path.as_ref().map(|path| Path::new(path).exists()).unwrap_or(false) {
+                                invalid(
+                                    format!("{}.crypto.{}", listener_prefix, key_name),
+                                    &format!("file {:?} not found", key_path.as_deref().unwrap_or("")),
+                                )?;
+                            }
+                        }
+                    }
+                    _ => {
+                        invalid(
+                            format!("{}.crypto.crypto_type", listener_prefix),
+                            "expected 'plain' or 'tls'",
+                        )?;
+                    }
+                }
+            }
+            for (listener_idx, listener) in service.udp_listeners.iter().flatten().enumerate() {
+                let listener_prefix = format!("{}.udp_listeners[{}]", prefix, listener_idx);
+                if listener.port == 0 {
+                    invalid(format!("{}.port", listener_prefix), "must not be 0")?;
+                }
+                if !udp_ports.insert(listener.port) {
+                    invalid(format!("{}.port", listener_prefix), "UDP port is used twice")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // FESL port -> platform of the clients connecting to it
+    pub fn get_fesl_platforms(&self) -> HashMap<u16, String> {
+        let mut platform_ports = HashMap::new();
+        for service in self.services.iter() {
+            let Some(platform) = &service.platform else {
+                continue;
+            };
+            for listener in service.tcp_listeners.iter().flatten() {
+                platform_ports.insert(listener.port, platform.to_string());
+            }
+        }
+        platform_ports
+    }
+}
@@ -1,17 +1,38 @@
 use chrono::NaiveDate;
 use indexmap::IndexMap;
 use uuid::Uuid;
-use tracing::{debug, info};
+use sea_orm::entity::*;
+use tracing::{debug, info, warn};
 
-use crate::handler::{submit_packet, to_error_packet};
+use crate::handler::{decode_request, submit_packet, to_error_packet};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
-use crate::handler::fesl::FeslHandler;
+use crate::handler::fesl::{record_login_failure, reject_if_throttled, FeslHandler};
+use crate::login_throttle::ThrottleKey;
+use crate::mail::account::send_verification_mail;
+use crate::orm::model::account;
 use crate::utils::auth::user::register_new_user;
 use crate::utils::data_validation::email::{email_normalize, email_validate};
 use crate::utils::data_validation::password::password_validate;
 
+plasma_struct! {
+    struct NuAddAccountRequest {
+        nuid: String = "nuid",
+        password: String = "password",
+        optin_global: bool = "globalOptin",
+        optin_thirdparty: bool = "thirdPartyOptin",
+        email_parental: String = "parentalEmail",
+        date_of_birth_day: u32 = "DOBDay",
+        date_of_birth_month: u32 = "DOBMonth",
+        date_of_birth_year: i32 = "DOBYear",
+        zip_code: String = "zipCode",
+        country: String = "country",
+        language: String = "language",
+        tos_version: String = "tosVersion",
+    }
+}
 
 pub async fn acct_nuaddaccount(
     fh: &FeslHandler,
@@ -30,51 +51,40 @@ pub async fn acct_nuaddaccount(
         "tosVersion": "1.0",
     */
 
-    let nuid: &String = prq.packet.data.get("nuid").unwrap();
-    let plain_password: &String = prq.packet.data.get("password").unwrap();
-    let optin_global: &String = prq.packet.data.get("globalOptin").unwrap();
-    let optin_thirdparty: &String = prq.packet.data.get("thirdPartyOptin").unwrap();
-    let email_parental: &String = prq.packet.data.get("parentalEmail").unwrap();
-    let date_of_birth_day: &String = prq.packet.data.get("DOBDay").unwrap();
-    let date_of_birth_month: &String = prq.packet.data.get("DOBMonth").unwrap();
-    let date_of_birth_year: &String = prq.packet.data.get("DOBYear").unwrap();
-    let zip_code: &String = prq.packet.data.get("zipCode").unwrap();
-    let country: &String = prq.packet.data.get("country").unwrap();
-    let language: &String = prq.packet.data.get("language").unwrap();
-    let tos_version: &String = prq.packet.data.get("tosVersion").unwrap();
-
-    // Convert "1" to true, "0" to false
-    let optin_global = optin_global == "1";
-    let optin_thirdparty = optin_thirdparty == "1";
+    let request: NuAddAccountRequest = decode_request(&prq).await?;
 
     // Normalize the email address first
-    let normalized_nuid = email_normalize(nuid);
+    let normalized_nuid = email_normalize(&request.nuid);
+
+    // Failed registrations are counted like failed logins (e.g. probing for emails)
+    let client_ip = prq.con.client_ip.clone();
+    let throttle_keys = [ThrottleKey::Ip(&client_ip), ThrottleKey::Account(&normalized_nuid)];
+    reject_if_throttled(&prq, "NuAddAccount", &throttle_keys).await?;
 
     // Validate the email address
     if let Err(mw_err) = email_validate(&normalized_nuid) {
+        record_login_failure(&prq, &throttle_keys);
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_LoginErrorHeading as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
         return Err("Invalid email address");
     }
 
     // Validate the password
-    if let Err(mw_err) = password_validate(&plain_password) {
+    if let Err(mw_err) = password_validate(&request.password) {
+        record_login_failure(&prq, &throttle_keys);
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_InvalidPassword as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
         return Err("Invalid password");
     }
-    // Parse the birthdate (Format: YYYY-MM-DD)
-    let birthdate: NaiveDate = chrono::NaiveDate::parse_from_str(
-        format!(
-            "{}-{}-{}",
-            date_of_birth_year.to_string(),
-            date_of_birth_month.to_string(),
-            date_of_birth_day.to_string()
-        )
-        .as_str(),
-        "%Y-%m-%d",
-    )
-    .unwrap();
+    let Some(birthdate) = NaiveDate::from_ymd_opt(
+        request.date_of_birth_year,
+        request.date_of_birth_month,
+        request.date_of_birth_day,
+    ) else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_LoginErrorHeading as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Invalid birthdate");
+    };
 
     // ToDo: Make this more general
 
@@ -86,22 +96,23 @@ pub async fn acct_nuaddaccount(
 
     let reg_result = register_new_user(
         &normalized_nuid,
-        plain_password,
+        &request.password,
         &lobby_key.to_string(),
         birthdate,
-        optin_global,
-        optin_thirdparty,
-        email_parental,
-        zip_code,
-        country,
-        language,
-        tos_version,
+        request.optin_global,
+        request.optin_thirdparty,
+        &request.email_parental,
+        &request.zip_code,
+        &request.country,
+        &request.language,
+        &request.tos_version,
         &entitlement_key,
         &prq.sstate,
     )
     .await;
 
     if let Err(mw_err) = reg_result {
+        record_login_failure(&prq, &throttle_keys);
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_LoginErrorHeading as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
         debug!(target: "fesl", "ACCT/NuAddAccount - Error occurred: {:?}", mw_err);
@@ -112,6 +123,16 @@ pub async fn acct_nuaddaccount(
 
     let user_id = reg_result.unwrap();
 
+    // Send the link to verify the email address. A failed mail does not fail the
+    // registration; a new link is sent on the next login if verification is required.
+    if let Ok(Some(db_account)) = account::Entity::find_by_id(user_id)
+        .one(&*prq.sstate.database)
+        .await
+        && let Err(mail_err) = send_verification_mail(&db_account, &prq.sstate).await
+    {
+        warn!(target: "mail", "Failed to send the verification mail to {}: {}", &db_account.email, mail_err);
+    }
+
     let mut response_hm = IndexMap::new();
     response_hm.insert("TXN".to_string(), "NuAddAccount".to_string());
 
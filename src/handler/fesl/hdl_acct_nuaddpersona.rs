@@ -3,34 +3,28 @@ use sea_orm::entity::*;
 use sea_orm::query::*;
 use sea_orm::sea_query::{Expr, Func};
 
-use crate::handler::{submit_packet, to_error_packet};
+use crate::handler::{decode_request, submit_packet, to_error_packet};
 use crate::orm::model::persona;
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::utils::config_values::get_cfg_value;
 use crate::handler::fesl::FeslHandler;
 use crate::utils::data_validation::persona::persona_validate;
 
+plasma_struct! {
+    struct NuAddPersonaRequest {
+        name: String = "name",
+    }
+}
 
 pub async fn acct_nuaddpersona(
     fh: &FeslHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
-    // User should be authenticated
-    if !prq.is_authenticated_user().await {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("User not authenticated.");
-    }
-    // User should not have selected a persona yet
-    if prq.get_active_persona_model().await.is_some() {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("Persona already selected.");
-    }
-
-    let selected_persona_name: String = prq.packet.data.get("name").unwrap().to_string();
+    let request: NuAddPersonaRequest = decode_request(&prq).await?;
+    let selected_persona_name = request.name;
 
     let Some(db_session) = prq.get_active_session_model().await else {
         panic!("Session not found although authenticated earlier...");
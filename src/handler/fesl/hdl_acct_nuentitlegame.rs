@@ -2,9 +2,10 @@ use indexmap::IndexMap;
 use sea_orm::entity::*;
 use sea_orm::query::*;
 
-use crate::handler::{submit_packet, to_error_packet};
+use crate::handler::{decode_request, submit_packet, to_error_packet};
 use crate::orm::model::account;
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::utils::config_values::get_cfg_value;
@@ -13,6 +14,11 @@ use crate::utils::auth::user::{
     get_credentials_from_packet, validate_credentials,
 };
 
+plasma_struct! {
+    struct NuEntitleGameRequest {
+        key: String = "key",
+    }
+}
 
 pub async fn acct_nuentitlegame(
     fh: &FeslHandler,
@@ -21,14 +27,8 @@ pub async fn acct_nuentitlegame(
     /* {"TXN": "NuEntitleGame", "key": "<License Key>", "nuid": "test14", "password": "test14"} } */
     // Allegedly, there can also be the authentication via encryptedInfo
 
-    // User should be authenticated
-    if !prq.is_authenticated_user().await {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("User not authenticated.");
-    }
-
-    let provided_entitlement_key: String = prq.packet.data.get("key").unwrap().to_string();
+    let request: NuEntitleGameRequest = decode_request(&prq).await?;
+    let provided_entitlement_key = request.key;
 
     // Extract login credentials from the packet
     let credentials = get_credentials_from_packet(&prq.packet, &prq.sstate).await;
@@ -40,7 +40,7 @@ pub async fn acct_nuentitlegame(
     let credentials = credentials.unwrap();
 
     // Validate the credentials
-    let validation = validate_credentials(&credentials, &prq.sstate).await;
+    let validation = validate_credentials(&credentials, prq.ban_subject(), &prq.sstate).await;
     if validation.is_err() {
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
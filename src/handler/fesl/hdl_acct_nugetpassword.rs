@@ -0,0 +1,76 @@
+use indexmap::IndexMap;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use tracing::{info, warn};
+
+use crate::handler::{submit_packet, to_error_packet};
+use crate::orm::model::account;
+use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_errors::EAError;
+use crate::plasma_handle::PlasmaRequestBundle;
+use crate::handler::fesl::{record_login_failure, reject_if_throttled, FeslHandler};
+use crate::login_throttle::ThrottleKey;
+use crate::mail::account::send_password_reset_mail;
+use crate::mail::MailErr;
+use crate::utils::data_validation::email::email_normalize;
+
+
+pub async fn acct_nugetpassword(
+    fh: &FeslHandler,
+    mut prq: PlasmaRequestBundle,
+) -> Result<(), &'static str> {
+    /* {"TXN": "NuGetPassword", "nuid": "player@example.com"} */
+    // The password cannot be sent (only its hash is stored), so a reset link is sent instead
+    let Some(nuid) = prq.packet.data.get("nuid").or(prq.packet.data.get("email")) else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("No account name given.");
+    };
+    let normalized_nuid = email_normalize(nuid);
+
+    // Unknown addresses are counted like failed logins (e.g. probing for emails)
+    let client_ip = prq.con.client_ip.clone();
+    let throttle_keys = [ThrottleKey::Ip(&client_ip), ThrottleKey::Account(&normalized_nuid)];
+    reject_if_throttled(&prq, "NuGetPassword", &throttle_keys).await?;
+
+    let Ok(Some(db_account)) = account::Entity::find()
+        .filter(account::Column::Email.eq(&normalized_nuid))
+        .one(&*prq.sstate.database)
+        .await
+    else {
+        record_login_failure(&prq, &throttle_keys);
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_EmailNotFound as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Account not found.");
+    };
+
+    match send_password_reset_mail(&db_account, &prq.sstate).await {
+        Ok(()) => {
+            info!(target: "auth", "Password reset mail sent for user: {} (via {})", &db_account.email, &prq.con.to_string());
+        }
+        Err(MailErr::RateLimited) => {
+            let err_pkt = to_error_packet(&prq.packet, EAError::EA_TooManyPassRecov as i32, None);
+            submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+            return Err("Too many password reset mails.");
+        }
+        Err(mail_err) => {
+            warn!(target: "mail", "Failed to send the password reset mail to {}: {}", &db_account.email, mail_err);
+            let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+            submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+            return Err("Sending the password reset mail failed.");
+        }
+    }
+
+    let mut response_hm = IndexMap::new();
+    response_hm.insert("TXN".to_string(), "NuGetPassword".to_string());
+
+    let response = DataPacket::new(
+        DataMode::FESL_ACCT,
+        PacketMode::FeslSinglePacketResponse,
+        prq.packet.packet_id,
+        response_hm,
+    );
+
+    submit_packet(response, &prq.con, &prq.sstate, 0).await;
+    Ok(())
+}
@@ -2,10 +2,9 @@ use indexmap::IndexMap;
 use sea_orm::entity::*;
 use sea_orm::query::*;
 
-use crate::handler::{submit_packet, to_error_packet};
+use crate::handler::submit_packet;
 use crate::orm::model::persona;
 use crate::packet::{DataMode, DataPacket, PacketMode};
-use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::handler::fesl::FeslHandler;
 
@@ -14,20 +13,6 @@ pub async fn acct_nugetpersona(
     fh: &FeslHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
-    // User should be authenticated
-    if !prq.is_authenticated_user().await {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("User not authenticated.");
-    }
-
-    // User should not have selected a persona yet
-    if prq.get_active_persona_model().await.is_some() {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("Persona already selected.");
-    }
-
     // Get the namespace argument
     let namespace: Option<String> = prq.packet.data.get("namespace").cloned();
 
@@ -1,18 +1,21 @@
 use indexmap::IndexMap;
 use sea_orm::entity::*;
 use sea_orm::query::*;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use crate::handler::{submit_packet, to_error_packet};
 use crate::mordorwide_errors::MWErr;
-use crate::orm::model::config;
+use crate::orm::model::{account, config};
 use crate::packet::{DataMode, DataPacket, PacketMode};
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
-use crate::utils::auth::jwt::{get_jwt_for_credentials, JWTErr};
-use crate::utils::auth::user::UserAuthErr;
+use crate::utils::auth::jwt::JWTErr;
+use crate::utils::auth::tokens::{issue_login_token, revoke_login_token};
+use crate::utils::auth::user::{get_credentials_from_packet, CredentialType, UserAuthErr};
+use crate::mail::account::send_verification_mail;
 use crate::utils::config_values::get_cfg_value;
-use crate::handler::fesl::FeslHandler;
+use crate::handler::fesl::{record_login_failure, reject_if_throttled, FeslHandler};
+use crate::login_throttle::ThrottleKey;
 
 
 pub async fn acct_nulogin(
@@ -31,18 +34,54 @@ pub async fn acct_nulogin(
     // let macAddr: Option<&String> = prq.packet.data.get("macAddr");
     let tos_version: Option<String> = prq.packet.data.get("tosVersion").cloned();
 
+    // Failed logins are counted per account and per source IP. Token logins only
+    // count for the IP, as the token does not name the account.
+    let (login_name, used_token_id) = match get_credentials_from_packet(&prq.packet, &prq.sstate).await {
+        Ok(CredentialType::PlainText(username, _)) => (Some(username), None),
+        Ok(CredentialType::RememberToken(_, token_id)) => (None, Some(token_id)),
+        Err(_) => (None, None),
+    };
+    let client_ip = prq.con.client_ip.clone();
+    let mut throttle_keys = vec![ThrottleKey::Ip(&client_ip)];
+    if let Some(login_name) = &login_name {
+        throttle_keys.push(ThrottleKey::Account(login_name));
+    }
+    reject_if_throttled(&prq, "NuLogin", &throttle_keys).await?;
+
     match prq.auth_by_packet().await {
-        Ok(()) => {}
+        Ok(()) => {
+            prq.sstate.login_throttle.record_success(&throttle_keys);
+        }
         Err(mw_err) => {
-            let error_id: i32 = match mw_err {
-                MWErr::UserAuthError(UserAuthErr::UserNotFound) => EAError::EA_EmailNotFound as i32,
+            // Bans, unverified accounts and database errors are not the fault of the credentials
+            if !matches!(
+                mw_err,
+                MWErr::UserAuthError(UserAuthErr::UserBanned(_))
+                    | MWErr::UserAuthError(UserAuthErr::NotVerified)
+                    | MWErr::DBError
+            ) {
+                record_login_failure(&prq, &throttle_keys);
+            }
+            let (error_id, error_text): (i32, Option<String>) = match &mw_err {
+                MWErr::UserAuthError(UserAuthErr::UserNotFound) => {
+                    (EAError::EA_EmailNotFound as i32, None)
+                }
                 MWErr::UserAuthError(UserAuthErr::InvalidPassword) => {
-                    EAError::EA_InvalidPassword as i32
+                    (EAError::EA_InvalidPassword as i32, None)
+                }
+                MWErr::UserAuthError(UserAuthErr::UserBanned(ban_text)) => {
+                    (EAError::EA_Banned as i32, Some(ban_text.to_string()))
+                }
+                MWErr::UserAuthError(UserAuthErr::NotVerified) => {
+                    resend_verification_mail(&prq, login_name.as_deref()).await;
+                    (
+                        EAError::EA_Pending as i32,
+                        Some("Please verify your email address first. Check your mails for the link.".to_string()),
+                    )
                 }
-                MWErr::UserAuthError(UserAuthErr::UserBanned) => EAError::EA_Banned as i32,
-                _ => EAError::EA_AuthFail as i32,
+                _ => (EAError::EA_AuthFail as i32, None),
             };
-            let err_pkt = to_error_packet(&prq.packet, error_id, None);
+            let err_pkt = to_error_packet(&prq.packet, error_id, error_text);
             submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
             debug!(target: "fesl", "ACCT/NuLogin - Error occurred: {:?}", mw_err);
             return Err("Authentication failed.");
@@ -69,11 +108,25 @@ pub async fn acct_nulogin(
     info!(target: "auth", "Login successful for user: {} (via {})", &db_account.email, &prq.con.to_string());
 
     if return_jwt_credentials {
-        match get_jwt_for_credentials(
-            &db_account.email,
-            &db_account.password_hashed,
-            &prq.sstate.server_secret,
-        ) {
+        // Rotate the token: the one used for this login is replaced by a new one
+        // If the used token cannot be revoked, no new one is issued
+        if let Some(used_token_id) = &used_token_id {
+            // No revoked token means that a concurrent login has used it already
+            let revoked = match revoke_login_token(used_token_id, &prq.sstate.database).await {
+                Ok(n_revoked) => n_revoked > 0,
+                Err(e) => {
+                    warn!(target: "auth", "Failed to revoke the used login token of user {}: {}", db_account.id, e);
+                    false
+                }
+            };
+            if !revoked {
+                let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
+                submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+                discard_login_session(&mut prq, db_session.id);
+                return Err("Revoking the used login token failed.");
+            }
+        }
+        match issue_login_token(db_account.id, &prq.sstate).await {
             Ok(encrypted_info) => {
                 response_hm.insert("encryptedLoginInfo".to_string(), encrypted_info);
             }
@@ -85,7 +138,8 @@ pub async fn acct_nulogin(
                 let err_pkt = to_error_packet(&prq.packet, error_id, None);
                 submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
                 debug!(target: "fesl", "ACCT/NuLogin - Error occurred: {:?}", mw_err);
-                return Err("JWT encoding failed.");
+                discard_login_session(&mut prq, db_session.id);
+                return Err("Issuing the login token failed.");
             }
         };
     }
@@ -179,3 +233,27 @@ pub async fn acct_nulogin(
 
     Ok(())
 }
+
+// Sends a new verification link to an unverified account that tries to log in
+async fn resend_verification_mail(prq: &PlasmaRequestBundle, login_name: Option<&str>) {
+    let Some(login_name) = login_name else {
+        return;
+    };
+    let Ok(Some(db_account)) = account::Entity::find()
+        .filter(account::Column::Email.eq(login_name))
+        .one(&*prq.sstate.database)
+        .await
+    else {
+        return;
+    };
+    if let Err(mail_err) = send_verification_mail(&db_account, &prq.sstate).await {
+        warn!(target: "mail", "Failed to resend the verification mail to {}: {}", &db_account.email, mail_err);
+    }
+}
+
+// Undoes the session registered by the authentication, so that a failed login does not
+// leave an authenticated connection behind. The connection stays open for another attempt.
+fn discard_login_session(prq: &mut PlasmaRequestBundle, session_id: i64) {
+    prq.sstate.registry.remove_session(session_id);
+    prq.flush();
+}
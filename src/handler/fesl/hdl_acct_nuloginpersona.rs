@@ -3,31 +3,27 @@ use sea_orm::entity::*;
 use sea_orm::query::*;
 use tracing::info;
 
-use crate::handler::{submit_packet, to_error_packet};
+use crate::handler::{decode_request, reject_if_banned, submit_packet, to_error_packet};
 use crate::orm::model::persona;
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
-use crate::handler::fesl::FeslHandler;
+use crate::utils::bans::BanSubject;
+use crate::handler::fesl::{flush_pending_messages, FeslHandler};
 
+plasma_struct! {
+    struct NuLoginPersonaRequest {
+        name: String = "name",
+    }
+}
 
 pub async fn acct_nuloginpersona(
     fh: &FeslHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
-    // User should be authenticated
-    if !prq.is_authenticated_user().await {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("User not authenticated.");
-    }
-    // User should not have selected a persona yet
-    if prq.get_active_persona_model().await.is_some() {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("Persona already selected.");
-    }
-    let persona_name: String = prq.packet.data.get("name").unwrap().to_string();
+    let request: NuLoginPersonaRequest = decode_request(&prq).await?;
+    let persona_name = request.name;
 
     // Check if the persona exists
     let Ok(Some(db_persona)) = persona::Entity::find()
@@ -54,17 +50,17 @@ pub async fn acct_nuloginpersona(
 
     let owner_name = db_account.email;
 
+    // The persona itself may be banned
+    let ban_subject = BanSubject {
+        email: Some(owner_name.clone()),
+        persona_id: Some(persona_id),
+        ..BanSubject::from_session(&db_session)
+    };
+    reject_if_banned(&prq, &ban_subject).await?;
+
     // Report the persona login
     info!(target: "auth", "Login successful for persona: {} (by user: {})", &persona_name, &owner_name);
 
-    let mut db_session_active = db_session.into_active_model();
-    db_session_active.persona_id = Set(persona_id);
-    db_session_active
-        .update(&*prq.sstate.database)
-        .await
-        .unwrap();
-    prq.flush();
-
     // Select the persona
     let set_success = prq.set_active_persona_session(persona_id).await;
     if !set_success {
@@ -88,5 +84,8 @@ pub async fn acct_nuloginpersona(
     );
 
     submit_packet(response, &prq.con, &prq.sstate, 0).await;
+
+    // Deliver the messages that arrived while the persona was offline
+    flush_pending_messages(&prq.sstate, persona_id).await;
     Ok(())
 }
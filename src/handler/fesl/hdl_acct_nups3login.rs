@@ -1,27 +1,22 @@
 use indexmap::IndexMap;
-use sea_orm::entity::*;
-use sea_orm::query::*;
-use tracing::{info};
+use tracing::{info, warn};
 
-use crate::handler::{submit_packet, to_error_packet};
-use crate::orm::model::{account, persona};
+use crate::handler::{reject_if_banned, submit_packet, to_error_packet};
 use crate::packet::{DataMode, DataPacket, PacketMode};
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
+use crate::utils::bans::BanSubject;
+use crate::utils::linking::IdentityPlatform;
 use crate::utils::psn::{dec_hex_str, PSNTicket};
-use crate::handler::fesl::FeslHandler;
+use crate::handler::fesl::{
+    record_login_failure, reject_if_throttled, resolve_console_identity, FeslHandler,
+};
+use crate::login_throttle::ThrottleKey;
 
 pub async fn acct_nups3login(
     fh: &FeslHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
-    // User should not be authenticated
-    if prq.is_authenticated_user().await {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("User already authenticated.");
-    }
-
     // Login via RPCS3 (requires an active RPCN account to be logged in)
     let ticket_opt = prq.packet.data.get("ticket").cloned();
     //let mac_addr_opt = prq.packet.data.get("macAddr");
@@ -29,6 +24,8 @@ pub async fn acct_nups3login(
 
     // Get PSN name from ticket, so we need to extract the ticket first
     let Some(mut ticket) = ticket_opt else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
         return Err("No ticket provided");
     };
 
@@ -36,50 +33,56 @@ pub async fn acct_nups3login(
     if ticket.starts_with("$") {
         ticket = ticket[1..].to_string();
     }
-    // Decode the hex string into bytes
-    let Ok(ticket_bytes) = dec_hex_str(&ticket) else {
-        return Err("Invalid ticket format");
+    // Decode the hex string into bytes and parse the ticket
+    let psn_ticket = if ticket.is_ascii() && ticket.len() % 2 == 0 {
+        dec_hex_str(&ticket)
+            .ok()
+            .and_then(|ticket_bytes| PSNTicket::from_bytes(&ticket_bytes).ok())
+    } else {
+        None
     };
-
-    // Parse ticket
-    let Ok(psn_ticket) = PSNTicket::from_bytes(&ticket_bytes) else {
+    // Extract PSN name (It should be the 6th entry from the body section)
+    let Some((psn_ticket, psn_name)) =
+        psn_ticket.and_then(|psn_ticket| psn_ticket.online_id().map(|psn_name| (psn_ticket, psn_name)))
+    else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
         return Err("Failed to parse ticket");
     };
 
-    // Extract PSN name (It should be the 6th entry from the first section)
-    let psn_name_bytes = psn_ticket.sections[0].data_entries[5].payload.clone();
-
-    // Remove trailing zeroes
-    let trimmed_psn_name = psn_name_bytes
-        .iter()
-        .rposition(|&byte| byte != 0)
-        .map(|last_non_zero| &psn_name_bytes[..=last_non_zero])
-        .unwrap_or(&[]);
-    let psn_name = String::from_utf8(trimmed_psn_name.to_vec()).unwrap();
+    // Failed logins are counted per PSN name and per source IP
+    let client_ip = prq.con.client_ip.clone();
+    let throttle_keys = [ThrottleKey::Ip(&client_ip), ThrottleKey::Account(&psn_name)];
+    reject_if_throttled(&prq, "NuPS3Login", &throttle_keys).await?;
+
+    // Only tickets signed by a configured issuer are trusted
+    if let Err(ticket_err) = prq.sstate.psn_verifier.verify(&psn_ticket) {
+        warn!(target: "auth", "Rejected PSN ticket for {} ({:?}, via {})", &psn_name, ticket_err, &prq.con.to_string());
+        // The PSN name of a forged ticket is chosen by the sender, so only the IP is
+        // counted. Otherwise, anyone could lock out the owner of the name.
+        record_login_failure(&prq, &[ThrottleKey::Ip(&client_ip)]);
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Invalid PSN ticket");
+    }
 
-    // Get persona data from the database
-    let Ok(Some(db_persona)) = persona::Entity::find()
-        .filter(
-            Condition::all()
-                .add(persona::Column::Name.eq(&psn_name))
-                .add(persona::Column::AllowInsecureLogin.eq(true)),
-        )
-        .one(&*prq.sstate.database)
-        .await
-    else {
-        // No persona found
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NotFound as i32, None);
+    // The persona is found by the PSN account id the ticket was issued for
+    let Some(psn_user_id) = psn_ticket.user_id() else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("Failed to retrieve persona data");
+        return Err("No PSN account id in ticket");
     };
-
-    // Get user data from the database
-    let Ok(Some(db_account)) = account::Entity::find_by_id(db_persona.user_id)
-        .one(&*prq.sstate.database)
-        .await
-    else {
-        return Err("Failed to retrieve account data");
+    let (db_account, db_persona) =
+        resolve_console_identity(&prq, IdentityPlatform::Psn, &psn_user_id.to_string(), &psn_name)
+            .await?;
+
+    // Banned accounts, personas and devices may not log in
+    let ban_subject = BanSubject {
+        email: Some(db_account.email.clone()),
+        persona_id: Some(db_persona.id),
+        ..prq.ban_subject()
     };
+    reject_if_banned(&prq, &ban_subject).await?;
 
     // Report the login
     info!(target: "auth", "Login via PS3 successful for user: {} (via {})", &db_account.email, &prq.con.to_string());
@@ -88,6 +91,7 @@ pub async fn acct_nups3login(
     let lobby_key = db_account.lobby_key.clone();
     let persona_name = db_persona.name.clone();
 
+    prq.sstate.login_throttle.record_success(&throttle_keys);
     prq.set_active_user_session(&lobby_key, user_id, None).await;
     prq.set_active_persona_session(db_persona.id).await;
 
@@ -2,26 +2,28 @@ use indexmap::IndexMap;
 use sea_orm::entity::*;
 use sea_orm::query::*;
 
-use crate::handler::submit_packet;
+use crate::handler::{decode_request, submit_packet};
 use crate::orm::model::persona;
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::handler::fesl::FeslHandler;
 
+plasma_struct! {
+    struct NuSuggestPersonasRequest {
+        name: String = "name",
+        max_suggestions: Option<usize> = "maxSuggestions",
+    }
+}
 
 pub async fn acct_nusuggestpersonas(
     fh: &FeslHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
     /* {"TXN": "NuSuggestPersonas", "name": "test12p1", "maxSuggestions": "4", "keywords.[]": "0"} */
-    let name: &String = prq.packet.data.get("name").unwrap();
-    let max_suggestions: usize = prq
-        .packet
-        .data
-        .get("maxSuggestions")
-        .unwrap_or(&"3".to_string())
-        .parse()
-        .unwrap();
+    let request: NuSuggestPersonasRequest = decode_request(&prq).await?;
+    let name = request.name;
+    let max_suggestions = request.max_suggestions.unwrap_or(3);
 
     let mut suggestions = Vec::with_capacity(max_suggestions);
     let mut ctr: usize = 1;
@@ -1,60 +1,49 @@
 use indexmap::IndexMap;
-use sea_orm::entity::*;
-use sea_orm::query::*;
 use tracing::{info};
 
-use crate::handler::{submit_packet, to_error_packet};
-use crate::orm::model::{account, persona};
+use crate::handler::{reject_if_banned, submit_packet, to_error_packet};
 use crate::packet::{DataMode, DataPacket, PacketMode};
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
-use crate::handler::fesl::FeslHandler;
+use crate::utils::bans::BanSubject;
+use crate::utils::linking::IdentityPlatform;
+use crate::handler::fesl::{reject_if_throttled, resolve_console_identity, FeslHandler};
+use crate::login_throttle::ThrottleKey;
 
 
 pub async fn acct_nuxbl360login(
     fh: &FeslHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
-    // The user should not be authenticated
-    if prq.is_authenticated_user().await {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("User already authenticated.");
-    }
-
     // Login via Xenia Xbox Live network (requires a Xenia WebService account to be logged in)
+    // Neither the gamertag nor the XUID is verified, so a linked XUID is no proof of
+    // ownership. The persona has to allow insecure logins (see resolve_console_identity).
     let gamertag_opt = prq.packet.data.get("gamertag").cloned();
     let xuid_opt = prq.packet.data.get("xuid").cloned();
-    let mac_addr_opt = prq.packet.data.get("macAddr").cloned();
-    let console_id_opt = prq.packet.data.get("consoleId").cloned();
-
-    let Some(gamertag) = gamertag_opt else {
-        return Err("No gamertag provided");
-    };
 
-    // Get persona data from the database
-    let Ok(Some(db_persona)) = persona::Entity::find()
-        .filter(
-            Condition::all()
-                .add(persona::Column::Name.eq(&gamertag))
-                .add(persona::Column::AllowInsecureLogin.eq(true)),
-        )
-        .one(&*prq.sstate.database)
-        .await
+    // The gamertag may change; the XUID identifies the Xbox Live account
+    let (Some(gamertag), Some(xuid)) = (gamertag_opt, xuid_opt.filter(|xuid| !xuid.is_empty()))
     else {
-        // No persona found
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NotFound as i32, None);
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("Failed to retrieve persona data");
+        return Err("No gamertag or XUID provided");
     };
 
-    // Get user data from the database
-    let Ok(Some(db_account)) = account::Entity::find_by_id(db_persona.user_id)
-        .one(&*prq.sstate.database)
-        .await
-    else {
-        return Err("Failed to retrieve account data");
+    // Failed logins are counted per gamertag and per source IP
+    let client_ip = prq.con.client_ip.clone();
+    let throttle_keys = [ThrottleKey::Ip(&client_ip), ThrottleKey::Account(&gamertag)];
+    reject_if_throttled(&prq, "NuXBL360Login", &throttle_keys).await?;
+
+    let (db_account, db_persona) =
+        resolve_console_identity(&prq, IdentityPlatform::Xbl, &xuid, &gamertag).await?;
+
+    // Banned accounts, personas and devices may not log in
+    let ban_subject = BanSubject {
+        email: Some(db_account.email.clone()),
+        persona_id: Some(db_persona.id),
+        ..prq.ban_subject()
     };
+    reject_if_banned(&prq, &ban_subject).await?;
 
     // Report the login
     info!(target: "auth", "Login via Xbox360 successful for user: {} (via {})", &db_account.email, &prq.con.to_string());
@@ -65,6 +54,7 @@ pub async fn acct_nuxbl360login(
     let lobby_key = db_account.lobby_key.clone();
     let persona_name = db_persona.name.clone();
 
+    prq.sstate.login_throttle.record_success(&throttle_keys);
     prq.set_active_user_session(&lobby_key, user_id, None).await;
     prq.set_active_persona_session(persona_id).await;
 
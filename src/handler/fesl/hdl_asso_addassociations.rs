@@ -1,27 +1,66 @@
 use indexmap::IndexMap;
+use sea_orm::entity::*;
+use sea_orm::query::*;
 
-use crate::handler::{submit_packet, to_error_packet};
+use crate::handler::{decode_request, submit_packet, to_error_packet};
+use crate::orm::model::persona;
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::handler::fesl::FeslHandler;
+use crate::utils::associations::{
+    add_association, get_association_list, get_max_list_size, has_association,
+    is_valid_asso_type, AddAssociationResult, ASSO_TYPE_FRIENDS, ASSO_TYPE_RECENT_PLAYERS,
+};
+
+use super::utils_presence::send_presence;
 
 
 pub const LOTRCQ_DOMAIN: &str = "eadm";
 pub const LOTRCQ_SUBDOMAIN: &str = "eadm";
 pub const LOTRCQ_PARTITION_ID: &str = "online_content";
 
+plasma_struct! {
+    struct AssociationOwner {
+        // Identical to the user id
+        id: String = "id",
+        // Seems to be 1 for a normal user
+        owner_type: String = "type",
+    }
+}
+
+plasma_struct! {
+    struct AssociationMember {
+        id: Option<String> = "id",
+        name: Option<String> = "name",
+        member_type: Option<String> = "type",
+    }
+}
+
+plasma_struct! {
+    struct AddRequest {
+        member: AssociationMember = "member",
+    }
+}
+
+plasma_struct! {
+    struct AddAssociationsRequest {
+        // Seems to be empty; to be set in the response
+        domain_partition_key: Option<String> = "domainPartition.key",
+        // "PlasmaMute", "PlasmaBlock", "PlasmaRecentPlayers", "PlasmaFriends"
+        asso_type: Option<String> = "type",
+        owner: AssociationOwner = "owner",
+        // What to do if the list is full
+        list_full_behavior: Option<String> = "listFullBehavior",
+        add_requests: Vec<AddRequest> = "addRequests",
+    }
+}
+
 pub async fn asso_addassociations(
     fh: &FeslHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
-    // User should be authenticated
-    if !prq.is_authenticated_user().await {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("User not authenticated.");
-    }
-
     let Some(db_session) = prq.get_active_session_model().await else {
         panic!("Session not found although authenticated earlier...");
     };
@@ -30,34 +69,20 @@ pub async fn asso_addassociations(
         panic!("User not found although authenticated earlier...");
     };
 
-    // Read the request parameters
-    let domainPartition_domain = prq
-        .packet
-        .data
-        .get("domainPartition.domain")
-        .unwrap()
-        .to_string(); // "pc"; matches "domainPartition.domain" in the FSYS/Hello response
-    let domainPartition_subDomain = prq
-        .packet
-        .data
-        .get("domainPartition.subDomain")
-        .unwrap()
-        .to_string(); // "LOTR"; matches "domainPartition.subDomain" in the FSYS/Hello response
-    let domainPartition_key = prq
-        .packet
-        .data
-        .get("domainPartition.key")
-        .unwrap()
-        .to_string(); // ""; seems to be empty; to be set in the response
-    let assoType = match prq.packet.data.get("type") {
-        Some(assoType) => assoType.to_string(),
-        None => "".to_string(),
-    }; // "PlasmaMute", "PlasmaBlock", "PlasmaRecentPlayers", "PlasmaFriends"
-
-    // owner.id matches the user_id
-    let owner_id = prq.packet.data.get("owner.id").unwrap().to_string(); // "1" -> Identical to nuid/user id, if not further separated
-    // Seems to be 1 for a normal user
-    let owner_type = prq.packet.data.get("owner.type").unwrap().to_string(); // "1"
+    // The lists are owned by the persona, so one has to be selected
+    let Some(db_persona) = prq.get_active_persona_model().await else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("No persona selected.");
+    };
+
+    // Read the request parameters. The domain partition matches the one of the
+    // FSYS/Hello response and is not checked.
+    let request: AddAssociationsRequest = decode_request(&prq).await?;
+    let domainPartition_key = request.domain_partition_key.unwrap_or_default();
+    let assoType = request.asso_type.unwrap_or_default();
+    let owner_id = request.owner.id;
+    let owner_type = request.owner.owner_type;
 
     // Check if the owner.id matches the user_id
     if &owner_id != &db_session.user_id.to_string() {
@@ -66,13 +91,19 @@ pub async fn asso_addassociations(
         return Err("Invalid owner.id");
     }
 
-    // What to do if the list is full...
-    let list_full_behavior = prq.packet.data.get("listFullBehavior").cloned().unwrap_or("".to_string());
+    let list_full_behavior = request.list_full_behavior.unwrap_or_default();
+    let add_requests_count = request.add_requests.len();
 
-    // Read how many request items are in the packet
-    let add_requests_count = prq.packet.data.get("addRequests.[]").cloned().unwrap_or("0".to_string()).parse::<u32>().unwrap_or(0);
+    if !is_valid_asso_type(&assoType) {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Invalid association type");
+    }
 
-    let max_list_size: u32 = if assoType == "PlasmaRecentPlayers" {20} else {100};
+    let max_list_size = get_max_list_size(&assoType);
+    // Recent players are always rolled, the other lists only if requested by the client
+    let roll_when_full =
+        list_full_behavior == "RollLeastRecentlyModified" || assoType == ASSO_TYPE_RECENT_PLAYERS;
 
     let mut response_hm: IndexMap<_, _, _> = IndexMap::new();
     response_hm.insert("TXN".to_string(), "AddAssociations".to_string());
@@ -93,7 +124,98 @@ pub async fn asso_addassociations(
     response_hm.insert("type".to_string(), assoType.to_string());
     response_hm.insert("maxListSize".to_string(), max_list_size.to_string());
 
-    response_hm.insert("result.[]".to_string(), "0".to_string());
+    for (idx, add_request) in request.add_requests.into_iter().enumerate() {
+        let member_id = add_request.member.id.unwrap_or_default();
+        let member_name = add_request.member.name.unwrap_or_default();
+        let member_type = add_request.member.member_type.unwrap_or("1".to_string());
+
+        // The member is identified by the persona id, or by the persona name as a fallback
+        let db_member = match member_id.parse::<i64>() {
+            Ok(member_id) => persona::Entity::find_by_id(member_id)
+                .one(&*prq.sstate.database)
+                .await
+                .unwrap_or(None),
+            Err(_) => None,
+        };
+        let db_member = match db_member {
+            Some(db_member) => Some(db_member),
+            None if !member_name.is_empty() => persona::Entity::find()
+                .filter(persona::Column::Name.eq(&member_name))
+                .one(&*prq.sstate.database)
+                .await
+                .unwrap_or(None),
+            None => None,
+        };
+
+        let mut added = false;
+        let outcome = match &db_member {
+            Some(db_member) if db_member.id != db_persona.id => {
+                match add_association(
+                    db_persona.id,
+                    db_member.id,
+                    &assoType,
+                    roll_when_full,
+                    &prq.sstate.database,
+                )
+                .await
+                {
+                    AddAssociationResult::Added => {
+                        added = true;
+                        EAError::EA_OK
+                    }
+                    AddAssociationResult::AlreadyPresent => EAError::EA_OK,
+                    AddAssociationResult::ListFull | AddAssociationResult::Failed => {
+                        EAError::EA_NoData
+                    }
+                }
+            }
+            // Unknown persona or the owner itself
+            _ => EAError::EA_NotFound,
+        };
+
+        let mutual = match &db_member {
+            Some(db_member) => {
+                has_association(db_member.id, db_persona.id, &assoType, &prq.sstate.database)
+                    .await
+            }
+            None => false,
+        };
+        // Adding a friend that already added the owner accepts its request, so both
+        // see the presence of each other from now on
+        if added
+            && mutual
+            && assoType == ASSO_TYPE_FRIENDS
+            && let Some(db_member) = &db_member
+        {
+            send_presence(&prq.sstate, &db_persona, db_member.id).await;
+            send_presence(&prq.sstate, db_member, db_persona.id).await;
+        }
+        let list_size = get_association_list(db_persona.id, &assoType, &prq.sstate.database)
+            .await
+            .len();
+
+        response_hm.insert(
+            format!("result.{}.member.id", idx),
+            db_member.as_ref().map(|m| m.id.to_string()).unwrap_or(member_id),
+        );
+        response_hm.insert(
+            format!("result.{}.member.name", idx),
+            db_member.as_ref().map(|m| m.name.to_string()).unwrap_or(member_name),
+        );
+        response_hm.insert(format!("result.{}.member.type", idx), member_type);
+        response_hm.insert(format!("result.{}.owner.id", idx), owner_id.to_string());
+        response_hm.insert(format!("result.{}.owner.type", idx), owner_type.to_string());
+        response_hm.insert(
+            format!("result.{}.mutual", idx),
+            (if mutual { "1" } else { "0" }).to_string(),
+        );
+        response_hm.insert(
+            format!("result.{}.outcome", idx),
+            (outcome as i32).to_string(),
+        );
+        response_hm.insert(format!("result.{}.listSize", idx), list_size.to_string());
+    }
+    response_hm.insert("result.[]".to_string(), add_requests_count.to_string());
 
     let response = DataPacket::new(
         DataMode::FESL_ASSO,
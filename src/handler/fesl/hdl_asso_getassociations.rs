@@ -1,27 +1,43 @@
 use indexmap::IndexMap;
+use sea_orm::entity::*;
 
-use crate::handler::{submit_packet, to_error_packet};
+use crate::handler::{decode_request, submit_packet, to_error_packet};
+use crate::orm::model::persona;
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::handler::fesl::FeslHandler;
+use crate::utils::associations::{
+    get_association_list, get_max_list_size, has_association, is_valid_asso_type,
+};
 
 
 pub const LOTRCQ_DOMAIN: &str = "eadm";
 pub const LOTRCQ_SUBDOMAIN: &str = "eadm";
 pub const LOTRCQ_PARTITION_ID: &str = "online_content";
 
+plasma_struct! {
+    struct AssociationOwner {
+        // Identical to the user id
+        id: String = "id",
+        // Seems to be 1 for a normal user
+        owner_type: String = "type",
+    }
+}
+
+plasma_struct! {
+    struct GetAssociationsRequest {
+        // "PlasmaMute", "PlasmaBlock", "PlasmaRecentPlayers", "PlasmaFriends"
+        asso_type: Option<String> = "type",
+        owner: AssociationOwner = "owner",
+    }
+}
+
 pub async fn asso_getassociations(
     fh: &FeslHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
-    // User should be authenticated
-    if !prq.is_authenticated_user().await {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("User not authenticated.");
-    }
-
     let Some(db_session) = prq.get_active_session_model().await else {
         panic!("Session not found although authenticated earlier...");
     };
@@ -30,34 +46,19 @@ pub async fn asso_getassociations(
         panic!("User not found although authenticated earlier...");
     };
 
-    // Read the request parameters
-    let domainPartition_domain = prq
-        .packet
-        .data
-        .get("domainPartition.domain")
-        .unwrap()
-        .to_string(); // "pc"; matches "domainPartition.domain" in the FSYS/Hello response
-    let domainPartition_subDomain = prq
-        .packet
-        .data
-        .get("domainPartition.subDomain")
-        .unwrap()
-        .to_string(); // "LOTR"; matches "domainPartition.subDomain" in the FSYS/Hello response
-    let domainPartition_key = prq
-        .packet
-        .data
-        .get("domainPartition.key")
-        .unwrap()
-        .to_string(); // ""; seems to be empty; to be set in the response
-    let assoType = match prq.packet.data.get("type") {
-        Some(assoType) => assoType.to_string(),
-        None => "".to_string(),
-    }; // "PlasmaMute", "PlasmaBlock", "PlasmaRecentPlayers", "PlasmaFriends"
-
-    // owner.id matches the user_id
-    let owner_id = prq.packet.data.get("owner.id").unwrap().to_string(); // "1" -> Identical to nuid/user id, if not further separated
-    // Seems to be 1 for a normal user
-    let owner_type = prq.packet.data.get("owner.type").unwrap().to_string(); // "1"
+    // The lists are owned by the persona, so one has to be selected
+    let Some(db_persona) = prq.get_active_persona_model().await else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("No persona selected.");
+    };
+
+    // Read the request parameters. The domain partition matches the one of the
+    // FSYS/Hello response and is not checked.
+    let request: GetAssociationsRequest = decode_request(&prq).await?;
+    let assoType = request.asso_type.unwrap_or_default();
+    let owner_id = request.owner.id;
+    let owner_type = request.owner.owner_type;
 
     // Check if the owner.id matches the user_id
     if &owner_id != &db_session.user_id.to_string() {
@@ -66,7 +67,16 @@ pub async fn asso_getassociations(
         return Err("Invalid owner.id");
     }
 
-    let max_list_size: u32 = if assoType == "PlasmaRecentPlayers" {20} else {100};
+    if !is_valid_asso_type(&assoType) {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Invalid association type");
+    }
+
+    let max_list_size = get_max_list_size(&assoType);
+
+    // Load the members of the list (oldest first)
+    let db_associations = get_association_list(db_persona.id, &assoType, &prq.sstate.database).await;
 
     let mut response_hm: IndexMap<_, _, _> = IndexMap::new();
     response_hm.insert("TXN".to_string(), "GetAssociations".to_string());
@@ -87,7 +97,40 @@ pub async fn asso_getassociations(
     response_hm.insert("owner.id".to_string(), owner_id.to_string());
     response_hm.insert("owner.type".to_string(), owner_type.to_string());
     response_hm.insert("type".to_string(), assoType.to_string());
-    response_hm.insert("members.[]".to_string(), "0".to_string());
+
+    let mut n_members = 0;
+    for db_association in db_associations {
+        // Skip members whose persona has been deleted in the meantime
+        let Ok(Some(db_member)) = persona::Entity::find_by_id(db_association.member_persona_id)
+            .one(&*prq.sstate.database)
+            .await
+        else {
+            continue;
+        };
+        let mutual = has_association(
+            db_member.id,
+            db_persona.id,
+            &assoType,
+            &prq.sstate.database,
+        )
+        .await;
+        let created = db_association
+            .created_at
+            .format("%h-%d-%Y %H:%M:%S UTC")
+            .to_string();
+
+        response_hm.insert(format!("members.{}.id", n_members), db_member.id.to_string());
+        response_hm.insert(format!("members.{}.name", n_members), db_member.name.to_string());
+        response_hm.insert(format!("members.{}.type", n_members), owner_type.to_string());
+        response_hm.insert(
+            format!("members.{}.mutual", n_members),
+            (if mutual { "1" } else { "0" }).to_string(),
+        );
+        response_hm.insert(format!("members.{}.created", n_members), created.to_string());
+        response_hm.insert(format!("members.{}.modified", n_members), created);
+        n_members += 1;
+    }
+    response_hm.insert("members.[]".to_string(), n_members.to_string());
 
     response_hm.insert("maxListSize".to_string(), max_list_size.to_string());
     // Not sure if this should be the nuid name (mail) or the persona name?
@@ -18,10 +18,10 @@ pub async fn fsys_hello(
 
     let mut response_hm = IndexMap::new();
 
-    let theaterIp = "theater.mordorwi.de";
-    let theaterPort = "18885";
-    let messengerIp = "messenger.mordorwi.de";
-    let messengerPort = "0";
+    let theaterIp = &prq.sstate.hello.theater_ip;
+    let theaterPort = prq.sstate.hello.theater_port;
+    let messengerIp = &prq.sstate.hello.messenger_ip;
+    let messengerPort = prq.sstate.hello.messenger_port;
 
     // Build response prq.packet payload
     response_hm.insert("TXN".to_string(), "Hello".to_string());
@@ -1,24 +1,51 @@
 use indexmap::IndexMap;
 use tracing::debug;
 
-use crate::handler::submit_packet;
+use crate::handler::{decode_request, submit_packet};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_handle::PlasmaRequestBundle;
-use crate::handler::fesl::FeslHandler;
+use crate::presence::{PresenceInfo, PresenceState, PRESENCE_SHOW_OFFLINE};
+use crate::handler::fesl::{update_presence, FeslHandler};
 
+plasma_struct! {
+    struct PresenceStatus {
+        show: String = "show",
+    }
+}
+
+plasma_struct! {
+    struct SetPresenceStatusRequest {
+        status: PresenceStatus = "status",
+    }
+}
 
 pub async fn pres_setpresencestatus(
     fh: &FeslHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
-    let status_show: String = prq.packet.data.get("status.show").unwrap().to_string(); // = 'disc'
+    let request: SetPresenceStatusRequest = decode_request(&prq).await?;
+    let status_show = request.status.show; // = 'disc'
 
-    // Check if the status is 'disc'
-    if status_show != "disc" {
+    // 'disc' takes the persona offline, the other values set the online state
+    let new_state = PresenceState::from_show(&status_show);
+    if new_state.is_none() && status_show != PRESENCE_SHOW_OFFLINE {
         debug!(target: "fesl", "PRES/SetPresenceStatus - Unexpected status.show: {:?}", status_show);
         return Err("Unexpected status.show");
     }
 
+    // Presence is only tracked for logged in personas
+    if let Some(db_persona) = prq.get_active_persona_model().await {
+        let current = prq.sstate.presence.get(&db_persona.id).map(|entry| entry.clone());
+        let presence = match (new_state, current) {
+            (None, _) => None,
+            // Being in a game is reported by the Theater, so keep it
+            (Some(_), Some(current)) if current.state == PresenceState::InGame => Some(current),
+            (Some(state), _) => Some(PresenceInfo::new(state, -1)),
+        };
+        update_presence(&prq.sstate, db_persona.id, presence).await;
+    }
+
     let mut response_hm: IndexMap<String, String> = IndexMap::new();
     response_hm.insert("TXN".to_string(), "SetPresenceStatus".to_string());
 
@@ -0,0 +1,127 @@
+use indexmap::IndexMap;
+
+use crate::handler::{decode_request, submit_packet, to_error_packet};
+use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::{plasma_struct, PlasmaEncode};
+use crate::plasma_errors::EAError;
+use crate::plasma_handle::PlasmaRequestBundle;
+use crate::handler::fesl::FeslHandler;
+use crate::utils::stats::{get_stat, get_stat_rank, prepare_stat_period, resolve_owner_persona};
+
+plasma_struct! {
+    struct StatOwner {
+        owner_id: String = "ownerId",
+        owner_type: Option<String> = "ownerType",
+    }
+}
+
+plasma_struct! {
+    struct GetRankedStatsForOwnersRequest {
+        owners: Vec<StatOwner> = "owners",
+        period_id: Option<i32> = "periodId",
+        period_past: Option<i32> = "periodPast",
+        // "0" ranks the highest values first, "1" the lowest
+        rank_order: Option<String> = "rankOrder",
+        keys: Vec<String> = "keys",
+    }
+}
+
+plasma_struct! {
+    struct RankedStatValue {
+        key: String = "key",
+        value: f64 = "value",
+        rank: u64 = "rank",
+    }
+}
+
+plasma_struct! {
+    struct OwnerRankedStats {
+        owner_id: String = "ownerId",
+        owner_type: String = "ownerType",
+        ranked_stats: Vec<RankedStatValue> = "rankedStats",
+    }
+}
+
+plasma_struct! {
+    struct GetRankedStatsForOwnersResponse {
+        ranked_stats: Vec<OwnerRankedStats> = "rankedStats",
+    }
+}
+
+pub async fn rank_getrankedstatsforowners(
+    fh: &FeslHandler,
+    mut prq: PlasmaRequestBundle,
+) -> Result<(), &'static str> {
+    /*
+    {"TXN": "GetRankedStatsForOwners", "owners.[]": "2", "owners.0.ownerId": "1", "owners.0.ownerType": "1", ..., "periodId": "0", "periodPast": "0", "rankOrder": "0", "keys.[]": "1", "keys.0": "3.2137219119.score"}
+    */
+    let request: GetRankedStatsForOwnersRequest = decode_request(&prq).await?;
+    let period_id = request.period_id.unwrap_or(0);
+    let period_past = request.period_past.unwrap_or(0);
+    if !prepare_stat_period(period_id, period_past, &prq.sstate.database).await {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Unsupported stat period");
+    }
+    let ascending = request.rank_order.is_some_and(|order| order == "1");
+
+    let mut ranked_owners = Vec::new();
+    for owner in request.owners {
+        // Unknown owners are left out of the response
+        let Ok(owner_id_num) = owner.owner_id.parse::<i64>() else {
+            continue;
+        };
+        let Some(db_persona) =
+            resolve_owner_persona(owner_id_num, &prq.sstate.registry, &prq.sstate.database).await
+        else {
+            continue;
+        };
+
+        let mut ranked_stats = Vec::new();
+        for stat_key in &request.keys {
+            // Stats that were never written are reported as 0 and unranked (rank 0)
+            let (value, rank) = match get_stat(
+                db_persona.id,
+                stat_key,
+                period_id,
+                period_past,
+                &*prq.sstate.database,
+            )
+            .await
+            {
+                Some(db_stat) => (
+                    db_stat.value,
+                    get_stat_rank(&db_stat, ascending, &prq.sstate.database).await,
+                ),
+                None => (0.0, 0),
+            };
+            ranked_stats.push(RankedStatValue {
+                key: stat_key.to_string(),
+                value,
+                rank,
+            });
+        }
+        ranked_owners.push(OwnerRankedStats {
+            owner_id: owner.owner_id,
+            owner_type: owner.owner_type.unwrap_or("1".to_string()),
+            ranked_stats,
+        });
+    }
+
+    let mut response_hm = IndexMap::new();
+    response_hm.insert("TXN".to_string(), "GetRankedStatsForOwners".to_string());
+    GetRankedStatsForOwnersResponse {
+        ranked_stats: ranked_owners,
+    }
+    .encode_into(&mut response_hm);
+
+    let response = DataPacket::new(
+        DataMode::FESL_RANK,
+        PacketMode::FeslSinglePacketResponse,
+        prq.packet.packet_id,
+        response_hm,
+    );
+
+    submit_packet(response, &prq.con, &prq.sstate, 0).await;
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use indexmap::IndexMap;
+
+use crate::handler::{decode_request, submit_packet, to_error_packet};
+use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::{plasma_struct, PlasmaEncode};
+use crate::plasma_errors::EAError;
+use crate::plasma_handle::PlasmaRequestBundle;
+use crate::handler::fesl::FeslHandler;
+use crate::utils::stats::{get_stat, prepare_stat_period, resolve_owner_persona};
+
+plasma_struct! {
+    struct GetStatsRequest {
+        owner: Option<i64> = "owner",
+        period_id: Option<i32> = "periodId",
+        period_past: Option<i32> = "periodPast",
+        keys: Vec<String> = "keys",
+    }
+}
+
+plasma_struct! {
+    struct StatValue {
+        key: String = "key",
+        value: f64 = "value",
+        text: String = "text",
+    }
+}
+
+plasma_struct! {
+    struct GetStatsResponse {
+        stats: Vec<StatValue> = "stats",
+    }
+}
+
+pub async fn rank_getstats(
+    fh: &FeslHandler,
+    mut prq: PlasmaRequestBundle,
+) -> Result<(), &'static str> {
+    let Some(db_session) = prq.get_active_session_model().await else {
+        panic!("Session not found although authenticated earlier...");
+    };
+
+    /*
+    {"TXN": "GetStats", "owner": "1", "ownerType": "1", "periodId": "0", "periodPast": "0", "keys.[]": "1", "keys.0": "3.2137219119.score"}
+    */
+    let request: GetStatsRequest = decode_request(&prq).await?;
+    // Default to the own stats
+    let owner_id = request.owner.unwrap_or(db_session.user_id);
+    let period_id = request.period_id.unwrap_or(0);
+    let period_past = request.period_past.unwrap_or(0);
+    if !prepare_stat_period(period_id, period_past, &prq.sstate.database).await {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Unsupported stat period");
+    }
+
+    let Some(db_persona) = resolve_owner_persona(owner_id, &prq.sstate.registry, &prq.sstate.database).await else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NotFound as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Stat owner not found");
+    };
+
+    let mut stats = Vec::new();
+    for stat_key in request.keys {
+        // Stats that were never written are reported as 0
+        let value = match get_stat(
+            db_persona.id,
+            &stat_key,
+            period_id,
+            period_past,
+            &*prq.sstate.database,
+        )
+        .await
+        {
+            Some(db_stat) => db_stat.value,
+            None => 0.0,
+        };
+        stats.push(StatValue {
+            key: stat_key,
+            value,
+            text: "".to_string(),
+        });
+    }
+
+    let mut response_hm = IndexMap::new();
+    response_hm.insert("TXN".to_string(), "GetStats".to_string());
+    GetStatsResponse { stats }.encode_into(&mut response_hm);
+
+    let response = DataPacket::new(
+        DataMode::FESL_RANK,
+        PacketMode::FeslSinglePacketResponse,
+        prq.packet.packet_id,
+        response_hm,
+    );
+
+    submit_packet(response, &prq.con, &prq.sstate, 0).await;
+    Ok(())
+}
@@ -1,27 +1,23 @@
 use indexmap::IndexMap;
-use rand::RngExt;
 use sea_orm::entity::*;
 use sea_orm::query::*;
+use std::collections::HashMap;
 
 use crate::handler::{submit_packet, to_error_packet};
-use crate::orm::model::persona;
+use crate::orm::model::{persona, stat};
 use crate::packet::{DataMode, DataPacket, PacketMode};
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::handler::fesl::FeslHandler;
+use crate::utils::stats::{get_leaderboard, get_stat, get_stat_rank, prepare_stat_period};
 
+// Most entries sent for a single request
+const MAX_RANK_ENTRIES: u64 = 100;
 
 pub async fn rank_gettopnandme(
     fh: &FeslHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
-    // Check if the user is authenticated
-    if !prq.is_authenticated_user().await {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("User not authenticated.");
-    }
-
     /*
     {"TXN": "GetTopNAndMe", "key": "3.2137219119.score", "ownerType": "1", "minRank": "1", "maxRank": "50", "periodId": "4", "periodPast": "0", "rankOrder": "0", "includeUser": "1"} }
     */
@@ -34,73 +30,121 @@ pub async fn rank_gettopnandme(
     let rank_order_opt = prq.packet.data.get("rankOrder");
     let include_user_opt = prq.packet.data.get("includeUser");
 
-    let Some(rank_key) = key_opt else {
+    let Some(rank_key) = key_opt.cloned() else {
         return Err("No key provided");
     };
 
+    // Ranks are read as u32, so the offset and the ranks of the entries cannot overflow
     let rank_min = min_rank_opt
         .unwrap_or(&"1".to_string())
         .parse::<u32>()
-        .unwrap();
+        .unwrap_or(1)
+        .max(1) as u64;
     let rank_max = max_rank_opt
         .unwrap_or(&"10".to_string())
         .parse::<u32>()
-        .unwrap();
-    let n_entries = rank_max - rank_min + 1;
+        .unwrap_or(10) as u64;
+    if rank_max < rank_min {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Invalid rank range");
+    }
+    let n_entries = (rank_max - rank_min + 1).min(MAX_RANK_ENTRIES);
+
+    let period_id = period_id_opt
+        .unwrap_or(&"0".to_string())
+        .parse::<i32>()
+        .unwrap_or(0);
+    let period_past = period_past_opt
+        .unwrap_or(&"0".to_string())
+        .parse::<i32>()
+        .unwrap_or(0);
+    if !prepare_stat_period(period_id, period_past, &prq.sstate.database).await {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Unsupported stat period");
+    }
+    // "0" lists the highest values first, "1" the lowest
+    let ascending = rank_order_opt.map(|order| order == "1").unwrap_or(false);
+    let include_user = include_user_opt.map(|flag| flag == "1").unwrap_or(false);
+
+    let db_stats = get_leaderboard(
+        &rank_key,
+        period_id,
+        period_past,
+        ascending,
+        rank_min - 1,
+        n_entries,
+        &prq.sstate.database,
+    )
+    .await;
 
-    let Ok(db_personas) = persona::Entity::find()
-        .limit(n_entries as u64)
+    // Collect (stat, rank) pairs to be sent
+    let mut ranked_stats: Vec<(stat::Model, u64)> = db_stats
+        .into_iter()
+        .enumerate()
+        .map(|(stat_idx, db_stat)| (db_stat, rank_min + stat_idx as u64))
+        .collect();
+
+    // Append the own entry if it is not part of the requested range
+    if include_user
+        && let Some(db_persona) = prq.get_active_persona_model().await
+        && !ranked_stats
+            .iter()
+            .any(|(db_stat, _)| db_stat.persona_id == db_persona.id)
+        && let Some(db_stat) = get_stat(
+            db_persona.id,
+            &rank_key,
+            period_id,
+            period_past,
+            &*prq.sstate.database,
+        )
+        .await
+    {
+        let rank = get_stat_rank(&db_stat, ascending, &prq.sstate.database).await;
+        ranked_stats.push((db_stat, rank));
+    }
+
+    // Load the personas of all entries at once
+    let persona_ids: Vec<i64> = ranked_stats.iter().map(|(db_stat, _)| db_stat.persona_id).collect();
+    let db_personas: HashMap<i64, persona::Model> = persona::Entity::find()
+        .filter(persona::Column::Id.is_in(persona_ids))
         .all(&*prq.sstate.database)
         .await
-    else {
-        return Err("Failed to retrieve persona data");
-    };
+        .unwrap_or_default()
+        .into_iter()
+        .map(|db_persona| (db_persona.id, db_persona))
+        .collect();
 
     let mut response_hm = IndexMap::new();
     response_hm.insert("TXN".to_string(), "GetTopNAndMe".to_string());
 
-    let top_n = db_personas.len();
-    // Add the top N personas (faked)
-    response_hm.insert("stats.[]".to_string(), top_n.to_string());
-    for (persona_idx, db_persona) in db_personas.iter().enumerate() {
-        let rank_data = vec![rank_key];
+    let mut n_stats = 0;
+    for (db_stat, rank) in ranked_stats {
+        let Some(db_persona) = db_personas.get(&db_stat.persona_id) else {
+            continue;
+        };
+        response_hm.insert(format!("stats.{}.addStats.[]", n_stats), "1".to_string());
         response_hm.insert(
-            format!("stats.{}.addStats.[]", persona_idx.to_string()),
-            rank_data.len().to_string(),
+            format!("stats.{}.addStats.0.key", n_stats),
+            db_stat.stat_key.to_string(),
         );
-        for (stat_idx, stat_key) in rank_data.iter().enumerate() {
-            // Generate a random number between 0 and 1000
-            let random_stat: u32 = rand::rng().random_range(0..1000);
-            response_hm.insert(
-                format!(
-                    "stats.{}.addStats.{}.key",
-                    persona_idx.to_string(),
-                    stat_idx.to_string()
-                ),
-                stat_key.to_string(),
-            );
-            response_hm.insert(
-                format!(
-                    "stats.{}.addStats.{}.value",
-                    persona_idx.to_string(),
-                    stat_idx.to_string()
-                ),
-                random_stat.to_string(),
-            );
-        }
         response_hm.insert(
-            format!("stats.{}.owner", persona_idx),
-            db_persona.user_id.to_string(),
+            format!("stats.{}.addStats.0.value", n_stats),
+            db_stat.value.to_string(),
         );
         response_hm.insert(
-            format!("stats.{}.name", persona_idx),
-            db_persona.name.to_string(),
+            format!("stats.{}.owner", n_stats),
+            db_persona.user_id.to_string(),
         );
         response_hm.insert(
-            format!("stats.{}.rank", persona_idx),
-            (persona_idx + 1).to_string(),
+            format!("stats.{}.name", n_stats),
+            db_persona.name.to_string(),
         );
+        response_hm.insert(format!("stats.{}.rank", n_stats), rank.to_string());
+        n_stats += 1;
     }
+    response_hm.insert("stats.[]".to_string(), n_stats.to_string());
 
     let response = DataPacket::new(
         DataMode::FESL_RANK,
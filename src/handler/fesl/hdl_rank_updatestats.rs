@@ -0,0 +1,114 @@
+use indexmap::IndexMap;
+use sea_orm::{DbErr, TransactionTrait};
+use tracing::warn;
+
+use crate::handler::{decode_request, submit_packet, to_error_packet};
+use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
+use crate::plasma_errors::EAError;
+use crate::plasma_handle::PlasmaRequestBundle;
+use crate::handler::fesl::FeslHandler;
+use crate::utils::stats::{prepare_stat_periods, resolve_writable_persona, update_stat};
+
+// Update type of a single stat; everything else overwrites the value
+const STAT_UPDATE_RELATIVE: &str = "3";
+
+plasma_struct! {
+    struct StatUpdate {
+        key: String = "k",
+        value: f64 = "v",
+        update_type: Option<String> = "ut",
+    }
+}
+
+plasma_struct! {
+    struct OwnerStatUpdates {
+        owner_id: i64 = "o",
+        stats: Vec<StatUpdate> = "s",
+    }
+}
+
+plasma_struct! {
+    struct UpdateStatsRequest {
+        updates: Vec<OwnerStatUpdates> = "u",
+    }
+}
+
+pub async fn rank_updatestats(
+    fh: &FeslHandler,
+    mut prq: PlasmaRequestBundle,
+) -> Result<(), &'static str> {
+    let Some(db_writer_persona) = prq.get_active_persona_model().await else {
+        panic!("Persona not found although authenticated earlier...");
+    };
+
+    /*
+    {"TXN": "UpdateStats", "u.[]": "1", "u.0.o": "1", "u.0.ot": "1", "u.0.s.[]": "1", "u.0.s.0.k": "3.2137219119.score", "u.0.s.0.v": "120.0", "u.0.s.0.ut": "3", "u.0.s.0.t": ""}
+    */
+    let request: UpdateStatsRequest = decode_request(&prq).await?;
+
+    // Validate all updates first, so that a rejected request does not leave partial writes
+    let mut stat_updates = Vec::new();
+    for owner_updates in request.updates {
+        let db_persona = match resolve_writable_persona(
+            owner_updates.owner_id,
+            &db_writer_persona,
+            &prq.sstate.registry,
+            &prq.sstate.database,
+        )
+        .await
+        {
+            Ok(db_persona) => db_persona,
+            Err(ea_error) => {
+                let err_pkt = to_error_packet(&prq.packet, ea_error as i32, None);
+                submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+                return Err("Not allowed to update the stats of this owner");
+            }
+        };
+
+        for stat_update in owner_updates.stats {
+            let relative = stat_update
+                .update_type
+                .is_some_and(|update_type| update_type == STAT_UPDATE_RELATIVE);
+            stat_updates.push((db_persona.id, stat_update.key, stat_update.value, relative));
+        }
+    }
+
+    // All updates are written or none of them
+    let write_result = match prepare_stat_periods(&prq.sstate.database).await {
+        Ok(period_ids) => prq
+            .sstate
+            .database
+            .transaction::<_, (), DbErr>(|txn| {
+                Box::pin(async move {
+                    for (persona_id, stat_key, stat_value, relative) in stat_updates {
+                        update_stat(persona_id, &stat_key, &period_ids, stat_value, relative, txn)
+                            .await?;
+                    }
+                    Ok(())
+                })
+            })
+            .await
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    if let Err(err) = write_result {
+        warn!(target: "fesl", "Failed to update stats: {}", err);
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Failed to update stats");
+    }
+
+    let mut response_hm = IndexMap::new();
+    response_hm.insert("TXN".to_string(), "UpdateStats".to_string());
+
+    let response = DataPacket::new(
+        DataMode::FESL_RANK,
+        PacketMode::FeslSinglePacketResponse,
+        prq.packet.packet_id,
+        response_hm,
+    );
+
+    submit_packet(response, &prq.con, &prq.sstate, 0).await;
+    Ok(())
+}
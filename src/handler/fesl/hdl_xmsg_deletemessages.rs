@@ -0,0 +1,58 @@
+use indexmap::IndexMap;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+
+use crate::handler::{decode_request, submit_packet, to_error_packet};
+use crate::orm::model::message;
+use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
+use crate::plasma_errors::EAError;
+use crate::plasma_handle::PlasmaRequestBundle;
+use crate::handler::fesl::FeslHandler;
+
+plasma_struct! {
+    struct DeleteMessagesRequest {
+        message_ids: Vec<i64> = "messageIds",
+    }
+}
+
+pub async fn xmsg_deletemessages(
+    fh: &FeslHandler,
+    mut prq: PlasmaRequestBundle,
+) -> Result<(), &'static str> {
+    let Some(db_persona) = prq.get_active_persona_model().await else {
+        panic!("Persona not found although authenticated earlier...");
+    };
+
+    // {"TXN": "DeleteMessages", "messageIds.[]": "2", "messageIds.0": "5", "messageIds.1": "7"}
+    let request: DeleteMessagesRequest = decode_request(&prq).await?;
+
+    // Only the own messages can be deleted
+    if message::Entity::delete_many()
+        .filter(
+            Condition::all()
+                .add(message::Column::Id.is_in(request.message_ids))
+                .add(message::Column::RecipientPersonaId.eq(db_persona.id)),
+        )
+        .exec(&*prq.sstate.database)
+        .await
+        .is_err()
+    {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Failed to delete messages");
+    }
+
+    let mut response_hm = IndexMap::new();
+    response_hm.insert("TXN".to_string(), "DeleteMessages".to_string());
+
+    let response = DataPacket::new(
+        DataMode::FESL_XMSG,
+        PacketMode::FeslSinglePacketResponse,
+        prq.packet.packet_id,
+        response_hm,
+    );
+
+    submit_packet(response, &prq.con, &prq.sstate, 0).await;
+    Ok(())
+}
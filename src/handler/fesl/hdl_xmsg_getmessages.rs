@@ -0,0 +1,73 @@
+use indexmap::IndexMap;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+
+use crate::handler::{submit_packet, to_error_packet};
+use crate::orm::model::message;
+use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_errors::EAError;
+use crate::plasma_handle::PlasmaRequestBundle;
+use crate::handler::fesl::FeslHandler;
+
+use super::utils_messages::{insert_message_fields, MAX_INBOX_MESSAGES};
+
+pub async fn xmsg_getmessages(
+    fh: &FeslHandler,
+    mut prq: PlasmaRequestBundle,
+) -> Result<(), &'static str> {
+    let Some(db_persona) = prq.get_active_persona_model().await else {
+        panic!("Persona not found although authenticated earlier...");
+    };
+
+    // {"TXN": "GetMessages", "attachmentTypes.[]": "1", "attachmentTypes.0": "text/plain", "box": "inbox", "chunkSize": "0"}
+    // Only the newest messages are sent, oldest first
+    let Ok(mut db_messages) = message::Entity::find()
+        .filter(
+            Condition::all()
+                .add(message::Column::RecipientPersonaId.eq(db_persona.id))
+                .add(message::Column::ExpiresAt.gt(chrono::Utc::now())),
+        )
+        .order_by(message::Column::SentAt, sea_orm::Order::Desc)
+        .order_by(message::Column::Id, sea_orm::Order::Desc)
+        .limit(MAX_INBOX_MESSAGES)
+        .all(&*prq.sstate.database)
+        .await
+    else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Failed to load messages");
+    };
+
+    db_messages.reverse();
+
+    let mut response_hm = IndexMap::new();
+    response_hm.insert("TXN".to_string(), "GetMessages".to_string());
+    response_hm.insert("messages.[]".to_string(), db_messages.len().to_string());
+    for (message_idx, db_message) in db_messages.iter().enumerate() {
+        insert_message_fields(
+            &mut response_hm,
+            &format!("messages.{}.", message_idx),
+            db_message,
+            &prq.sstate,
+        )
+        .await;
+    }
+
+    // Fetched messages do not need to be pushed anymore
+    let message_ids: Vec<i64> = db_messages.iter().map(|db_message| db_message.id).collect();
+    let _ = message::Entity::update_many()
+        .col_expr(message::Column::Delivered, sea_orm::sea_query::Expr::value(true))
+        .filter(message::Column::Id.is_in(message_ids))
+        .exec(&*prq.sstate.database)
+        .await;
+
+    let response = DataPacket::new(
+        DataMode::FESL_XMSG,
+        PacketMode::FeslSinglePacketResponse,
+        prq.packet.packet_id,
+        response_hm,
+    );
+
+    submit_packet(response, &prq.con, &prq.sstate, 0).await;
+    Ok(())
+}
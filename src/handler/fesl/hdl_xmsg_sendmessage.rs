@@ -0,0 +1,222 @@
+use indexmap::IndexMap;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+
+use crate::handler::{decode_request, submit_packet, to_error_packet};
+use crate::orm::model::persona;
+use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
+use crate::plasma_errors::EAError;
+use crate::plasma_handle::PlasmaRequestBundle;
+use crate::handler::fesl::FeslHandler;
+use crate::rate_limit::RateLimit;
+use crate::utils::associations::{has_association, ASSO_TYPE_BLOCK};
+
+use super::utils_messages::{
+    broadcast_message, deliver_message, get_message_expiration, is_inbox_full, store_message,
+    MAX_BROADCAST_TEXT_LENGTH, MAX_MESSAGE_ATTACHMENTS, MAX_MESSAGE_ATTACHMENTS_LENGTH,
+    MAX_MESSAGE_RECIPIENTS,
+};
+
+// Recipient that addresses every persona (staff accounts only)
+const BROADCAST_RECIPIENT: &str = "*";
+// Every broadcast writes a message per persona
+const BROADCAST_RATE_LIMIT: RateLimit = RateLimit::per_minute(1);
+
+plasma_struct! {
+    struct MessageAttachment {
+        key: Option<String> = "key",
+        content_type: Option<String> = "type",
+        data: Option<String> = "data",
+    }
+}
+
+plasma_struct! {
+    struct SendMessageRequest {
+        recipients: Vec<String> = "to",
+        message_type: Option<String> = "messageType",
+        expires: Option<i64> = "expires",
+        attachments: Vec<MessageAttachment> = "attachments",
+    }
+}
+
+pub async fn xmsg_sendmessage(
+    fh: &FeslHandler,
+    mut prq: PlasmaRequestBundle,
+) -> Result<(), &'static str> {
+    let Some(db_account) = prq.get_active_user_model().await else {
+        panic!("User not found although authenticated earlier...");
+    };
+    let Some(db_persona) = prq.get_active_persona_model().await else {
+        panic!("Persona not found although authenticated earlier...");
+    };
+
+    /*
+    {"TXN": "SendMessage", "to.[]": "1", "to.0": "2", "messageType": "ginv", "expires": "0", "attachments.[]": "1", "attachments.0.key": "body", "attachments.0.type": "text/plain", "attachments.0.data": "Hello"}
+    */
+    let request: SendMessageRequest = decode_request(&prq).await?;
+    let recipients = request.recipients;
+    let message_type = request.message_type.unwrap_or("".to_string());
+    // Every recipient gets its own copy of the message
+    if recipients.len() > MAX_MESSAGE_RECIPIENTS {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Too many message recipients");
+    }
+    let attachments_length: usize = request
+        .attachments
+        .iter()
+        .map(|attachment| {
+            [&attachment.key, &attachment.content_type, &attachment.data]
+                .into_iter()
+                .map(|field| field.as_ref().map_or(0, |field| field.len()))
+                .sum::<usize>()
+        })
+        .sum();
+    if request.attachments.len() > MAX_MESSAGE_ATTACHMENTS
+        || attachments_length > MAX_MESSAGE_ATTACHMENTS_LENGTH
+    {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Message attachments too large");
+    }
+    let Some(expires_at) = get_message_expiration(&prq.sstate, request.expires).await else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Invalid message expiration");
+    };
+    let attachments: Vec<serde_json::Value> = request
+        .attachments
+        .into_iter()
+        .map(|attachment| {
+            serde_json::json!({
+                "key": attachment.key.unwrap_or_default(),
+                "type": attachment.content_type.unwrap_or_default(),
+                "data": attachment.data.unwrap_or_default(),
+            })
+        })
+        .collect();
+    let attachments = serde_json::Value::Array(attachments);
+
+    let mut response_hm = IndexMap::new();
+    response_hm.insert("TXN".to_string(), "SendMessage".to_string());
+
+    // Server-wide broadcast
+    if recipients.iter().any(|recipient| recipient == BROADCAST_RECIPIENT) {
+        if !db_account.is_staff && !db_account.is_superuser {
+            let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
+            submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+            return Err("Broadcasts are restricted to staff accounts.");
+        }
+        let text = attachments[0]["data"].as_str().unwrap_or("").to_string();
+        if text.len() > MAX_BROADCAST_TEXT_LENGTH {
+            let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+            submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+            return Err("Broadcast text too long");
+        }
+        if let Err(retry_in) = prq.sstate.request_limiter.check(
+            "xmsg/broadcast",
+            &prq.con.client_ip,
+            &BROADCAST_RATE_LIMIT,
+        ) {
+            prq.sstate.metrics.record_rate_limited("xmsg/broadcast");
+            let err_pkt = to_error_packet(
+                &prq.packet,
+                EAError::EA_TooManyAttempts as i32,
+                Some(format!(
+                    "Too many requests. Try again in {} seconds.",
+                    retry_in.as_secs().max(1)
+                )),
+            );
+            submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+            return Err("Rate limited");
+        }
+        let n_sent = broadcast_message(&prq.sstate, &text, expires_at).await;
+        response_hm.insert("messageId".to_string(), "0".to_string());
+        response_hm.insert("status.[]".to_string(), "0".to_string());
+        response_hm.insert("sent".to_string(), n_sent.to_string());
+
+        let response = DataPacket::new(
+            DataMode::FESL_XMSG,
+            PacketMode::FeslSinglePacketResponse,
+            prq.packet.packet_id,
+            response_hm,
+        );
+        submit_packet(response, &prq.con, &prq.sstate, 0).await;
+        return Ok(());
+    }
+
+    let mut first_message_id = 0;
+    for (recipient_idx, recipient) in recipients.iter().enumerate() {
+        // Recipients are addressed by persona id, or by name as a fallback
+        let db_recipient = match recipient.parse::<i64>() {
+            Ok(recipient_id) => persona::Entity::find_by_id(recipient_id)
+                .one(&*prq.sstate.database)
+                .await
+                .unwrap_or(None),
+            Err(_) => persona::Entity::find()
+                .filter(persona::Column::Name.eq(recipient))
+                .one(&*prq.sstate.database)
+                .await
+                .unwrap_or(None),
+        };
+
+        let outcome = match db_recipient {
+            None => EAError::EA_NotFound,
+            // Messages to personas that blocked the sender are dropped silently
+            Some(db_recipient)
+                if has_association(
+                    db_recipient.id,
+                    db_persona.id,
+                    ASSO_TYPE_BLOCK,
+                    &prq.sstate.database,
+                )
+                .await =>
+            {
+                EAError::EA_OK
+            }
+            // Full inboxes take no messages until some expire or are deleted
+            Some(db_recipient) if is_inbox_full(&prq.sstate, db_recipient.id).await => {
+                EAError::EA_NoData
+            }
+            Some(db_recipient) => {
+                match store_message(
+                    &prq.sstate,
+                    db_persona.id,
+                    db_recipient.id,
+                    &message_type,
+                    &attachments,
+                    expires_at,
+                )
+                .await
+                {
+                    Some(db_message) => {
+                        if first_message_id == 0 {
+                            first_message_id = db_message.id;
+                        }
+                        deliver_message(&prq.sstate, db_message).await;
+                        EAError::EA_OK
+                    }
+                    None => EAError::EA_NoData,
+                }
+            }
+        };
+        response_hm.insert(format!("status.{}.id", recipient_idx), recipient.to_string());
+        response_hm.insert(
+            format!("status.{}.outcome", recipient_idx),
+            (outcome as i32).to_string(),
+        );
+    }
+    response_hm.insert("status.[]".to_string(), recipients.len().to_string());
+    response_hm.insert("messageId".to_string(), first_message_id.to_string());
+
+    let response = DataPacket::new(
+        DataMode::FESL_XMSG,
+        PacketMode::FeslSinglePacketResponse,
+        prq.packet.packet_id,
+        response_hm,
+    );
+
+    submit_packet(response, &prq.con, &prq.sstate, 0).await;
+    Ok(())
+}
@@ -1,13 +1,13 @@
-use sea_orm::entity::*;
-use sea_orm::query::*;
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock};
 use tracing::info;
 
 use crate::client_connection::{ClientConnectionDescriptor, ProtoType, ServiceType};
+use crate::handler::routes::{Access, Router, FESL_REQUEST, FESL_RESPONSE};
 use crate::handler::Handler;
-use crate::orm::model::{game, participant, session};
-use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::handler::theater::advance_queue;
+use crate::packet::{DataMode, DataPacket};
 use crate::plasma_handle::PlasmaRequestBundle;
+use crate::rate_limit::RateLimit;
 use crate::sharedstate::SharedState;
 
 
@@ -19,7 +19,6 @@ pub const LOTRCQ_DOMAIN: &str = "eadm";
 pub const LOTRCQ_SUBDOMAIN: &str = "eadm";
 pub const LOTRCQ_GUID: &str = "lotr_conquest";
 pub const LOTRCQ_CONTENTSTRING: &str = "lotr_conquest";
-pub const LOBBY_ID: isize = 1;
 
 mod hdl_fsys_hello;
 use hdl_fsys_hello::fsys_hello;
@@ -78,15 +77,164 @@ use utils_memcheck::send_memcheck;
 mod utils_ping;
 use utils_ping::send_ping;
 
+mod utils_presence;
+pub(crate) use utils_presence::update_presence;
+
+mod utils_messages;
+pub(crate) use utils_messages::flush_pending_messages;
+
+mod utils_login_throttle;
+use utils_login_throttle::{record_login_failure, reject_if_throttled};
+
+mod utils_console_login;
+use utils_console_login::resolve_console_identity;
+
 mod hdl_acct_nups3login;
 use hdl_acct_nups3login::acct_nups3login;
 
 mod hdl_rank_gettopnandme;
 use hdl_rank_gettopnandme::rank_gettopnandme;
 
+mod hdl_rank_getstats;
+use hdl_rank_getstats::rank_getstats;
+
+mod hdl_rank_updatestats;
+use hdl_rank_updatestats::rank_updatestats;
+
+mod hdl_rank_getrankedstats;
+use hdl_rank_getrankedstats::rank_getrankedstats;
+
+mod hdl_rank_getrankedstatsforowners;
+use hdl_rank_getrankedstatsforowners::rank_getrankedstatsforowners;
+
 mod hdl_acct_nuxbl360login;
 use hdl_acct_nuxbl360login::acct_nuxbl360login;
 
+mod hdl_acct_nugetpassword;
+use hdl_acct_nugetpassword::acct_nugetpassword;
+
+mod hdl_acct_nugetaccountname;
+use hdl_acct_nugetaccountname::acct_nugetaccountname;
+
+mod hdl_xmsg_sendmessage;
+use hdl_xmsg_sendmessage::xmsg_sendmessage;
+
+mod hdl_xmsg_getmessages;
+use hdl_xmsg_getmessages::xmsg_getmessages;
+
+mod hdl_xmsg_deletemessages;
+use hdl_xmsg_deletemessages::xmsg_deletemessages;
+
+// Transactions that are only sent by the server
+const FESL_EVENT_TXNS: [&str; 2] = ["AsyncMessageEvent", "AsyncPresenceStatusEvent"];
+
+// Whether the TXN is one of the FESL transactions the server knows
+pub(crate) fn is_known_txn(txn: &str) -> bool {
+    FESL_EVENT_TXNS.contains(&txn) || FESL_ROUTES.has_txn(txn)
+}
+
+// Requirements and rate limits of the FESL transactions
+static FESL_ROUTES: LazyLock<Router<FeslHandler>> = LazyLock::new(|| {
+    Router::new(FESL_REQUEST, FESL_RESPONSE)
+        .request(DataMode::FESL_FSYS, Some("Hello"), Access::Any, |fh, prq| {
+            Box::pin(fsys_hello(fh, prq))
+        })
+        .request(DataMode::FESL_FSYS, Some("Goodbye"), Access::Any, |fh, prq| {
+            Box::pin(fsys_goodbye(fh, prq))
+        })
+        .request(DataMode::FESL_FSYS, Some("GetPingSites"), Access::Any, |fh, prq| {
+            Box::pin(fsys_getpingsites(fh, prq))
+        })
+        .response(DataMode::FESL_FSYS, Some("MemCheck"), Access::Any, |fh, prq| {
+            Box::pin(fsys_memcheck(fh, prq))
+        })
+        .response(DataMode::FESL_FSYS, Some("Ping"), Access::Any, |fh, prq| {
+            Box::pin(fsys_ping(fh, prq))
+        })
+        .request(DataMode::FESL_ACCT, Some("GetCountryList"), Access::Any, |fh, prq| {
+            Box::pin(acct_getcountrylist(fh, prq))
+        })
+        .request(DataMode::FESL_ACCT, Some("NuGetTos"), Access::Any, |fh, prq| {
+            Box::pin(acct_nugettos(fh, prq))
+        })
+        .request(DataMode::FESL_ACCT, Some("NuAddAccount"), Access::Any, |fh, prq| {
+            Box::pin(acct_nuaddaccount(fh, prq))
+        })
+        .limit(RateLimit::per_minute(5))
+        // Re-sent after NuAddAccount and NuEntitleGame on the authenticated connection
+        .request(DataMode::FESL_ACCT, Some("NuLogin"), Access::Any, |fh, prq| {
+            Box::pin(fh.handle_rq_acct_nulogin(prq))
+        })
+        .request(DataMode::FESL_ACCT, Some("NuPS3Login"), Access::Unauthenticated, |fh, prq| {
+            Box::pin(fh.handle_rq_acct_nups3login(prq))
+        })
+        .request(DataMode::FESL_ACCT, Some("NuXBL360Login"), Access::Unauthenticated, |fh, prq| {
+            Box::pin(fh.handle_rq_acct_nuxbl360login(prq))
+        })
+        .request(DataMode::FESL_ACCT, Some("NuGetPassword"), Access::Any, |fh, prq| {
+            Box::pin(acct_nugetpassword(fh, prq))
+        })
+        .limit(RateLimit::per_minute(5))
+        .request(DataMode::FESL_ACCT, Some("NuGetAccountName"), Access::Any, |fh, prq| {
+            Box::pin(acct_nugetaccountname(fh, prq))
+        })
+        .limit(RateLimit::per_minute(5))
+        .request(DataMode::FESL_ACCT, Some("NuEntitleGame"), Access::User, |fh, prq| {
+            Box::pin(acct_nuentitlegame(fh, prq))
+        })
+        .limit(RateLimit::per_minute(10))
+        .request(DataMode::FESL_ACCT, Some("NuGetPersonas"), Access::UserWithoutPersona, |fh, prq| {
+            Box::pin(acct_nugetpersona(fh, prq))
+        })
+        .request(DataMode::FESL_ACCT, Some("NuLoginPersona"), Access::UserWithoutPersona, |fh, prq| {
+            Box::pin(acct_nuloginpersona(fh, prq))
+        })
+        .request(DataMode::FESL_ACCT, Some("NuAddPersona"), Access::UserWithoutPersona, |fh, prq| {
+            Box::pin(acct_nuaddpersona(fh, prq))
+        })
+        .limit(RateLimit::per_minute(10))
+        .request(DataMode::FESL_ACCT, Some("NuSuggestPersonas"), Access::Any, |fh, prq| {
+            Box::pin(acct_nusuggestpersonas(fh, prq))
+        })
+        .limit(RateLimit::per_minute(20))
+        .request(DataMode::FESL_ASSO, Some("GetAssociations"), Access::User, |fh, prq| {
+            Box::pin(asso_getassociations(fh, prq))
+        })
+        .request(DataMode::FESL_ASSO, Some("AddAssociations"), Access::User, |fh, prq| {
+            Box::pin(asso_addassociations(fh, prq))
+        })
+        .limit(RateLimit::per_minute(30))
+        // Presence is only tracked for personas, but the request is answered anyway
+        .request(DataMode::FESL_PRES, Some("SetPresenceStatus"), Access::Any, |fh, prq| {
+            Box::pin(pres_setpresencestatus(fh, prq))
+        })
+        .request(DataMode::FESL_RANK, Some("GetTopNAndMe"), Access::User, |fh, prq| {
+            Box::pin(rank_gettopnandme(fh, prq))
+        })
+        .request(DataMode::FESL_RANK, Some("GetStats"), Access::User, |fh, prq| {
+            Box::pin(rank_getstats(fh, prq))
+        })
+        .request(DataMode::FESL_RANK, Some("UpdateStats"), Access::Persona, |fh, prq| {
+            Box::pin(rank_updatestats(fh, prq))
+        })
+        .request(DataMode::FESL_RANK, Some("GetRankedStats"), Access::User, |fh, prq| {
+            Box::pin(rank_getrankedstats(fh, prq))
+        })
+        .request(DataMode::FESL_RANK, Some("GetRankedStatsForOwners"), Access::User, |fh, prq| {
+            Box::pin(rank_getrankedstatsforowners(fh, prq))
+        })
+        .request(DataMode::FESL_XMSG, Some("SendMessage"), Access::Persona, |fh, prq| {
+            Box::pin(xmsg_sendmessage(fh, prq))
+        })
+        .limit(RateLimit::per_minute(30))
+        .request(DataMode::FESL_XMSG, Some("GetMessages"), Access::Persona, |fh, prq| {
+            Box::pin(xmsg_getmessages(fh, prq))
+        })
+        .request(DataMode::FESL_XMSG, Some("DeleteMessages"), Access::Persona, |fh, prq| {
+            Box::pin(xmsg_deletemessages(fh, prq))
+        })
+});
+
 pub struct FeslHandler;
 
 #[async_trait::async_trait]
@@ -105,64 +253,38 @@ impl Handler for FeslHandler {
         }
 
         // Load session
-        let Ok(db_sessions) = session::Entity::find()
-            .filter(session::Column::FeslTcpHandle.eq(con.to_string()))
-            .all(&*sstate.database)
-            .await
-        else {
-            return;
-        };
+        let db_sessions = sstate
+            .registry
+            .get_sessions_by_fesl_tcp_handle(&con.to_string());
 
         for db_session in db_sessions {
             let persona_id = db_session.persona_id;
 
-            // Check if the session has hosted games
-            let Ok(db_games) = game::Entity::find()
-                .filter(game::Column::PersonaId.eq(persona_id))
-                .all(&*sstate.database)
-                .await
-            else {
-                return;
-            };
-
-            for db_game in db_games {
-                // Remove all participants of the game from the participant table...
-                let Ok(db_participants) = participant::Entity::delete_many()
-                    .filter(participant::Column::GameId.eq(db_game.id))
-                    .exec(&*sstate.database)
-                    .await
-                else {
-                    return;
-                };
+            // The persona goes offline
+            update_presence(&sstate, persona_id, None).await;
+
+            // Delete the hosted games and all of their participants
+            for db_game in sstate.registry.get_games_by_persona(persona_id) {
+                sstate.registry.remove_participants_by_game(db_game.id);
+                sstate.registry.remove_game(db_game.id);
             }
 
-            // Delete the game entries
-            let Ok(_) = game::Entity::delete_many()
-                .filter(game::Column::PersonaId.eq(persona_id))
-                .exec(&*sstate.database)
-                .await
-            else {
-                return;
-            };
-
-            // Clear the participant entries...
-            let Ok(db_participants) = participant::Entity::delete_many()
-                .filter(participant::Column::PersonaId.eq(persona_id))
-                .exec(&*sstate.database)
-                .await
-            else {
-                return;
-            };
-        }
+            // Clear the participant entries, but remember the games the persona was
+            // participating in or queued for
+            let mut joined_game_ids = Vec::new();
+            for db_participant in sstate.registry.get_participants_by_persona(persona_id) {
+                sstate.registry.remove_participant(db_participant.id);
+                joined_game_ids.push(db_participant.game_id);
+            }
 
-        // Delete all sessions of the client
-        let Ok(_) = session::Entity::delete_many()
-            .filter(session::Column::FeslTcpHandle.eq(con.to_string()))
-            .exec(&*sstate.database)
-            .await
-        else {
-            return;
-        };
+            // Free slots and queue positions may move up queued players
+            for game_id in joined_game_ids {
+                advance_queue(game_id, &sstate).await;
+            }
+
+            // Delete the session
+            sstate.registry.remove_session(db_session.id);
+        }
     }
 
     async fn handle_packet(
@@ -171,302 +293,30 @@ impl Handler for FeslHandler {
         con: ClientConnectionDescriptor,
         sstate: Arc<SharedState>,
     ) -> Result<(), &'static str> {
+        let Some(route) = FESL_ROUTES.find(&packet) else {
+            info!(
+                target: "fesl",
+                "Unhandled {:?} {:?} (TXN {:?}), ignoring...",
+                packet.packet_mode,
+                packet.mode,
+                packet.data.get("TXN")
+            );
+            return Ok(());
+        };
         let prq = PlasmaRequestBundle::new(packet, con, sstate);
-
-        // Interpret the prq.packet
-        match prq.packet.packet_mode {
-            PacketMode::FeslSinglePacketRequest | PacketMode::FeslMultiPacketRequest => {
-                match prq.packet.mode {
-                    DataMode::FESL_FSYS => {
-                        match prq.packet.data.get("TXN") {
-                            Some(txn) => {
-                                match txn.as_str() {
-                                    "Hello" => {
-                                        return self.handle_rq_fsys_hello(prq).await;
-                                    }
-                                    "Goodbye" => {
-                                        return self.handle_rq_fsys_goodbye(prq).await;
-                                    }
-                                    "GetPingSites" => {
-                                        return self.handle_rq_fsys_getpingsites(prq).await;
-                                    }
-                                    _ => {
-                                        info!(target: "fesl", "FSYS - Unhandled TXN: {:?}, ignoring...", txn);
-                                        return Ok(()); // Ignore unknown TXNs
-                                    }
-                                }
-                            }
-                            _ => {
-                                info!(target: "fesl", "FSYS - No TXN, ignoring...");
-                                return Ok(()); // Ignore empty prq.packets TXNs
-                            }
-                        }
-                    }
-                    DataMode::FESL_ACCT => {
-                        match prq.packet.data.get("TXN") {
-                            Some(txn) => {
-                                match txn.as_str() {
-                                    "GetCountryList" => {
-                                        return self.handle_rq_acct_getcountrylist(prq).await;
-                                    }
-                                    "NuGetTos" => {
-                                        return self.handle_rq_acct_nugettos(prq).await;
-                                    }
-                                    "NuAddAccount" => {
-                                        return self.handle_rq_acct_nuaddaccount(prq).await;
-                                    }
-                                    "NuLogin" => {
-                                        return self.handle_rq_acct_nulogin(prq).await;
-                                    }
-                                    "NuAddPersona" => {
-                                        return self.handle_rq_acct_nuaddpersona(prq).await;
-                                    }
-                                    "NuGetPersonas" => {
-                                        return self.handle_rq_acct_nugetpersonas(prq).await;
-                                    }
-                                    "NuLoginPersona" => {
-                                        return self.handle_rq_acct_nuloginpersona(prq).await;
-                                    }
-                                    "NuSuggestPersonas" => {
-                                        return self.handle_rq_acct_nusuggestpersonas(prq).await;
-                                    }
-                                    "NuEntitleGame" => {
-                                        return self.handle_rq_acct_nuentitlegame(prq).await;
-                                    }
-                                    "NuPS3Login" => {
-                                        return self.handle_rq_acct_nups3login(prq).await;
-                                    }
-                                    "NuXBL360Login" => {
-                                        return self.handle_rq_acct_nuxbl360login(prq).await;
-                                    }
-                                    _ => {
-                                        info!(target: "fesl", "ACCT - Unhandled TXN: {:?}, ignoring...", txn);
-                                        return Ok(()); // Ignore unknown TXNs
-                                    }
-                                }
-                            }
-                            _ => {
-                                info!(target: "fesl", "ACCT - No TXN, ignoring...");
-                                return Ok(()); // Ignore empty prq.packets TXNs
-                            }
-                        }
-                    }
-                    DataMode::FESL_ASSO => {
-                        match prq.packet.data.get("TXN") {
-                            Some(txn) => {
-                                match txn.as_str() {
-                                    //"GetAssociations" => {
-                                    //    return self.handle_rq_asso_getassociations(prq).await;
-                                    //}
-                                    //"AddAssociations" => {
-                                    //    return self.handle_rq_asso_addassociations(prq).await;
-                                    //}
-                                    _ => {
-                                        info!(target: "fesl", "ASSO - Unhandled TXN: {:?}, ignoring...", txn);
-                                        return Ok(()); // Ignore unknown TXNs
-                                    }
-                                }
-                            }
-                            _ => {
-                                info!(target: "fesl", "ASSO - No TXN, ignoring...");
-                                return Ok(()); // Ignore empty prq.packets TXNs
-                            }
-                        }
-                    }
-                    DataMode::FESL_PRES => {
-                        match prq.packet.data.get("TXN") {
-                            Some(txn) => {
-                                match txn.as_str() {
-                                    "SetPresenceStatus" => {
-                                        return self.handle_rq_pres_setpresencestatus(prq).await;
-                                    }
-                                    _ => {
-                                        info!(target: "fesl", "PRES - Unhandled TXN: {:?}, ignoring...", txn);
-                                        return Ok(()); // Ignore unknown TXNs
-                                    }
-                                }
-                            }
-                            _ => {
-                                info!(target: "fesl", "PRES - No TXN, ignoring...");
-                                return Ok(()); // Ignore empty prq.packets TXNs
-                            }
-                        }
-                    }
-                    DataMode::FESL_RANK => {
-                        match prq.packet.data.get("TXN") {
-                            Some(tnx) => {
-                                match tnx.as_str() {
-                                    "GetTopNAndMe" => {
-                                        return self.handle_rq_rank_gettopnandme(prq).await;
-                                    }
-                                    _ => {
-                                        info!(target: "fesl", "RANK - Unhandled TXN: {:?}, ignoring...", tnx);
-                                        return Ok(()); // Ignore unknown TXNs
-                                    }
-                                }
-                            }
-                            _ => {
-                                info!(target: "fesl", "RANK - No TXN, ignoring...");
-                                return Ok(()); // Ignore empty prq.packets TXNs
-                            }
-                        }
-                    }
-                    _ => {
-                        info!(target: "fesl", "Unhandled DataMode: {:?}, ignoring...", &prq.packet.mode);
-                        return Ok(());
-                    }
-                }
-            }
-            PacketMode::FeslSinglePacketResponse | PacketMode::FeslMultiPacketResponse => {
-                match prq.packet.mode {
-                    DataMode::FESL_FSYS => {
-                        match prq.packet.data.get("TXN") {
-                            Some(txn) => {
-                                match txn.as_str() {
-                                    "MemCheck" => {
-                                        return self.handle_rsp_fsys_memcheck(prq).await;
-                                    }
-                                    "Ping" => {
-                                        return self.handle_rsp_fsys_ping(prq).await;
-                                    }
-                                    _ => {
-                                        info!(target: "fesl", "FSYS - Unhandled TXN: {:?}, ignoring...", txn);
-                                        return Ok(()); // Ignore unknown TXNs
-                                    }
-                                }
-                            }
-                            _ => {
-                                info!(target: "fesl", "FSYS - No TXN, ignoring...");
-                                return Ok(()); // Ignore empty prq.packets TXNs
-                            }
-                        }
-                    }
-                    _ => {
-                        info!(target: "fesl", "Unhandled DataMode: {:?}, ignoring...", &prq.packet.mode);
-                        return Ok(());
-                    }
-                }
-            }
-            _ => {
-                info!(target: "fesl", "Unhandled PacketMode: {:?}, ignoring...", &prq.packet.packet_mode);
-                return Ok(());
-            }
-        }
+        FESL_ROUTES.dispatch(route, self, prq).await
     }
 }
 
 impl FeslHandler {
-    async fn handle_rq_fsys_hello(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        fsys_hello(&self, prq).await
-    }
-
-    async fn handle_rq_fsys_goodbye(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        fsys_goodbye(&self, prq).await
-    }
-
-    async fn handle_rq_fsys_getpingsites(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        fsys_getpingsites(&self, prq).await
-    }
-
-    async fn handle_rq_acct_getcountrylist(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        acct_getcountrylist(&self, prq).await
-    }
-
-    async fn handle_rq_acct_nugettos(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        acct_nugettos(&self, prq).await
-    }
-
-    async fn handle_rq_acct_nuaddaccount(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        acct_nuaddaccount(&self, prq).await
-    }
-
     async fn handle_rq_acct_nulogin(
         &self,
         mut prq: PlasmaRequestBundle,
     ) -> Result<(), &'static str> {
-        acct_nulogin(&self, prq).await
-    }
-
-    async fn handle_rq_acct_nuentitlegame(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        acct_nuentitlegame(&self, prq).await
-    }
-
-    async fn handle_rq_acct_nugetpersonas(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        acct_nugetpersona(&self, prq).await
-    }
-
-    async fn handle_rq_acct_nusuggestpersonas(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        acct_nusuggestpersonas(&self, prq).await
-    }
-
-    async fn handle_rq_acct_nuloginpersona(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        acct_nuloginpersona(&self, prq).await
-    }
-
-    async fn handle_rq_acct_nuaddpersona(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        acct_nuaddpersona(&self, prq).await
-    }
-
-    async fn handle_rq_asso_getassociations(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        asso_getassociations(&self, prq).await
-    }
-
-    async fn handle_rq_asso_addassociations(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        asso_addassociations(&self, prq).await
-    }
-
-    async fn handle_rq_pres_setpresencestatus(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        pres_setpresencestatus(&self, prq).await
-    }
-
-    async fn handle_rsp_fsys_memcheck(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        fsys_memcheck(&self, prq).await
-    }
-
-    async fn handle_rsp_fsys_ping(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        fsys_ping(&self, prq).await
+        let sstate = prq.sstate.clone();
+        let result = acct_nulogin(&self, prq).await;
+        sstate.metrics.record_login("NuLogin", result.is_ok());
+        result
     }
 
     async fn send_memcheck(
@@ -491,20 +341,19 @@ impl FeslHandler {
         &self,
         mut prq: PlasmaRequestBundle,
     ) -> Result<(), &'static str> {
-        acct_nups3login(&self, prq).await
-    }
-
-    async fn handle_rq_rank_gettopnandme(
-        &self,
-        mut prq: PlasmaRequestBundle,
-    ) -> Result<(), &'static str> {
-        rank_gettopnandme(&self, prq).await
+        let sstate = prq.sstate.clone();
+        let result = acct_nups3login(&self, prq).await;
+        sstate.metrics.record_login("NuPS3Login", result.is_ok());
+        result
     }
 
     async fn handle_rq_acct_nuxbl360login(
         &self,
         mut prq: PlasmaRequestBundle,
     ) -> Result<(), &'static str> {
-        acct_nuxbl360login(&self, prq).await
+        let sstate = prq.sstate.clone();
+        let result = acct_nuxbl360login(&self, prq).await;
+        sstate.metrics.record_login("NuXBL360Login", result.is_ok());
+        result
     }
 }
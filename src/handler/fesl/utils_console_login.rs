@@ -0,0 +1,161 @@
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use tracing::{info, warn};
+
+use crate::handler::{submit_packet, to_error_packet};
+use crate::orm::model::{account, persona};
+use crate::plasma_errors::EAError;
+use crate::plasma_handle::PlasmaRequestBundle;
+use crate::rate_limit::RateLimit;
+use crate::utils::linking::{find_linked_identity, link_identity, IdentityPlatform, PendingLinks};
+
+// New link codes per client IP
+const LINK_CODE_RATE_LIMIT: RateLimit = RateLimit::per_minute(5);
+
+async fn load_persona_and_account(
+    prq: &PlasmaRequestBundle,
+    persona_id: i64,
+) -> Option<(account::Model, persona::Model)> {
+    let db_persona = persona::Entity::find_by_id(persona_id)
+        .one(&*prq.sstate.database)
+        .await
+        .ok()??;
+    let db_account = account::Entity::find_by_id(db_persona.user_id)
+        .one(&*prq.sstate.database)
+        .await
+        .ok()??;
+    Some((db_account, db_persona))
+}
+
+// Persona that was linked by name before the identities were linked
+async fn find_legacy_persona(
+    prq: &PlasmaRequestBundle,
+    display_name: &str,
+) -> Option<(account::Model, persona::Model)> {
+    let db_persona = persona::Entity::find()
+        .filter(
+            Condition::all()
+                .add(persona::Column::Name.eq(display_name))
+                .add(persona::Column::AllowInsecureLogin.eq(true)),
+        )
+        .one(&*prq.sstate.database)
+        .await
+        .ok()??;
+    load_persona_and_account(prq, db_persona.id).await
+}
+
+// Looks up the account and persona the console identity is linked to. Unlinked
+// identities get a code to link them on the account web. Errors are sent to the client.
+pub async fn resolve_console_identity(
+    prq: &PlasmaRequestBundle,
+    platform: IdentityPlatform,
+    external_id: &str,
+    display_name: &str,
+) -> Result<(account::Model, persona::Model), &'static str> {
+    let db_identity = match find_linked_identity(platform, external_id, &prq.sstate.database).await {
+        Ok(db_identity) => db_identity,
+        Err(_) => {
+            let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
+            submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+            return Err("Failed to retrieve the linked identity");
+        }
+    };
+
+    if let Some(db_identity) = db_identity {
+        if let Some((db_account, db_persona)) =
+            load_persona_and_account(prq, db_identity.persona_id).await
+        {
+            // The identity of unverified platforms may be claimed by anyone
+            if !platform.is_verified() && !db_persona.allow_insecure_login {
+                let err_pkt = to_error_packet(
+                    &prq.packet,
+                    EAError::EA_AuthFail as i32,
+                    Some(format!(
+                        "{} logins are not allowed for this persona.",
+                        platform.display_name()
+                    )),
+                );
+                submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+                return Err("Insecure login not allowed for the persona");
+            }
+            return Ok((db_account, db_persona));
+        }
+        // The persona is gone, so the identity has to be linked again
+        warn!(target: "auth", "Persona {} of the linked {} identity {} does not exist", db_identity.persona_id, platform.as_str(), external_id);
+    } else if prq.sstate.console_linking.allow_legacy_name_login
+        && let Some((db_account, db_persona)) = find_legacy_persona(prq, display_name).await
+    {
+        // Bind the identity on its first login, so that later logins no longer depend on the name
+        if link_identity(
+            platform,
+            external_id,
+            display_name,
+            db_account.id,
+            db_persona.id,
+            &prq.sstate.database,
+        )
+        .await
+        .is_ok()
+        {
+            info!(target: "auth", "Linked {} identity {} to persona {} by name", platform.as_str(), external_id, &db_persona.name);
+        }
+        return Ok((db_account, db_persona));
+    }
+
+    // Without the account web, the identity cannot be linked by the player
+    if !prq.sstate.account_web_enabled {
+        let err_pkt = to_error_packet(
+            &prq.packet,
+            EAError::EA_NotFound as i32,
+            Some(format!("This {} account is not linked yet.", platform.display_name())),
+        );
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Console identity not linked");
+    }
+
+    // Every login of an unlinked identity would otherwise hand out a new code
+    let code = match prq.sstate.pending_links.find(platform, external_id) {
+        Some(code) => Some(code),
+        None => {
+            if let Err(retry_in) = prq.sstate.request_limiter.check(
+                "link/issue",
+                &prq.con.client_ip,
+                &LINK_CODE_RATE_LIMIT,
+            ) {
+                prq.sstate.metrics.record_rate_limited("link/issue");
+                let err_pkt = to_error_packet(
+                    &prq.packet,
+                    EAError::EA_TooManyAttempts as i32,
+                    Some(format!(
+                        "Too many requests. Try again in {} seconds.",
+                        retry_in.as_secs().max(1)
+                    )),
+                );
+                submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+                return Err("Rate limited");
+            }
+            prq.sstate.pending_links.issue(platform, external_id, display_name)
+        }
+    };
+    let Some(code) = code else {
+        let err_pkt = to_error_packet(
+            &prq.packet,
+            EAError::EA_TooManyAttempts as i32,
+            Some("Too many pending links. Try again later.".to_string()),
+        );
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Too many pending links");
+    };
+    let err_pkt = to_error_packet(
+        &prq.packet,
+        EAError::EA_NotFound as i32,
+        Some(format!(
+            "This {} account is not linked yet. Visit {} and enter the code {}",
+            platform.display_name(),
+            prq.sstate.mailer.page_url("/link"),
+            PendingLinks::format_code(&code)
+        )),
+    );
+    submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+    Err("Console identity not linked")
+}
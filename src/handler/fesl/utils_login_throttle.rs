@@ -0,0 +1,35 @@
+use tracing::warn;
+
+use crate::handler::{submit_packet, to_error_packet};
+use crate::login_throttle::ThrottleKey;
+use crate::plasma_errors::EAError;
+use crate::plasma_handle::PlasmaRequestBundle;
+
+// Rejects the login with EA_TooManyAttempts while one of the keys is locked
+pub async fn reject_if_throttled(
+    prq: &PlasmaRequestBundle,
+    method: &str,
+    keys: &[ThrottleKey<'_>],
+) -> Result<(), &'static str> {
+    let Some(locked_for) = prq.sstate.login_throttle.check(keys) else {
+        return Ok(());
+    };
+    prq.sstate.metrics.record_login_throttled(method);
+    let err_pkt = to_error_packet(
+        &prq.packet,
+        EAError::EA_TooManyAttempts as i32,
+        Some(format!(
+            "Too many failed attempts. Try again in {} seconds.",
+            locked_for.as_secs().max(1)
+        )),
+    );
+    submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+    Err("Too many failed login attempts")
+}
+
+pub fn record_login_failure(prq: &PlasmaRequestBundle, keys: &[ThrottleKey<'_>]) {
+    for locked_key in prq.sstate.login_throttle.record_failure(keys) {
+        warn!(target: "auth", "Locked {:?} after repeated failed logins (via {})", locked_key, prq.con.to_string());
+        prq.sstate.metrics.record_login_lockout(locked_key.kind());
+    }
+}
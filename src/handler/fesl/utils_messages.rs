@@ -0,0 +1,261 @@
+use indexmap::IndexMap;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::handler::submit_packet;
+use crate::orm::model::{message, persona};
+use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::sharedstate::SharedState;
+use crate::utils::config_values::get_cfg_value;
+
+use super::utils_presence::get_persona_fesl_connection;
+
+// Sender id and name of messages sent by the server itself
+pub const SERVER_SENDER_ID: i64 = -1;
+pub const SERVER_SENDER_NAME: &str = "MordorWide";
+pub const BROADCAST_MESSAGE_TYPE: &str = "broadcast";
+// Longest text of a broadcast
+pub const MAX_BROADCAST_TEXT_LENGTH: usize = 1024;
+// Messages inserted per statement when broadcasting
+const BROADCAST_BATCH_SIZE: usize = 500;
+// Limits of a single SendMessage
+pub const MAX_MESSAGE_RECIPIENTS: usize = 10;
+pub const MAX_MESSAGE_ATTACHMENTS: usize = 4;
+// Total length of the keys, types and data of the attachments of a message
+pub const MAX_MESSAGE_ATTACHMENTS_LENGTH: usize = 4096;
+// Unexpired messages a persona may have from other personas. Also the most messages
+// that are sent for a single GetMessages or pushed at once after a login.
+pub const MAX_INBOX_MESSAGES: u64 = 100;
+
+async fn get_message_lifetime_cfg(key: &str, sstate: &Arc<SharedState>) -> i64 {
+    get_cfg_value(key, &sstate.database)
+        .await
+        .and_then(|lifetime| lifetime.parse::<i64>().ok())
+        .unwrap_or(2592000)
+}
+
+// Expiration of a new message. The lifetime requested by the sender (if any) is capped
+// at MESSAGE_MAX_EXPIRATION_SECONDS. None if the expiration cannot be represented.
+pub async fn get_message_expiration(
+    sstate: &Arc<SharedState>,
+    requested_secs: Option<i64>,
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    let max_lifetime_secs = get_message_lifetime_cfg("MESSAGE_MAX_EXPIRATION_SECONDS", sstate).await;
+    let lifetime_secs = match requested_secs {
+        Some(requested_secs) if requested_secs > 0 => requested_secs,
+        _ => get_message_lifetime_cfg("MESSAGE_EXPIRATION_SECONDS", sstate).await,
+    };
+    let lifetime = chrono::TimeDelta::try_seconds(lifetime_secs.min(max_lifetime_secs))?;
+    chrono::Utc::now().checked_add_signed(lifetime)
+}
+
+// Whether the persona cannot receive more messages from other personas
+pub async fn is_inbox_full(sstate: &Arc<SharedState>, recipient_persona_id: i64) -> bool {
+    message::Entity::find()
+        .filter(
+            Condition::all()
+                .add(message::Column::RecipientPersonaId.eq(recipient_persona_id))
+                .add(message::Column::SenderPersonaId.ne(SERVER_SENDER_ID))
+                .add(message::Column::ExpiresAt.gt(chrono::Utc::now())),
+        )
+        .count(&*sstate.database)
+        .await
+        .map_or(true, |n_messages| n_messages >= MAX_INBOX_MESSAGES)
+}
+
+pub async fn store_message(
+    sstate: &Arc<SharedState>,
+    sender_persona_id: i64,
+    recipient_persona_id: i64,
+    message_type: &str,
+    attachments: &serde_json::Value,
+    expires_at: chrono::DateTime<chrono::Utc>,
+) -> Option<message::Model> {
+    let new_message = message::ActiveModel {
+        sender_persona_id: Set(sender_persona_id),
+        recipient_persona_id: Set(recipient_persona_id),
+        message_type: Set(message_type.to_string()),
+        attachments: Set(attachments.to_string()),
+        delivered: Set(false),
+        sent_at: Set(chrono::Utc::now()),
+        expires_at: Set(expires_at),
+        ..Default::default()
+    };
+    match new_message.insert(&*sstate.database).await {
+        Ok(db_message) => Some(db_message),
+        Err(err) => {
+            warn!(target: "fesl", "Failed to store message: {}", err);
+            None
+        }
+    }
+}
+
+async fn get_persona_name(persona_id: i64, sstate: &Arc<SharedState>) -> String {
+    if persona_id == SERVER_SENDER_ID {
+        return SERVER_SENDER_NAME.to_string();
+    }
+    match persona::Entity::find_by_id(persona_id)
+        .one(&*sstate.database)
+        .await
+    {
+        Ok(Some(db_persona)) => db_persona.name,
+        _ => "".to_string(),
+    }
+}
+
+// Adds the message in the Plasma list format, e.g. "messages.0.messageId"
+pub async fn insert_message_fields(
+    hm: &mut IndexMap<String, String>,
+    prefix: &str,
+    db_message: &message::Model,
+    sstate: &Arc<SharedState>,
+) {
+    let sender_name = get_persona_name(db_message.sender_persona_id, sstate).await;
+    let recipient_name = get_persona_name(db_message.recipient_persona_id, sstate).await;
+
+    hm.insert(format!("{}messageId", prefix), db_message.id.to_string());
+    hm.insert(format!("{}messageType", prefix), db_message.message_type.to_string());
+    hm.insert(format!("{}from.id", prefix), db_message.sender_persona_id.to_string());
+    hm.insert(format!("{}from.name", prefix), sender_name);
+    hm.insert(format!("{}from.type", prefix), "1".to_string());
+    hm.insert(format!("{}to.[]", prefix), "1".to_string());
+    hm.insert(format!("{}to.0.id", prefix), db_message.recipient_persona_id.to_string());
+    hm.insert(format!("{}to.0.name", prefix), recipient_name);
+    hm.insert(format!("{}to.0.type", prefix), "1".to_string());
+    hm.insert(
+        format!("{}timeSent", prefix),
+        db_message.sent_at.format("%h-%d-%Y %H:%M:%S UTC").to_string(),
+    );
+    hm.insert(
+        format!("{}expiration", prefix),
+        db_message.expires_at.format("%h-%d-%Y %H:%M:%S UTC").to_string(),
+    );
+
+    let attachments: Vec<serde_json::Value> =
+        serde_json::from_str(&db_message.attachments).unwrap_or_default();
+    hm.insert(format!("{}attachments.[]", prefix), attachments.len().to_string());
+    for (attachment_idx, attachment) in attachments.iter().enumerate() {
+        for field in ["key", "type", "data"] {
+            hm.insert(
+                format!("{}attachments.{}.{}", prefix, attachment_idx, field),
+                attachment[field].as_str().unwrap_or("").to_string(),
+            );
+        }
+    }
+}
+
+// Pushes the message to the recipient if it is online. Returns whether it was delivered.
+pub async fn deliver_message(sstate: &Arc<SharedState>, db_message: message::Model) -> bool {
+    if !sstate.presence.contains_key(&db_message.recipient_persona_id) {
+        return false;
+    }
+    let Some(recipient_con) =
+        get_persona_fesl_connection(db_message.recipient_persona_id, sstate).await
+    else {
+        return false;
+    };
+
+    let mut request_hm = IndexMap::new();
+    request_hm.insert("TXN".to_string(), "AsyncMessageEvent".to_string());
+    insert_message_fields(&mut request_hm, "", &db_message, sstate).await;
+
+    let event = DataPacket::new(
+        DataMode::FESL_XMSG,
+        PacketMode::FeslSinglePacketRequest,
+        0,
+        request_hm,
+    );
+    submit_packet(event, &recipient_con, sstate, 0).await;
+
+    let mut db_message = db_message.into_active_model();
+    db_message.delivered = Set(true);
+    db_message.update(&*sstate.database).await.is_ok()
+}
+
+// Forgets the expired messages (of all recipients)
+async fn delete_expired_messages(sstate: &Arc<SharedState>) {
+    if let Err(err) = message::Entity::delete_many()
+        .filter(message::Column::ExpiresAt.lte(chrono::Utc::now()))
+        .exec(&*sstate.database)
+        .await
+    {
+        warn!(target: "fesl", "Failed to delete expired messages: {}", err);
+    }
+}
+
+// Pushes all messages that arrived while the persona was offline
+pub async fn flush_pending_messages(sstate: &Arc<SharedState>, persona_id: i64) {
+    let Ok(db_messages) = message::Entity::find()
+        .filter(
+            Condition::all()
+                .add(message::Column::RecipientPersonaId.eq(persona_id))
+                .add(message::Column::Delivered.eq(false))
+                .add(message::Column::ExpiresAt.gt(chrono::Utc::now())),
+        )
+        .order_by(message::Column::SentAt, sea_orm::Order::Asc)
+        .limit(MAX_INBOX_MESSAGES)
+        .all(&*sstate.database)
+        .await
+    else {
+        return;
+    };
+    for db_message in db_messages {
+        deliver_message(sstate, db_message).await;
+    }
+}
+
+// Sends a text message from the server to every persona. Online personas receive it
+// immediately, the others on their next login. Returns the number of recipients.
+pub async fn broadcast_message(
+    sstate: &Arc<SharedState>,
+    text: &str,
+    expires_at: chrono::DateTime<chrono::Utc>,
+) -> usize {
+    // Every broadcast adds a row per persona, so the expired ones are dropped first
+    delete_expired_messages(sstate).await;
+
+    let Ok(persona_ids) = persona::Entity::find()
+        .select_only()
+        .column(persona::Column::Id)
+        .into_tuple::<i64>()
+        .all(&*sstate.database)
+        .await
+    else {
+        return 0;
+    };
+    let attachments = serde_json::json!([
+        {"key": "body", "type": "text/plain", "data": text}
+    ])
+    .to_string();
+
+    let sent_at = chrono::Utc::now();
+    let mut n_recipients = 0;
+    for persona_ids in persona_ids.chunks(BROADCAST_BATCH_SIZE) {
+        let new_messages = persona_ids.iter().map(|persona_id| message::ActiveModel {
+            sender_persona_id: Set(SERVER_SENDER_ID),
+            recipient_persona_id: Set(*persona_id),
+            message_type: Set(BROADCAST_MESSAGE_TYPE.to_string()),
+            attachments: Set(attachments.clone()),
+            delivered: Set(false),
+            sent_at: Set(sent_at),
+            expires_at: Set(expires_at),
+            ..Default::default()
+        });
+        match message::Entity::insert_many(new_messages)
+            .exec_without_returning(&*sstate.database)
+            .await
+        {
+            Ok(_) => n_recipients += persona_ids.len(),
+            Err(err) => warn!(target: "fesl", "Failed to store broadcast messages: {}", err),
+        }
+    }
+
+    // The online personas get the broadcast together with anything else still pending
+    let online_persona_ids: Vec<i64> = sstate.presence.iter().map(|entry| *entry.key()).collect();
+    for persona_id in online_persona_ids {
+        flush_pending_messages(sstate, persona_id).await;
+    }
+    n_recipients
+}
@@ -0,0 +1,172 @@
+use dashmap::mapref::entry::Entry;
+use indexmap::IndexMap;
+use sea_orm::entity::*;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::client_connection::ClientConnectionDescriptor;
+use crate::handler::submit_packet;
+use crate::orm::model::persona;
+use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::presence::{PresenceInfo, PRESENCE_SHOW_OFFLINE};
+use crate::sharedstate::SharedState;
+use crate::utils::associations::get_mutual_friends;
+
+
+// FESL connection of the session the persona is logged in with
+pub async fn get_persona_fesl_connection(
+    persona_id: i64,
+    sstate: &Arc<SharedState>,
+) -> Option<ClientConnectionDescriptor> {
+    let db_session = sstate.registry.get_session_by_persona(persona_id)?;
+    if db_session.fesl_tcp_handle.is_empty() {
+        return None;
+    }
+    Some(ClientConnectionDescriptor::from_string(
+        &db_session.fesl_tcp_handle,
+    ))
+}
+
+fn build_presence_event(
+    db_persona: &persona::Model,
+    presence: Option<&PresenceInfo>,
+    initial: bool,
+) -> DataPacket {
+    let mut request_hm = IndexMap::new();
+    request_hm.insert("TXN".to_string(), "AsyncPresenceStatusEvent".to_string());
+    request_hm.insert("initial".to_string(), (if initial { "1" } else { "0" }).to_string());
+    request_hm.insert("owner.id".to_string(), db_persona.id.to_string());
+    request_hm.insert("owner.type".to_string(), "1".to_string());
+    request_hm.insert("owner.name".to_string(), db_persona.name.to_string());
+    match presence {
+        Some(presence) => {
+            request_hm.insert("status.show".to_string(), presence.state.to_show().to_string());
+            request_hm.insert("status.gid".to_string(), presence.gid.to_string());
+        }
+        None => {
+            request_hm.insert("status.show".to_string(), PRESENCE_SHOW_OFFLINE.to_string());
+            request_hm.insert("status.gid".to_string(), "-1".to_string());
+        }
+    }
+    request_hm.insert("status.statusText".to_string(), "".to_string());
+
+    DataPacket::new(
+        DataMode::FESL_PRES,
+        PacketMode::FeslSinglePacketRequest,
+        0,
+        request_hm,
+    )
+}
+
+// Sends the current presence of the persona to another persona, if both are online
+pub async fn send_presence(
+    sstate: &Arc<SharedState>,
+    db_persona: &persona::Model,
+    recipient_persona_id: i64,
+) {
+    let Some(presence) = sstate.presence.get(&db_persona.id).map(|entry| entry.clone()) else {
+        return;
+    };
+    if !sstate.presence.contains_key(&recipient_persona_id) {
+        return;
+    }
+    if let Some(recipient_con) = get_persona_fesl_connection(recipient_persona_id, sstate).await {
+        let event = build_presence_event(db_persona, Some(&presence), true);
+        submit_packet(event, &recipient_con, sstate, 0).await;
+    }
+}
+
+// Updates the presence of the persona (None = offline) and notifies its online mutual
+// friends. The updates of a persona are applied one at a time, so that the friends get
+// them in order.
+pub async fn update_presence(
+    sstate: &Arc<SharedState>,
+    persona_id: i64,
+    presence: Option<PresenceInfo>,
+) {
+    if persona_id == -1 {
+        return;
+    }
+
+    let update_lock = sstate
+        .presence_update_locks
+        .entry(persona_id)
+        .or_default()
+        .clone();
+    let update_guard = update_lock.lock().await;
+    let went_offline = presence.is_none();
+    apply_presence_update(sstate, persona_id, presence).await;
+    // Forget the lock of an offline persona, unless another update waits for it
+    if went_offline {
+        sstate
+            .presence_update_locks
+            .remove_if(&persona_id, |_, lock| Arc::strong_count(lock) == 2);
+    }
+    drop(update_guard);
+}
+
+async fn apply_presence_update(
+    sstate: &Arc<SharedState>,
+    persona_id: i64,
+    presence: Option<PresenceInfo>,
+) {
+    let previous = match sstate.presence.entry(persona_id) {
+        Entry::Occupied(mut entry) => match &presence {
+            Some(presence) if entry.get().same_status(presence) => return,
+            Some(presence) => Some(entry.insert(presence.clone())),
+            None => Some(entry.remove()),
+        },
+        Entry::Vacant(entry) => match &presence {
+            Some(presence) => {
+                entry.insert(presence.clone());
+                None
+            }
+            None => return,
+        },
+    };
+
+    let Ok(Some(db_persona)) = persona::Entity::find_by_id(persona_id)
+        .one(&*sstate.database)
+        .await
+    else {
+        return;
+    };
+    debug!(target: "fesl", "Presence of persona {} changed: {:?}", &db_persona.name, &presence);
+
+    // Notify the friends that are online. Friend entries that were not added back are
+    // requests and do not reveal the presence.
+    let friend_persona_ids = get_mutual_friends(persona_id, &sstate.database).await;
+    for friend_persona_id in friend_persona_ids.iter().copied() {
+        if !sstate.presence.contains_key(&friend_persona_id) {
+            continue;
+        }
+        if let Some(friend_con) = get_persona_fesl_connection(friend_persona_id, sstate).await {
+            let event = build_presence_event(&db_persona, presence.as_ref(), false);
+            submit_packet(event, &friend_con, sstate, 0).await;
+        }
+    }
+
+    // A persona that just came online gets the presence of its own friends
+    if previous.is_none() && presence.is_some() {
+        let Some(own_con) = get_persona_fesl_connection(persona_id, sstate).await else {
+            return;
+        };
+        for friend_persona_id in friend_persona_ids {
+            let friend_presence = sstate
+                .presence
+                .get(&friend_persona_id)
+                .map(|entry| entry.clone());
+            if friend_presence.is_none() {
+                continue;
+            }
+            let Ok(Some(db_friend_persona)) = persona::Entity::find_by_id(friend_persona_id)
+                .one(&*sstate.database)
+                .await
+            else {
+                continue;
+            };
+            let event = build_presence_event(&db_friend_persona, friend_presence.as_ref(), true);
+            submit_packet(event, &own_con, sstate, 0).await;
+        }
+    }
+}
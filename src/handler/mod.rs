@@ -1,16 +1,22 @@
 pub mod fesl;
 pub mod theater;
+mod routes;
 
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
 use indexmap::IndexMap;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::net::UdpSocket;
-use tracing::debug;
+use tracing::{debug, info, warn};
 
 use crate::client_connection::{ClientConnectionDescriptor, ProtoType, SendDataType, ServiceType};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::{PlasmaDataErr, PlasmaDecode};
+use crate::plasma_errors::EAError;
+use crate::plasma_handle::PlasmaRequestBundle;
 use crate::sharedstate::SharedState;
+use crate::utils::bans::{ban_message, find_active_ban, BanSubject};
 use crate::utils::stun_turn::{
     StunRelayRequestBody, StunRelayResponseBody,
 };
@@ -33,6 +39,8 @@ async fn submit_packet(
     sstate: &Arc<SharedState>,
     delay: i64,
 ) {
+    sstate.metrics.record_packet_out(&packet);
+
     // Spawn new thread to delay the packet
     let delayed_sstate = sstate.clone();
     let delayed_con = con.clone();
@@ -94,6 +102,7 @@ async fn submit_packet(
                     };
 
                     // Send packet to STUNRelay via POST request
+                    let started_at = Instant::now();
                     let Ok(response) = reqwest::Client::new()
                         .post(&format!(
                             "http://{}:{}/send",
@@ -104,14 +113,19 @@ async fn submit_packet(
                         .await
                     else {
                         // Failed to send packet to STUNRelay
+                        delayed_sstate.metrics.record_relay_request("stun", started_at, false);
                         return;
                     };
 
                     // Check response (not really required though...)
                     let Ok(response_body) = response.json::<StunRelayResponseBody>().await else {
                         // Failed to parse response body
+                        delayed_sstate.metrics.record_relay_request("stun", started_at, false);
                         return;
                     };
+                    delayed_sstate
+                        .metrics
+                        .record_relay_request("stun", started_at, response_body.success);
 
                     if !response_body.success {
                         // STUNRelay failed to send packet
@@ -125,6 +139,68 @@ async fn submit_packet(
     });
 }
 
+// Decodes the fields of the packet. Malformed packets are logged and counted.
+fn decode_packet<T: PlasmaDecode>(prq: &PlasmaRequestBundle) -> Result<T, PlasmaDataErr> {
+    T::decode(&prq.packet.data).inspect_err(|data_err| {
+        // FESL packets are named by their TXN, Theater packets by their mode
+        let packet_name = prq
+            .packet
+            .data
+            .get("TXN")
+            .map(String::as_str)
+            .unwrap_or(prq.packet.mode.value());
+        warn!(target: "packet", "Malformed {} packet from {}: {}", packet_name, prq.con.to_string(), data_err);
+        prq.sstate.metrics.record_malformed_packet(&prq.packet);
+    })
+}
+
+// Decodes the fields of the request. Missing or invalid fields are answered with EA_NoData.
+async fn decode_request<T: PlasmaDecode>(prq: &PlasmaRequestBundle) -> Result<T, &'static str> {
+    match decode_packet(prq) {
+        Ok(request) => Ok(request),
+        Err(data_err) => {
+            let err_pkt = to_error_packet(
+                &prq.packet,
+                EAError::EA_NoData as i32,
+                Some(data_err.to_string()),
+            );
+            submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+            Err("Malformed request")
+        }
+    }
+}
+
+// Decodes the fields of a response of the client. Malformed responses are dropped
+// without a reply.
+fn decode_response<T: PlasmaDecode>(prq: &PlasmaRequestBundle) -> Result<T, &'static str> {
+    decode_packet(prq).map_err(|_| "Malformed response")
+}
+
+// Sends EA_Banned (with the reason of the ban) if the subject is banned
+async fn reject_if_banned(
+    prq: &PlasmaRequestBundle,
+    ban_subject: &BanSubject,
+) -> Result<(), &'static str> {
+    match find_active_ban(ban_subject, &prq.sstate.database).await {
+        Ok(None) => Ok(()),
+        Ok(Some(db_ban)) => {
+            info!(target: "auth", "Rejected banned client {} (ban {})", prq.con.to_string(), db_ban.id);
+            let err_pkt = to_error_packet(
+                &prq.packet,
+                EAError::EA_Banned as i32,
+                Some(ban_message(&db_ban)),
+            );
+            submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+            Err("Client is banned")
+        }
+        Err(_) => {
+            let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
+            submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+            Err("Failed to check the bans")
+        }
+    }
+}
+
 fn to_error_packet(packet: &DataPacket, error_code: i32, error_text: Option<String>) -> DataPacket {
     let mut error_hm: IndexMap<String, String> = IndexMap::new();
     let mut packet_id = 0;
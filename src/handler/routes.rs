@@ -0,0 +1,300 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Instant;
+use tracing::{debug, info, info_span, Instrument};
+
+use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_errors::EAError;
+use crate::plasma_handle::PlasmaRequestBundle;
+use crate::rate_limit::RateLimit;
+
+use super::{submit_packet, to_error_packet};
+
+pub const FESL_REQUEST: &[PacketMode] = &[
+    PacketMode::FeslSinglePacketRequest,
+    PacketMode::FeslMultiPacketRequest,
+];
+pub const FESL_RESPONSE: &[PacketMode] = &[
+    PacketMode::FeslSinglePacketResponse,
+    PacketMode::FeslMultiPacketResponse,
+];
+pub const THEATER_REQUEST: &[PacketMode] = &[PacketMode::TheaterRequest];
+pub const THEATER_RESPONSE: &[PacketMode] = &[PacketMode::FeslPingOrTheaterResponse];
+
+// Who may send the packets of a route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Any,
+    // Only connections without a session (e.g. the console logins)
+    Unauthenticated,
+    // A logged in account
+    User,
+    // A logged in account that has not selected a persona yet
+    UserWithoutPersona,
+    // A logged in persona
+    Persona,
+    // A logged in persona that hosts the game of the packet (GID)
+    GameHost,
+}
+
+// What the middleware gets to know about the route of a packet
+#[derive(Debug, Clone)]
+pub struct RouteSpec {
+    // "acct/NuLogin" for FESL, "CGAM" for Theater
+    pub name: String,
+    pub mode: DataMode,
+    // FESL packets are routed by TXN as well
+    pub txn: Option<&'static str>,
+    pub access: Access,
+    // Requests per client IP
+    pub rate_limit: Option<RateLimit>,
+    // Responses of the client are dropped without an error packet
+    pub is_response: bool,
+}
+
+pub type RouteFuture<'a> = Pin<Box<dyn Future<Output = Result<(), &'static str>> + Send + 'a>>;
+pub type RouteFn<H> = for<'a> fn(&'a H, PlasmaRequestBundle) -> RouteFuture<'a>;
+
+pub struct Route<H> {
+    pub spec: RouteSpec,
+    handler: RouteFn<H>,
+}
+
+// Step that runs before the handler of every route, in the order of the router
+#[async_trait::async_trait]
+pub trait Middleware: Send + Sync {
+    // An error stops the request. The middleware answers the client itself.
+    async fn before(
+        &self,
+        route: &RouteSpec,
+        prq: &mut PlasmaRequestBundle,
+    ) -> Result<(), &'static str>;
+}
+
+// Routes the packets of a service to their handlers
+pub struct Router<H> {
+    request_modes: &'static [PacketMode],
+    response_modes: &'static [PacketMode],
+    routes: Vec<Route<H>>,
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl<H: Sync> Router<H> {
+    // Starts with the rate limits and the access checks of the routes
+    pub fn new(
+        request_modes: &'static [PacketMode],
+        response_modes: &'static [PacketMode],
+    ) -> Self {
+        Self {
+            request_modes,
+            response_modes,
+            routes: Vec::new(),
+            middleware: vec![Box::new(LimitRate), Box::new(CheckAccess)],
+        }
+    }
+
+    // Appends a middleware to the chain
+    pub fn layer(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    pub fn request(
+        self,
+        mode: DataMode,
+        txn: Option<&'static str>,
+        access: Access,
+        handler: RouteFn<H>,
+    ) -> Self {
+        self.add_route(false, mode, txn, access, handler)
+    }
+
+    pub fn response(
+        self,
+        mode: DataMode,
+        txn: Option<&'static str>,
+        access: Access,
+        handler: RouteFn<H>,
+    ) -> Self {
+        self.add_route(true, mode, txn, access, handler)
+    }
+
+    // Sets the rate limit of the route added last
+    pub fn limit(mut self, rate_limit: RateLimit) -> Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.spec.rate_limit = Some(rate_limit);
+        }
+        self
+    }
+
+    fn add_route(
+        mut self,
+        is_response: bool,
+        mode: DataMode,
+        txn: Option<&'static str>,
+        access: Access,
+        handler: RouteFn<H>,
+    ) -> Self {
+        let name = match txn {
+            Some(txn) => format!("{}/{}", mode.value(), txn),
+            None => mode.value().to_string(),
+        };
+        self.routes.push(Route {
+            spec: RouteSpec {
+                name,
+                mode,
+                txn,
+                access,
+                rate_limit: None,
+                is_response,
+            },
+            handler,
+        });
+        self
+    }
+
+    // Whether any route of the router is routed by the TXN
+    pub fn has_txn(&self, txn: &str) -> bool {
+        self.routes.iter().any(|route| route.spec.txn == Some(txn))
+    }
+
+    pub fn find(&self, packet: &DataPacket) -> Option<&Route<H>> {
+        let is_response = if self.request_modes.contains(&packet.packet_mode) {
+            false
+        } else if self.response_modes.contains(&packet.packet_mode) {
+            true
+        } else {
+            return None;
+        };
+        let txn = packet.data.get("TXN").map(String::as_str);
+        self.routes.iter().find(|route| {
+            route.spec.is_response == is_response
+                && route.spec.mode == packet.mode
+                && route.spec.txn.is_none_or(|route_txn| txn == Some(route_txn))
+        })
+    }
+
+    // Runs the middleware and the handler of the route within a span of the request
+    pub async fn dispatch(
+        &self,
+        route: &Route<H>,
+        handler: &H,
+        mut prq: PlasmaRequestBundle,
+    ) -> Result<(), &'static str> {
+        let span = info_span!(
+            target: "route",
+            "request",
+            route = %route.spec.name,
+            client = %prq.con.to_string(),
+        );
+        async move {
+            let started_at = Instant::now();
+            for middleware in self.middleware.iter() {
+                middleware.before(&route.spec, &mut prq).await?;
+            }
+            let result = (route.handler)(handler, prq).await;
+            debug!(target: "route", "Handled in {} ms: {:?}", started_at.elapsed().as_millis(), result);
+            result
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+// Rejects the requests that do not meet the access of the route with EA_AuthFail
+pub struct CheckAccess;
+
+#[async_trait::async_trait]
+impl Middleware for CheckAccess {
+    async fn before(
+        &self,
+        route: &RouteSpec,
+        prq: &mut PlasmaRequestBundle,
+    ) -> Result<(), &'static str> {
+        let denied = match route.access {
+            Access::Any => None,
+            Access::Unauthenticated => prq
+                .is_authenticated_user()
+                .await
+                .then_some("User already authenticated."),
+            Access::User => (!prq.is_authenticated_user().await).then_some("User not authenticated."),
+            Access::UserWithoutPersona => {
+                if !prq.is_authenticated_user().await {
+                    Some("User not authenticated.")
+                } else if prq.get_active_persona_model().await.is_some() {
+                    Some("Persona already selected.")
+                } else {
+                    None
+                }
+            }
+            Access::Persona => (!prq.is_authenticated_user_and_persona().await)
+                .then_some("Persona not authenticated."),
+            Access::GameHost => match prq.get_active_persona_model().await {
+                None => Some("Persona not authenticated."),
+                Some(db_persona) => {
+                    let db_game = prq
+                        .packet
+                        .data
+                        .get("GID")
+                        .and_then(|gid| gid.parse::<i64>().ok())
+                        .and_then(|gid| prq.sstate.registry.get_game(gid));
+                    match db_game {
+                        None => Some("Game not found."),
+                        Some(db_game) if db_game.persona_id != db_persona.id => {
+                            Some("Persona does not host the game.")
+                        }
+                        Some(_) => None,
+                    }
+                }
+            },
+        };
+        let Some(reason) = denied else {
+            return Ok(());
+        };
+        debug!(target: "route", "Denied {} to {}: {}", route.name, prq.con.to_string(), reason);
+        if route.is_response {
+            return Err(reason);
+        }
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        Err(reason)
+    }
+}
+
+// Rejects the requests above the rate limit of the route with EA_TooManyAttempts
+pub struct LimitRate;
+
+#[async_trait::async_trait]
+impl Middleware for LimitRate {
+    async fn before(
+        &self,
+        route: &RouteSpec,
+        prq: &mut PlasmaRequestBundle,
+    ) -> Result<(), &'static str> {
+        let Some(rate_limit) = route.rate_limit else {
+            return Ok(());
+        };
+        let Err(retry_in) =
+            prq.sstate
+                .request_limiter
+                .check(&route.name, &prq.con.client_ip, &rate_limit)
+        else {
+            return Ok(());
+        };
+        info!(target: "route", "Rate limited {} from {}", route.name, prq.con.to_string());
+        prq.sstate.metrics.record_rate_limited(&route.name);
+        if route.is_response {
+            return Err("Rate limited");
+        }
+        let err_pkt = to_error_packet(
+            &prq.packet,
+            EAError::EA_TooManyAttempts as i32,
+            Some(format!(
+                "Too many requests. Try again in {} seconds.",
+                retry_in.as_secs().max(1)
+            )),
+        );
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        Err("Rate limited")
+    }
+}
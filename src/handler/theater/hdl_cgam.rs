@@ -1,16 +1,45 @@
 use indexmap::IndexMap;
 use sea_orm::entity::*;
-use sea_orm::query::*;
 
 use crate::client_connection::ClientConnectionDescriptor;
-use crate::handler::{submit_packet, to_error_packet};
-use crate::orm::model::{game, persona, session};
+use crate::handler::{decode_request, submit_packet, to_error_packet};
+use crate::orm::model::{game, persona};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::utils::data_validation::game_name::game_name_validate;
 use crate::handler::theater::TheaterHandler;
+use crate::utils::lobbies::{get_available_lobbies, get_available_lobby, get_session_platform};
 
+plasma_struct! {
+    struct CgamRequest {
+        tid: String = "TID",
+        // "-1" lets the server pick the lobby
+        lid: Option<i32> = "LID",
+        reserve_host: bool = "RESERVE-HOST",
+        name: String = "NAME",
+        port: i32 = "PORT",
+        host_type: String = "HTTYPE",
+        game_type: String = "TYPE",
+        queue_length: usize = "QLEN",
+        disable_auto_dequeue: bool = "DISABLE-AUTO-DEQUEUE",
+        hxfr: String = "HXFR",
+        internal_port: i32 = "INT-PORT",
+        internal_ip: String = "INT-IP",
+        max_players: usize = "MAX-PLAYERS",
+        max_observers: usize = "B-maxObservers",
+        friends_only: Option<bool> = "B-U-FriendsOnly",
+        pc_dedicated: Option<bool> = "B-U-PCDedicated",
+        dlc: Option<String> = "B-U-DLC",
+        play_mode: String = "B-U-PlayMode",
+        ranked: Option<bool> = "B-U-Ranked",
+        client_version: String = "B-U-Version",
+        server_version: String = "B-version",
+        join_mode: String = "JOIN",
+        rt: String = "RT",
+    }
+}
 
 pub async fn handle_rq_cgam(
     fh: &TheaterHandler,
@@ -50,59 +79,32 @@ pub async fn handle_rq_cgam(
             */
 
     // Extract Game Data
-    let tid = prq.packet.data.get("TID").unwrap();
-    let lid: usize = 1; // prq.packet.data.get("LID").unwrap();
-    let reserve_host: bool = prq.packet.data.get("RESERVE-HOST").unwrap() == "1";
-    let name: &str = prq.packet.data.get("NAME").unwrap();
-    let port: i32 = prq.packet.data.get("PORT").unwrap().parse().unwrap();
-    let httype = prq.packet.data.get("HTTYPE").unwrap();
-    let game_type = prq.packet.data.get("TYPE").unwrap();
-    let queue_len: usize = prq.packet.data.get("QLEN").unwrap().parse().unwrap();
-    let disable_auto_dequeue: bool = prq.packet.data.get("DISABLE-AUTO-DEQUEUE").unwrap() == "1";
-    let hxfr: &str = prq.packet.data.get("HXFR").unwrap();
-    let int_port: i32 = prq.packet.data.get("INT-PORT").unwrap().parse().unwrap();
-    let int_ip = prq.packet.data.get("INT-IP").unwrap();
-    let max_players: usize = prq.packet.data.get("MAX-PLAYERS").unwrap().parse().unwrap();
-    let b_max_observers: usize = prq
-        .packet
-        .data
-        .get("B-maxObservers")
-        .unwrap()
-        .parse()
-        .unwrap();
-    let b_num_observers: usize = prq
-        .packet
-        .data
-        .get("B-numObservers")
-        .unwrap()
-        .parse()
-        .unwrap();
+    let request: CgamRequest = decode_request(&prq).await?;
+    let tid = &request.tid;
+    let requested_lid = request.lid.unwrap_or(-1);
+    let reserve_host = request.reserve_host;
+    let name: &str = &request.name;
+    let port = request.port;
+    let httype = &request.host_type;
+    let game_type = &request.game_type;
+    let queue_len = request.queue_length;
+    let disable_auto_dequeue = request.disable_auto_dequeue;
+    let hxfr: &str = &request.hxfr;
+    let int_port = request.internal_port;
+    let int_ip = &request.internal_ip;
+    let max_players = request.max_players;
+    let b_max_observers = request.max_observers;
     let ugid = "NOGUID"; //packet.data.get("UGID").unwrap();
     let secret = "NOSECRET"; // prq.packet.data.get("SECRET").unwrap();
-    let b_u_friends_only: bool = prq
-        .packet
-        .data
-        .get("B-U-FriendsOnly")
-        .unwrap_or(&String::from("0"))
-        == "1";
-    let b_u_pcdedicated: bool = prq
-        .packet
-        .data
-        .get("B-U-PCDedicated")
-        .unwrap_or(&String::from("0"))
-        == "1";
-    let b_u_dlc: String = prq.packet.data.get("B-U-DLC").cloned().unwrap_or_else(|| "".to_string());
-    let b_u_play_mode: &str = prq.packet.data.get("B-U-PlayMode").unwrap();
-    let b_u_ranked: bool = prq
-        .packet
-        .data
-        .get("B-U-Ranked")
-        .unwrap_or(&String::from("0"))
-        == "1";
-    let client_version: &str = prq.packet.data.get("B-U-Version").unwrap();
-    let server_version: &str = prq.packet.data.get("B-version").unwrap();
-    let join_mode = prq.packet.data.get("JOIN").unwrap();
-    let rt = prq.packet.data.get("RT").unwrap();
+    let b_u_friends_only = request.friends_only.unwrap_or(false);
+    let b_u_pcdedicated = request.pc_dedicated.unwrap_or(false);
+    let b_u_dlc = request.dlc.clone().unwrap_or_default();
+    let b_u_play_mode: &str = &request.play_mode;
+    let b_u_ranked = request.ranked.unwrap_or(false);
+    let client_version: &str = &request.client_version;
+    let server_version: &str = &request.server_version;
+    let join_mode = &request.join_mode;
+    let rt = &request.rt;
 
     const EKEY: &str = "NOENCYRPTIONKEY";
     //const SECRET: &str = "NOSECRET";
@@ -110,10 +112,10 @@ pub async fn handle_rq_cgam(
 
     // Do not use the name from the packet, but the persona name from the session
     // (dedicated servers do not set the name to the persona name)
-    let Ok(Some(db_session)) = session::Entity::find()
-        .filter(session::Column::TheaterTcpHandle.eq(prq.con.to_string()))
-        .one(&*prq.sstate.database)
-        .await
+    let Some(db_session) = prq
+        .sstate
+        .registry
+        .get_session_by_theater_tcp_handle(&prq.con.to_string())
     else {
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
@@ -131,6 +133,30 @@ pub async fn handle_rq_cgam(
         return Err("Persona not found");
     };
 
+    // Validate the lobby
+    let platform = get_session_platform(&db_session, &prq.sstate);
+    let db_lobby = if requested_lid == -1 {
+        get_available_lobbies(platform.as_deref(), &prq.sstate.database)
+            .await
+            .into_iter()
+            .next()
+    } else {
+        get_available_lobby(requested_lid, platform.as_deref(), &prq.sstate.database).await
+    };
+    let Some(db_lobby) = db_lobby else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NotFound as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Lobby not found");
+    };
+    let lid = db_lobby.id;
+
+    let n_lobby_games = prq.sstate.registry.get_games_by_lobby(lid).len();
+    if n_lobby_games >= db_lobby.max_games.max(0) as usize {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Lobby is full");
+    }
+
     // Validate game name
     if let Err(game_validation_error) = game_name_validate(&name.to_string()) {
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
@@ -139,16 +165,13 @@ pub async fn handle_rq_cgam(
     }
 
     // Check if games with the same name exist to avoid duplicate game names...
-    let Ok(n_same_gamename) = game::Entity::find()
-        .filter(game::Column::Name.eq(name.to_string()))
-        .count(&*prq.sstate.database)
-        .await
-    else {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("Failed to get number of games");
-    };
-    if n_same_gamename > 0 {
+    let name_taken = prq
+        .sstate
+        .registry
+        .get_games()
+        .iter()
+        .any(|db_game| db_game.name == name);
+    if name_taken {
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
         return Err("Game with same name already exists");
@@ -164,9 +187,11 @@ pub async fn handle_rq_cgam(
         let udp_handle = ClientConnectionDescriptor::from_string(&db_session.theater_udp_handle);
         if udp_handle.client_port as i32 == port && udp_handle.client_port as i32 == int_port {
             // We have a simple NAT :)
-            let mut active_session = db_session.clone().into_active_model();
-            active_session.nat_type = Set(2);
-            let _ = active_session.update(&*prq.sstate.database).await;
+            prq.sstate.registry.modify_session(db_session.id, |session| {
+                if session.nat_type == 3 {
+                    session.nat_type = 2;
+                }
+            });
         } else {
             // We have a strict NAT :(
             // thus, we don't need to update the DB...
@@ -174,44 +199,40 @@ pub async fn handle_rq_cgam(
     }
 
     // Create a new game entry
-    let db_new_game = game::ActiveModel {
-        lobby_id: Set(lid as i32),
-        reserve_host: Set(reserve_host),
-        name: Set(name.to_string()), // Persona Name (or Game Name if dedicated)
-        persona_id: Set(persona_id), // Persona ID
-        port: Set(port),
-        host_type: Set(httype.to_string()),
-        game_type: Set(game_type.to_string()),
-        queue_length: Set(queue_len as i32),
-        disable_autodequeue: Set(disable_auto_dequeue),
-        hxfr: Set(hxfr.to_string()),
-        internal_port: Set(int_port),
-        internal_ip: Set(int_ip.to_string()),
-        max_players: Set(max_players as i32),
-        max_observers: Set(b_max_observers as i32),
-        user_group_id: Set(ugid.to_string()),
-        secret: Set(secret.to_string()),
-        user_friends_only: Set(b_u_friends_only),
-        user_pcdedicated: Set(b_u_pcdedicated),
-        user_dlc: Set(b_u_dlc.to_string()),
-        user_playmode: Set(b_u_play_mode.to_string()),
-        user_ranked: Set(b_u_ranked),
-        user_levelkey: Set("".to_string()),
-        user_levelname: Set("".to_string()),
-        user_mode: Set("".to_string()),
-        client_version: Set(client_version.to_string()),
-        server_version: Set(server_version.to_string()),
-        join_mode: Set(join_mode.to_string()),
-        rt: Set(rt.to_string()),
-        encryption_key: Set(EKEY.to_string()),
-        other_as_json: Set(serde_json::json!([]).to_string()),
-        ..Default::default()
-    };
-    let Ok(db_new_game) = db_new_game.insert(&*prq.sstate.database).await else {
-        return Err("Failed to insert new game");
-    };
+    let db_new_game = prq.sstate.registry.insert_game(game::Model {
+        id: 0,
+        lobby_id: lid,
+        reserve_host,
+        name: name.to_string(), // Persona Name (or Game Name if dedicated)
+        persona_id, // Persona ID
+        port,
+        host_type: httype.to_string(),
+        game_type: game_type.to_string(),
+        queue_length: queue_len as i32,
+        disable_autodequeue: disable_auto_dequeue,
+        hxfr: hxfr.to_string(),
+        internal_port: int_port,
+        internal_ip: int_ip.to_string(),
+        max_players: max_players as i32,
+        max_observers: b_max_observers as i32,
+        user_group_id: ugid.to_string(),
+        secret: secret.to_string(),
+        user_friends_only: b_u_friends_only,
+        user_pcdedicated: b_u_pcdedicated,
+        user_dlc: b_u_dlc.to_string(),
+        user_playmode: b_u_play_mode.to_string(),
+        user_ranked: b_u_ranked,
+        user_levelkey: "".to_string(),
+        user_levelname: "".to_string(),
+        user_mode: "".to_string(),
+        client_version: client_version.to_string(),
+        server_version: server_version.to_string(),
+        join_mode: join_mode.to_string(),
+        rt: rt.to_string(),
+        encryption_key: EKEY.to_string(),
+        other_as_json: serde_json::json!([]).to_string(),
+    });
 
-    let db_new_game: game::Model = db_new_game.into();
     let game_id = db_new_game.id;
 
     let mut response_hm = IndexMap::new();
@@ -1,24 +1,35 @@
 use chrono::Utc;
 use indexmap::IndexMap;
 
-use crate::handler::submit_packet;
+use crate::handler::{decode_request, reject_if_banned, submit_packet};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_handle::PlasmaRequestBundle;
+use crate::utils::bans::BanSubject;
 use crate::handler::theater::TheaterHandler;
 
+plasma_struct! {
+    struct ConnRequest {
+        tid: String = "TID",
+        protocol: String = "PROT",
+    }
+}
 
 pub async fn handle_rq_conn(
     fh: &TheaterHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
     //{"PROT": "2", "PROD": "lotr-pandemic-pc", "VERS": "1.0", "PLAT": "PC", "LOCALE": "de", "SDKVERSION": "4.3.6.0.0", "TID": "1"} }
-    let tid = prq.packet.data.get("TID").unwrap();
-    let prot = prq.packet.data.get("PROT").unwrap();
-    let prod = prq.packet.data.get("PROD").unwrap();
-    let vers = prq.packet.data.get("VERS").unwrap();
-    let plat = prq.packet.data.get("PLAT").unwrap();
-    let locale = prq.packet.data.get("LOCALE").unwrap();
-    let sdkversion = prq.packet.data.get("SDKVERSION").unwrap();
+    let request: ConnRequest = decode_request(&prq).await?;
+    let tid = request.tid;
+    let prot = request.protocol;
+
+    // Banned IP addresses are rejected before any login
+    let ban_subject = BanSubject {
+        ip: Some(prq.con.client_ip.clone()),
+        ..Default::default()
+    };
+    reject_if_banned(&prq, &ban_subject).await?;
 
     const ACTIVITY_TIMEOUT_SECS: u32 = 0;
 
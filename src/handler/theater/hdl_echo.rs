@@ -1,15 +1,21 @@
 use indexmap::IndexMap;
-use sea_orm::entity::*;
-use sea_orm::query::*;
 use tracing::info;
 
 use crate::client_connection::{ClientConnectionDescriptor, ProtoType};
-use crate::handler::submit_packet;
-use crate::orm::model::{game, session};
+use crate::handler::{decode_response, submit_packet};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::handler::theater::TheaterHandler;
 
+plasma_struct! {
+    struct EchoResponse {
+        user_id: i64 = "UID",
+        // Should be const value "1"
+        echo_type: String = "TYPE",
+        tid: String = "TID",
+    }
+}
 
 pub async fn handle_rsp_echo(
     fh: &TheaterHandler,
@@ -23,20 +29,18 @@ pub async fn handle_rsp_echo(
     }
 
     // Get user id (=account id) from the packet
-    let uid = prq.packet.data.get("UID").unwrap();
-    let echo_type = prq.packet.data.get("TYPE").unwrap(); // Should be const value "1"
-    let tid = prq.packet.data.get("TID").unwrap();
-
-    // Parse UID
-    let Ok(uid_int) = uid.parse::<i64>() else {
-        return Err("UID not parsable");
-    };
+    // Malformed datagrams are dropped without a reply
+    let echo: EchoResponse = decode_response(&prq)?;
+    let uid_int = echo.user_id;
+    let echo_type = echo.echo_type;
 
     // Find related session
-    let Ok(Some(db_session)) = session::Entity::find()
-        .filter(session::Column::UserId.eq(uid_int))
-        .one(&*prq.sstate.database)
-        .await
+    let Some(db_session) = prq
+        .sstate
+        .registry
+        .get_sessions_by_user(uid_int)
+        .into_iter()
+        .next()
     else {
         return Err("Session not found");
     };
@@ -45,36 +49,28 @@ pub async fn handle_rsp_echo(
     // We know the following "types":
     // NAT_UNKNOWN: 0, NAT_OPEN: 1, NAT_SIMPLE: 2, NAT_STRICT: 3
     let current_nat_type = db_session.nat_type;
-    let udp_port_changed: bool;
+    let mut udp_port_changed = false;
 
     // Check if the session has a udp handle set and if it matches the current connection
-    if db_session.theater_udp_handle == "" {
-        // We don't have any information on the udp handle.
-        // -> This is the first time we set the udp handle!
-        let mut db_session: session::ActiveModel = db_session.clone().into_active_model();
-        db_session.theater_udp_handle = Set(prq.con.to_string());
-        let Ok(_) = db_session.update(&*prq.sstate.database).await else {
-            return Err("Failed to update session");
-        };
-        udp_port_changed = false;
-    } else if db_session.theater_udp_handle != prq.con.to_string() {
-        let old_handle = db_session.theater_udp_handle.clone();
-        // The UDP handle does not match the current connection
+    if db_session.theater_udp_handle != prq.con.to_string() {
+        // We don't have any information on the udp handle (first time we set it),
+        // or the UDP handle does not match the current connection
         // -> Update the UDP handle
-        let mut db_session: session::ActiveModel = db_session.clone().into_active_model();
-        db_session.theater_udp_handle = Set(prq.con.to_string());
-        let Ok(_) = db_session.update(&*prq.sstate.database).await else {
+        let mut old_handle = String::new();
+        let modified = prq.sstate.registry.modify_session(db_session.id, |session| {
+            old_handle = std::mem::replace(&mut session.theater_udp_handle, prq.con.to_string());
+        });
+        if modified.is_none() {
             return Err("Failed to update session");
-        };
-        udp_port_changed = true;
-        info!(
-            target: "nat",
-            "UDP handle mismatch: Old handle: {}, New handle: {}",
-            old_handle, prq.con.to_string()
-        );
-    } else {
-        // The UDP handle matches the current connection
-        udp_port_changed = false;
+        }
+        if !old_handle.is_empty() && old_handle != prq.con.to_string() {
+            udp_port_changed = true;
+            info!(
+                target: "nat",
+                "UDP handle mismatch: Old handle: {}, New handle: {}",
+                old_handle, prq.con.to_string()
+            );
+        }
     }
 
     // Get external UDP information
@@ -83,10 +79,7 @@ pub async fn handle_rsp_echo(
 
     // Prepare the response
     let mut response_hm = IndexMap::new();
-    response_hm.insert(
-        "TID".to_string(),
-        prq.packet.data.get("TID").unwrap().to_string(),
-    );
+    response_hm.insert("TID".to_string(), echo.tid);
     response_hm.insert("IP".to_string(), external_ip.to_string());
     response_hm.insert("PORT".to_string(), external_port.to_string());
     response_hm.insert("ERR".to_string(), "0".to_string());
@@ -101,10 +94,12 @@ pub async fn handle_rsp_echo(
             // The NAT type is not NAT_OPEN -> We need to further differentiate the NAT type
             let persona_id = db_session.persona_id;
             // Get the game from the database
-            let Ok(Some(db_game)) = game::Entity::find()
-                .filter(game::Column::PersonaId.eq(persona_id))
-                .one(&*prq.sstate.database)
-                .await
+            let Some(db_game) = prq
+                .sstate
+                .registry
+                .get_games_by_persona(persona_id)
+                .into_iter()
+                .next()
             else {
                 return Err("Game not found");
             };
@@ -114,11 +109,14 @@ pub async fn handle_rsp_echo(
             {
                 // The external port has not changed and is identical to the advertised game port
                 // -> We assume NAT_SIMPLE
-                let mut db_session: session::ActiveModel = db_session.clone().into_active_model();
-                db_session.nat_type = Set(2);
-                let Ok(_) = db_session.update(&*prq.sstate.database).await else {
+                let modified = prq.sstate.registry.modify_session(db_session.id, |session| {
+                    if session.nat_type != 1 {
+                        session.nat_type = 2;
+                    }
+                });
+                if modified.is_none() {
                     return Err("Failed to update session");
-                };
+                }
             } else {
                 // The external port has changed or is not identical to the advertised game port
                 // -> We keep assuming NAT_STRICT
@@ -177,11 +175,14 @@ pub async fn handle_rsp_echo(
             }
 
             // Set the NAT type to NAT_OPEN
-            let mut db_session: session::ActiveModel = db_session.into_active_model();
-            db_session.nat_type = Set(1);
-            let Ok(_) = db_session.update(&*prq.sstate.database).await else {
+            let modified = prq.sstate.registry.modify_session(db_session.id, |session| {
+                if session.nat_type == 0 {
+                    session.nat_type = 1;
+                }
+            });
+            if modified.is_none() {
                 return Err("Failed to update session");
-            };
+            }
 
             // Send the packet via STUNRelay or local port
             let response_packet: DataPacket = DataPacket {
@@ -199,11 +200,14 @@ pub async fn handle_rsp_echo(
             // Hence, we will assume NAT_STRICT here.
 
             // Update the session with the new NAT type
-            let mut db_session: session::ActiveModel = db_session.clone().into_active_model();
-            db_session.nat_type = Set(3);
-            let Ok(_) = db_session.update(&*prq.sstate.database).await else {
+            let modified = prq.sstate.registry.modify_session(db_session.id, |session| {
+                if session.nat_type == 1 {
+                    session.nat_type = 3;
+                }
+            });
+            if modified.is_none() {
                 return Err("Failed to update session");
-            };
+            }
 
             // Send the packet via various methods
             let response_packet: DataPacket = DataPacket {
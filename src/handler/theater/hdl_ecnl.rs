@@ -1,45 +1,42 @@
 use indexmap::IndexMap;
-use sea_orm::entity::*;
-use sea_orm::query::*;
 
-use crate::handler::{submit_packet, to_error_packet};
-use crate::orm::model::{game, participant, session};
+use crate::handler::{decode_request, submit_packet, to_error_packet};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
-use crate::handler::theater::TheaterHandler;
+use crate::handler::theater::{advance_queue, TheaterHandler};
 
+plasma_struct! {
+    struct EcnlRequest {
+        tid: String = "TID",
+        lid: String = "LID",
+        game_id: i64 = "GID",
+    }
+}
 
 pub async fn handle_rq_ecnl(
     fh: &TheaterHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
     // Cancel Game Entry (notified by the client)
-    let tid = prq.packet.data.get("TID").unwrap();
-    let lid = prq.packet.data.get("LID").unwrap();
-    let gid = prq.packet.data.get("GID").unwrap();
+    let request: EcnlRequest = decode_request(&prq).await?;
+    let tid = request.tid;
+    let lid = request.lid;
+    let gid_int = request.game_id;
 
-    // Parse GID first
-    let Ok(gid_int) = gid.parse::<i64>() else {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("Game ID not parsable");
-    };
     // Get Game from the database
-    let Ok(Some(db_game)) = game::Entity::find_by_id(gid_int)
-        .one(&*prq.sstate.database)
-        .await
-    else {
+    let Some(db_game) = prq.sstate.registry.get_game(gid_int) else {
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
         return Err("Game not found");
     };
 
     // Get the session via the con descr
-    let Ok(Some(db_session)) = session::Entity::find()
-        .filter(session::Column::TheaterTcpHandle.eq(prq.con.to_string()))
-        .one(&*prq.sstate.database)
-        .await
+    let Some(db_session) = prq
+        .sstate
+        .registry
+        .get_session_by_theater_tcp_handle(&prq.con.to_string())
     else {
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_AuthFail as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
@@ -50,14 +47,16 @@ pub async fn handle_rq_ecnl(
 
     let client_pid = db_session.persona_id;
     // Remove the participant entry
-    let _ = participant::Entity::delete_many()
-        .filter(
-            Condition::all()
-                .add(participant::Column::GameId.eq(gid_int))
-                .add(participant::Column::PersonaId.eq(client_pid)),
-        )
-        .exec(&*prq.sstate.database)
-        .await;
+    if let Some(db_participant) = prq
+        .sstate
+        .registry
+        .get_participant_by_game_and_persona(gid_int, client_pid)
+    {
+        prq.sstate.registry.remove_participant(db_participant.id);
+    }
+
+    // Move up the remaining queue
+    advance_queue(gid_int, &prq.sstate).await;
 
     /*
     // If no one is there anymore, delete the game entry (we don't have dedicated servers anymore anyhow...)
@@ -75,7 +74,7 @@ pub async fn handle_rq_ecnl(
     let mut response_hm: IndexMap<_, _, _> = IndexMap::new();
     response_hm.insert("TID".to_string(), tid.to_string());
     response_hm.insert("LID".to_string(), lid.to_string());
-    response_hm.insert("GID".to_string(), gid.to_string());
+    response_hm.insert("GID".to_string(), gid_int.to_string());
 
     let response_packet = DataPacket {
         packet_mode: PacketMode::FeslPingOrTheaterResponse,
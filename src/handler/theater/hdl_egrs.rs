@@ -1,15 +1,24 @@
 use indexmap::IndexMap;
 use sea_orm::entity::*;
-use sea_orm::query::*;
 
 use crate::client_connection::ClientConnectionDescriptor;
-use crate::handler::{submit_packet, to_error_packet};
-use crate::orm::model::{account, game, participant, persona, session};
+use crate::handler::{decode_request, submit_packet, to_error_packet};
+use crate::orm::model::{account, persona};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
-use crate::handler::theater::TheaterHandler;
-
+use crate::handler::theater::{advance_queue, TheaterHandler};
+
+plasma_struct! {
+    struct EgrsRequest {
+        lid: String = "LID",
+        tid: String = "TID",
+        game_id: i64 = "GID",
+        persona_id: i64 = "PID",
+        allowed: Option<bool> = "ALLOWED",
+    }
+}
 
 pub async fn handle_rq_egrs(
     fh: &TheaterHandler,
@@ -19,11 +28,12 @@ pub async fn handle_rq_egrs(
 
     // Enter Game Response
     // {"LID": "1", "GID": "10", "ALLOWED": "1", "PID": "1", "TID": "6"} }
-    let lid = prq.packet.data.get("LID").unwrap();
-    let tid = prq.packet.data.get("TID").unwrap();
-    let gid = prq.packet.data.get("GID").unwrap();
-    let pid = prq.packet.data.get("PID").unwrap();
-    let allowed = prq.packet.data.get("ALLOWED").unwrap_or(&"1".to_string()) == "1";
+    let request: EgrsRequest = decode_request(&prq).await?;
+    let lid = request.lid;
+    let tid = request.tid;
+    let gid_int = request.game_id;
+    let client_persona_id = request.persona_id;
+    let allowed = request.allowed.unwrap_or(true);
 
     let mut response_hm = IndexMap::new();
     response_hm.insert("TID".to_string(), tid.to_string());
@@ -37,28 +47,19 @@ pub async fn handle_rq_egrs(
     submit_packet(response_packet, &prq.con, &prq.sstate, 0).await;
 
     // Now, send the EGEG packet to the actual client of the corresponding PID
-    // ToDo: Handle queue stuff
 
     // Get the game from the database
-    let Ok(gid_int) = gid.parse::<i64>() else {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("Game ID not parsable");
-    };
-    let Ok(Some(db_game)) = game::Entity::find_by_id(gid_int)
-        .one(&*prq.sstate.database)
-        .await
-    else {
+    let Some(db_game) = prq.sstate.registry.get_game(gid_int) else {
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
         return Err("Game not found");
     };
 
     // Get the host session from the database
-    let Ok(Some(db_host_session)) = session::Entity::find()
-        .filter(session::Column::TheaterTcpHandle.eq(prq.con.to_string()))
-        .one(&*prq.sstate.database)
-        .await
+    let Some(db_host_session) = prq
+        .sstate
+        .registry
+        .get_session_by_theater_tcp_handle(&prq.con.to_string())
     else {
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
@@ -95,29 +96,20 @@ pub async fn handle_rq_egrs(
         return Err("Host account not found");
     };
 
-    let Ok(client_persona_id) = pid.parse::<i64>() else {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("PID not parsable");
-    };
     // Get the client participant from the database
-    let Ok(Some(db_client_participant)) = participant::Entity::find()
-        .filter(
-            Condition::all()
-                .add(participant::Column::GameId.eq(gid_int))
-                .add(participant::Column::PersonaId.eq(client_persona_id)),
-        )
-        .one(&*prq.sstate.database)
-        .await
+    let Some(db_client_participant) = prq
+        .sstate
+        .registry
+        .get_participant_by_game_and_persona(gid_int, client_persona_id)
     else {
         return Err("Client participant entry not found");
     };
 
     // Get the session of the client
-    let Ok(Some(db_client_session)) = session::Entity::find()
-        .filter(session::Column::PersonaId.eq(db_client_participant.persona_id))
-        .one(&*prq.sstate.database)
-        .await
+    let Some(db_client_session) = prq
+        .sstate
+        .registry
+        .get_session_by_persona(db_client_participant.persona_id)
     else {
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
@@ -143,9 +135,8 @@ pub async fn handle_rq_egrs(
 
     if !allowed {
         // Delete the participation entry
-        let _ = participant::Entity::delete_by_id(db_client_participant.id)
-            .exec(&*prq.sstate.database)
-            .await;
+        prq.sstate.registry.remove_participant(db_client_participant.id);
+        advance_queue(gid_int, &prq.sstate).await;
 
         // TODO: Send a notification to the client that joining is not allowed
         return Err("Joining not allowed");
@@ -158,7 +149,7 @@ pub async fn handle_rq_egrs(
     // PID := Player "Ticket" ID??? -> Just needs to be identical to the egam PID!
 
     //let joiningplayer_id = 1501; // db_game.num_players + db_game.queue_length + 1;
-    response_hm.insert("PID".to_string(), pid.to_string());
+    response_hm.insert("PID".to_string(), client_persona_id.to_string());
 
     // Host Port (TURN-aware)
     response_hm.insert("P".to_string(), client_expected_host_port.to_string()); // Port of the host
@@ -175,7 +166,7 @@ pub async fn handle_rq_egrs(
     response_hm.insert("I".to_string(), client_expected_host_ip.to_string()); // IP-Address of the host
                                                                               //response_hm.insert("I".to_string(), host_ip.to_string()); // IP-Address of the host
     response_hm.insert("LID".to_string(), lid.to_string());
-    response_hm.insert("GID".to_string(), gid.to_string());
+    response_hm.insert("GID".to_string(), gid_int.to_string());
 
     let response_packet = DataPacket {
         packet_mode: PacketMode::FeslPingOrTheaterResponse,
@@ -0,0 +1,127 @@
+use indexmap::IndexMap;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+
+use crate::handler::{submit_packet, to_error_packet};
+use crate::orm::model::persona;
+use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_errors::EAError;
+use crate::plasma_handle::PlasmaRequestBundle;
+use crate::handler::theater::TheaterHandler;
+
+use super::utils_game_data::{
+    build_game_data, count_active_players, get_name_mod_ping_site, parse_other_fields,
+};
+
+
+pub async fn handle_rq_gdat(
+    fh: &TheaterHandler,
+    mut prq: PlasmaRequestBundle,
+) -> Result<(), &'static str> {
+    // Game Data (details of a single game)
+    // {"LID": "1", "GID": "6", "TID": "8"}
+    // {"USER": "lookingforgofp1", "TID": "8"} (game hosted by the persona)
+    let tid = prq.packet.data.get("TID").cloned().unwrap_or("0".to_string());
+
+    // Find the game by GID or by the name of the host persona
+    let db_game = if let Some(raw_gid) = prq.packet.data.get("GID") {
+        let Ok(gid_int) = raw_gid.parse::<i64>() else {
+            let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+            submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+            return Err("Game ID not parsable");
+        };
+        prq.sstate.registry.get_game(gid_int)
+    } else if let Some(host_persona_name) = prq.packet.data.get("USER") {
+        match persona::Entity::find()
+            .filter(persona::Column::Name.eq(host_persona_name))
+            .one(&*prq.sstate.database)
+            .await
+        {
+            Ok(Some(db_host_persona)) => prq
+                .sstate
+                .registry
+                .get_games_by_persona(db_host_persona.id)
+                .into_iter()
+                .next(),
+            _ => None,
+        }
+    } else {
+        None
+    };
+    let Some(db_game) = db_game else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Game not found");
+    };
+    let lid = db_game.lobby_id.to_string();
+
+    let n_cur_players = count_active_players(db_game.id, &prq.sstate.registry);
+    let name_mod_ping_site = get_name_mod_ping_site(&mut prq).await;
+    let other_fields = parse_other_fields(&db_game);
+
+    // GDAT: The game itself
+    let game_data_response = build_game_data(
+        &tid,
+        &lid,
+        &db_game,
+        n_cur_players,
+        &other_fields,
+        name_mod_ping_site.as_deref(),
+        &prq.con.client_ip,
+    );
+    let response_packet = DataPacket {
+        packet_mode: PacketMode::FeslPingOrTheaterResponse,
+        mode: DataMode::THEATER_GDAT,
+        packet_id: 0,
+        data: game_data_response,
+    };
+    submit_packet(response_packet, &prq.con, &prq.sstate, 0).await;
+
+    // GDET: Game details (user group and the game attributes)
+    let mut game_details_response = IndexMap::new();
+    game_details_response.insert("TID".to_string(), tid.to_string());
+    game_details_response.insert("LID".to_string(), lid.to_string());
+    game_details_response.insert("GID".to_string(), db_game.id.to_string());
+    game_details_response.insert("UGID".to_string(), db_game.user_group_id.to_string());
+    for (key, value) in other_fields.iter() {
+        game_details_response.insert(key.to_string(), value.to_string());
+    }
+    let details_packet = DataPacket {
+        packet_mode: PacketMode::FeslPingOrTheaterResponse,
+        mode: DataMode::THEATER_GDET,
+        packet_id: 0,
+        data: game_details_response,
+    };
+    submit_packet(details_packet, &prq.con, &prq.sstate, 0).await;
+
+    // PDAT: One packet per player in the game
+    let db_participants = prq.sstate.registry.get_participants_by_game(db_game.id);
+    for db_participant in db_participants
+        .iter()
+        .filter(|db_participant| db_participant.queue_pos == -1)
+    {
+        let Ok(Some(db_player_persona)) = persona::Entity::find_by_id(db_participant.persona_id)
+            .one(&*prq.sstate.database)
+            .await
+        else {
+            continue;
+        };
+        let mut player_data_response = IndexMap::new();
+        player_data_response.insert("TID".to_string(), tid.to_string());
+        player_data_response.insert("LID".to_string(), lid.to_string());
+        player_data_response.insert("GID".to_string(), db_game.id.to_string());
+        player_data_response.insert("PID".to_string(), db_player_persona.id.to_string());
+        player_data_response.insert("UID".to_string(), db_player_persona.user_id.to_string());
+        player_data_response.insert("NAME".to_string(), db_player_persona.name.to_string());
+
+        let player_packet = DataPacket {
+            packet_mode: PacketMode::FeslPingOrTheaterResponse,
+            mode: DataMode::THEATER_PDAT,
+            packet_id: 0,
+            data: player_data_response,
+        };
+        submit_packet(player_packet, &prq.con, &prq.sstate, 0).await;
+    }
+
+    Ok(())
+}
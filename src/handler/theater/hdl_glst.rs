@@ -1,14 +1,28 @@
 use indexmap::IndexMap;
+use std::collections::HashSet;
 use sea_orm::entity::*;
 use sea_orm::query::*;
-use tracing::{error, warn};
 
-use crate::handler::submit_packet;
-use crate::orm::model::{game, participant, session};
+use crate::handler::{decode_request, submit_packet, to_error_packet};
+use crate::orm::model::{game, persona};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
+use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::handler::theater::TheaterHandler;
-use crate::utils::config_values::get_cfg_value;
+use crate::utils::associations::{has_association, ASSO_TYPE_FRIENDS};
+use crate::utils::lobbies::{get_available_lobby, get_session_platform};
+
+use super::utils_game_data::{
+    build_game_data, count_active_players, get_name_mod_ping_site, parse_other_fields,
+};
+
+plasma_struct! {
+    struct GlstRequest {
+        tid: String = "TID",
+        lid: String = "LID",
+    }
+}
 
 
 pub async fn handle_rq_glst(
@@ -34,161 +48,130 @@ pub async fn handle_rq_glst(
         "FAV-GAME-UID": "",
         "TID": "4"
     } */
-    let tid = prq.packet.data.get("TID").cloned().unwrap();
-    let lid = prq.packet.data.get("LID").cloned().unwrap();
+    let request: GlstRequest = decode_request(&prq).await?;
+    let tid = request.tid;
+    let lid = request.lid;
+
+    let Ok(lid_int) = lid.parse::<i32>() else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NotFound as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Lobby ID not parsable");
+    };
 
-    let lid_int: i32 = lid.parse().unwrap();
-    // TODO: Implement filtering
-    const MAX_GAMES: usize = 1000;
-    // TODO: Add limit filter
-    /*let Ok(db_games_in_lobby) = game::Entity::find()
-        .filter(
-            Condition::all()
-                .add(game::Column::LobbyId.eq(lid_int))
-                .add(game::Column::UserFriendsOnly.eq(false)) // Hide 'private' games
-        )
-        .all(&*prq.sstate.database)
-        .await else {
-        return Err("Unable to query games.");
-    };*/
+    let filter = GameListFilter::from_packet(&prq.packet.data);
 
-    // Query the user preferences
-    // PING:
-    // -> Check if the user has a ping site preference
-    // -> Check if the ping site preference is set and valid
-    let mut name_mod_ping_site: Option<String> = None;
-    if let Some(db_user) = prq.get_active_user_model().await {
-        let preferred_ping_site = db_user.name_mod_ping_site.clone();
+    let name_mod_ping_site = get_name_mod_ping_site(&mut prq).await;
 
-        // Check if the ping site preference is set and valid
-        if preferred_ping_site != "" {
-            // Check if the ping site is valid
-            if let Some(ping_site) = get_cfg_value("GetPingSites_PingSites", &*prq.sstate.database).await {
-                // Parse the ping site
-                let available_ping_sites = serde_json::from_str::<Vec<IndexMap<String, String>>>(&ping_site)
-                    .unwrap_or_else(|_| {
-                        error!(target: "theater", "Failed to parse ping site preference: {}", ping_site);
-                        Vec::new()
-                    });
-                // Check if the preferred ping site is in the list of available ping sites
-                if available_ping_sites.iter().any(|site| site.get("name") == Some(&preferred_ping_site)) {
-                    // Add the ping site to the game data response
-                    name_mod_ping_site = Some(preferred_ping_site);
-                }
-            }
+    // Session of the requesting player
+    let Some(session_info) = prq
+        .sstate
+        .registry
+        .get_session_by_theater_tcp_handle(&prq.con.to_string())
+    else {
+        return Err("Session not found");
+    };
+
+    // The lobby has to exist and be open to the platform of the client
+    let platform = get_session_platform(&session_info, &prq.sstate);
+    let Some(db_lobby) = get_available_lobby(lid_int, platform.as_deref(), &prq.sstate.database).await else {
+        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NotFound as i32, None);
+        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
+        return Err("Lobby not found");
+    };
+    let max_games = db_lobby.max_games.max(0) as usize;
+
+    let db_games_in_lobby = prq.sstate.registry.get_games_by_lobby(lid_int);
+    let favorite_persona_ids = get_favorite_persona_ids(&filter, &prq).await;
+    let num_lobby_games = db_games_in_lobby.len();
+
+    // Apply the filters and build the game data
+    let mut favorite_games: Vec<IndexMap<String, String>> = Vec::new();
+    let mut other_games: Vec<IndexMap<String, String>> = Vec::new();
+    for db_game in db_games_in_lobby.iter() {
+        // Friends-only games are only listed to the host and its friends
+        if db_game.user_friends_only
+            && db_game.persona_id != session_info.persona_id
+            && (filter.not_private
+                || !has_association(
+                    db_game.persona_id,
+                    session_info.persona_id,
+                    ASSO_TYPE_FRIENDS,
+                    &prq.sstate.database,
+                )
+                .await)
+        {
+            continue;
+        }
+        if filter.not_closed && db_game.join_mode != "O" {
+            continue;
         }
-    }
 
-    let db_games_in_lobby = game::Entity::find()
-        .filter(
-            Condition::all()
-                .add(game::Column::LobbyId.eq(lid_int))
-                .add(game::Column::UserFriendsOnly.eq(false)), // Hide 'private' games
-        )
-        .all(&*prq.sstate.database)
-        .await
-        .unwrap();
+        // Determine number of current players:
+        let n_cur_players = count_active_players(db_game.id, &prq.sstate.registry);
+        if filter.not_full && n_cur_players >= db_game.max_players.max(0) as u64 {
+            continue;
+        }
+        if n_cur_players < filter.min_size {
+            continue;
+        }
 
-    // Transform into a vector
-    let mut games_in_lobby: Vec<IndexMap<String, String>> = Vec::new();
-    for db_game in db_games_in_lobby {
-        let mut game_hm = IndexMap::new();
-        game_hm.insert("id".to_string(), db_game.id.to_string());
-        game_hm.insert("lobby_id".to_string(), db_game.lobby_id.to_string());
-        game_hm.insert(
-            "reserve_host".to_string(),
-            (if db_game.reserve_host { "1" } else { "0" }).to_string(),
-        );
-        game_hm.insert("name".to_string(), db_game.name.to_string());
-        game_hm.insert("persona_id".to_string(), db_game.persona_id.to_string());
-        game_hm.insert("port".to_string(), db_game.port.to_string());
-        game_hm.insert("host_type".to_string(), db_game.host_type.to_string());
-        game_hm.insert("game_type".to_string(), db_game.game_type.to_string());
-        game_hm.insert("queue_length".to_string(), db_game.queue_length.to_string());
-        game_hm.insert(
-            "disable_autodequeue".to_string(),
-            (if db_game.disable_autodequeue {
-                "1"
-            } else {
-                "0"
-            })
-            .to_string(),
-        );
-        game_hm.insert("hxfr".to_string(), db_game.hxfr.to_string());
-        game_hm.insert(
-            "internal_port".to_string(),
-            db_game.internal_port.to_string(),
-        );
-        game_hm.insert("internal_ip".to_string(), db_game.internal_ip.to_string());
-        game_hm.insert("max_players".to_string(), db_game.max_players.to_string());
-        game_hm.insert(
-            "max_observers".to_string(),
-            db_game.max_observers.to_string(),
-        );
-        // The game does not support observers.
-        game_hm.insert("num_observers".to_string(), 0.to_string());
-        game_hm.insert(
-            "user_group_id".to_string(),
-            db_game.user_group_id.to_string(),
-        );
-        game_hm.insert("secret".to_string(), db_game.secret.to_string());
-        game_hm.insert(
-            "user_friends_only".to_string(),
-            (if db_game.user_friends_only { "1" } else { "0" }).to_string(),
+        let other_fields = parse_other_fields(db_game);
+        let mut game_data_response = build_game_data(
+            &tid,
+            &lid,
+            db_game,
+            n_cur_players,
+            &other_fields,
+            name_mod_ping_site.as_deref(),
+            &prq.con.client_ip,
         );
-        game_hm.insert(
-            "user_pcdedicated".to_string(),
-            (if db_game.user_pcdedicated { "1" } else { "0" }).to_string(),
-        );
-        game_hm.insert(
-            "user_dlc".to_string(),
-            db_game.user_dlc.to_string(),
-        );
-        game_hm.insert(
-            "user_playmode".to_string(),
-            db_game.user_playmode.to_string(),
-        );
-        game_hm.insert(
-            "user_ranked".to_string(),
-            (if db_game.user_ranked { "1" } else { "0" }).to_string(),
-        );
-        game_hm.insert(
-            "user_levelkey".to_string(),
-            db_game.user_levelkey.to_string(),
-        );
-        game_hm.insert(
-            "user_levelname".to_string(),
-            db_game.user_levelname.to_string(),
-        );
-        game_hm.insert("user_mode".to_string(), db_game.user_mode.to_string());
-        game_hm.insert(
-            "client_version".to_string(),
-            db_game.client_version.to_string(),
-        );
-        game_hm.insert(
-            "server_version".to_string(),
-            db_game.server_version.to_string(),
-        );
-        game_hm.insert("join_mode".to_string(), db_game.join_mode.to_string());
-        game_hm.insert("rt".to_string(), db_game.rt.to_string());
-        game_hm.insert(
-            "encryption_key".to_string(),
-            db_game.encryption_key.to_string(),
+        if !filter.matches_attributes(db_game, &game_data_response) {
+            continue;
+        }
+
+        // Favorites
+        let is_favorite_game = filter.is_favorite_game(db_game.id);
+        let n_favorite_players = count_favorite_players(&favorite_persona_ids, db_game, &prq);
+        if filter.favorites_only && !is_favorite_game && n_favorite_players == 0 {
+            continue;
+        }
+        game_data_response.insert(
+            "F".to_string(),
+            (if is_favorite_game { "1" } else { "0" }).to_string(),
         );
-        game_hm.insert("other".to_string(), db_game.other_as_json.to_string());
+        game_data_response.insert("NF".to_string(), n_favorite_players.to_string());
 
-        games_in_lobby.push(game_hm);
+        if is_favorite_game || n_favorite_players > 0 {
+            favorite_games.push(game_data_response);
+        } else {
+            other_games.push(game_data_response);
+        }
     }
 
-    let num_games = games_in_lobby.len() as usize;
+    // Favorites first, then limit to COUNT (-1 means no limit)
+    let num_favorite_games = favorite_games.len();
+    let num_favorite_players_games = favorite_games
+        .iter()
+        .filter(|game_data| game_data.get("NF").map(|nf| nf != "0").unwrap_or(false))
+        .count();
+    let max_count = match filter.count {
+        Some(count) => count.min(max_games),
+        None => max_games,
+    };
+    let games: Vec<IndexMap<String, String>> = favorite_games
+        .into_iter()
+        .chain(other_games)
+        .take(max_count)
+        .collect();
+    let num_games = games.len();
 
     let mut response_hm = IndexMap::new();
     response_hm.insert("TID".to_string(), tid.to_string());
     response_hm.insert("LID".to_string(),  lid_int.to_string());
-    response_hm.insert("LOBBY-NUM-GAMES".to_string(), num_games.to_string());
-    response_hm.insert("LOBBY-MAX-GAMES".to_string(), MAX_GAMES.to_string());
-    response_hm.insert("FAVORITE-GAMES".to_string(), "0".to_string());
-    response_hm.insert("FAVORITE-PLAYERS".to_string(), "0".to_string());
+    response_hm.insert("LOBBY-NUM-GAMES".to_string(), num_lobby_games.to_string());
+    response_hm.insert("LOBBY-MAX-GAMES".to_string(), max_games.to_string());
+    response_hm.insert("FAVORITE-GAMES".to_string(), num_favorite_games.to_string());
+    response_hm.insert("FAVORITE-PLAYERS".to_string(), num_favorite_players_games.to_string());
     response_hm.insert("NUM-GAMES".to_string(), num_games.to_string());
 
     let response_packet = DataPacket {
@@ -200,184 +183,146 @@ pub async fn handle_rq_glst(
     submit_packet(response_packet, &prq.con, &prq.sstate, 0).await;
 
     // Send individual game infos
-    let Ok(Some(session_info)) = session::Entity::find()
-        .filter(session::Column::TheaterTcpHandle.eq(prq.con.to_string()))
-        .one(&*prq.sstate.database)
-        .await
-    else {
-        return Err("Session not found");
-    };
-
-    let mut entries: Vec<IndexMap<String, String>> = Vec::new();
-
-    for (i_game, game) in games_in_lobby.iter().enumerate() {
-        // Determine number of current players:
-        let Ok(n_cur_players) = participant::Entity::find()
-            .filter(
-                Condition::all()
-                    .add(
-                        participant::Column::GameId.eq(game
-                            .get("id")
-                            .unwrap()
-                            .parse::<i64>()
-                            .unwrap()),
-                    )
-                    .add(participant::Column::QueuePos.eq(-1 as i32)),
-            )
-            .count(&*prq.sstate.database)
-            .await
-        else {
-            return Err("Failed to get number of players");
+    for game_data_response in games {
+        let response_packet = DataPacket {
+            packet_mode: PacketMode::FeslPingOrTheaterResponse,
+            mode: DataMode::THEATER_GDAT,
+            packet_id: 0,
+            data: game_data_response,
         };
+        submit_packet(response_packet, &prq.con, &prq.sstate, 0).await;
+    }
 
-        // Parse the remaining, JSON-encoded fields first
-        let mut other_fields: IndexMap<String, String> = IndexMap::new();
-        if game.get("other").unwrap() != "" {
-            let others = game.get("other").unwrap().as_str();
-            if let Ok(other_items) = serde_json::from_str::<IndexMap<String,String>>(others) {
-                for (key, value) in other_items.iter() {
-                    other_fields.insert(key.to_string(), value.to_string());
-                }
-            } else {
-                warn!(target: "theater", "Failed to parse other field: {}", game.get("other").unwrap());
-            }
-        }
-
-        let mut game_data_response = IndexMap::new();
-        game_data_response.insert("TID".to_string(), tid.to_string());
-        game_data_response.insert("LID".to_string(), lid.to_string());
-        game_data_response.insert("GID".to_string(), game.get("id").unwrap().to_string());
+    Ok(())
+}
 
-        // Host Name (normally == Persona Name)
-        game_data_response.insert("HN".to_string(), game.get("name").unwrap().to_string());
-        // Host ID (Persona ID)
-        game_data_response.insert("HU".to_string(), game.get("persona_id").unwrap().to_string());
+// Filters of the GLST request. Flag filters set to "0" are ignored.
+struct GameListFilter {
+    not_full: bool,
+    not_private: bool,
+    not_closed: bool,
+    min_size: u64,
+    favorites_only: bool,
+    // "FILTER-ATTR-U-<name>" -> value
+    attributes: IndexMap<String, String>,
+    count: Option<usize>,
+    fav_game_ids: Vec<i64>,
+    fav_player_names: Vec<String>,
+    // FAV-PLAYER-UID holds user ids, not persona ids
+    fav_player_user_ids: Vec<i64>,
+}
 
-        let mut game_name = game.get("name").unwrap().to_string();
-        // May add the ping time to the game name?
-        if let Some(ref name_ping_site) = name_mod_ping_site {
-            // Get the ping of the server to this ping site
-            let ping_site_key = format!("B-U-{}", &name_ping_site);
-            let ping_time = other_fields.get(&ping_site_key).cloned();
-            if let Some(ping_time) = ping_time {
-                // Try to parse the ping time
-                if let Ok(ping_time) = ping_time.parse::<i32>() {
-                    // Add the ping time to the game name
-                    game_name = format!("[{}ms] {}", ping_time, game_name);
+impl GameListFilter {
+    fn from_packet(data: &IndexMap<String, String>) -> Self {
+        let flag = |key: &str| data.get(key).map(|value| value == "1").unwrap_or(false);
+        // Favorites are separated by ';' (or ',')
+        let list = |key: &str| -> Vec<String> {
+            data.get(key)
+                .map(|value| {
+                    value
+                        .split([';', ','])
+                        .map(|item| item.trim().to_string())
+                        .filter(|item| !item.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let id_list = |key: &str| -> Vec<i64> {
+            list(key)
+                .iter()
+                .filter_map(|item| item.parse::<i64>().ok())
+                .collect()
+        };
 
-                    // Truncate the game name to 31-3 characters and end with "..."
-                    if game_name.len() > 31 {
-                        game_name = format!("{}...", &game_name[0..31-3]);
-                    }
-                };
+        let mut attributes = IndexMap::new();
+        for (key, value) in data.iter() {
+            if let Some(attr_name) = key.strip_prefix("FILTER-ATTR-U-")
+                && !value.is_empty()
+                && value != "0"
+            {
+                attributes.insert(attr_name.to_string(), value.to_string());
             }
         }
-        // Server Name (normally == Persona Name)
-        game_data_response.insert("N".to_string(), game_name);
-
-        ////game_data_response.insert("PING".to_string(), "10".to_string());
-        //game_data_response.insert("B-U-PING".to_string(), "10".to_string());
-        //game_data_response.insert("B-U-Ping".to_string(), "10".to_string());
-        //game_data_response.insert("Ping".to_string(), "10".to_string());
-        // IP/Port of the host
-        game_data_response.insert("I".to_string(), prq.con.client_ip.to_string());
-        game_data_response.insert("P".to_string(), game.get("port").unwrap().to_string());
-
-        // Version of the host
-        //game_data_response.insert("V".to_string(), "1.0".to_string());
-
-        // Platform
-        //game_data_response.insert("PL".to_string(), "pc".to_string());
-
-        // Max Players
-        game_data_response.insert(
-            "MP".to_string(),
-            game.get("max_players").unwrap().to_string(),
-        );
-        // Current Players
-        game_data_response.insert("AP".to_string(), n_cur_players.to_string());
-        // Current Queue
-        game_data_response.insert("QP".to_string(), "0".to_string());
-
-        // Is favorite server
-        game_data_response.insert("F".to_string(), "0".to_string());
-        // Number of favorite players?
-        game_data_response.insert("NF".to_string(), "0".to_string());
-        // Join mode
-        game_data_response.insert("J".to_string(), game.get("join_mode").unwrap().to_string());
-        // #Players joining
-        game_data_response.insert("JP".to_string(), "0".to_string());
-        // Game type
-        game_data_response.insert(
-            "TYPE".to_string(),
-            game.get("game_type").unwrap().to_string(),
-        );
-
-        // Server requires password?
-        game_data_response.insert("PW".to_string(), "0".to_string());
-
-        game_data_response.insert(
-            "B-version".to_string(),
-            game.get("server_version").unwrap().to_string(),
-        );
-        game_data_response.insert(
-            "B-numObservers".to_string(),
-             game.get("b_num_observers")
-                .unwrap_or(&"0".to_string())
-                .to_string(),
-        );
-        game_data_response.insert(
-            "B-maxObservers".to_string(),
-            game.get("max_observers").unwrap().to_string(),
-        );
-
-        if game.get("user_levelkey").unwrap() != "" {
-            game_data_response.insert(
-                "B-U-LevelKey".to_string(),
-                game.get("user_levelkey").unwrap().to_string(),
-            );
-        }
 
-        if game.get("user_levelname").unwrap() != "" {
-            game_data_response.insert(
-                "B-U-LevelName".to_string(),
-                game.get("user_levelname").unwrap().to_string(),
-            );
-        }
-        if game.get("user_mode").unwrap() != "" {
-            game_data_response.insert(
-                "B-U-Mode".to_string(),
-                game.get("user_mode").unwrap().to_string(),
-            );
-        }
-        if game.get("user_ranked").unwrap() == "1" {
-            game_data_response.insert("B-U-Ranked".to_string(), "1".to_string());
-        }
-
-        if game.get("user_pcdedicated").unwrap() == "1" {
-            game_data_response.insert("B-U-PCDedicated".to_string(), "1".to_string());
+        let mut fav_game_ids = id_list("FAV-GAME");
+        fav_game_ids.extend(id_list("FAV-GAME-UID"));
+
+        Self {
+            not_full: flag("FILTER-NOT-FULL"),
+            not_private: flag("FILTER-NOT-PRIVATE"),
+            not_closed: flag("FILTER-NOT-CLOSED"),
+            min_size: data
+                .get("FILTER-MIN-SIZE")
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(0),
+            favorites_only: flag("FILTER-FAV-ONLY"),
+            attributes,
+            count: data
+                .get("COUNT")
+                .and_then(|value| value.parse::<i64>().ok())
+                .filter(|count| *count >= 0)
+                .map(|count| count as usize),
+            fav_game_ids,
+            fav_player_names: list("FAV-PLAYER"),
+            fav_player_user_ids: id_list("FAV-PLAYER-UID"),
         }
+    }
 
-        if game.get("user_dlc").unwrap() != "" {
-            game_data_response.insert(
-                "B-U-DLC".to_string(),
-                game.get("user_dlc").unwrap().to_string(),
-            );
-        }
+    fn matches_attributes(&self, db_game: &game::Model, game_data: &IndexMap<String, String>) -> bool {
+        self.attributes.iter().all(|(attr_name, value)| {
+            let game_value = match attr_name.as_str() {
+                "Version" => Some(db_game.client_version.to_string()),
+                "FriendsOnly" => Some((if db_game.user_friends_only { "1" } else { "0" }).to_string()),
+                "Ranked" => Some((if db_game.user_ranked { "1" } else { "0" }).to_string()),
+                _ => game_data.get(&format!("B-U-{}", attr_name)).cloned(),
+            };
+            game_value.as_deref().unwrap_or("0") == value
+        })
+    }
 
-        // Add the remaining, JSON-encoded fields
-        for (key, value) in other_fields.iter() {
-            game_data_response.insert(key.to_string(), value.to_string());
-        }
+    fn is_favorite_game(&self, game_id: i64) -> bool {
+        self.fav_game_ids.contains(&game_id)
+    }
+}
 
-        let response_packet = DataPacket {
-            packet_mode: PacketMode::FeslPingOrTheaterResponse,
-            mode: DataMode::THEATER_GDAT,
-            packet_id: 0,
-            data: game_data_response,
-        };
-        submit_packet(response_packet, &prq.con, &prq.sstate, 0).await;
+// Personas of the favorite players, loaded once per request
+async fn get_favorite_persona_ids(
+    filter: &GameListFilter,
+    prq: &PlasmaRequestBundle,
+) -> HashSet<i64> {
+    if filter.fav_player_names.is_empty() && filter.fav_player_user_ids.is_empty() {
+        return HashSet::new();
     }
+    let Ok(db_personas) = persona::Entity::find()
+        .filter(
+            Condition::any()
+                .add(persona::Column::Name.is_in(filter.fav_player_names.clone()))
+                .add(persona::Column::UserId.is_in(filter.fav_player_user_ids.clone())),
+        )
+        .all(&*prq.sstate.database)
+        .await
+    else {
+        return HashSet::new();
+    };
+    db_personas.iter().map(|db_persona| db_persona.id).collect()
+}
 
-    Ok(())
+// Number of favorite players hosting or playing in the game
+fn count_favorite_players(
+    favorite_persona_ids: &HashSet<i64>,
+    db_game: &game::Model,
+    prq: &PlasmaRequestBundle,
+) -> usize {
+    if favorite_persona_ids.is_empty() {
+        return 0;
+    }
+    let n_participants = prq
+        .sstate
+        .registry
+        .get_participants_by_game(db_game.id)
+        .iter()
+        .filter(|db_participant| db_participant.persona_id != db_game.persona_id)
+        .filter(|db_participant| favorite_persona_ids.contains(&db_participant.persona_id))
+        .count();
+    n_participants + favorite_persona_ids.contains(&db_game.persona_id) as usize
 }
@@ -1,13 +1,17 @@
 use indexmap::IndexMap;
-use sea_orm::entity::*;
-use sea_orm::query::*;
 
-use crate::handler::submit_packet;
-use crate::orm::model::game;
+use crate::handler::{decode_request, submit_packet};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::handler::theater::TheaterHandler;
+use crate::utils::lobbies::{get_available_lobbies, get_session_platform};
 
+plasma_struct! {
+    struct LlstRequest {
+        tid: String = "TID",
+    }
+}
 
 pub async fn handle_rq_llst(
     fh: &TheaterHandler,
@@ -26,17 +30,24 @@ pub async fn handle_rq_llst(
         "FAV-GAME-UID": "",
         "TID": "3"
     }*/
-    let tid = prq.packet.data.get("TID").unwrap();
-    let lid = match prq.packet.data.get("LID") {
-        Some(lid) => lid,
-        None => &"1".to_string(),
+    let request: LlstRequest = decode_request(&prq).await?;
+    let tid = request.tid;
+
+    // Only list the lobbies of the platform of the client
+    let platform = match prq
+        .sstate
+        .registry
+        .get_session_by_theater_tcp_handle(&prq.con.to_string())
+    {
+        Some(db_session) => get_session_platform(&db_session, &prq.sstate),
+        None => None,
     };
-    let lobby_id: i32 = lid.parse().unwrap();
+    let db_lobbies = get_available_lobbies(platform.as_deref(), &prq.sstate.database).await;
 
     // Prepare lobby list
     let mut lobby_list = IndexMap::new();
     lobby_list.insert("TID".to_string(), tid.to_string());
-    lobby_list.insert("NUM-LOBBIES".to_string(), "1".to_string());
+    lobby_list.insert("NUM-LOBBIES".to_string(), db_lobbies.len().to_string());
 
     let lobby_list_response = DataPacket {
         packet_mode: PacketMode::FeslPingOrTheaterResponse,
@@ -49,44 +60,38 @@ pub async fn handle_rq_llst(
     submit_packet(lobby_list_response, &prq.con, &prq.sstate, 0).await;
 
     // Prepare lobby data
-    const NAME: &str = "lotr-pandemic";
-    const LOCALE: &str = "en_US";
-    const MAX_GAMES: u32 = 1000;
     const FAVORITE_GAMES: u32 = 0;
     const FAVORITE_PLAYERS: u32 = 0;
 
-    //let games = sstate.database.get_games_by_lobby_id(lid.parse().unwrap(), MAX_GAMES as usize).unwrap();
-    let Ok(num_games) = game::Entity::find()
-        .filter(
-            Condition::all()
-                .add(game::Column::LobbyId.eq(lobby_id))
-                .add(game::Column::UserFriendsOnly.eq(false)), // Hide 'private' games
-        )
-        .count(&*prq.sstate.database)
-        .await
-    else {
-        return Err("Failed to get number of games");
-    };
+    for db_lobby in db_lobbies {
+        let num_games = prq
+            .sstate
+            .registry
+            .get_games_by_lobby(db_lobby.id)
+            .iter()
+            .filter(|db_game| !db_game.user_friends_only) // Hide 'private' games
+            .count();
 
-    let mut lobby_data = IndexMap::new();
-    lobby_data.insert("TID".to_string(), tid.to_string());
-    lobby_data.insert("LID".to_string(), lid.to_string());
+        let mut lobby_data = IndexMap::new();
+        lobby_data.insert("TID".to_string(), tid.to_string());
+        lobby_data.insert("LID".to_string(), db_lobby.id.to_string());
 
-    lobby_data.insert("PASSING".to_string(), num_games.to_string());
-    lobby_data.insert("NAME".to_string(), NAME.to_string());
-    lobby_data.insert("LOCALE".to_string(), LOCALE.to_string());
-    lobby_data.insert("MAX-GAMES".to_string(), MAX_GAMES.to_string());
-    lobby_data.insert("FAVORITE-GAMES".to_string(), FAVORITE_GAMES.to_string());
-    lobby_data.insert("FAVORITE-PLAYERS".to_string(), FAVORITE_PLAYERS.to_string());
-    lobby_data.insert("NUM-GAMES".to_string(), num_games.to_string());
+        lobby_data.insert("PASSING".to_string(), num_games.to_string());
+        lobby_data.insert("NAME".to_string(), db_lobby.name.to_string());
+        lobby_data.insert("LOCALE".to_string(), db_lobby.locale.to_string());
+        lobby_data.insert("MAX-GAMES".to_string(), db_lobby.max_games.to_string());
+        lobby_data.insert("FAVORITE-GAMES".to_string(), FAVORITE_GAMES.to_string());
+        lobby_data.insert("FAVORITE-PLAYERS".to_string(), FAVORITE_PLAYERS.to_string());
+        lobby_data.insert("NUM-GAMES".to_string(), num_games.to_string());
 
-    let lobby_data_response = DataPacket {
-        packet_mode: PacketMode::FeslPingOrTheaterResponse,
-        mode: DataMode::THEATER_LDAT,
-        packet_id: prq.packet.packet_id,
-        data: lobby_data,
-    };
-    submit_packet(lobby_data_response, &prq.con, &prq.sstate, 0).await;
+        let lobby_data_response = DataPacket {
+            packet_mode: PacketMode::FeslPingOrTheaterResponse,
+            mode: DataMode::THEATER_LDAT,
+            packet_id: prq.packet.packet_id,
+            data: lobby_data,
+        };
+        submit_packet(lobby_data_response, &prq.con, &prq.sstate, 0).await;
+    }
 
     Ok(())
 }
@@ -1,14 +1,21 @@
 use indexmap::IndexMap;
-use sea_orm::entity::*;
-use sea_orm::query::*;
 
-use crate::handler::{submit_packet, to_error_packet};
-use crate::orm::model::{game, participant, session};
+use crate::handler::{decode_request, submit_packet, to_error_packet};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::handler::theater::TheaterHandler;
+use crate::handler::fesl::update_presence;
+use crate::presence::{PresenceInfo, PresenceState};
 
+plasma_struct! {
+    struct PentRequest {
+        persona_id: i64 = "PID",
+        tid: String = "TID",
+        game_id: i64 = "GID",
+    }
+}
 
 pub async fn handle_rq_pent(
     fh: &TheaterHandler,
@@ -16,41 +23,22 @@ pub async fn handle_rq_pent(
 ) -> Result<(), &'static str> {
     // Player Enter (notified by the game host)
     // {"PID": "1", "TID": "7"} }
-    let pid = prq.packet.data.get("PID").unwrap();
-    let tid = prq.packet.data.get("TID").unwrap();
-    let gid = prq.packet.data.get("GID").unwrap();
+    let request: PentRequest = decode_request(&prq).await?;
+    let client_persona_id = request.persona_id;
+    let tid = request.tid;
+    let gid_int = request.game_id;
 
-    // Parse PID
-    let Ok(client_persona_id) = pid.parse::<i64>() else {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("Client PID not parsable");
-    };
-    // Parse GID
-    let Ok(gid_int) = gid.parse::<i64>() else {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("Game ID not parsable");
-    };
     // Lookup GID game in the database
-    let Ok(Some(db_game)) = game::Entity::find_by_id(gid_int)
-        .one(&*prq.sstate.database)
-        .await
-    else {
+    let Some(db_game) = prq.sstate.registry.get_game(gid_int) else {
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
         return Err("Game not found");
     };
     // Search for participant entry
-    let Ok(Some(db_participant)) = participant::Entity::find()
-        .filter(
-            Condition::all()
-                .add(participant::Column::PersonaId.eq(client_persona_id))
-                .add(participant::Column::GameId.eq(gid_int)),
-        )
-        // .filter(participant::Column::GameId.eq(gid.parse::<i64>().unwrap()))
-        .one(&*prq.sstate.database)
-        .await
+    let Some(db_participant) = prq
+        .sstate
+        .registry
+        .get_participant_by_game_and_persona(gid_int, client_persona_id)
     else {
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
@@ -58,19 +46,29 @@ pub async fn handle_rq_pent(
     };
 
     // Set player entry as active (queue_len = -1)
-    let mut db_participant = db_participant.into_active_model();
-    db_participant.queue_pos = Set(-1 as i32);
-    let Ok(db_participant) = db_participant.update(&*prq.sstate.database).await else {
+    let Some(db_participant) = prq
+        .sstate
+        .registry
+        .modify_participant(db_participant.id, |participant| participant.queue_pos = -1)
+    else {
         let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
         submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
         return Err("Failed to set participant as active player.");
     };
 
+    // The player is in the game now
+    update_presence(
+        &prq.sstate,
+        db_participant.persona_id,
+        Some(PresenceInfo::new(PresenceState::InGame, gid_int)),
+    )
+    .await;
+
     // Get session of client
-    let Ok(Some(db_client_session)) = session::Entity::find()
-        .filter(session::Column::PersonaId.eq(db_participant.persona_id))
-        .one(&*prq.sstate.database)
-        .await
+    let Some(db_client_session) = prq
+        .sstate
+        .registry
+        .get_session_by_persona(db_participant.persona_id)
     else {
         return Err("Session not found");
     };
@@ -80,7 +78,7 @@ pub async fn handle_rq_pent(
 
     let mut response_hm = IndexMap::new();
     response_hm.insert("TID".to_string(), tid.to_string());
-    response_hm.insert("PID".to_string(), pid.to_string());
+    response_hm.insert("PID".to_string(), client_persona_id.to_string());
 
     let response_packet = DataPacket {
         packet_mode: PacketMode::FeslPingOrTheaterResponse,
@@ -1,14 +1,20 @@
 use indexmap::IndexMap;
-use sea_orm::entity::*;
-use sea_orm::query::*;
 
-use crate::handler::{submit_packet, to_error_packet};
-use crate::orm::model::participant;
+use crate::handler::{decode_request, submit_packet};
 use crate::packet::{DataMode, DataPacket, PacketMode};
-use crate::plasma_errors::EAError;
+use crate::plasma_data::plasma_struct;
 use crate::plasma_handle::PlasmaRequestBundle;
-use crate::handler::theater::TheaterHandler;
+use crate::handler::theater::{advance_queue, TheaterHandler};
+use crate::handler::fesl::update_presence;
+use crate::presence::{PresenceInfo, PresenceState};
 
+plasma_struct! {
+    struct PlvtRequest {
+        persona_id: i64 = "PID",
+        game_id: i64 = "GID",
+        tid: String = "TID",
+    }
+}
 
 pub async fn handle_rq_plvt(
     fh: &TheaterHandler,
@@ -17,37 +23,39 @@ pub async fn handle_rq_plvt(
     // Player Leave (notified by the game host)
     // {"LID": "1", "GID": "6", "PID": "3", "TID": "90"} }
 
-    let pid = prq.packet.data.get("PID").unwrap();
-    let gid = prq.packet.data.get("GID").unwrap();
-    let tid = prq.packet.data.get("TID").unwrap();
-
-    // Parse PID
-    let Ok(client_persona_id) = pid.parse::<i64>() else {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("Client PID not parsable");
-    };
-
-    // Parse GID
-    let Ok(gid_int) = gid.parse::<i64>() else {
-        let err_pkt = to_error_packet(&prq.packet, EAError::EA_NoData as i32, None);
-        submit_packet(err_pkt, &prq.con, &prq.sstate, 0).await;
-        return Err("Game ID not parsable");
-    };
+    let request: PlvtRequest = decode_request(&prq).await?;
+    let client_persona_id = request.persona_id;
+    let gid_int = request.game_id;
+    let tid = request.tid;
 
     // Search for participant entry
     // Note: The participant may be removed eariler, so don't throw an error here!
-    if let Err(_) = participant::Entity::delete_many()
-        .filter(
-            Condition::all()
-                .add(participant::Column::GameId.eq(gid_int))
-                .add(participant::Column::PersonaId.eq(client_persona_id)),
-        )
-        .exec(&*prq.sstate.database)
-        .await
+    if let Some(db_participant) = prq
+        .sstate
+        .registry
+        .get_participant_by_game_and_persona(gid_int, client_persona_id)
     {
-        return Err("Failed to remove the participant from the table.");
-    };
+        prq.sstate.registry.remove_participant(db_participant.id);
+    }
+
+    // The slot of the player may be taken by a queued player now
+    advance_queue(gid_int, &prq.sstate).await;
+
+    // Back to online, unless the player went offline or moved to another game in the meantime
+    let left_game = prq
+        .sstate
+        .presence
+        .get(&client_persona_id)
+        .map(|presence| presence.gid == gid_int)
+        .unwrap_or(false);
+    if left_game {
+        update_presence(
+            &prq.sstate,
+            client_persona_id,
+            Some(PresenceInfo::new(PresenceState::Online, -1)),
+        )
+        .await;
+    }
 
     let mut response_hm = IndexMap::new();
     response_hm.insert("TID".to_string(), tid.to_string());
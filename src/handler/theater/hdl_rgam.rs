@@ -1,13 +1,17 @@
 use indexmap::IndexMap;
-use sea_orm::entity::*;
-use sea_orm::query::*;
 
-use crate::handler::submit_packet;
-use crate::orm::model::{game, participant};
+use crate::handler::{decode_request, submit_packet};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::handler::theater::TheaterHandler;
 
+plasma_struct! {
+    struct RgamRequest {
+        game_id: i64 = "GID",
+        tid: String = "TID",
+    }
+}
 
 pub async fn handle_rq_rgam(
     fh: &TheaterHandler,
@@ -15,24 +19,14 @@ pub async fn handle_rq_rgam(
 ) -> Result<(), &'static str> {
     // Remove Game
     // {"LID": "1", "GID": "2", "TID": "8"} }
-    let lid = prq.packet.data.get("LID").unwrap();
-    let gid = prq.packet.data.get("GID").unwrap();
-    let tid = prq.packet.data.get("TID").unwrap();
+    let request: RgamRequest = decode_request(&prq).await?;
+    let gid_int = request.game_id;
+    let tid = request.tid;
 
     // Remove game
-    let Ok(gid_int) = gid.parse::<i64>() else {
-        return Err("Game ID not parsable");
-    };
 
-    participant::Entity::delete_many()
-        .filter(participant::Column::GameId.eq(gid_int))
-        .exec(&*prq.sstate.database)
-        .await
-        .unwrap();
-    game::Entity::delete_by_id(gid_int)
-        .exec(&*prq.sstate.database)
-        .await
-        .unwrap();
+    prq.sstate.registry.remove_participants_by_game(gid_int);
+    prq.sstate.registry.remove_game(gid_int);
 
     let mut response_hm = IndexMap::new();
     response_hm.insert("TID".to_string(), tid.to_string());
@@ -1,10 +1,16 @@
 use indexmap::IndexMap;
 
-use crate::handler::submit_packet;
+use crate::handler::{decode_request, submit_packet};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_handle::PlasmaRequestBundle;
 use crate::handler::theater::TheaterHandler;
 
+plasma_struct! {
+    struct UbraRequest {
+        tid: String = "TID",
+    }
+}
 
 pub async fn handle_rq_ubra(
     fh: &TheaterHandler,
@@ -17,10 +23,8 @@ pub async fn handle_rq_ubra(
     // Therefore, UGAM is a "response" packet (accoding to the packet mode)
 
     // ToDo: Implement useful locking mechanism...
-    let lid = prq.packet.data.get("LID").unwrap();
-    let gid = prq.packet.data.get("GID").unwrap();
-    let start = prq.packet.data.get("START").unwrap();
-    let tid = prq.packet.data.get("TID").unwrap();
+    let request: UbraRequest = decode_request(&prq).await?;
+    let tid = request.tid;
 
     // Just plainly respond with the TID
     let mut response_hm = IndexMap::new();
@@ -2,20 +2,29 @@ use indexmap::IndexMap;
 use sea_orm::entity::*;
 use sea_orm::query::*;
 
-use crate::handler::{submit_packet, to_error_packet};
-use crate::orm::model::{account, persona, session};
+use crate::handler::{decode_request, reject_if_banned, submit_packet, to_error_packet};
+use crate::orm::model::{account, persona};
 use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::plasma_data::plasma_struct;
 use crate::plasma_errors::EAError;
 use crate::plasma_handle::PlasmaRequestBundle;
+use crate::utils::bans::BanSubject;
 use crate::handler::theater::TheaterHandler;
 
+plasma_struct! {
+    struct UserRequest {
+        lkey: String = "LKEY",
+        tid: String = "TID",
+    }
+}
 
 pub async fn handle_rq_user(
     fh: &TheaterHandler,
     mut prq: PlasmaRequestBundle,
 ) -> Result<(), &'static str> {
-    let lkey = prq.packet.data.get("LKEY").unwrap();
-    let tid = prq.packet.data.get("TID").unwrap();
+    let request: UserRequest = decode_request(&prq).await?;
+    let lkey = &request.lkey;
+    let tid = &request.tid;
     // Other, unused fields
     // let cid = prq.packet.data.get("CID"); // Usually empty
     // let mac = prq.packet.data.get("MAC"); // Usually some hash
@@ -38,11 +47,7 @@ pub async fn handle_rq_user(
     };
 
     // Get the session vai the lobby key
-    let Ok(Some(db_session)) = session::Entity::find()
-        .filter(session::Column::LobbyKey.eq(lkey.clone()))
-        .one(&*prq.sstate.database)
-        .await
-    else {
+    let Some(db_session) = prq.sstate.registry.get_session_by_lobby_key(lkey) else {
         let err_pkt = to_error_packet(
             &prq.packet,
             EAError::EA_AuthFail as i32,
@@ -73,10 +78,19 @@ pub async fn handle_rq_user(
         return Err("Persona for given Persona ID not found.");
     };
 
+    // Bans issued since the FESL login apply as well
+    let ban_subject = BanSubject {
+        email: Some(user_name.clone()),
+        persona_id: Some(db_persona.id),
+        ..BanSubject::from_session(&db_session)
+    };
+    reject_if_banned(&prq, &ban_subject).await?;
+
     // Add theater handle to session
-    let mut active_session = db_session.into_active_model();
-    active_session.theater_tcp_handle = Set(prq.con.to_string());
-    let _ = active_session.update(&*prq.sstate.database).await;
+    let theater_tcp_handle = prq.con.to_string();
+    prq.sstate.registry.modify_session(db_session.id, |session| {
+        session.theater_tcp_handle = theater_tcp_handle;
+    });
 
     let mut response_hm: IndexMap<String, String> = IndexMap::new();
     //response_hm.insert("NAME".to_string(), user_name.to_string());
@@ -1,10 +1,12 @@
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock};
 use tracing::info;
 
 use crate::client_connection::{ClientConnectionDescriptor, ServiceType};
+use crate::handler::routes::{Access, Router, THEATER_REQUEST, THEATER_RESPONSE};
 use crate::handler::Handler;
-use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::packet::{DataMode, DataPacket};
 use crate::plasma_handle::PlasmaRequestBundle;
+use crate::rate_limit::RateLimit;
 use crate::sharedstate::SharedState;
 
 const THEATER_PING_INTERVAL: u64 = 60; // 1 min
@@ -42,6 +44,9 @@ use hdl_plvt::handle_rq_plvt;
 mod hdl_glst;
 use hdl_glst::handle_rq_glst;
 
+mod hdl_gdat;
+use hdl_gdat::handle_rq_gdat;
+
 mod hdl_ubra;
 use hdl_ubra::handle_rq_ubra;
 
@@ -57,6 +62,72 @@ use hdl_echo::handle_rsp_echo;
 mod utils_ping;
 use utils_ping::send_ping;
 
+mod utils_game_data;
+
+mod utils_matchmaking;
+
+mod utils_nat;
+
+mod utils_queue;
+pub(crate) use utils_queue::advance_queue;
+
+// Requirements and rate limits of the Theater packets. The session of the Theater
+// connection is attached by USER.
+static THEATER_ROUTES: LazyLock<Router<TheaterHandler>> = LazyLock::new(|| {
+    Router::new(THEATER_REQUEST, THEATER_RESPONSE)
+        .request(DataMode::THEATER_CONN, None, Access::Any, |th, prq| {
+            Box::pin(handle_rq_conn(th, prq))
+        })
+        .request(DataMode::THEATER_USER, None, Access::Any, |th, prq| {
+            Box::pin(handle_rq_user(th, prq))
+        })
+        .request(DataMode::THEATER_LLST, None, Access::Persona, |th, prq| {
+            Box::pin(handle_rq_llst(th, prq))
+        })
+        .request(DataMode::THEATER_GLST, None, Access::Persona, |th, prq| {
+            Box::pin(handle_rq_glst(th, prq))
+        })
+        .request(DataMode::THEATER_GDAT, None, Access::Persona, |th, prq| {
+            Box::pin(handle_rq_gdat(th, prq))
+        })
+        .request(DataMode::THEATER_CGAM, None, Access::Persona, |th, prq| {
+            Box::pin(handle_rq_cgam(th, prq))
+        })
+        .limit(RateLimit::per_minute(10))
+        .request(DataMode::THEATER_EGAM, None, Access::Persona, |th, prq| {
+            Box::pin(handle_rq_egam(th, prq))
+        })
+        .request(DataMode::THEATER_ECNL, None, Access::Persona, |th, prq| {
+            Box::pin(handle_rq_ecnl(th, prq))
+        })
+        // Sent by the host of the game
+        .request(DataMode::THEATER_RGAM, None, Access::GameHost, |th, prq| {
+            Box::pin(handle_rq_rgam(th, prq))
+        })
+        .request(DataMode::THEATER_EGRS, None, Access::GameHost, |th, prq| {
+            Box::pin(handle_rq_egrs(th, prq))
+        })
+        .request(DataMode::THEATER_PENT, None, Access::GameHost, |th, prq| {
+            Box::pin(handle_rq_pent(th, prq))
+        })
+        .request(DataMode::THEATER_PLVT, None, Access::GameHost, |th, prq| {
+            Box::pin(handle_rq_plvt(th, prq))
+        })
+        .request(DataMode::THEATER_UBRA, None, Access::GameHost, |th, prq| {
+            Box::pin(handle_rq_ubra(th, prq))
+        })
+        .response(DataMode::THEATER_PING, None, Access::Any, |th, prq| {
+            Box::pin(handle_rsp_ping(th, prq))
+        })
+        .response(DataMode::THEATER_UGAM, None, Access::GameHost, |th, prq| {
+            Box::pin(handle_rsp_ugam(th, prq))
+        })
+        // UDP, before the session knows the UDP handle
+        .response(DataMode::THEATER_ECHO, None, Access::Any, |th, prq| {
+            Box::pin(handle_rsp_echo(th, prq))
+        })
+});
+
 pub struct TheaterHandler;
 
 #[async_trait::async_trait]
@@ -75,135 +146,16 @@ impl Handler for TheaterHandler {
         con: ClientConnectionDescriptor,
         sstate: Arc<SharedState>,
     ) -> Result<(), &'static str> {
+        let Some(route) = THEATER_ROUTES.find(&packet) else {
+            info!(target: "theater", "Unhandled {:?} {:?}, ignoring...", packet.packet_mode, packet.mode);
+            return Ok(());
+        };
         let prq = PlasmaRequestBundle::new(packet, con, sstate);
-
-        match prq.packet.packet_mode {
-            PacketMode::TheaterRequest => match prq.packet.mode {
-                DataMode::THEATER_CONN => {
-                    return self.handle_rq_conn(prq).await;
-                }
-                DataMode::THEATER_USER => {
-                    return self.handle_rq_user(prq).await;
-                }
-                DataMode::THEATER_ECNL => {
-                    return self.handle_rq_ecnl(prq).await;
-                }
-                DataMode::THEATER_LLST => {
-                    return self.handle_rq_llst(prq).await;
-                }
-                DataMode::THEATER_CGAM => {
-                    return self.handle_rq_cgam(prq).await;
-                }
-                DataMode::THEATER_EGAM => {
-                    return self.handle_rq_egam(prq).await;
-                }
-                DataMode::THEATER_EGRS => {
-                    return self.handle_rq_egrs(prq).await;
-                }
-                DataMode::THEATER_PENT => {
-                    return self.handle_rq_pent(prq).await;
-                }
-                DataMode::THEATER_RGAM => {
-                    return self.handle_rq_rgam(prq).await;
-                }
-                DataMode::THEATER_GLST => {
-                    return self.handle_rq_glst(prq).await;
-                }
-                DataMode::THEATER_UBRA => {
-                    return self.handle_rq_ubra(prq).await;
-                }
-                DataMode::THEATER_PLVT => {
-                    return self.handle_rq_plvt(prq).await;
-                }
-                _ => {
-                    info!(target: "theater", "Unhandled DataMode: {:?}, ignoring...", &prq.packet.mode);
-                    return Ok(());
-                }
-            },
-            PacketMode::FeslPingOrTheaterResponse => match prq.packet.mode {
-                DataMode::THEATER_PING => {
-                    return self.handle_rsp_ping(prq).await;
-                }
-                DataMode::THEATER_UGAM => {
-                    return self.handle_rsp_ugam(prq).await;
-                }
-                DataMode::THEATER_ECHO => {
-                    return self.handle_rsp_echo(prq).await;
-                }
-                _ => {
-                    info!(target: "theater", "Unhandled DataMode: {:?}, ignoring...", &prq.packet.mode);
-                    return Ok(());
-                }
-            },
-            _ => {
-                info!(target: "theater", "Unhandled PacketMode: {:?}, ignoring...", &prq.packet.packet_mode);
-                return Ok(());
-            }
-        }
+        THEATER_ROUTES.dispatch(route, self, prq).await
     }
 }
 
 impl TheaterHandler {
-    async fn handle_rq_conn(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rq_conn(self, prq).await
-    }
-
-    async fn handle_rq_user(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rq_user(self, prq).await
-    }
-
-    async fn handle_rq_llst(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rq_llst(self, prq).await
-    }
-
-    async fn handle_rq_cgam(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rq_cgam(self, prq).await
-    }
-
-    async fn handle_rq_ecnl(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rq_ecnl(self, prq).await
-    }
-
-    async fn handle_rq_egam(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rq_egam(self, prq).await
-    }
-
-    async fn handle_rq_egrs(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rq_egrs(self, prq).await
-    }
-
-    async fn handle_rq_pent(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rq_pent(self, prq).await
-    }
-
-    async fn handle_rq_rgam(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rq_rgam(self, prq).await
-    }
-
-    async fn handle_rq_plvt(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rq_plvt(self, prq).await
-    }
-
-    async fn handle_rq_glst(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rq_glst(self, prq).await
-    }
-
-    async fn handle_rq_ubra(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rq_ubra(self, prq).await
-    }
-
-    async fn handle_rsp_ugam(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rsp_ugam(self, prq).await
-    }
-
-    async fn handle_rsp_ping(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rsp_ping(self, prq).await
-    }
-
-    async fn handle_rsp_echo(&self, mut prq: PlasmaRequestBundle) -> Result<(), &'static str> {
-        handle_rsp_echo(self, prq).await
-    }
-
     async fn send_ping(
         &self,
         con: &ClientConnectionDescriptor,
@@ -0,0 +1,159 @@
+use indexmap::IndexMap;
+use tracing::{error, warn};
+
+use crate::orm::model::game;
+use crate::plasma_handle::PlasmaRequestBundle;
+use crate::registry::Registry;
+use crate::utils::config_values::get_cfg_value;
+
+
+// Parse the remaining, JSON-encoded fields of the game
+pub fn parse_other_fields(db_game: &game::Model) -> IndexMap<String, String> {
+    let mut other_fields: IndexMap<String, String> = IndexMap::new();
+    if !db_game.other_as_json.is_empty() {
+        if let Ok(other_items) = serde_json::from_str::<IndexMap<String, String>>(&db_game.other_as_json) {
+            for (key, value) in other_items.iter() {
+                other_fields.insert(key.to_string(), value.to_string());
+            }
+        } else {
+            warn!(target: "theater", "Failed to parse other field: {}", &db_game.other_as_json);
+        }
+    }
+    other_fields
+}
+
+// Number of players that are in the game (not queued)
+pub fn count_active_players(game_id: i64, registry: &Registry) -> u64 {
+    registry
+        .get_participants_by_game(game_id)
+        .iter()
+        .filter(|db_participant| db_participant.queue_pos == -1)
+        .count() as u64
+}
+
+// Query the user preferences
+// PING:
+// -> Check if the user has a ping site preference
+// -> Check if the ping site preference is set and valid
+pub async fn get_name_mod_ping_site(prq: &mut PlasmaRequestBundle) -> Option<String> {
+    let db_user = prq.get_active_user_model().await?;
+    let preferred_ping_site = db_user.name_mod_ping_site.clone();
+
+    // Check if the ping site preference is set and valid
+    if preferred_ping_site.is_empty() {
+        return None;
+    }
+    // Check if the ping site is valid
+    let ping_site = get_cfg_value("GetPingSites_PingSites", &prq.sstate.database).await?;
+    // Parse the ping site
+    let available_ping_sites = serde_json::from_str::<Vec<IndexMap<String, String>>>(&ping_site)
+        .unwrap_or_else(|_| {
+            error!(target: "theater", "Failed to parse ping site preference: {}", ping_site);
+            Vec::new()
+        });
+    // Check if the preferred ping site is in the list of available ping sites
+    if available_ping_sites.iter().any(|site| site.get("name") == Some(&preferred_ping_site)) {
+        // Add the ping site to the game data response
+        return Some(preferred_ping_site);
+    }
+    None
+}
+
+// Fields of a GDAT packet describing the game
+pub fn build_game_data(
+    tid: &str,
+    lid: &str,
+    db_game: &game::Model,
+    n_cur_players: u64,
+    other_fields: &IndexMap<String, String>,
+    name_mod_ping_site: Option<&str>,
+    host_ip: &str,
+) -> IndexMap<String, String> {
+    let mut game_data_response = IndexMap::new();
+    game_data_response.insert("TID".to_string(), tid.to_string());
+    game_data_response.insert("LID".to_string(), lid.to_string());
+    game_data_response.insert("GID".to_string(), db_game.id.to_string());
+
+    // Host Name (normally == Persona Name)
+    game_data_response.insert("HN".to_string(), db_game.name.to_string());
+    // Host ID (Persona ID)
+    game_data_response.insert("HU".to_string(), db_game.persona_id.to_string());
+
+    let mut game_name = db_game.name.to_string();
+    // May add the ping time to the game name?
+    if let Some(name_ping_site) = name_mod_ping_site {
+        // Get the ping of the server to this ping site
+        let ping_site_key = format!("B-U-{}", &name_ping_site);
+        let ping_time = other_fields.get(&ping_site_key).cloned();
+        if let Some(ping_time) = ping_time {
+            // Try to parse the ping time
+            if let Ok(ping_time) = ping_time.parse::<i32>() {
+                // Add the ping time to the game name
+                game_name = format!("[{}ms] {}", ping_time, game_name);
+
+                // Truncate the game name to 31-3 characters and end with "..."
+                if game_name.len() > 31 {
+                    game_name = format!("{}...", &game_name[0..31-3]);
+                }
+            };
+        }
+    }
+    // Server Name (normally == Persona Name)
+    game_data_response.insert("N".to_string(), game_name);
+
+    // IP/Port of the host
+    game_data_response.insert("I".to_string(), host_ip.to_string());
+    game_data_response.insert("P".to_string(), db_game.port.to_string());
+
+    // Max Players
+    game_data_response.insert("MP".to_string(), db_game.max_players.to_string());
+    // Current Players
+    game_data_response.insert("AP".to_string(), n_cur_players.to_string());
+    // Current Queue
+    game_data_response.insert("QP".to_string(), "0".to_string());
+
+    // Is favorite server
+    game_data_response.insert("F".to_string(), "0".to_string());
+    // Number of favorite players?
+    game_data_response.insert("NF".to_string(), "0".to_string());
+    // Join mode
+    game_data_response.insert("J".to_string(), db_game.join_mode.to_string());
+    // #Players joining
+    game_data_response.insert("JP".to_string(), "0".to_string());
+    // Game type
+    game_data_response.insert("TYPE".to_string(), db_game.game_type.to_string());
+
+    // Server requires password?
+    game_data_response.insert("PW".to_string(), "0".to_string());
+
+    game_data_response.insert("B-version".to_string(), db_game.server_version.to_string());
+    // The game does not support observers.
+    game_data_response.insert("B-numObservers".to_string(), "0".to_string());
+    game_data_response.insert("B-maxObservers".to_string(), db_game.max_observers.to_string());
+
+    if !db_game.user_levelkey.is_empty() {
+        game_data_response.insert("B-U-LevelKey".to_string(), db_game.user_levelkey.to_string());
+    }
+    if !db_game.user_levelname.is_empty() {
+        game_data_response.insert("B-U-LevelName".to_string(), db_game.user_levelname.to_string());
+    }
+    if !db_game.user_mode.is_empty() {
+        game_data_response.insert("B-U-Mode".to_string(), db_game.user_mode.to_string());
+    }
+    if db_game.user_ranked {
+        game_data_response.insert("B-U-Ranked".to_string(), "1".to_string());
+    }
+    if db_game.user_pcdedicated {
+        game_data_response.insert("B-U-PCDedicated".to_string(), "1".to_string());
+    }
+    if !db_game.user_dlc.is_empty() {
+        game_data_response.insert("B-U-DLC".to_string(), db_game.user_dlc.to_string());
+    }
+
+    // Add the remaining, JSON-encoded fields
+    for (key, value) in other_fields.iter() {
+        game_data_response.insert(key.to_string(), value.to_string());
+    }
+
+    game_data_response
+}
@@ -0,0 +1,150 @@
+use indexmap::IndexMap;
+use tracing::debug;
+
+use crate::orm::model::game;
+use crate::plasma_handle::PlasmaRequestBundle;
+use crate::utils::associations::{has_association, ASSO_TYPE_FRIENDS};
+use crate::utils::lobbies::{get_available_lobbies, get_available_lobby, get_session_platform};
+
+use super::utils_game_data::{get_name_mod_ping_site, parse_other_fields};
+use super::utils_queue::{count_joined_participants, get_queued_participants};
+
+
+// Weights of the quick-match score
+const QM_SCORE_PER_FREE_SLOT: i64 = 5;
+const QM_MAX_SCORED_FREE_SLOTS: i64 = 8;
+const QM_SCORE_RANKED: i64 = 50;
+const QM_SCORE_PLAYMODE: i64 = 40;
+const QM_SCORE_MODE: i64 = 30;
+// Each x ms of latency to the preferred ping site cost one point
+const QM_LATENCY_MS_PER_POINT: i64 = 5;
+
+// Preferences of the player looking for a game. They are sent in the EGAM request
+// using the same attribute names as the GLST filters.
+struct QuickMatchPreferences {
+    lobby_id: Option<i32>,
+    version: Option<String>,
+    ranked: Option<bool>,
+    playmode: Option<String>,
+    mode: Option<String>,
+}
+
+impl QuickMatchPreferences {
+    fn from_packet(data: &IndexMap<String, String>) -> Self {
+        let attribute = |name: &str| {
+            data.get(&format!("FILTER-ATTR-U-{}", name))
+                .or_else(|| data.get(&format!("B-U-{}", name)))
+                .filter(|value| !value.is_empty())
+                .cloned()
+        };
+        Self {
+            lobby_id: data.get("LID").and_then(|lid| lid.parse::<i32>().ok()),
+            version: attribute("Version").filter(|version| version != "0"),
+            ranked: attribute("Ranked").map(|ranked| ranked == "1"),
+            playmode: attribute("PlayMode"),
+            mode: attribute("Mode"),
+        }
+    }
+}
+
+// Finds the best open game for a player that did not request a specific game
+// (EGAM without GID or USER). Games the player cannot join right away are skipped.
+pub async fn find_quick_match_game(prq: &mut PlasmaRequestBundle) -> Option<game::Model> {
+    let preferences = QuickMatchPreferences::from_packet(&prq.packet.data);
+    let name_mod_ping_site = get_name_mod_ping_site(prq).await;
+
+    let db_session = prq
+        .sstate
+        .registry
+        .get_session_by_theater_tcp_handle(&prq.con.to_string())?;
+
+    // Open games of the enabled lobbies of the platform, oldest first
+    let platform = get_session_platform(&db_session, &prq.sstate);
+    let db_games = match preferences.lobby_id {
+        Some(lobby_id) => {
+            get_available_lobby(lobby_id, platform.as_deref(), &prq.sstate.database).await?;
+            prq.sstate.registry.get_games_by_lobby(lobby_id)
+        }
+        None => {
+            let lobby_ids: Vec<i32> =
+                get_available_lobbies(platform.as_deref(), &prq.sstate.database)
+                    .await
+                    .into_iter()
+                    .map(|db_lobby| db_lobby.id)
+                    .collect();
+            prq.sstate
+                .registry
+                .get_games()
+                .into_iter()
+                .filter(|db_game| lobby_ids.contains(&db_game.lobby_id))
+                .collect()
+        }
+    };
+
+    let mut best_game: Option<(i64, game::Model)> = None;
+    for db_game in db_games {
+        if db_game.join_mode != "O" {
+            continue;
+        }
+        // Do not match the player with its own game
+        if db_game.persona_id == db_session.persona_id {
+            continue;
+        }
+        // Incompatible client versions cannot play together
+        if let Some(version) = &preferences.version
+            && !db_game.client_version.is_empty()
+            && &db_game.client_version != version
+        {
+            continue;
+        }
+        if db_game.user_friends_only
+            && !has_association(
+                db_game.persona_id,
+                db_session.persona_id,
+                ASSO_TYPE_FRIENDS,
+                &prq.sstate.database,
+            )
+            .await
+        {
+            continue;
+        }
+
+        // Only games that can be joined without waiting in the queue
+        let n_joined = count_joined_participants(db_game.id, &prq.sstate).await as i64;
+        let n_free_slots = db_game.max_players as i64 - n_joined;
+        if n_free_slots <= 0 || !get_queued_participants(db_game.id, &prq.sstate).await.is_empty() {
+            continue;
+        }
+
+        let mut score = n_free_slots.min(QM_MAX_SCORED_FREE_SLOTS) * QM_SCORE_PER_FREE_SLOT;
+        if let Some(ranked) = preferences.ranked {
+            score += if db_game.user_ranked == ranked { QM_SCORE_RANKED } else { -QM_SCORE_RANKED };
+        }
+        if let Some(playmode) = &preferences.playmode
+            && &db_game.user_playmode == playmode
+        {
+            score += QM_SCORE_PLAYMODE;
+        }
+        if let Some(mode) = &preferences.mode
+            && &db_game.user_mode == mode
+        {
+            score += QM_SCORE_MODE;
+        }
+        // Prefer games close to the ping site of the player (as reported by the host)
+        if let Some(ping_site) = &name_mod_ping_site
+            && let Some(ping_time) = parse_other_fields(&db_game)
+                .get(&format!("B-U-{}", ping_site))
+                .and_then(|ping_time| ping_time.parse::<i64>().ok())
+        {
+            score -= ping_time.max(0) / QM_LATENCY_MS_PER_POINT;
+        }
+
+        debug!(target: "theater", "Quick-match score of game {}: {}", db_game.id, score);
+        // Older games win ties
+        if best_game.as_ref().map(|(best_score, _)| score > *best_score).unwrap_or(true) {
+            best_game = Some((score, db_game));
+        }
+    }
+
+    best_game.map(|(_, db_game)| db_game)
+}
@@ -0,0 +1,142 @@
+use sea_orm::entity::*;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, info};
+
+use crate::client_connection::ClientConnectionDescriptor;
+use crate::orm::model::{account, game, participant};
+use crate::sharedstate::SharedState;
+use crate::utils::stun_turn::{TurnRequestBody, TurnResponseBody};
+
+// Sets the addresses under which host and client of the participant reach each other.
+// A TURN relay is launched if their NAT types require it, so this is only done once the
+// player is admitted to the game (and not while queued).
+pub async fn assign_peer_addresses(
+    db_game: &game::Model,
+    db_participant: &mut participant::Model,
+    sstate: &Arc<SharedState>,
+) -> Result<(), &'static str> {
+    let Some(db_client_session) = sstate.registry.get_session_by_persona(db_participant.persona_id)
+    else {
+        return Err("Client session not found");
+    };
+    let Some(db_host_session) = sstate.registry.get_session_by_persona(db_game.persona_id) else {
+        return Err("Server session not found");
+    };
+
+    // The UDP addresses are only known once both sent their UDP ECHO
+    if db_host_session.theater_udp_handle.is_empty() {
+        return Err("Server UDP address unknown");
+    }
+    if db_client_session.theater_udp_handle.is_empty() {
+        return Err("Client UDP address unknown");
+    }
+
+    // Get the actual UDP connection data of host and client
+    let host_udp_con = ClientConnectionDescriptor::from_string(&db_host_session.theater_udp_handle);
+    let client_udp_con =
+        ClientConnectionDescriptor::from_string(&db_client_session.theater_udp_handle);
+
+    // First, set the default values if no TURN server is available
+    db_participant.host_expected_client_ip = client_udp_con.client_ip.clone();
+    db_participant.host_expected_client_port = client_udp_con.client_port as i32;
+    db_participant.client_expected_host_ip = host_udp_con.client_ip.clone();
+    db_participant.client_expected_host_port = host_udp_con.client_port as i32;
+
+    let enter_own_game = db_client_session.persona_id == db_game.persona_id;
+    if enter_own_game || !sstate.turn.enabled {
+        // We
+        // - are entering our own game, or
+        // - don't have any TURN server available.
+        // Let's hope for the best....
+        info!(target: "turn", "Need TURN: false");
+        return Ok(());
+    }
+
+    let Ok(Some(db_client_account)) = account::Entity::find_by_id(db_client_session.user_id)
+        .one(&*sstate.database)
+        .await
+    else {
+        return Err("Client account not found.");
+    };
+    let Ok(Some(db_host_account)) = account::Entity::find_by_id(db_host_session.user_id)
+        .one(&*sstate.database)
+        .await
+    else {
+        return Err("Host account not found");
+    };
+
+    let mut need_turn = true;
+    let client_nat_type = db_client_session.nat_type;
+    let host_nat_type = db_host_session.nat_type;
+
+    // We can avoid using the TURN server if
+    // - the host NAT type is open
+    // - the host NAT type is moderate and the client NAT type is moderate (or open)
+    // Otherwise, we probably need to use the TURN server if
+    // - the host NAT type is strict
+    // - the host NAT type is moderate and the client NAT type is strict
+    if host_nat_type == 1 {
+        need_turn = false;
+    }
+    if host_nat_type == 2 && (client_nat_type == 2 || client_nat_type == 1) {
+        need_turn = false;
+    }
+
+    need_turn = need_turn || db_host_account.force_server_turn || db_client_account.force_client_turn;
+    info!(target: "turn", "Need TURN: {}", need_turn);
+    if !need_turn {
+        // Apparently, no TURN server required.
+        // Therefore, we advertise the direct connection.
+        return Ok(());
+    }
+
+    // Use the TURN server for the connection
+    let turn_request_body = TurnRequestBody {
+        client_ip_0: client_udp_con.client_ip.clone(),
+        client_port_0: client_udp_con.client_port,
+        client_ip_1: host_udp_con.client_ip.clone(),
+        client_port_1: host_udp_con.client_port,
+    };
+    // Send the TURN request to the TURN server
+    let started_at = Instant::now();
+    let Ok(response) = reqwest::Client::new()
+        .post(format!(
+            "http://{}:{}/launch",
+            sstate.turn.control_host, sstate.turn.control_port
+        ))
+        .json(&turn_request_body)
+        .send()
+        .await
+    else {
+        sstate.metrics.record_relay_request("turn", started_at, false);
+        return Err("Failed to send TURN request");
+    };
+
+    let Ok(turn_response) = response.json::<TurnResponseBody>().await else {
+        sstate.metrics.record_relay_request("turn", started_at, false);
+        return Err("Failed to parse TURN response");
+    };
+    sstate
+        .metrics
+        .record_relay_request("turn", started_at, turn_response.success);
+
+    if !turn_response.success {
+        return Err("TURN server failed to create connection");
+    }
+
+    debug!(target: "turn", "TURN response: {:?}", turn_response);
+
+    let (Some(turn_client_port), Some(turn_host_port)) =
+        (turn_response.relay_port_0, turn_response.relay_port_1)
+    else {
+        return Err("TURN response without relay ports");
+    };
+
+    // Set TURN-relayed connection data
+    db_participant.host_expected_client_ip = sstate.turn.external_ip.clone();
+    db_participant.host_expected_client_port = turn_host_port as i32;
+    db_participant.client_expected_host_ip = sstate.turn.external_ip.clone();
+    db_participant.client_expected_host_port = turn_client_port as i32;
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use indexmap::IndexMap;
+use sea_orm::entity::*;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::client_connection::ClientConnectionDescriptor;
+use crate::handler::submit_packet;
+use crate::orm::model::{game, participant, persona};
+use crate::packet::{DataMode, DataPacket, PacketMode};
+use crate::sharedstate::SharedState;
+
+use super::utils_nat::assign_peer_addresses;
+
+
+// Number of participants that are in the game or about to join it
+pub async fn count_joined_participants(game_id: i64, sstate: &Arc<SharedState>) -> u64 {
+    sstate
+        .registry
+        .get_participants_by_game(game_id)
+        .iter()
+        .filter(|db_participant| db_participant.queue_pos <= 0)
+        .count() as u64
+}
+
+// Queued participants, ordered by their queue position
+pub async fn get_queued_participants(game_id: i64, sstate: &Arc<SharedState>) -> Vec<participant::Model> {
+    let mut db_queued: Vec<participant::Model> = sstate
+        .registry
+        .get_participants_by_game(game_id)
+        .into_iter()
+        .filter(|db_participant| db_participant.queue_pos > 0)
+        .collect();
+    db_queued.sort_by_key(|db_participant| db_participant.queue_pos);
+    db_queued
+}
+
+async fn get_theater_connection(
+    persona_id: i64,
+    sstate: &Arc<SharedState>,
+) -> Option<ClientConnectionDescriptor> {
+    let db_session = sstate.registry.get_session_by_persona(persona_id)?;
+    if db_session.theater_tcp_handle.is_empty() {
+        return None;
+    }
+    Some(ClientConnectionDescriptor::from_string(
+        &db_session.theater_tcp_handle,
+    ))
+}
+
+// Send EGRQ to the host (join server request)
+pub async fn send_join_request(
+    db_game: &game::Model,
+    db_participant: &participant::Model,
+    sstate: &Arc<SharedState>,
+) -> Result<(), &'static str> {
+    let Some(host_con_descr) = get_theater_connection(db_game.persona_id, sstate).await else {
+        return Err("Host connection not found");
+    };
+    let Ok(Some(db_client_persona)) = persona::Entity::find_by_id(db_participant.persona_id)
+        .one(&*sstate.database)
+        .await
+    else {
+        return Err("Client persona not found");
+    };
+
+    let mut egrq_hm = IndexMap::new();
+    egrq_hm.insert("R-INT-PORT".to_string(), db_participant.remote_int_port.to_string());
+    egrq_hm.insert("R-INT-IP".to_string(), db_participant.remote_int_ip.to_string());
+
+    // Add the client IP and port (TURN-aware)
+    egrq_hm.insert("IP".to_string(), db_participant.host_expected_client_ip.to_string());
+    egrq_hm.insert("PORT".to_string(), db_participant.host_expected_client_port.to_string());
+
+    egrq_hm.insert("NAME".to_string(), db_client_persona.name.to_string());
+    egrq_hm.insert("PTYPE".to_string(), "P".to_string());
+    egrq_hm.insert("TICKET".to_string(), db_participant.ticket.to_string());
+    egrq_hm.insert("PID".to_string(), db_client_persona.id.to_string());
+    egrq_hm.insert("UID".to_string(), db_client_persona.user_id.to_string());
+    egrq_hm.insert("LID".to_string(), db_game.lobby_id.to_string());
+    egrq_hm.insert("GID".to_string(), db_game.id.to_string());
+
+    let egrq_request = DataPacket {
+        packet_mode: PacketMode::FeslPingOrTheaterResponse,
+        mode: DataMode::THEATER_EGRQ,
+        packet_id: 0,
+        data: egrq_hm,
+    };
+    submit_packet(egrq_request, &host_con_descr, sstate, 0).await;
+    Ok(())
+}
+
+// Moves queued players into free slots (unless the host dequeues manually) and sends the
+// new queue positions to the players that are still waiting.
+pub async fn advance_queue(game_id: i64, sstate: &Arc<SharedState>) {
+    let Some(db_game) = sstate.registry.get_game(game_id) else {
+        return;
+    };
+
+    if !db_game.disable_autodequeue {
+        // Each admission takes a free slot, so concurrent calls cannot overfill the game
+        while let Some(mut db_dequeued) =
+            sstate.registry.admit_queued_participant(game_id, db_game.max_players)
+        {
+            debug!(target: "theater", "Dequeuing persona {} into game {}", db_dequeued.persona_id, game_id);
+            if assign_peer_addresses(&db_game, &mut db_dequeued, sstate).await.is_err() {
+                sstate.registry.remove_participant(db_dequeued.id);
+                continue;
+            }
+            let Some(db_dequeued) =
+                sstate.registry.modify_participant(db_dequeued.id, |participant| {
+                    participant.host_expected_client_ip = db_dequeued.host_expected_client_ip.clone();
+                    participant.host_expected_client_port = db_dequeued.host_expected_client_port;
+                    participant.client_expected_host_ip = db_dequeued.client_expected_host_ip.clone();
+                    participant.client_expected_host_port = db_dequeued.client_expected_host_port;
+                })
+            else {
+                continue;
+            };
+            if send_join_request(&db_game, &db_dequeued, sstate).await.is_err() {
+                sstate.registry.remove_participant(db_dequeued.id);
+            }
+        }
+    }
+
+    // Close the gaps in the queue and report the positions
+    let db_queued = get_queued_participants(game_id, sstate).await;
+    let queue_len = db_queued.len();
+    for (queue_idx, db_participant) in db_queued.into_iter().enumerate() {
+        let queue_pos = queue_idx as i32 + 1;
+        let persona_id = db_participant.persona_id;
+        if db_participant.queue_pos != queue_pos {
+            sstate.registry.modify_participant(db_participant.id, |participant| {
+                // Only players that are still queued are moved up
+                if participant.queue_pos > 0 {
+                    participant.queue_pos = queue_pos;
+                }
+            });
+        }
+        let Some(client_con_descr) = get_theater_connection(persona_id, sstate).await else {
+            continue;
+        };
+        let mut qent_hm = IndexMap::new();
+        qent_hm.insert("QPOS".to_string(), queue_pos.to_string());
+        qent_hm.insert("QLEN".to_string(), queue_len.to_string());
+        qent_hm.insert("LID".to_string(), db_game.lobby_id.to_string());
+        qent_hm.insert("GID".to_string(), db_game.id.to_string());
+
+        let qent_update = DataPacket {
+            packet_mode: PacketMode::FeslPingOrTheaterResponse,
+            mode: DataMode::THEATER_QENT,
+            packet_id: 0,
+            data: qent_hm,
+        };
+        submit_packet(qent_update, &client_con_descr, sstate, 0).await;
+    }
+}
@@ -100,7 +100,9 @@ impl Listener {
         shared_state.udp_sockets.insert(port, atomic_socket.clone());
 
         tokio::spawn(async move {
-            let mut framed = UdpFramed::new(atomic_socket, DataPacketCodec);
+            let codec =
+                DataPacketCodec::for_datagrams(shared_state.max_frame_size, shared_state.metrics.clone());
+            let mut framed = UdpFramed::new(atomic_socket, codec);
             while let Some(frame) = framed.next().await {
                 match frame {
                     Ok((data_packet, addr)) => {
@@ -112,12 +114,18 @@ impl Listener {
                             addr.port(),
                         );
                         debug!(target: "net", "[{}->SERVER]: {:?}", ccon.to_string(), data_packet);
+                        shared_state.metrics.record_packet_in(&data_packet);
+                        let mode = data_packet.mode.value().to_string();
+                        let txn = data_packet.data.get("TXN").cloned().unwrap_or_default();
                         match handler
                             .handle_packet(data_packet, ccon, shared_state.clone())
                             .await
                         {
                             Ok(_) => {}
-                            Err(e) => debug!(target: "net", "Failed to handle packet: {}", e),
+                            Err(e) => {
+                                debug!(target: "net", "Failed to handle packet: {}", e);
+                                shared_state.metrics.record_handler_error(&mode, &txn);
+                            }
                         }
                     }
                     Err(e) => debug!(target: "net", "Error reading from stream: {}", e),
@@ -162,7 +170,7 @@ async fn handle_plain_tcp_connection(
                 SendDataType::Data(packet) => {
                     // Send packet to client
                     debug!(target: "net", "[Server=>{}]: {:?}", outgoing_pkg_ccon.to_string(), packet);
-                    if let Err(err) = write_stream.write_all(&packet.to_bytes()).await {
+                    if let Err(err) = write_stream.write_all(&packet.to_fragmented_bytes()).await {
                         debug!(target: "net", "Failed to send message to client {}: {:?}", outgoing_pkg_ccon.to_string(), err);
                         break;
                     }
@@ -181,18 +189,25 @@ async fn handle_plain_tcp_connection(
     });
 
     // Process incoming packets using Frame from Tokio
-    let mut framed = FramedRead::new(read_stream, DataPacketCodec);
+    let codec = DataPacketCodec::new(shared_state.max_frame_size, shared_state.metrics.clone());
+    let mut framed = FramedRead::new(read_stream, codec);
     while let Some(frame) = framed.next().await {
         match frame {
             Ok(data_packet) => {
                 debug!(target: "net", "[{}->SERVER]: {:?}", ccon_descriptor.to_string(), data_packet);
+                shared_state.metrics.record_packet_in(&data_packet);
+                let mode = data_packet.mode.value().to_string();
+                let txn = data_packet.data.get("TXN").cloned().unwrap_or_default();
                 // Handle the packet
                 match handler
                     .handle_packet(data_packet, ccon_descriptor.clone(), shared_state.clone())
                     .await
                 {
                     Ok(_) => {}
-                    Err(e) => debug!(target: "net", "Failed to handle packet: {}", e),
+                    Err(e) => {
+                        debug!(target: "net", "Failed to handle packet: {}", e);
+                        shared_state.metrics.record_handler_error(&mode, &txn);
+                    }
                 }
             }
             Err(e) => debug!(target: "net", "Error reading from stream: {}", e),
@@ -270,7 +285,7 @@ async fn handle_tls_tcp_connection(
                 SendDataType::Data(packet) => {
                     // Send packet to client
                     debug!(target: "net", "[Server=>{}]: {:?}", outgoing_pkg_ccon.to_string(), packet);
-                    if let Err(err) = write_stream.write_all(&packet.to_bytes()).await {
+                    if let Err(err) = write_stream.write_all(&packet.to_fragmented_bytes()).await {
                         debug!(target: "net", "Failed to send message to client {}: {:?}", outgoing_pkg_ccon.to_string(), err);
                         break;
                     }
@@ -289,18 +304,25 @@ async fn handle_tls_tcp_connection(
     });
 
     // Process incoming packets using Frame from Tokio
-    let mut framed = FramedRead::new(read_stream, DataPacketCodec);
+    let codec = DataPacketCodec::new(shared_state.max_frame_size, shared_state.metrics.clone());
+    let mut framed = FramedRead::new(read_stream, codec);
     while let Some(frame) = framed.next().await {
         match frame {
             Ok(data_packet) => {
                 debug!(target: "net", "[{}->SERVER]: {:?}", ccon_descriptor.to_string(), data_packet);
+                shared_state.metrics.record_packet_in(&data_packet);
+                let mode = data_packet.mode.value().to_string();
+                let txn = data_packet.data.get("TXN").cloned().unwrap_or_default();
                 // Handle the packet
                 match handler
                     .handle_packet(data_packet, ccon_descriptor.clone(), shared_state.clone())
                     .await
                 {
                     Ok(_) => {}
-                    Err(e) => debug!(target: "net", "Failed to handle packet: {}", e),
+                    Err(e) => {
+                        debug!(target: "net", "Failed to handle packet: {}", e);
+                        shared_state.metrics.record_handler_error(&mode, &txn);
+                    }
                 }
                 //let _ = handler.handle_packet(data_packet, ccon_descriptor.clone(), shared_state.clone()).await;
             }
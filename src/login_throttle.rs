@@ -0,0 +1,206 @@
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::LoginThrottleSection;
+
+// Login attempts are counted per account (email or console name) and per source IP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleKey<'a> {
+    Account(&'a str),
+    Ip(&'a str),
+}
+
+impl ThrottleKey<'_> {
+    fn to_key(self) -> String {
+        match self {
+            ThrottleKey::Account(account) => format!("account:{}", account.to_lowercase()),
+            ThrottleKey::Ip(ip) => format!("ip:{}", ip),
+        }
+    }
+
+    // Label of the metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ThrottleKey::Account(_) => "account",
+            ThrottleKey::Ip(_) => "ip",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ThrottleEntry {
+    // Failures within the window, oldest first
+    failures: VecDeque<Instant>,
+    last_failure_at: Option<Instant>,
+    // Number of lockouts so far; every lockout doubles the duration of the next one
+    n_lockouts: u32,
+    locked_until: Option<Instant>,
+}
+
+impl ThrottleEntry {
+    fn prune_failures(&mut self, window: Duration, now: Instant) {
+        while let Some(failed_at) = self.failures.front() {
+            if now.duration_since(*failed_at) <= window {
+                break;
+            }
+            self.failures.pop_front();
+        }
+    }
+
+    fn remaining_lockout(&self, now: Instant) -> Option<Duration> {
+        self.locked_until
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now)
+    }
+}
+
+// State of a key, as reported to the admin API
+#[derive(Debug, Clone)]
+pub struct ThrottleStatus {
+    pub key: String,
+    pub n_failures: usize,
+    pub n_lockouts: u32,
+    pub locked_for: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub struct LoginThrottle {
+    config: LoginThrottleSection,
+    entries: DashMap<String, ThrottleEntry>,
+    last_pruned_at: Mutex<Instant>,
+}
+
+impl LoginThrottle {
+    pub fn new(config: &LoginThrottleSection) -> Self {
+        Self {
+            config: config.clone(),
+            entries: DashMap::new(),
+            last_pruned_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.config.window_secs)
+    }
+
+    fn max_failures(&self, key: ThrottleKey) -> usize {
+        match key {
+            ThrottleKey::Account(_) => self.config.max_failures_per_account,
+            ThrottleKey::Ip(_) => self.config.max_failures_per_ip,
+        }
+    }
+
+    fn lockout_duration(&self, n_lockouts: u32) -> Duration {
+        let lockout_secs = self
+            .config
+            .lockout_secs
+            .saturating_mul(1u64.checked_shl(n_lockouts).unwrap_or(u64::MAX))
+            .min(self.config.max_lockout_secs);
+        Duration::from_secs(lockout_secs)
+    }
+
+    // Remaining lockout of the keys (the longest one), if any of them is locked
+    pub fn check(&self, keys: &[ThrottleKey]) -> Option<Duration> {
+        if !self.config.enabled {
+            return None;
+        }
+        let now = Instant::now();
+        keys.iter()
+            .filter_map(|key| {
+                self.entries
+                    .get(&key.to_key())
+                    .and_then(|entry| entry.remaining_lockout(now))
+            })
+            .max()
+    }
+
+    // Records a failed attempt. Returns the keys that got locked by it.
+    pub fn record_failure<'a>(&self, keys: &[ThrottleKey<'a>]) -> Vec<ThrottleKey<'a>> {
+        if !self.config.enabled {
+            return Vec::new();
+        }
+        self.prune();
+
+        let now = Instant::now();
+        let mut locked_keys = Vec::new();
+        for key in keys {
+            let mut entry = self.entries.entry(key.to_key()).or_default();
+            entry.prune_failures(self.window(), now);
+            entry.failures.push_back(now);
+            entry.last_failure_at = Some(now);
+            if entry.remaining_lockout(now).is_none() && entry.failures.len() >= self.max_failures(*key) {
+                let lockout = self.lockout_duration(entry.n_lockouts);
+                entry.locked_until = Some(now + lockout);
+                entry.n_lockouts = entry.n_lockouts.saturating_add(1);
+                entry.failures.clear();
+                locked_keys.push(*key);
+            }
+        }
+        locked_keys
+    }
+
+    // A successful login clears the failures of the account. The IP keeps its record,
+    // so that a single valid account cannot be used to reset the limit of the IP.
+    pub fn record_success(&self, keys: &[ThrottleKey]) {
+        for key in keys {
+            if let ThrottleKey::Account(_) = key {
+                self.entries.remove(&key.to_key());
+            }
+        }
+    }
+
+    // Removes the record of the key (e.g. "account:player@example.com" or "ip:1.2.3.4")
+    pub fn unlock(&self, key: &str) -> bool {
+        self.entries.remove(key).is_some()
+    }
+
+    pub fn get_status(&self) -> Vec<ThrottleStatus> {
+        let now = Instant::now();
+        let mut status: Vec<ThrottleStatus> = self
+            .entries
+            .iter()
+            .map(|entry| ThrottleStatus {
+                key: entry.key().to_string(),
+                n_failures: entry
+                    .failures
+                    .iter()
+                    .filter(|failed_at| now.duration_since(**failed_at) <= self.window())
+                    .count(),
+                n_lockouts: entry.n_lockouts,
+                locked_for: entry.remaining_lockout(now),
+            })
+            .collect();
+        status.sort_by(|a, b| a.key.cmp(&b.key));
+        status
+    }
+
+    pub fn count_locked(&self) -> usize {
+        let now = Instant::now();
+        self.entries
+            .iter()
+            .filter(|entry| entry.remaining_lockout(now).is_some())
+            .count()
+    }
+
+    // Forgets the keys that are neither locked nor failed recently. The escalation
+    // is kept until the maximum lockout has passed without failures.
+    fn prune(&self) {
+        let now = Instant::now();
+        {
+            let mut last_pruned_at = self.last_pruned_at.lock().unwrap();
+            if now.duration_since(*last_pruned_at) < self.window() {
+                return;
+            }
+            *last_pruned_at = now;
+        }
+        let keep_for = self.window().max(Duration::from_secs(self.config.max_lockout_secs));
+        self.entries.retain(|_, entry| {
+            entry.remaining_lockout(now).is_some()
+                || entry
+                    .last_failure_at
+                    .is_some_and(|failed_at| now.duration_since(failed_at) <= keep_for)
+        });
+    }
+}
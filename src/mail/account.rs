@@ -0,0 +1,122 @@
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use std::sync::Arc;
+
+use crate::orm::model::{account, persona};
+use crate::sharedstate::SharedState;
+use crate::utils::auth::account_tokens::{issue_account_token, AccountTokenPurpose};
+
+use super::{Mail, MailErr};
+
+// Sends the link that verifies the email address of the account
+pub async fn send_verification_mail(
+    db_account: &account::Model,
+    sstate: &Arc<SharedState>,
+) -> Result<(), MailErr> {
+    let mailer = &sstate.mailer;
+    // Checked first, as a new token replaces the link of the previous mail
+    if !mailer.try_acquire("verify_email", db_account.id) {
+        return Err(MailErr::RateLimited);
+    }
+    let lifetime =
+        chrono::Duration::seconds(mailer.config().verification_token_lifetime_secs as i64);
+    let Ok(token) = issue_account_token(
+        db_account.id,
+        AccountTokenPurpose::VerifyEmail,
+        lifetime,
+        &sstate.database,
+    )
+    .await
+    else {
+        return Err(MailErr::DBError);
+    };
+
+    let mail = Mail {
+        to: db_account.email.to_string(),
+        subject: "Verify your MordorWide account".to_string(),
+        body: format!(
+            "Welcome to MordorWide!\n\n\
+            Please verify your email address by opening the following link:\n\n{}\n\n\
+            The link is valid for {} hours. If you did not create this account, you can \
+            ignore this mail.\n",
+            mailer.link("/verify", &token),
+            lifetime.num_hours()
+        ),
+    };
+    mailer.send(mail).await
+}
+
+// Sends the link to choose a new password
+pub async fn send_password_reset_mail(
+    db_account: &account::Model,
+    sstate: &Arc<SharedState>,
+) -> Result<(), MailErr> {
+    let mailer = &sstate.mailer;
+    // Checked first, as a new token replaces the link of the previous mail
+    if !mailer.try_acquire("reset_password", db_account.id) {
+        return Err(MailErr::RateLimited);
+    }
+    let lifetime = chrono::Duration::seconds(mailer.config().reset_token_lifetime_secs as i64);
+    let Ok(token) = issue_account_token(
+        db_account.id,
+        AccountTokenPurpose::ResetPassword,
+        lifetime,
+        &sstate.database,
+    )
+    .await
+    else {
+        return Err(MailErr::DBError);
+    };
+
+    let mail = Mail {
+        to: db_account.email.to_string(),
+        subject: "Reset your MordorWide password".to_string(),
+        body: format!(
+            "A new password has been requested for your MordorWide account.\n\n\
+            Choose a new password by opening the following link:\n\n{}\n\n\
+            The link is valid for {} minutes. If you did not request it, you can ignore \
+            this mail; your password stays the same.\n",
+            mailer.link("/reset-password", &token),
+            lifetime.num_minutes()
+        ),
+    };
+    mailer.send(mail).await
+}
+
+// Reminds the player of the account name (the email) and the personas
+pub async fn send_account_name_mail(
+    db_account: &account::Model,
+    sstate: &Arc<SharedState>,
+) -> Result<(), MailErr> {
+    if !sstate.mailer.try_acquire("account_name", db_account.id) {
+        return Err(MailErr::RateLimited);
+    }
+    let Ok(db_personas) = persona::Entity::find()
+        .filter(persona::Column::UserId.eq(db_account.id))
+        .order_by(persona::Column::Id, sea_orm::Order::Asc)
+        .all(&*sstate.database)
+        .await
+    else {
+        return Err(MailErr::DBError);
+    };
+    let persona_names = if db_personas.is_empty() {
+        "  (none)".to_string()
+    } else {
+        db_personas
+            .iter()
+            .map(|db_persona| format!("  {}", db_persona.name))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let mail = Mail {
+        to: db_account.email.to_string(),
+        subject: "Your MordorWide account name".to_string(),
+        body: format!(
+            "You asked for the name of your MordorWide account.\n\n\
+            Account name: {}\n\nPersonas:\n{}\n",
+            db_account.email, persona_names
+        ),
+    };
+    sstate.mailer.send(mail).await
+}
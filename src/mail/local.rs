@@ -0,0 +1,52 @@
+use lettre::Message;
+use std::path::PathBuf;
+use tracing::info;
+
+use super::{MailErr, MailTransport};
+
+// Logs the mails, e.g. for local testing
+#[derive(Debug)]
+pub struct StdoutMailTransport;
+
+#[async_trait::async_trait]
+impl MailTransport for StdoutMailTransport {
+    async fn send(&self, message: Message) -> Result<(), MailErr> {
+        let formatted = String::from_utf8_lossy(&message.formatted()).to_string();
+        info!(target: "mail", "Mail (not sent):\n{}", formatted);
+        Ok(())
+    }
+}
+
+// Writes every mail to a .eml file in the directory
+#[derive(Debug)]
+pub struct FileMailTransport {
+    dir: PathBuf,
+}
+
+impl FileMailTransport {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl MailTransport for FileMailTransport {
+    async fn send(&self, message: Message) -> Result<(), MailErr> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| MailErr::Transport(e.to_string()))?;
+        let file_name = format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%d%H%M%S"),
+            uuid::Uuid::new_v4().simple()
+        );
+        let path = self.dir.join(file_name);
+        tokio::fs::write(&path, message.formatted())
+            .await
+            .map_err(|e| MailErr::Transport(e.to_string()))?;
+        info!(target: "mail", "Mail written to {}", path.display());
+        Ok(())
+    }
+}
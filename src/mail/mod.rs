@@ -0,0 +1,126 @@
+use dashmap::DashMap;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::Message;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::config::{AccountWebSection, MailSection};
+
+pub mod account;
+mod local;
+mod smtp;
+
+use local::{FileMailTransport, StdoutMailTransport};
+use smtp::SmtpMailTransport;
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum MailErr {
+    // Too many mails of the kind for the account
+    RateLimited,
+    InvalidAddress(String),
+    Transport(String),
+    DBError,
+}
+
+impl fmt::Display for MailErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailErr::RateLimited => write!(f, "too many mails"),
+            MailErr::InvalidAddress(address) => write!(f, "invalid mail address '{}'", address),
+            MailErr::Transport(e) => write!(f, "failed to send the mail: {}", e),
+            MailErr::DBError => write!(f, "database error"),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+pub trait MailTransport: Send + Sync + fmt::Debug {
+    async fn send(&self, message: Message) -> Result<(), MailErr>;
+}
+
+fn build_transport(config: &MailSection) -> Box<dyn MailTransport> {
+    match config.transport.as_str() {
+        "smtp" => Box::new(SmtpMailTransport::new(config)),
+        "file" => Box::new(FileMailTransport::new(&config.file_dir)),
+        _ => Box::new(StdoutMailTransport),
+    }
+}
+
+#[derive(Debug)]
+pub struct Mailer {
+    config: MailSection,
+    // Base URL of the links in the mails
+    public_url: String,
+    transport: Box<dyn MailTransport>,
+    // Recently sent mails per kind and account, oldest first
+    sent: DashMap<String, VecDeque<Instant>>,
+}
+
+impl Mailer {
+    pub fn new(config: &MailSection, account_web: &AccountWebSection) -> Self {
+        Self {
+            config: config.clone(),
+            public_url: account_web.public_url.trim_end_matches('/').to_string(),
+            transport: build_transport(config),
+            sent: DashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &MailSection {
+        &self.config
+    }
+
+    // URL of a page of the account web
+    pub fn page_url(&self, path: &str) -> String {
+        format!("{}{}", self.public_url, path)
+    }
+
+    pub fn link(&self, path: &str, token: &str) -> String {
+        format!("{}?token={}", self.page_url(path), token)
+    }
+
+    // Counts a mail of the kind against the hourly limit of the account. Returns false
+    // if the limit has been reached.
+    pub fn try_acquire(&self, kind: &str, user_id: i64) -> bool {
+        let now = Instant::now();
+        let window = Duration::from_secs(3600);
+        let mut sent = self.sent.entry(format!("{}:{}", kind, user_id)).or_default();
+        while let Some(sent_at) = sent.front() {
+            if now.duration_since(*sent_at) <= window {
+                break;
+            }
+            sent.pop_front();
+        }
+        if sent.len() >= self.config.max_mails_per_hour {
+            return false;
+        }
+        sent.push_back(now);
+        true
+    }
+
+    pub async fn send(&self, mail: Mail) -> Result<(), MailErr> {
+        let Ok(from) = self.config.from.parse::<Mailbox>() else {
+            return Err(MailErr::InvalidAddress(self.config.from.to_string()));
+        };
+        let Ok(to) = mail.to.parse::<Mailbox>() else {
+            return Err(MailErr::InvalidAddress(mail.to));
+        };
+        let message = Message::builder()
+            .from(from)
+            .to(to)
+            .subject(mail.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body)
+            .map_err(|e| MailErr::Transport(e.to_string()))?;
+        self.transport.send(message).await
+    }
+}
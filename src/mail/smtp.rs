@@ -0,0 +1,52 @@
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::config::MailSection;
+
+use super::{MailErr, MailTransport};
+
+#[derive(Debug)]
+pub struct SmtpMailTransport {
+    transport: Result<AsyncSmtpTransport<Tokio1Executor>, String>,
+}
+
+impl SmtpMailTransport {
+    // An invalid relay is reported when a mail is sent, so that the server still starts
+    pub fn new(config: &MailSection) -> Self {
+        let builder = match config.smtp_security.as_str() {
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host),
+            "none" => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                &config.smtp_host,
+            )),
+            _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host),
+        };
+        let transport = builder
+            .map(|builder| {
+                let builder = builder.port(config.smtp_port);
+                if config.smtp_user.is_empty() {
+                    builder.build()
+                } else {
+                    builder
+                        .credentials(Credentials::new(
+                            config.smtp_user.to_string(),
+                            config.smtp_password.to_string(),
+                        ))
+                        .build()
+                }
+            })
+            .map_err(|e| e.to_string());
+        Self { transport }
+    }
+}
+
+#[async_trait::async_trait]
+impl MailTransport for SmtpMailTransport {
+    async fn send(&self, message: Message) -> Result<(), MailErr> {
+        let transport = self.transport.as_ref().map_err(|e| MailErr::Transport(e.to_string()))?;
+        transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| MailErr::Transport(e.to_string()))
+    }
+}
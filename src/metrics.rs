@@ -0,0 +1,309 @@
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use dashmap::DashMap;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::client_connection::{ProtoType, ServiceType};
+use crate::handler::fesl::is_known_txn;
+use crate::packet::DataPacket;
+use crate::sharedstate::SharedState;
+
+// Help texts of the counters, in the order they are rendered
+const COUNTER_HELP: [(&str, &str); 11] = [
+    ("mordorwide_packets_in_total", "Packets received from clients"),
+    ("mordorwide_packets_out_total", "Packets submitted to clients"),
+    ("mordorwide_handler_errors_total", "Packets whose handler returned an error"),
+    ("mordorwide_malformed_frames_total", "Frames from clients that were dropped by reason"),
+    ("mordorwide_malformed_packets_total", "Packets whose fields could not be decoded"),
+    ("mordorwide_logins_total", "Login attempts by method and result"),
+    ("mordorwide_login_throttled_total", "Login attempts rejected because of a lockout"),
+    ("mordorwide_login_lockouts_total", "Lockouts after repeated failed logins by key"),
+    ("mordorwide_rate_limited_total", "Requests rejected by the rate limit of their route"),
+    ("mordorwide_relay_requests_total", "Requests to the STUN/TURN relays by result"),
+    ("mordorwide_relay_request_duration_seconds", "Duration of the requests to the STUN/TURN relays"),
+];
+
+pub struct MetricsConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+// Counters, keyed by metric name and then by the rendered label set
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: DashMap<String, DashMap<String, f64>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let label_str = render_labels(labels);
+        *self
+            .counters
+            .entry(name.to_string())
+            .or_default()
+            .entry(label_str)
+            .or_insert(0.0) += value;
+    }
+
+    pub fn record_packet_in(&self, packet: &DataPacket) {
+        self.add("mordorwide_packets_in_total", &packet_labels(packet), 1.0);
+    }
+
+    pub fn record_packet_out(&self, packet: &DataPacket) {
+        self.add("mordorwide_packets_out_total", &packet_labels(packet), 1.0);
+    }
+
+    pub fn record_handler_error(&self, mode: &str, txn: &str) {
+        self.add(
+            "mordorwide_handler_errors_total",
+            &[("mode", mode), ("txn", txn_label(txn))],
+            1.0,
+        );
+    }
+
+    // reason: see FrameErr::as_str
+    pub fn record_malformed_frame(&self, reason: &str) {
+        self.add("mordorwide_malformed_frames_total", &[("reason", reason)], 1.0);
+    }
+
+    pub fn record_malformed_packet(&self, packet: &DataPacket) {
+        self.add("mordorwide_malformed_packets_total", &packet_labels(packet), 1.0);
+    }
+
+    // method: "NuLogin", "NuPS3Login" or "NuXBL360Login"
+    pub fn record_login(&self, method: &str, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.add(
+            "mordorwide_logins_total",
+            &[("method", method), ("result", result)],
+            1.0,
+        );
+    }
+
+    pub fn record_login_throttled(&self, method: &str) {
+        self.add("mordorwide_login_throttled_total", &[("method", method)], 1.0);
+    }
+
+    // key: "account" or "ip"
+    pub fn record_login_lockout(&self, key: &str) {
+        self.add("mordorwide_login_lockouts_total", &[("key", key)], 1.0);
+    }
+
+    // route: e.g. "acct/NuAddAccount" or "CGAM"
+    pub fn record_rate_limited(&self, route: &str) {
+        self.add("mordorwide_rate_limited_total", &[("route", route)], 1.0);
+    }
+
+    // relay: "stun" or "turn"
+    pub fn record_relay_request(&self, relay: &str, started_at: Instant, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.add(
+            "mordorwide_relay_requests_total",
+            &[("relay", relay), ("result", result)],
+            1.0,
+        );
+        self.add(
+            "mordorwide_relay_request_duration_seconds_sum",
+            &[("relay", relay)],
+            started_at.elapsed().as_secs_f64(),
+        );
+        self.add(
+            "mordorwide_relay_request_duration_seconds_count",
+            &[("relay", relay)],
+            1.0,
+        );
+    }
+
+    fn render_counters(&self, output: &mut String) {
+        for (name, help) in COUNTER_HELP.iter() {
+            // The duration is rendered as a summary of its _sum and _count series
+            let (metric_type, series_names) = if name.ends_with("_seconds") {
+                ("summary", vec![format!("{}_sum", name), format!("{}_count", name)])
+            } else {
+                ("counter", vec![name.to_string()])
+            };
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, metric_type);
+            for series_name in series_names {
+                let Some(series) = self.counters.get(&series_name) else {
+                    continue;
+                };
+                let sorted: BTreeMap<String, f64> = series
+                    .iter()
+                    .map(|entry| (entry.key().clone(), *entry.value()))
+                    .collect();
+                for (label_str, value) in sorted {
+                    let _ = writeln!(output, "{}{} {}", series_name, label_str, value);
+                }
+            }
+        }
+    }
+}
+
+fn packet_labels(packet: &DataPacket) -> [(&str, &str); 2] {
+    [
+        ("mode", packet.mode.value()),
+        ("txn", txn_label(packet.data.get("TXN").map(|txn| txn.as_str()).unwrap_or(""))),
+    ]
+}
+
+// The TXN is sent by the client, so only the known ones are used as label values.
+// Anything else would let a client create an unbounded number of series.
+fn txn_label(txn: &str) -> &str {
+    if txn.is_empty() || is_known_txn(txn) {
+        txn
+    } else {
+        "other"
+    }
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let rendered: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect();
+    format!("{{{}}}", rendered.join(","))
+}
+
+fn write_gauge(output: &mut String, name: &str, help: &str, series: &BTreeMap<String, u64>) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} gauge", name);
+    for (label_str, value) in series {
+        let _ = writeln!(output, "{}{} {}", name, label_str, value);
+    }
+}
+
+// Renders the counters and the gauges that are read from the current state
+pub async fn render_metrics(sstate: &Arc<SharedState>) -> String {
+    let mut output = String::new();
+    sstate.metrics.render_counters(&mut output);
+
+    // Connected clients per service, protocol and port
+    let mut connected_clients: BTreeMap<String, u64> = BTreeMap::new();
+    for entry in sstate.connections.iter() {
+        let con = entry.key();
+        let service = match con.service_type {
+            ServiceType::Fesl => "fesl",
+            ServiceType::Theater => "theater",
+        };
+        let proto = match con.proto_type {
+            ProtoType::Tcp => "tcp",
+            ProtoType::Udp => "udp",
+            ProtoType::RemoteUdp => "remoteudp",
+        };
+        let port = con.host_port.to_string();
+        *connected_clients
+            .entry(render_labels(&[("service", service), ("proto", proto), ("port", &port)]))
+            .or_insert(0) += 1;
+    }
+    write_gauge(
+        &mut output,
+        "mordorwide_connected_clients",
+        "Open client connections",
+        &connected_clients,
+    );
+
+    let n_games = sstate.registry.count_games() as u64;
+    write_gauge(
+        &mut output,
+        "mordorwide_active_games",
+        "Games registered in the Theater",
+        &BTreeMap::from([(String::new(), n_games)]),
+    );
+
+    let db_participants = sstate.registry.get_participants();
+    let n_players = db_participants
+        .iter()
+        .filter(|db_participant| db_participant.queue_pos <= 0)
+        .count() as u64;
+    let n_queued = db_participants.len() as u64 - n_players;
+    write_gauge(
+        &mut output,
+        "mordorwide_active_players",
+        "Players in or waiting for a game",
+        &BTreeMap::from([
+            (render_labels(&[("state", "joined")]), n_players),
+            (render_labels(&[("state", "queued")]), n_queued),
+        ]),
+    );
+
+    // NAT types as determined by the ECHO handler
+    let mut nat_types: BTreeMap<String, u64> = BTreeMap::new();
+    let db_sessions = sstate.registry.get_sessions();
+    for (nat_type, nat_name) in [(0, "unknown"), (1, "open"), (2, "simple"), (3, "strict")] {
+        let n_sessions = db_sessions
+            .iter()
+            .filter(|db_session| db_session.nat_type == nat_type)
+            .count() as u64;
+        nat_types.insert(render_labels(&[("nat_type", nat_name)]), n_sessions);
+    }
+    write_gauge(
+        &mut output,
+        "mordorwide_sessions_by_nat_type",
+        "Sessions by NAT type",
+        &nat_types,
+    );
+
+    let n_locked = sstate.login_throttle.count_locked() as u64;
+    write_gauge(
+        &mut output,
+        "mordorwide_login_lockouts_active",
+        "Accounts and IPs that are locked after failed logins",
+        &BTreeMap::from([(String::new(), n_locked)]),
+    );
+
+    output
+}
+
+async fn get_metrics(State(sstate): State<Arc<SharedState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render_metrics(&sstate).await,
+    )
+}
+
+pub async fn spawn_metrics_endpoint(
+    config: MetricsConfig,
+    shared_state: Arc<SharedState>,
+) -> Option<JoinHandle<()>> {
+    let router = Router::new()
+        .route("/metrics", get(get_metrics))
+        .with_state(shared_state);
+
+    let addr = format!("{}:{}", config.host, config.port);
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(target: "metrics", "Failed to bind the metrics endpoint to {}: {}", addr, e);
+            return None;
+        }
+    };
+    info!(target: "metrics", "Metrics endpoint listening on {}/metrics", addr);
+
+    Some(tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, router).await {
+            error!(target: "metrics", "Metrics endpoint stopped: {}", e);
+        }
+    }))
+}
@@ -0,0 +1,425 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::entity::*;
+use sea_orm::sea_query::{
+    Alias, ColumnDef, Index, IndexCreateStatement, Table, TableAlterStatement,
+    TableCreateStatement,
+};
+use sea_orm::{
+    ConnectionTrait, DatabaseTransaction, DbBackend, DbErr, Schema, Statement, TransactionTrait,
+};
+use tracing::info;
+
+use super::model::{
+    account, account_token, association, ban, config, game, linked_identity, lobby, login_token,
+    message, participant, persona, schema_migration, session, stat, stat_period,
+};
+
+// A single schema change. The steps are written so that they can be applied to
+// databases that were created before the migrations were tracked.
+enum MigrationStep {
+    // Skipped if the table exists
+    CreateTable(TableCreateStatement),
+    // Skipped if the column exists (table name, column name, statement)
+    AddColumn(&'static str, &'static str, TableAlterStatement),
+    // Skipped if the old column does not exist (table name, old column name, statement)
+    RenameColumn(&'static str, &'static str, TableAlterStatement),
+    // Skipped if the index exists
+    CreateIndex(IndexCreateStatement),
+}
+
+struct Migration {
+    version: i32,
+    name: &'static str,
+    steps: fn(&Schema) -> Vec<MigrationStep>,
+}
+
+// All migrations, in the order they are applied. Never change a migration that has
+// been released; add a new one instead (e.g. an AddColumn step for a new model field).
+const MIGRATIONS: [Migration; 10] = [
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        steps: initial_schema,
+    },
+    Migration {
+        version: 2,
+        name: "association_stat_message_lobby",
+        steps: association_stat_message_lobby,
+    },
+    Migration {
+        version: 3,
+        name: "participant_internal_address",
+        steps: participant_internal_address,
+    },
+    Migration {
+        version: 4,
+        name: "lookup_indexes",
+        steps: lookup_indexes,
+    },
+    Migration {
+        version: 5,
+        name: "ban_scopes",
+        steps: ban_scopes,
+    },
+    Migration {
+        version: 6,
+        name: "login_tokens",
+        steps: login_tokens,
+    },
+    Migration {
+        version: 7,
+        name: "account_tokens",
+        steps: account_tokens,
+    },
+    Migration {
+        version: 8,
+        name: "linked_identities",
+        steps: linked_identities,
+    },
+    Migration {
+        version: 9,
+        name: "ban_scope_target_index",
+        steps: ban_scope_target_index,
+    },
+    Migration {
+        version: 10,
+        name: "stat_periods",
+        steps: stat_periods,
+    },
+];
+
+fn create_table<E: EntityTrait>(schema: &Schema, entity: E) -> MigrationStep {
+    let mut stmt = schema.create_table_from_entity(entity);
+    stmt.if_not_exists();
+    MigrationStep::CreateTable(stmt)
+}
+
+fn create_index<E: EntityTrait, C: ColumnTrait>(name: &str, entity: E, column: C) -> MigrationStep {
+    MigrationStep::CreateIndex(
+        Index::create()
+            .if_not_exists()
+            .name(name)
+            .table(entity)
+            .col(column)
+            .to_owned(),
+    )
+}
+
+// Index over several columns
+fn create_multi_column_index<E: EntityTrait, C: ColumnTrait>(
+    name: &str,
+    entity: E,
+    columns: Vec<C>,
+) -> MigrationStep {
+    let mut stmt = Index::create();
+    stmt.if_not_exists().name(name).table(entity);
+    for column in columns {
+        stmt.col(column);
+    }
+    MigrationStep::CreateIndex(stmt.to_owned())
+}
+
+// Unique index over several columns
+fn create_unique_index<E: EntityTrait, C: ColumnTrait>(
+    name: &str,
+    entity: E,
+    columns: Vec<C>,
+) -> MigrationStep {
+    let mut stmt = Index::create();
+    stmt.if_not_exists().unique().name(name).table(entity);
+    for column in columns {
+        stmt.col(column);
+    }
+    MigrationStep::CreateIndex(stmt.to_owned())
+}
+
+fn initial_schema(schema: &Schema) -> Vec<MigrationStep> {
+    vec![
+        create_table(schema, session::Entity),
+        create_table(schema, account::Entity),
+        create_table(schema, persona::Entity),
+        create_table(schema, game::Entity),
+        create_table(schema, participant::Entity),
+        create_table(schema, ban::Entity),
+        create_table(schema, config::Entity),
+    ]
+}
+
+fn association_stat_message_lobby(schema: &Schema) -> Vec<MigrationStep> {
+    vec![
+        create_table(schema, association::Entity),
+        create_table(schema, stat::Entity),
+        create_table(schema, message::Entity),
+        create_table(schema, lobby::Entity),
+    ]
+}
+
+fn participant_internal_address(_schema: &Schema) -> Vec<MigrationStep> {
+    vec![
+        MigrationStep::AddColumn(
+            "Participant",
+            "remote_int_port",
+            Table::alter()
+                .table(participant::Entity)
+                .add_column(
+                    ColumnDef::new(participant::Column::RemoteIntPort)
+                        .integer()
+                        .not_null()
+                        .default(0),
+                )
+                .to_owned(),
+        ),
+        MigrationStep::AddColumn(
+            "Participant",
+            "remote_int_ip",
+            Table::alter()
+                .table(participant::Entity)
+                .add_column(
+                    ColumnDef::new(participant::Column::RemoteIntIp)
+                        .string()
+                        .not_null()
+                        .default(""),
+                )
+                .to_owned(),
+        ),
+    ]
+}
+
+fn lookup_indexes(_schema: &Schema) -> Vec<MigrationStep> {
+    vec![
+        create_index("idx_session_lobby_key", session::Entity, session::Column::LobbyKey),
+        create_index("idx_persona_name", persona::Entity, persona::Column::Name),
+        create_index("idx_game_persona_id", game::Entity, game::Column::PersonaId),
+        create_index("idx_participant_game_id", participant::Entity, participant::Column::GameId),
+    ]
+}
+
+fn ban_scopes(_schema: &Schema) -> Vec<MigrationStep> {
+    vec![
+        MigrationStep::RenameColumn(
+            "Ban",
+            "email_hash",
+            Table::alter()
+                .table(ban::Entity)
+                .rename_column(Alias::new("email_hash"), ban::Column::Target)
+                .to_owned(),
+        ),
+        // Existing bans were account bans
+        MigrationStep::AddColumn(
+            "Ban",
+            "scope",
+            Table::alter()
+                .table(ban::Entity)
+                .add_column(
+                    ColumnDef::new(ban::Column::Scope)
+                        .string()
+                        .not_null()
+                        .default("account"),
+                )
+                .to_owned(),
+        ),
+        MigrationStep::AddColumn(
+            "Ban",
+            "issued_by",
+            Table::alter()
+                .table(ban::Entity)
+                .add_column(
+                    ColumnDef::new(ban::Column::IssuedBy)
+                        .string()
+                        .not_null()
+                        .default(""),
+                )
+                .to_owned(),
+        ),
+        MigrationStep::AddColumn(
+            "Ban",
+            "expires_at",
+            Table::alter()
+                .table(ban::Entity)
+                .add_column(
+                    ColumnDef::new(ban::Column::ExpiresAt)
+                        .timestamp_with_time_zone()
+                        .null(),
+                )
+                .to_owned(),
+        ),
+        MigrationStep::AddColumn(
+            "InGameSession",
+            "mac_addr",
+            Table::alter()
+                .table(session::Entity)
+                .add_column(
+                    ColumnDef::new(session::Column::MacAddr)
+                        .string()
+                        .not_null()
+                        .default(""),
+                )
+                .to_owned(),
+        ),
+        MigrationStep::AddColumn(
+            "InGameSession",
+            "console_id",
+            Table::alter()
+                .table(session::Entity)
+                .add_column(
+                    ColumnDef::new(session::Column::ConsoleId)
+                        .string()
+                        .not_null()
+                        .default(""),
+                )
+                .to_owned(),
+        ),
+        create_index("idx_ban_target", ban::Entity, ban::Column::Target),
+    ]
+}
+
+fn login_tokens(schema: &Schema) -> Vec<MigrationStep> {
+    vec![
+        create_table(schema, login_token::Entity),
+        create_index("idx_login_token_user_id", login_token::Entity, login_token::Column::UserId),
+    ]
+}
+
+fn account_tokens(schema: &Schema) -> Vec<MigrationStep> {
+    vec![
+        create_table(schema, account_token::Entity),
+        create_index(
+            "idx_account_token_user_id",
+            account_token::Entity,
+            account_token::Column::UserId,
+        ),
+    ]
+}
+
+fn linked_identities(schema: &Schema) -> Vec<MigrationStep> {
+    vec![
+        create_table(schema, linked_identity::Entity),
+        // One link per console identity
+        create_unique_index(
+            "idx_linked_identity_external_id",
+            linked_identity::Entity,
+            vec![linked_identity::Column::Platform, linked_identity::Column::ExternalId],
+        ),
+        create_index(
+            "idx_linked_identity_user_id",
+            linked_identity::Entity,
+            linked_identity::Column::UserId,
+        ),
+    ]
+}
+
+fn ban_scope_target_index(_schema: &Schema) -> Vec<MigrationStep> {
+    vec![
+        // Exact bans are looked up by scope and target, IP bans by scope
+        create_multi_column_index(
+            "idx_ban_scope_target",
+            ban::Entity,
+            vec![ban::Column::Scope, ban::Column::Target],
+        ),
+    ]
+}
+
+fn stat_periods(schema: &Schema) -> Vec<MigrationStep> {
+    vec![
+        create_table(schema, stat_period::Entity),
+        // Leaderboards are read by key and period
+        create_multi_column_index(
+            "idx_stat_key_period",
+            stat::Entity,
+            vec![stat::Column::StatKey, stat::Column::PeriodId, stat::Column::PeriodPast],
+        ),
+    ]
+}
+
+async fn column_exists(
+    txn: &DatabaseTransaction,
+    table_name: &str,
+    column_name: &str,
+) -> Result<bool, DbErr> {
+    let backend = txn.get_database_backend();
+    let stmt = match backend {
+        DbBackend::Postgres => Statement::from_sql_and_values(
+            backend,
+            "SELECT COUNT(*) AS n FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1 AND column_name = $2",
+            [table_name.into(), column_name.into()],
+        ),
+        _ => Statement::from_sql_and_values(
+            backend,
+            "SELECT COUNT(*) AS n FROM pragma_table_info(?) WHERE name = ?",
+            [table_name.into(), column_name.into()],
+        ),
+    };
+    let n_hits = match txn.query_one(stmt).await? {
+        Some(row) => row.try_get::<i64>("", "n")?,
+        None => 0,
+    };
+    Ok(n_hits > 0)
+}
+
+async fn apply_migration(db: &DbConn, migration: &Migration) -> Result<(), DbErr> {
+    let backend = db.get_database_backend();
+    let schema = Schema::new(backend);
+
+    // Each migration is applied as a whole or not at all
+    let txn = db.begin().await?;
+    for step in (migration.steps)(&schema) {
+        match step {
+            MigrationStep::CreateTable(stmt) => {
+                txn.execute(backend.build(&stmt)).await?;
+            }
+            MigrationStep::AddColumn(table_name, column_name, stmt) => {
+                if column_exists(&txn, table_name, column_name).await? {
+                    continue;
+                }
+                txn.execute(backend.build(&stmt)).await?;
+            }
+            MigrationStep::RenameColumn(table_name, column_name, stmt) => {
+                if !column_exists(&txn, table_name, column_name).await? {
+                    continue;
+                }
+                txn.execute(backend.build(&stmt)).await?;
+            }
+            MigrationStep::CreateIndex(stmt) => {
+                txn.execute(backend.build(&stmt)).await?;
+            }
+        }
+    }
+    let applied_migration = schema_migration::ActiveModel {
+        version: Set(migration.version),
+        name: Set(migration.name.to_string()),
+        applied_at: Set(chrono::Utc::now()),
+    };
+    applied_migration.insert(&txn).await?;
+    txn.commit().await
+}
+
+// Applies the pending migrations and returns how many were applied
+pub async fn run_migrations(db: &DbConn) -> Result<usize, DbErr> {
+    let backend = db.get_database_backend();
+    let mut stmt = Schema::new(backend).create_table_from_entity(schema_migration::Entity);
+    stmt.if_not_exists();
+    db.execute(backend.build(&stmt)).await?;
+
+    let applied_versions: Vec<i32> = schema_migration::Entity::find()
+        .all(db)
+        .await?
+        .iter()
+        .map(|applied_migration| applied_migration.version)
+        .collect();
+
+    let mut n_applied = 0;
+    for migration in MIGRATIONS.iter() {
+        if applied_versions.contains(&migration.version) {
+            continue;
+        }
+        info!(target: "init", "Applying database migration {} ({})...", migration.version, migration.name);
+        apply_migration(db, migration).await.map_err(|e| {
+            DbErr::Migration(format!(
+                "Migration {} ({}) failed: {}",
+                migration.version, migration.name, e
+            ))
+        })?;
+        n_applied += 1;
+    }
+    Ok(n_applied)
+}
@@ -1,8 +1,9 @@
+pub mod migrations;
 pub mod model;
-use model::{account, ban, config, game, participant, persona, session};
+use model::{config, game, lobby, participant, session};
 use sea_orm::entity::prelude::*;
 use sea_orm::entity::*;
-use sea_orm::{DbBackend, DbErr, Schema};
+use sea_orm::DbErr;
 use tracing::warn;
 
 pub fn build_database_conn_string(
@@ -43,88 +44,6 @@ pub fn build_database_conn_string(
     conn_string
 }
 
-pub async fn create_tables(db: &DbConn) {
-    // Setup Schema helper
-    let schema = Schema::new(DbBackend::Sqlite);
-
-    // Setup table Session
-    if let Err(_) = db
-        .execute(
-            db.get_database_backend()
-                .build(&schema.create_table_from_entity(session::Entity)),
-        )
-        .await
-    {
-        warn!(target: "init", "Unable to create a new table InGameSession. The table probably already exists.");
-    }
-
-    // Setup table Account
-    if let Err(_) = db
-        .execute(
-            db.get_database_backend()
-                .build(&schema.create_table_from_entity(account::Entity)),
-        )
-        .await
-    {
-        warn!(target: "init", "Unable to create a new table Account. The table probably already exists.");
-    }
-
-    // Setup table Persona
-    if let Err(_) = db
-        .execute(
-            db.get_database_backend()
-                .build(&schema.create_table_from_entity(persona::Entity)),
-        )
-        .await
-    {
-        warn!(target: "init", "Unable to create a new table Persona. The table probably already exists.");
-    }
-
-    // Setup table Game
-    if let Err(_) = db
-        .execute(
-            db.get_database_backend()
-                .build(&schema.create_table_from_entity(game::Entity)),
-        )
-        .await
-    {
-        warn!(target: "init", "Unable to create a new table Game. The table probably already exists.");
-    }
-
-    // Setup table Participant
-    if let Err(_) = db
-        .execute(
-            db.get_database_backend()
-                .build(&schema.create_table_from_entity(participant::Entity)),
-        )
-        .await
-    {
-        warn!(target: "init", "Unable to create a new table Participant. The table probably already exists.");
-    }
-
-    // Setup table Ban
-    if let Err(_) = db
-        .execute(
-            db.get_database_backend()
-                .build(&schema.create_table_from_entity(ban::Entity)),
-        )
-        .await
-    {
-        warn!(target: "init", "Unable to create a new table Ban. The table probably already exists.");
-    }
-
-    // Setup table Config + defaults
-    if let Err(_) = db
-        .execute(
-            db.get_database_backend()
-                .build(&schema.create_table_from_entity(config::Entity)),
-        )
-        .await
-    {
-        warn!(target: "init", "Unable to create a new table Config. The table probably already exists.");
-    }
-}
-
 pub async fn clear_old_db_data(db: &DbConn) {
     // Clear old ingamesessions
     if let Err(_) = session::Entity::delete_many().exec(&*db).await {
@@ -222,6 +141,61 @@ pub async fn add_default_configuration_keys(db: &DbConn) {
         };
         let db_max_personas = max_personas_entry.insert(&*db).await.unwrap();
     }
+    // Add STAT_PERIODS
+    if let Ok(None) = config::Entity::find()
+        .filter(config::Column::Key.eq("STAT_PERIODS"))
+        .one(db)
+        .await
+    {
+        let stat_periods_entry = config::ActiveModel {
+            key: Set("STAT_PERIODS".to_string()),
+            // "period id:length in days" pairs, a length of 0 keeps the stats for all time
+            value: Set("0:0,1:1,2:7,3:30,4:365".to_string()),
+            ..Default::default()
+        };
+        let db_stat_periods = stat_periods_entry.insert(db).await.unwrap();
+    }
+    // Add MESSAGE_EXPIRATION_SECONDS
+    if let Ok(None) = config::Entity::find()
+        .filter(config::Column::Key.eq("MESSAGE_EXPIRATION_SECONDS"))
+        .one(&*db)
+        .await
+    {
+        let message_expiration_entry = config::ActiveModel {
+            key: Set("MESSAGE_EXPIRATION_SECONDS".to_string()),
+            // 30 days, used if the sender does not set an expiration
+            value: Set("2592000".to_string()),
+            ..Default::default()
+        };
+        let db_message_expiration = message_expiration_entry.insert(&*db).await.unwrap();
+    }
+    // Add MESSAGE_MAX_EXPIRATION_SECONDS
+    if let Ok(None) = config::Entity::find()
+        .filter(config::Column::Key.eq("MESSAGE_MAX_EXPIRATION_SECONDS"))
+        .one(db)
+        .await
+    {
+        let message_max_expiration_entry = config::ActiveModel {
+            key: Set("MESSAGE_MAX_EXPIRATION_SECONDS".to_string()),
+            // 30 days, the longest expiration a sender may set
+            value: Set("2592000".to_string()),
+            ..Default::default()
+        };
+        let db_message_max_expiration = message_max_expiration_entry.insert(db).await.unwrap();
+    }
+
+    // Add the default lobby (open to all platforms) if there is none
+    if let Ok(0) = lobby::Entity::find().count(&*db).await {
+        let default_lobby_entry = lobby::ActiveModel {
+            id: Set(1),
+            name: Set("lotr-pandemic".to_string()),
+            locale: Set("en_US".to_string()),
+            max_games: Set(1000),
+            platform: Set("".to_string()),
+            enabled: Set(true),
+        };
+        let db_default_lobby = default_lobby_entry.insert(&*db).await.unwrap();
+    }
 
     // Add GetPingSites_minPingSitesToPing
     if let Ok(None) = config::Entity::find()
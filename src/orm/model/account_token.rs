@@ -0,0 +1,26 @@
+use sea_orm::entity::prelude::*;
+
+// Single-use token sent by mail (email verification, password reset).
+// Only the hash of the token is stored.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "AccountToken")]
+pub struct Model {
+    #[sea_orm(primary_key, column_name = "id")]
+    pub id: i64,
+    #[sea_orm(column_name = "user_id")]
+    pub user_id: i64,
+    // "verify_email" or "reset_password"
+    #[sea_orm(column_name = "purpose")]
+    pub purpose: String,
+    #[sea_orm(column_name = "token_hash", unique)]
+    pub token_hash: String,
+    #[sea_orm(column_name = "created_at")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[sea_orm(column_name = "expires_at")]
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
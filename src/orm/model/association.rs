@@ -0,0 +1,22 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "Association")]
+pub struct Model {
+    #[sea_orm(primary_key, column_name = "id")]
+    pub id: i64,
+    #[sea_orm(column_name = "owner_persona_id")]
+    pub owner_persona_id: i64,
+    #[sea_orm(column_name = "member_persona_id")]
+    pub member_persona_id: i64,
+    // "PlasmaFriends", "PlasmaBlock", "PlasmaMute" or "PlasmaRecentPlayers"
+    #[sea_orm(column_name = "asso_type")]
+    pub asso_type: String,
+    #[sea_orm(column_name = "created_at")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -5,10 +5,19 @@ use sea_orm::entity::prelude::*;
 pub struct Model {
     #[sea_orm(primary_key, column_name = "id")]
     pub id: i64,
-    #[sea_orm(column_name = "email_hash")]
-    pub email_hash: String,
+    // account, persona, ip, mac or console
+    #[sea_orm(column_name = "scope")]
+    pub scope: String,
+    // Email hash, persona id, IP address (or CIDR range), MAC address or console id
+    #[sea_orm(column_name = "target")]
+    pub target: String,
     #[sea_orm(column_name = "reason")]
     pub reason: String,
+    #[sea_orm(column_name = "issued_by")]
+    pub issued_by: String,
+    // Permanent if not set
+    #[sea_orm(column_name = "expires_at")]
+    pub expires_at: Option<DateTimeUtc>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
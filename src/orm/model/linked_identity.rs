@@ -0,0 +1,29 @@
+use sea_orm::entity::prelude::*;
+
+// Console identity (PSN account, XUID) bound to an account and the persona it logs in with
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "LinkedIdentity")]
+pub struct Model {
+    #[sea_orm(primary_key, column_name = "id")]
+    pub id: i64,
+    // "psn" or "xbl"
+    #[sea_orm(column_name = "platform")]
+    pub platform: String,
+    // PSN account id or XUID
+    #[sea_orm(column_name = "external_id")]
+    pub external_id: String,
+    // PSN name or gamertag at the time of linking (informational only)
+    #[sea_orm(column_name = "display_name")]
+    pub display_name: String,
+    #[sea_orm(column_name = "user_id")]
+    pub user_id: i64,
+    #[sea_orm(column_name = "persona_id")]
+    pub persona_id: i64,
+    #[sea_orm(column_name = "linked_at")]
+    pub linked_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,24 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "Lobby")]
+pub struct Model {
+    #[sea_orm(primary_key, column_name = "id")]
+    pub id: i32,
+    #[sea_orm(column_name = "name")]
+    pub name: String,
+    #[sea_orm(column_name = "locale")]
+    pub locale: String,
+    #[sea_orm(column_name = "max_games")]
+    pub max_games: i32,
+    // "pc", "ps3", "xbox360" or "" for all platforms
+    #[sea_orm(column_name = "platform")]
+    pub platform: String,
+    #[sea_orm(column_name = "enabled")]
+    pub enabled: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,23 @@
+use sea_orm::entity::prelude::*;
+
+// "Remember me" token handed out as encryptedLoginInfo. Deleting the row revokes it.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "LoginToken")]
+pub struct Model {
+    #[sea_orm(primary_key, column_name = "id")]
+    pub id: i64,
+    // "jti" claim of the JWT
+    #[sea_orm(column_name = "token_id", unique)]
+    pub token_id: String,
+    #[sea_orm(column_name = "user_id")]
+    pub user_id: i64,
+    #[sea_orm(column_name = "created_at")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[sea_orm(column_name = "expires_at")]
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,29 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "Message")]
+pub struct Model {
+    #[sea_orm(primary_key, column_name = "id")]
+    pub id: i64,
+    // -1 for messages sent by the server (broadcasts)
+    #[sea_orm(column_name = "sender_persona_id")]
+    pub sender_persona_id: i64,
+    #[sea_orm(column_name = "recipient_persona_id")]
+    pub recipient_persona_id: i64,
+    #[sea_orm(column_name = "message_type")]
+    pub message_type: String,
+    // JSON list of {"key", "type", "data"} objects
+    #[sea_orm(column_name = "attachments")]
+    pub attachments: String,
+    #[sea_orm(column_name = "delivered")]
+    pub delivered: bool,
+    #[sea_orm(column_name = "sent_at")]
+    pub sent_at: chrono::DateTime<chrono::Utc>,
+    #[sea_orm(column_name = "expires_at")]
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
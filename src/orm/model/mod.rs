@@ -1,7 +1,16 @@
 pub mod account;
+pub mod account_token;
+pub mod association;
 pub mod ban;
 pub mod config;
 pub mod game;
+pub mod linked_identity;
+pub mod lobby;
+pub mod login_token;
+pub mod message;
 pub mod participant;
 pub mod persona;
+pub mod schema_migration;
 pub mod session;
+pub mod stat;
+pub mod stat_period;
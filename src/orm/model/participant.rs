@@ -9,6 +9,7 @@ pub struct Model {
     pub game_id: i64,
     #[sea_orm(column_name = "persona_id")]
     pub persona_id: i64,
+    // -1: in the game, 0: joining, >0: position in the join queue
     #[sea_orm(column_name = "queue_pos")]
     pub queue_pos: i32,
     #[sea_orm(column_name = "ticket")]
@@ -24,6 +25,12 @@ pub struct Model {
     pub host_expected_client_port: i32,
     #[sea_orm(column_name = "host_expected_client_ip")]
     pub host_expected_client_ip: String,
+
+    // Internal address of the client, needed to send the join request after dequeuing
+    #[sea_orm(column_name = "remote_int_port")]
+    pub remote_int_port: i32,
+    #[sea_orm(column_name = "remote_int_ip")]
+    pub remote_int_ip: String,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
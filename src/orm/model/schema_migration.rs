@@ -0,0 +1,18 @@
+use sea_orm::entity::prelude::*;
+
+// Migrations that have been applied to the database
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "SchemaMigration")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false, column_name = "version")]
+    pub version: i32,
+    #[sea_orm(column_name = "name")]
+    pub name: String,
+    #[sea_orm(column_name = "applied_at")]
+    pub applied_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -21,6 +21,12 @@ pub struct Model {
     pub theater_udp_handle: String,
     #[sea_orm(column_name = "nat_type")]
     pub nat_type: i32,
+
+    // Reported by the client on login (used for bans)
+    #[sea_orm(column_name = "mac_addr")]
+    pub mac_addr: String,
+    #[sea_orm(column_name = "console_id")]
+    pub console_id: String,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
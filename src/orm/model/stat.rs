@@ -0,0 +1,28 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "Stat")]
+pub struct Model {
+    #[sea_orm(primary_key, column_name = "id")]
+    pub id: i64,
+    #[sea_orm(column_name = "persona_id")]
+    pub persona_id: i64,
+    // e.g. "3.2137219119.score"
+    #[sea_orm(column_name = "stat_key")]
+    pub stat_key: String,
+    // See STAT_PERIODS; period id 0 is kept for all time
+    #[sea_orm(column_name = "period_id")]
+    pub period_id: i32,
+    // 0 for the running period, 1 for the one before, ...
+    #[sea_orm(column_name = "period_past")]
+    pub period_past: i32,
+    #[sea_orm(column_name = "value")]
+    pub value: f64,
+    #[sea_orm(column_name = "updated_at")]
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
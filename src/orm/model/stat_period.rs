@@ -0,0 +1,18 @@
+use sea_orm::entity::prelude::*;
+
+// Running period of a stat period id that is not kept for all time. The stats of the
+// period id are rolled over when the running period changes.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "StatPeriod")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false, column_name = "period_id")]
+    pub period_id: i32,
+    // Number of whole periods since the Unix epoch
+    #[sea_orm(column_name = "running_index")]
+    pub running_index: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,4 +1,7 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use indexmap::IndexMap;
+use std::collections::HashMap;
 use std::{fmt, str};
 use tokio::net::UdpSocket;
 use tracing::debug;
@@ -82,6 +85,8 @@ pub enum DataMode {
     THEATER_RGAM,
     THEATER_PLVT,
     THEATER_UGDE,
+    THEATER_GDET, // Only response
+    THEATER_PDAT, // Only response
     THEATER_PING,
 
     THEATER_ECHO, // UDP
@@ -119,6 +124,8 @@ impl DataMode {
             DataMode::THEATER_RGAM => "RGAM",
             DataMode::THEATER_PLVT => "PLVT",
             DataMode::THEATER_UGDE => "UGDE",
+            DataMode::THEATER_GDET => "GDET", // Only response
+            DataMode::THEATER_PDAT => "PDAT", // Only response
             DataMode::THEATER_PING => "PING",
             DataMode::THEATER_ECHO => "ECHO", // UDP
         }
@@ -154,6 +161,8 @@ impl DataMode {
             "RGAM" => Ok(DataMode::THEATER_RGAM),
             "PLVT" => Ok(DataMode::THEATER_PLVT),
             "UGDE" => Ok(DataMode::THEATER_UGDE),
+            "GDET" => Ok(DataMode::THEATER_GDET), // Only response
+            "PDAT" => Ok(DataMode::THEATER_PDAT), // Only response
             "PING" => Ok(DataMode::THEATER_PING),
 
             "ECHO" => Ok(DataMode::THEATER_ECHO), // UDP
@@ -193,6 +202,8 @@ impl std::fmt::Debug for DataMode {
             DataMode::THEATER_RGAM => write!(f, "RGAM"),
             DataMode::THEATER_PLVT => write!(f, "PLVT"),
             DataMode::THEATER_UGDE => write!(f, "UGDE"),
+            DataMode::THEATER_GDET => write!(f, "GDET"), // Only response
+            DataMode::THEATER_PDAT => write!(f, "PDAT"), // Only response
             DataMode::THEATER_PING => write!(f, "PING"),
             DataMode::THEATER_ECHO => write!(f, "ECHO"), // UDP
         }
@@ -235,10 +246,58 @@ impl std::fmt::Debug for PacketMode {
     }
 }
 
+// FESL payloads bigger than this are sent as multi-packet fragments
+const FESL_MAX_PAYLOAD_SIZE: usize = 8096;
+// Number of base64 characters per fragment
+const FESL_FRAGMENT_DATA_SIZE: usize = 8000;
+// Upper bound for reassembled client payloads
+const FESL_MAX_REASSEMBLED_SIZE: usize = 1024 * 1024;
+// Multi-packets that are reassembled at the same time per connection
+const FESL_MAX_PARTIAL_PACKETS: usize = 16;
+// Data mode (4 bytes), packet mode (1), packet ID (3) and packet length (4)
+const PACKET_HEADER_SIZE: usize = 12;
+
 const PACKET_DATA_ENTRY_SPLIT: u8 = '\n' as u8;
 const PACKET_DATA_KV_SPLIT: u8 = '=' as u8;
 const PACKET_DATA_STOP: u8 = '\0' as u8;
 
+// Reason why a frame of a client was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameErr {
+    // The reported length is smaller than the header
+    InvalidLength,
+    // The reported length exceeds the maximum frame size
+    Oversize,
+    UnknownDataMode,
+    UnknownPacketMode,
+    InvalidPayload,
+    // The datagram or the connection ended within a frame
+    Truncated,
+    // A multi-packet could not be reassembled
+    InvalidFragment,
+}
+
+impl FrameErr {
+    // Label of the metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FrameErr::InvalidLength => "invalid_length",
+            FrameErr::Oversize => "oversize",
+            FrameErr::UnknownDataMode => "unknown_data_mode",
+            FrameErr::UnknownPacketMode => "unknown_packet_mode",
+            FrameErr::InvalidPayload => "invalid_payload",
+            FrameErr::Truncated => "truncated",
+            FrameErr::InvalidFragment => "invalid_fragment",
+        }
+    }
+}
+
+impl fmt::Display for FrameErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Malformed frame ({})", self.as_str())
+    }
+}
+
 #[derive(Clone)]
 pub struct DataPacket {
     pub mode: DataMode,
@@ -309,7 +368,7 @@ impl DataPacket {
         }
     }
 
-    fn parse_payload_to_hm(payload: Vec<u8>) -> Result<IndexMap<String, String>, &'static str> {
+    fn parse_payload_to_hm(payload: &[u8]) -> Result<IndexMap<String, String>, &'static str> {
         // Parse bytestream to hashmap
         let mut hm = IndexMap::new();
 
@@ -344,67 +403,48 @@ impl DataPacket {
         Ok(hm)
     }
 
-    pub fn from_bytes(bytes: Vec<u8>) -> Result<Option<(usize, Self)>, &'static str> {
-        // Check if the packet _header_ is complete
-        if bytes.len() < 12 {
-            debug!(target: "packet", "Packet header is incomplete. Waiting for more data...");
-            // The packet is incomplete so far... so we just await for more data
-            return Ok(None); //Err("Invalid packet length");
+    // Reads the header of a frame, which may not be complete yet
+    pub fn peek_frame_length(bytes: &[u8]) -> Option<usize> {
+        if bytes.len() < PACKET_HEADER_SIZE {
+            return None;
         }
+        Some(u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize)
+    }
 
-        // First: check if the packet is complete...
-        // Reported length of packet
-        let reported_packet_length: usize =
-            u32::from_be_bytes([bytes[8 + 0], bytes[8 + 1], bytes[8 + 2], bytes[8 + 3]]) as usize;
-
-        // Check if packet length is correct
-        let actual_bytedata_length: usize = bytes.len();
-        if reported_packet_length > actual_bytedata_length {
-            debug!(
-                target: "packet",
-                "Packet length is smaller than reported by the header. Reported: {}, Actual: {}. Waiting for more data...",
-                reported_packet_length, actual_bytedata_length
-            );
-            return Ok(None); //return Err("Reported packet length is bigger than the received data. Packet is incomplete!");
+    // Parses a complete frame (header and payload) in place
+    pub fn from_frame(frame: &[u8]) -> Result<Self, FrameErr> {
+        if frame.len() < PACKET_HEADER_SIZE {
+            return Err(FrameErr::InvalidLength);
         }
 
-        // We can now parse the header :)
-        // Read first bytes of header
         // fsys etc.
-        let Ok(mode_str) = str::from_utf8(&bytes[0..4]) else {
-            debug!(target: "packet", "Failed to parse data mode byte sequence");
-            return Ok(None); //return Err("Unexpected data mode byte sequence");
+        let Ok(mode_str) = str::from_utf8(&frame[0..4]) else {
+            return Err(FrameErr::UnknownDataMode);
         };
-        // Convert to DataMode
         let Ok(mode) = DataMode::from_value(mode_str) else {
             debug!(target: "packet", "Unknown data mode: {}", mode_str);
-            return Ok(None); //return Err("Unknown data mode");
+            return Err(FrameErr::UnknownDataMode);
         };
 
         // Request or Response?
-        let Ok(packet_mode) = PacketMode::from_value(bytes[4]) else {
-            debug!(target: "packet", "Invalid packet mode: {}", bytes[4]);
-            return Ok(None); //return Err("Invalid packet mode");
+        let Ok(packet_mode) = PacketMode::from_value(frame[4]) else {
+            debug!(target: "packet", "Invalid packet mode: {}", frame[4]);
+            return Err(FrameErr::UnknownPacketMode);
         };
 
-        let packet_id: u32 = u32::from_be_bytes([0x00, bytes[4 + 1], bytes[4 + 2], bytes[4 + 3]]);
+        let packet_id: u32 = u32::from_be_bytes([0x00, frame[5], frame[6], frame[7]]);
 
-        // Parse the payload
-        let payload: Vec<u8> = bytes[12..reported_packet_length].to_vec();
-        let Ok(data) = Self::parse_payload_to_hm(payload) else {
+        let Ok(data) = Self::parse_payload_to_hm(&frame[PACKET_HEADER_SIZE..]) else {
             debug!(target: "packet", "Failed to parse payload");
-            return Ok(None); //return Err("Failed to parse payload");
+            return Err(FrameErr::InvalidPayload);
         };
 
-        Ok(Some((
-            reported_packet_length,
-            DataPacket {
-                mode,
-                packet_mode,
-                packet_id,
-                data,
-            },
-        )))
+        Ok(DataPacket {
+            mode,
+            packet_mode,
+            packet_id,
+            data,
+        })
     }
 
     fn serialize_hm_to_payload(&self) -> Vec<u8> {
@@ -455,16 +495,158 @@ impl DataPacket {
 
         bytes
     }
+
+    // Serializes the packet like to_bytes, but splits large FESL packets into
+    // multi-packet fragments carrying the base64-encoded payload.
+    pub fn to_fragmented_bytes(&self) -> Vec<u8> {
+        let multi_packet_mode = match self.packet_mode {
+            PacketMode::FeslSinglePacketResponse => PacketMode::FeslMultiPacketResponse,
+            PacketMode::FeslSinglePacketRequest => PacketMode::FeslMultiPacketRequest,
+            _ => return self.to_bytes(),
+        };
+
+        let payload = self.serialize_hm_to_payload();
+        if payload.len() <= FESL_MAX_PAYLOAD_SIZE {
+            return self.to_bytes();
+        }
+
+        let encoded_payload = STANDARD.encode(&payload);
+        let mut bytes: Vec<u8> = Vec::new();
+        for chunk in encoded_payload.as_bytes().chunks(FESL_FRAGMENT_DATA_SIZE) {
+            let mut fragment_hm = IndexMap::new();
+            fragment_hm.insert("decodedSize".to_string(), payload.len().to_string());
+            fragment_hm.insert("size".to_string(), encoded_payload.len().to_string());
+            fragment_hm.insert(
+                "data".to_string(),
+                String::from_utf8_lossy(chunk).to_string(),
+            );
+            let fragment = DataPacket::new(
+                self.mode.clone(),
+                multi_packet_mode.clone(),
+                self.packet_id,
+                fragment_hm,
+            );
+            bytes.extend(fragment.to_bytes());
+        }
+        bytes
+    }
 }
 
-use bytes::{Buf, BytesMut};
+use bytes::BytesMut;
 use tokio_util::codec::Decoder;
 
-pub struct DataPacketCodec;
+use crate::metrics::Metrics;
+
+// Multi-packet fragments received so far
+struct PartialPacket {
+    mode: DataMode,
+    packet_mode: PacketMode,
+    encoded_size: usize,
+    encoded_data: String,
+}
+
+pub struct DataPacketCodec {
+    // Keyed by the packet ID
+    partial_packets: HashMap<u32, PartialPacket>,
+    // Multi-packets are only reassembled on streams. A UDP socket shares its codec
+    // between all peers, so their fragments could not be told apart.
+    reassemble_fragments: bool,
+    // Largest accepted frame, header included
+    max_frame_size: usize,
+    metrics: Arc<Metrics>,
+}
 
 impl DataPacketCodec {
-    pub fn new() -> Self {
-        DataPacketCodec {}
+    pub fn new(max_frame_size: usize, metrics: Arc<Metrics>) -> Self {
+        DataPacketCodec {
+            partial_packets: HashMap::new(),
+            reassemble_fragments: true,
+            max_frame_size,
+            metrics,
+        }
+    }
+
+    // Codec of a UDP socket. Multi-packet fragments are dropped.
+    pub fn for_datagrams(max_frame_size: usize, metrics: Arc<Metrics>) -> Self {
+        DataPacketCodec {
+            partial_packets: HashMap::new(),
+            reassemble_fragments: false,
+            max_frame_size,
+            metrics,
+        }
+    }
+
+    fn drop_frame(&self, frame_err: FrameErr) {
+        debug!(target: "packet", "Dropping frame: {}", frame_err);
+        self.metrics.record_malformed_frame(frame_err.as_str());
+    }
+
+    // Adds a fragment and returns the logical packet once all fragments arrived
+    fn reassemble(&mut self, fragment: DataPacket) -> Option<DataPacket> {
+        let packet_mode = match fragment.packet_mode {
+            PacketMode::FeslMultiPacketRequest => PacketMode::FeslSinglePacketRequest,
+            PacketMode::FeslMultiPacketResponse => PacketMode::FeslSinglePacketResponse,
+            _ => return Some(fragment),
+        };
+        if !self.reassemble_fragments {
+            debug!(target: "packet", "Dropping multi-packet fragment {} received over UDP", fragment.packet_id);
+            self.drop_frame(FrameErr::InvalidFragment);
+            return None;
+        }
+
+        let encoded_size = fragment
+            .data
+            .get("size")
+            .and_then(|size| size.parse::<usize>().ok())
+            .unwrap_or(0);
+        let chunk = fragment.data.get("data").cloned().unwrap_or_default();
+
+        if !self.partial_packets.contains_key(&fragment.packet_id)
+            && self.partial_packets.len() >= FESL_MAX_PARTIAL_PACKETS
+        {
+            debug!(target: "packet", "Too many incomplete multi-packets. Dropping {}...", fragment.packet_id);
+            self.drop_frame(FrameErr::InvalidFragment);
+            return None;
+        }
+        let partial_packet = self
+            .partial_packets
+            .entry(fragment.packet_id)
+            .or_insert(PartialPacket {
+                mode: fragment.mode.clone(),
+                packet_mode,
+                encoded_size,
+                encoded_data: String::new(),
+            });
+        partial_packet.encoded_data.push_str(&chunk);
+
+        if partial_packet.encoded_data.len() > FESL_MAX_REASSEMBLED_SIZE {
+            debug!(target: "packet", "Multi-packet {} is too big. Dropping it...", fragment.packet_id);
+            self.partial_packets.remove(&fragment.packet_id);
+            self.drop_frame(FrameErr::InvalidFragment);
+            return None;
+        }
+        if partial_packet.encoded_data.len() < partial_packet.encoded_size {
+            // Wait for the remaining fragments
+            return None;
+        }
+
+        let partial_packet = self.partial_packets.remove(&fragment.packet_id)?;
+        let Ok(payload) = STANDARD.decode(partial_packet.encoded_data.as_bytes()) else {
+            debug!(target: "packet", "Failed to decode multi-packet {}", fragment.packet_id);
+            self.drop_frame(FrameErr::InvalidFragment);
+            return None;
+        };
+        let Ok(data) = DataPacket::parse_payload_to_hm(&payload) else {
+            debug!(target: "packet", "Failed to parse payload of multi-packet {}", fragment.packet_id);
+            self.drop_frame(FrameErr::InvalidFragment);
+            return None;
+        };
+        Some(DataPacket {
+            mode: partial_packet.mode,
+            packet_mode: partial_packet.packet_mode,
+            packet_id: fragment.packet_id,
+            data,
+        })
     }
 }
 
@@ -473,24 +655,59 @@ impl Decoder for DataPacketCodec {
     type Error = std::io::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        // Result<(usize, Self), &'static str>
-        let parse_attempt = DataPacket::from_bytes(src.to_vec());
+        // Fragments are consumed until a complete logical packet is available
+        loop {
+            let Some(frame_length) = DataPacket::peek_frame_length(src) else {
+                // The header is incomplete so far
+                return Ok(None);
+            };
 
-        match parse_attempt {
-            Ok(Some((n_read, datapacket))) => {
-                src.advance(n_read);
-                Ok(Some(datapacket))
-            }
-            Ok(None) => Ok(None),
-            Err(error_data) => {
-                debug!(target: "packet", "Error parsing packet: {}", error_data);
-                Err(std::io::Error::new(
+            // The length is checked before any payload is buffered. A frame with a bad
+            // length cannot be skipped, so the connection is closed.
+            if frame_length < PACKET_HEADER_SIZE || frame_length > self.max_frame_size {
+                let frame_err = if frame_length < PACKET_HEADER_SIZE {
+                    FrameErr::InvalidLength
+                } else {
+                    FrameErr::Oversize
+                };
+                self.drop_frame(frame_err);
+                src.clear();
+                return Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidData,
-                    error_data,
-                ))
+                    frame_err.to_string(),
+                ));
+            }
+
+            if src.len() < frame_length {
+                // Wait for the rest of the frame, reserving its space only once
+                src.reserve(frame_length - src.len());
+                return Ok(None);
+            }
+
+            let frame = src.split_to(frame_length);
+            match DataPacket::from_frame(&frame) {
+                Ok(fragment) => {
+                    if let Some(datapacket) = self.reassemble(fragment) {
+                        return Ok(Some(datapacket));
+                    }
+                }
+                // The length was valid, so the following frames can still be read
+                Err(frame_err) => self.drop_frame(frame_err),
             }
         }
     }
+
+    // Called at the end of every UDP datagram and of the TCP stream
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(datapacket) = self.decode(src)? {
+            return Ok(Some(datapacket));
+        }
+        if !src.is_empty() {
+            self.drop_frame(FrameErr::Truncated);
+            src.clear();
+        }
+        Ok(None)
+    }
 }
 
 use tokio_util::codec::Encoder;
@@ -499,8 +716,8 @@ impl Encoder<DataPacket> for DataPacketCodec {
     type Error = std::io::Error;
 
     fn encode(&mut self, item: DataPacket, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        // Convert the packet to bytes
-        let datapacket_bytes = item.to_bytes();
+        // Convert the packet to bytes (split into fragments if needed)
+        let datapacket_bytes = item.to_fragmented_bytes();
 
         // Reserve space in the buffer.
         dst.reserve(datapacket_bytes.len());
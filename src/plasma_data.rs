@@ -0,0 +1,240 @@
+use indexmap::IndexMap;
+use std::fmt;
+
+// Key/value data of a Plasma packet (see DataPacket.data). Nested values are stored with
+// dotted keys ("stats.0.key") and lists announce their length with "<key>.[]".
+pub type PlasmaData = IndexMap<String, String>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlasmaDataErr {
+    // Key of the missing field
+    MissingField(String),
+    // Key and value of the field that could not be parsed
+    InvalidField(String, String),
+}
+
+impl PlasmaDataErr {
+    pub fn key(&self) -> &str {
+        match self {
+            PlasmaDataErr::MissingField(key) => key,
+            PlasmaDataErr::InvalidField(key, _) => key,
+        }
+    }
+}
+
+impl fmt::Display for PlasmaDataErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlasmaDataErr::MissingField(key) => write!(f, "Missing field {}", key),
+            PlasmaDataErr::InvalidField(key, value) => {
+                write!(f, "Invalid value {:?} of field {}", value, key)
+            }
+        }
+    }
+}
+
+// Key of a field below the prefix; the fields of the top level have no prefix
+pub fn join_key(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+pub fn has_prefix(data: &PlasmaData, prefix: &str) -> bool {
+    let prefix = format!("{}.", prefix);
+    data.keys().any(|key| key.starts_with(&prefix))
+}
+
+pub trait PlasmaDecode: Sized {
+    // Reads the value stored under the key (the prefix of the fields for structs)
+    fn decode_at(data: &PlasmaData, key: &str) -> Result<Self, PlasmaDataErr>;
+
+    // Whether the value was sent at all; decides between None and Some for Option<T>
+    fn is_present(data: &PlasmaData, key: &str) -> bool {
+        data.contains_key(key)
+    }
+
+    fn decode(data: &PlasmaData) -> Result<Self, PlasmaDataErr> {
+        Self::decode_at(data, "")
+    }
+}
+
+pub trait PlasmaEncode {
+    fn encode_at(&self, data: &mut PlasmaData, key: &str);
+
+    // Appends the fields to the data, e.g. after the TXN of a response
+    fn encode_into(&self, data: &mut PlasmaData) {
+        self.encode_at(data, "")
+    }
+}
+
+fn get_value<'a>(data: &'a PlasmaData, key: &str) -> Result<&'a String, PlasmaDataErr> {
+    data.get(key)
+        .ok_or_else(|| PlasmaDataErr::MissingField(key.to_string()))
+}
+
+impl PlasmaDecode for String {
+    fn decode_at(data: &PlasmaData, key: &str) -> Result<Self, PlasmaDataErr> {
+        get_value(data, key).cloned()
+    }
+}
+
+impl PlasmaEncode for String {
+    fn encode_at(&self, data: &mut PlasmaData, key: &str) {
+        data.insert(key.to_string(), self.clone());
+    }
+}
+
+// Flags are sent as "1" and "0"; an empty value counts as unset
+impl PlasmaDecode for bool {
+    fn decode_at(data: &PlasmaData, key: &str) -> Result<Self, PlasmaDataErr> {
+        match get_value(data, key)?.as_str() {
+            "1" | "true" => Ok(true),
+            "0" | "false" | "" => Ok(false),
+            value => Err(PlasmaDataErr::InvalidField(key.to_string(), value.to_string())),
+        }
+    }
+}
+
+impl PlasmaEncode for bool {
+    fn encode_at(&self, data: &mut PlasmaData, key: &str) {
+        data.insert(key.to_string(), if *self { "1" } else { "0" }.to_string());
+    }
+}
+
+macro_rules! impl_plasma_number {
+    ($($number:ty),*) => {
+        $(
+            impl PlasmaDecode for $number {
+                fn decode_at(data: &PlasmaData, key: &str) -> Result<Self, PlasmaDataErr> {
+                    let value = get_value(data, key)?;
+                    value
+                        .trim()
+                        .parse()
+                        .map_err(|_| PlasmaDataErr::InvalidField(key.to_string(), value.to_string()))
+                }
+            }
+
+            impl PlasmaEncode for $number {
+                fn encode_at(&self, data: &mut PlasmaData, key: &str) {
+                    data.insert(key.to_string(), self.to_string());
+                }
+            }
+        )*
+    };
+}
+
+impl_plasma_number!(i8, i16, i32, i64, u8, u16, u32, u64, usize, f32, f64);
+
+impl<T: PlasmaDecode> PlasmaDecode for Option<T> {
+    fn decode_at(data: &PlasmaData, key: &str) -> Result<Self, PlasmaDataErr> {
+        if T::is_present(data, key) {
+            T::decode_at(data, key).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn is_present(data: &PlasmaData, key: &str) -> bool {
+        T::is_present(data, key)
+    }
+}
+
+impl<T: PlasmaEncode> PlasmaEncode for Option<T> {
+    fn encode_at(&self, data: &mut PlasmaData, key: &str) {
+        if let Some(value) = self {
+            value.encode_at(data, key);
+        }
+    }
+}
+
+// Lists are sent as "<key>.[]" (the length) and the elements at "<key>.0", "<key>.1", ...
+// A list without a length is empty.
+impl<T: PlasmaDecode> PlasmaDecode for Vec<T> {
+    fn decode_at(data: &PlasmaData, key: &str) -> Result<Self, PlasmaDataErr> {
+        let length_key = format!("{}.[]", key);
+        let Some(length) = data.get(&length_key) else {
+            return Ok(Vec::new());
+        };
+        // Every element takes at least one key, so longer lists cannot be complete
+        let length = length
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|length| *length <= data.len())
+            .ok_or_else(|| PlasmaDataErr::InvalidField(length_key.clone(), length.to_string()))?;
+        (0..length)
+            .map(|idx| T::decode_at(data, &format!("{}.{}", key, idx)))
+            .collect()
+    }
+
+    fn is_present(data: &PlasmaData, key: &str) -> bool {
+        data.contains_key(&format!("{}.[]", key))
+    }
+}
+
+impl<T: PlasmaEncode> PlasmaEncode for Vec<T> {
+    fn encode_at(&self, data: &mut PlasmaData, key: &str) {
+        data.insert(format!("{}.[]", key), self.len().to_string());
+        for (idx, value) in self.iter().enumerate() {
+            value.encode_at(data, &format!("{}.{}", key, idx));
+        }
+    }
+}
+
+// Declares a struct together with its Plasma encoding. Every field names its key:
+//
+// plasma_struct! {
+//     pub struct StatValue {
+//         pub key: String = "key",
+//         pub value: f64 = "value",
+//     }
+// }
+//
+// Nested structs are stored below the key of their field, lists use the ".[]" convention
+// and Option fields may be missing.
+macro_rules! plasma_struct {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident : $ty:ty = $key:literal),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty),*
+        }
+
+        impl $crate::plasma_data::PlasmaDecode for $name {
+            fn decode_at(
+                data: &$crate::plasma_data::PlasmaData,
+                key: &str,
+            ) -> Result<Self, $crate::plasma_data::PlasmaDataErr> {
+                Ok(Self {
+                    $($field: <$ty as $crate::plasma_data::PlasmaDecode>::decode_at(
+                        data,
+                        &$crate::plasma_data::join_key(key, $key),
+                    )?),*
+                })
+            }
+
+            fn is_present(data: &$crate::plasma_data::PlasmaData, key: &str) -> bool {
+                key.is_empty() || $crate::plasma_data::has_prefix(data, key)
+            }
+        }
+
+        impl $crate::plasma_data::PlasmaEncode for $name {
+            fn encode_at(&self, data: &mut $crate::plasma_data::PlasmaData, key: &str) {
+                $(<$ty as $crate::plasma_data::PlasmaEncode>::encode_at(
+                    &self.$field,
+                    data,
+                    &$crate::plasma_data::join_key(key, $key),
+                );)*
+            }
+        }
+    };
+}
+
+pub(crate) use plasma_struct;
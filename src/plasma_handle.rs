@@ -1,6 +1,8 @@
 use crate::client_connection::{ClientConnectionDescriptor, ProtoType, SendDataType, ServiceType};
-use crate::orm::model::{account, game, participant, persona, session};
+use crate::orm::model::{account, persona, session};
+use crate::handler::fesl::update_presence;
 use crate::packet::DataPacket;
+use crate::presence::{PresenceInfo, PresenceState};
 use crate::sharedstate::SharedState;
 use core::panic;
 use std::sync::Arc;
@@ -8,6 +10,7 @@ use std::sync::Arc;
 use crate::mordorwide_errors::MWErr;
 
 use crate::utils::auth::user::{get_credentials_from_packet, validate_credentials};
+use crate::utils::bans::{normalize_mac_addr, BanSubject};
 
 use sea_orm::entity::*;
 use sea_orm::query::*;
@@ -44,94 +47,12 @@ impl PlasmaRequestBundle {
     }
 
     async fn clear_active_session(&mut self, session: session::Model) {
-        let persona_id = session.persona_id;
-
-        if persona_id != -1 {
-            // Clear participant entries
-            let Ok(participants) = participant::Entity::find()
-                .filter(participant::Column::PersonaId.eq(persona_id))
-                .all(&*self.sstate.database)
-                .await
-            else {
-                panic!("Failed to clear participants");
-            };
-
-            // Find all associated games
-            let Ok(games) = game::Entity::find()
-                .filter(game::Column::PersonaId.eq(persona_id))
-                .all(&*self.sstate.database)
-                .await
-            else {
-                panic!("Failed to find games");
-            };
-            let game_ids = games.iter().map(|g| g.id).collect::<Vec<i64>>();
-
-            // Clear participants to the persona-owned games
-            for game_id in game_ids {
-                let Ok(_) = participant::Entity::delete_many()
-                    .filter(participant::Column::GameId.eq(game_id))
-                    .exec(&*self.sstate.database)
-                    .await
-                else {
-                    panic!("Failed to clear participants");
-                };
-            }
-
-            // Clear games
-            let Ok(_) = game::Entity::delete_many()
-                .filter(game::Column::PersonaId.eq(persona_id))
-                .exec(&*self.sstate.database)
-                .await
-            else {
-                panic!("Failed to clear games");
-            };
-        }
-
-        // Terminate TCP connections (TCP+FESL)
-        if session.fesl_tcp_handle != "" {
-            let con_descr = ClientConnectionDescriptor::from_string(&session.fesl_tcp_handle);
-            {
-                let conns = &*self.sstate.connections;
-                if conns.contains_key(&con_descr) {
-                    if let Some(tcp_con) = conns.get_mut(&con_descr) {
-                        tcp_con.send(SendDataType::Close).await;
-                    };
-                    conns.remove(&con_descr);
-                }
-            }
-        }
-        // Terminate TCP connections (TCP+THEATER)
-        if session.theater_tcp_handle != "" {
-            let con_descr = ClientConnectionDescriptor::from_string(&session.theater_tcp_handle);
-            {
-                let conns = &*self.sstate.connections;
-                if conns.contains_key(&con_descr) {
-                    if let Some(tcp_con) = conns.get_mut(&con_descr) {
-                        tcp_con.send(SendDataType::Close).await;
-                    };
-                    conns.remove(&con_descr);
-                }
-            }
-        }
-
-        // Clear session
-        let Ok(_) = session::Entity::delete_by_id(session.id)
-            .exec(&*self.sstate.database)
-            .await
-        else {
-            panic!("Failed to clear session");
-        };
+        close_session(session, &self.sstate).await;
         self.flush();
     }
 
     async fn clear_active_sessions_by_user(&mut self, user_id: i64, except: Option<i64>) {
-        let Ok(sessions) = session::Entity::find()
-            .filter(session::Column::UserId.eq(user_id))
-            .all(&*self.sstate.database)
-            .await
-        else {
-            panic!("Failed to find sessions");
-        };
+        let sessions = self.sstate.registry.get_sessions_by_user(user_id);
 
         for session in sessions {
             if except.is_some() && except.unwrap() == session.id {
@@ -147,37 +68,28 @@ impl PlasmaRequestBundle {
         }
 
         if self.con.proto_type == ProtoType::Tcp && self.con.service_type == ServiceType::Fesl {
-            let Ok(Some(session)) = session::Entity::find()
-                .filter(session::Column::FeslTcpHandle.eq(self.con.to_string()))
-                .one(&*self.sstate.database)
-                .await
-            else {
-                return None;
-            };
+            let session = self
+                .sstate
+                .registry
+                .get_session_by_fesl_tcp_handle(&self.con.to_string())?;
             self.session_model = Some(session.clone());
             return Some(session);
         } else if self.con.proto_type == ProtoType::Tcp
             && self.con.service_type == ServiceType::Theater
         {
-            let Ok(Some(session)) = session::Entity::find()
-                .filter(session::Column::TheaterTcpHandle.eq(self.con.to_string()))
-                .one(&*self.sstate.database)
-                .await
-            else {
-                return None;
-            };
+            let session = self
+                .sstate
+                .registry
+                .get_session_by_theater_tcp_handle(&self.con.to_string())?;
             self.session_model = Some(session.clone());
             return Some(session);
         } else if self.con.proto_type == ProtoType::Udp
             && self.con.service_type == ServiceType::Theater
         {
-            let Ok(Some(session)) = session::Entity::find()
-                .filter(session::Column::TheaterUdpHandle.eq(self.con.to_string()))
-                .one(&*self.sstate.database)
-                .await
-            else {
-                return None;
-            };
+            let session = self
+                .sstate
+                .registry
+                .get_session_by_theater_udp_handle(&self.con.to_string())?;
             self.session_model = Some(session.clone());
             return Some(session);
         } else {
@@ -229,6 +141,16 @@ impl PlasmaRequestBundle {
         return None;
     }
 
+    // Ban subject with the details of the connection and the login packet
+    pub fn ban_subject(&self) -> BanSubject {
+        BanSubject {
+            ip: Some(self.con.client_ip.clone()),
+            mac_addr: self.packet.data.get("macAddr").map(|mac_addr| normalize_mac_addr(mac_addr)),
+            console_id: self.packet.data.get("consoleId").cloned(),
+            ..Default::default()
+        }
+    }
+
     pub async fn set_active_user_session(
         &mut self,
         lobby_key: &String,
@@ -242,35 +164,37 @@ impl PlasmaRequestBundle {
         self.clear_active_sessions_by_user(user_id, except).await;
 
         // Insert new session
-        let mut session = session::ActiveModel {
-            lobby_key: Set(lobby_key.clone()),
-            user_id: Set(user_id),
-            persona_id: Set(-1),
-            fesl_tcp_handle: Set(self.con.to_string()),
-            theater_tcp_handle: Set("".to_string()),
-            theater_udp_handle: Set("".to_string()),
-            nat_type: Set(0),
+        let mut session = session::Model {
+            lobby_key: lobby_key.clone(),
+            user_id,
+            persona_id: -1,
+            fesl_tcp_handle: self.con.to_string(),
+            theater_tcp_handle: "".to_string(),
+            theater_udp_handle: "".to_string(),
+            nat_type: 0,
+            mac_addr: self
+                .packet
+                .data
+                .get("macAddr")
+                .map(|mac_addr| normalize_mac_addr(mac_addr))
+                .unwrap_or_default(),
+            console_id: self.packet.data.get("consoleId").cloned().unwrap_or_default(),
             ..Default::default()
         };
 
         // Check if the session should be re-used?
         if let Some(old_session_id) = except {
-            if let Ok(Some(old_session)) = session::Entity::find_by_id(old_session_id)
-                .one(&*self.sstate.database)
-                .await
-            {
-                session.id = Set(old_session_id);
+            if let Some(old_session) = self.sstate.registry.get_session(old_session_id) {
+                session.id = old_session_id;
                 // The FESL connection should be in-fact identical to the previous one.
                 // Play it safe and use the new handles though...
-                // fesl_tcp_handle = Set(old_session.fesl_tcp_handle);
-                session.theater_tcp_handle = Set(old_session.theater_tcp_handle);
-                session.theater_udp_handle = Set(old_session.theater_udp_handle);
+                // fesl_tcp_handle = old_session.fesl_tcp_handle;
+                session.theater_tcp_handle = old_session.theater_tcp_handle;
+                session.theater_udp_handle = old_session.theater_udp_handle;
             };
         }
-        // Now, write the session to the database
-        let Ok(_) = session.save(&*self.sstate.database).await else {
-            return false;
-        };
+        // Now, register the session
+        self.sstate.registry.insert_session(session);
 
         // Update the login date of the account model
         let Ok(Some(db_user)) = account::Entity::find_by_id(user_id)
@@ -297,11 +221,22 @@ impl PlasmaRequestBundle {
         let session = self.get_active_session_model().await;
 
         if let Some(session) = session {
-            let mut session = session.into_active_model();
-            session.persona_id = Set(persona_id);
+            let modified = self
+                .sstate
+                .registry
+                .modify_session(session.id, |session| session.persona_id = persona_id);
 
-            if let Ok(_) = session.save(&*self.sstate.database).await {
+            if modified.is_some() {
                 self.flush();
+                // The persona is online now
+                if !self.sstate.presence.contains_key(&persona_id) {
+                    update_presence(
+                        &self.sstate,
+                        persona_id,
+                        Some(PresenceInfo::new(PresenceState::Online, -1)),
+                    )
+                    .await;
+                }
                 return true;
             };
         }
@@ -344,7 +279,7 @@ impl PlasmaRequestBundle {
         }
         let credentials = credentials.unwrap();
 
-        let validation = validate_credentials(&credentials, &self.sstate).await;
+        let validation = validate_credentials(&credentials, self.ban_subject(), &self.sstate).await;
         if validation.is_err() {
             return Err(validation.unwrap_err());
         }
@@ -376,3 +311,38 @@ impl PlasmaRequestBundle {
         Ok(())
     }
 }
+
+// Removes the games of the session, closes its connections and deletes it
+pub async fn close_session(session: session::Model, sstate: &Arc<SharedState>) {
+    let persona_id = session.persona_id;
+
+    if persona_id != -1 {
+        // Find all associated games
+        let games = sstate.registry.get_games_by_persona(persona_id);
+
+        // Clear the persona-owned games and their participants
+        for game in games {
+            sstate.registry.remove_participants_by_game(game.id);
+            sstate.registry.remove_game(game.id);
+        }
+    }
+
+    // Terminate TCP connections (TCP+FESL)
+    close_connection(&session.fesl_tcp_handle, sstate).await;
+    // Terminate TCP connections (TCP+THEATER)
+    close_connection(&session.theater_tcp_handle, sstate).await;
+
+    // Clear session
+    sstate.registry.remove_session(session.id);
+}
+
+// Closes the open client connection of the handle, if any
+pub async fn close_connection(handle: &String, sstate: &Arc<SharedState>) {
+    if handle.is_empty() {
+        return;
+    }
+    let con_descr = ClientConnectionDescriptor::from_string(handle);
+    if let Some((_, tcp_con)) = sstate.connections.remove(&con_descr) {
+        tcp_con.send(SendDataType::Close).await;
+    }
+}
@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresenceState {
+    Online,
+    Away,
+    InGame,
+}
+
+impl PresenceState {
+    // Value of "status.show" in the PRES packets
+    pub fn to_show(self) -> &'static str {
+        match self {
+            PresenceState::Online => "chat",
+            PresenceState::Away => "away",
+            PresenceState::InGame => "ingame",
+        }
+    }
+
+    pub fn from_show(show: &str) -> Option<Self> {
+        match show {
+            "chat" | "online" => Some(PresenceState::Online),
+            "away" | "xa" | "dnd" => Some(PresenceState::Away),
+            "ingame" => Some(PresenceState::InGame),
+            _ => None,
+        }
+    }
+}
+
+// Offline personas have no entry in the registry
+pub const PRESENCE_SHOW_OFFLINE: &str = "disc";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PresenceInfo {
+    pub state: PresenceState,
+    // GID of the game the persona is in, -1 if not in a game
+    pub gid: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl PresenceInfo {
+    pub fn new(state: PresenceState, gid: i64) -> Self {
+        Self {
+            state,
+            gid,
+            updated_at: Utc::now(),
+        }
+    }
+
+    // Ignores the update timestamp
+    pub fn same_status(&self, other: &PresenceInfo) -> bool {
+        self.state == other.state && self.gid == other.gid
+    }
+}
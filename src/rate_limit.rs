@@ -0,0 +1,106 @@
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitSection;
+
+// How often the entries of idle clients are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Maximum number of requests within the sliding window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub max_requests: usize,
+    pub window: Duration,
+}
+
+impl RateLimit {
+    pub const fn per_minute(max_requests: usize) -> Self {
+        Self {
+            max_requests,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LimitEntry {
+    window: Duration,
+    // Accepted requests within the window, oldest first
+    requests: VecDeque<Instant>,
+}
+
+impl LimitEntry {
+    fn prune_requests(&mut self, now: Instant) {
+        while let Some(requested_at) = self.requests.front() {
+            if now.duration_since(*requested_at) < self.window {
+                break;
+            }
+            self.requests.pop_front();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RequestLimiter {
+    enabled: bool,
+    // "<route>|<client ip>" -> requests
+    entries: DashMap<String, LimitEntry>,
+    last_pruned_at: Mutex<Instant>,
+}
+
+impl RequestLimiter {
+    pub fn new(config: &RateLimitSection) -> Self {
+        Self {
+            enabled: config.enabled,
+            entries: DashMap::new(),
+            last_pruned_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Records the request of the client if it is within the limit of the route.
+    // Otherwise returns the time until the next request is accepted.
+    pub fn check(&self, route: &str, client_ip: &str, limit: &RateLimit) -> Result<(), Duration> {
+        if !self.enabled {
+            return Ok(());
+        }
+        self.prune();
+
+        let now = Instant::now();
+        let mut entry = self
+            .entries
+            .entry(format!("{}|{}", route, client_ip))
+            .or_insert_with(|| LimitEntry {
+                window: limit.window,
+                requests: VecDeque::new(),
+            });
+        entry.prune_requests(now);
+        if entry.requests.len() >= limit.max_requests {
+            let retry_in = entry
+                .requests
+                .front()
+                .map(|requested_at| limit.window.saturating_sub(now.duration_since(*requested_at)))
+                .unwrap_or(limit.window);
+            return Err(retry_in);
+        }
+        entry.requests.push_back(now);
+        Ok(())
+    }
+
+    // Forgets the clients without requests in the window of their route
+    fn prune(&self) {
+        let now = Instant::now();
+        {
+            let mut last_pruned_at = self.last_pruned_at.lock().unwrap();
+            if now.duration_since(*last_pruned_at) < PRUNE_INTERVAL {
+                return;
+            }
+            *last_pruned_at = now;
+        }
+        self.entries.retain(|_, entry| {
+            entry.prune_requests(now);
+            !entry.requests.is_empty()
+        });
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+
+use crate::orm::model::game;
+
+use super::mirror::MirrorOp;
+use super::{MultiIndex, Registry};
+
+#[derive(Debug)]
+pub(super) struct GameStore {
+    by_id: BTreeMap<i64, game::Model>,
+    by_persona: MultiIndex<i64>,
+    by_lobby: MultiIndex<i32>,
+}
+
+impl GameStore {
+    pub(super) fn new() -> Self {
+        Self {
+            by_id: BTreeMap::new(),
+            by_persona: MultiIndex::new(),
+            by_lobby: MultiIndex::new(),
+        }
+    }
+
+    fn index(&mut self, game: &game::Model) {
+        self.by_persona.insert(game.persona_id, game.id);
+        self.by_lobby.insert(game.lobby_id, game.id);
+    }
+
+    fn unindex(&mut self, game: &game::Model) {
+        self.by_persona.remove(&game.persona_id, game.id);
+        self.by_lobby.remove(&game.lobby_id, game.id);
+    }
+
+    fn get_all(&self, ids: Vec<i64>) -> Vec<game::Model> {
+        ids.iter().filter_map(|id| self.by_id.get(id).cloned()).collect()
+    }
+}
+
+impl Registry {
+    // Adds the game and returns it with its id
+    pub fn insert_game(&self, mut game: game::Model) -> game::Model {
+        game.id = Self::assign_id(&self.next_game_id, game.id);
+        let mut store = self.games.write().unwrap();
+        if let Some(old_game) = store.by_id.remove(&game.id) {
+            store.unindex(&old_game);
+        }
+        store.index(&game);
+        store.by_id.insert(game.id, game.clone());
+        drop(store);
+        self.mirror(MirrorOp::UpsertGame(Box::new(game.clone())));
+        game
+    }
+
+    // Changes the game under the write lock, so that concurrent changes of other
+    // fields are not overwritten. Returns the changed game, or None if the game
+    // does not exist (anymore).
+    pub fn modify_game(
+        &self,
+        game_id: i64,
+        modify: impl FnOnce(&mut game::Model),
+    ) -> Option<game::Model> {
+        let mut store = self.games.write().unwrap();
+        let mut game = store.by_id.remove(&game_id)?;
+        store.unindex(&game);
+        modify(&mut game);
+        game.id = game_id;
+        store.index(&game);
+        store.by_id.insert(game.id, game.clone());
+        drop(store);
+        self.mirror(MirrorOp::UpsertGame(Box::new(game.clone())));
+        Some(game)
+    }
+
+    pub fn remove_game(&self, game_id: i64) -> Option<game::Model> {
+        let mut store = self.games.write().unwrap();
+        let game = store.by_id.remove(&game_id)?;
+        store.unindex(&game);
+        drop(store);
+        self.mirror(MirrorOp::DeleteGame(game_id));
+        Some(game)
+    }
+
+    pub fn get_game(&self, game_id: i64) -> Option<game::Model> {
+        self.games.read().unwrap().by_id.get(&game_id).cloned()
+    }
+
+    // All games, oldest first
+    pub fn get_games(&self) -> Vec<game::Model> {
+        self.games.read().unwrap().by_id.values().cloned().collect()
+    }
+
+    pub fn get_games_by_persona(&self, persona_id: i64) -> Vec<game::Model> {
+        let store = self.games.read().unwrap();
+        store.get_all(store.by_persona.all(&persona_id))
+    }
+
+    pub fn get_games_by_lobby(&self, lobby_id: i32) -> Vec<game::Model> {
+        let store = self.games.read().unwrap();
+        store.get_all(store.by_lobby.all(&lobby_id))
+    }
+
+    pub fn count_games(&self) -> usize {
+        self.games.read().unwrap().by_id.len()
+    }
+}
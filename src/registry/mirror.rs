@@ -0,0 +1,81 @@
+use sea_orm::entity::*;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{DatabaseConnection, DbErr, Iterable};
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Sender};
+use tracing::warn;
+
+use crate::orm::model::{game, participant, session};
+
+// Changes that may wait for the writer. Further changes are dropped while it is full.
+const MIRROR_QUEUE_CAPACITY: usize = 10000;
+
+#[derive(Debug)]
+pub(super) enum MirrorOp {
+    UpsertSession(session::Model),
+    DeleteSession(i64),
+    UpsertGame(Box<game::Model>),
+    DeleteGame(i64),
+    UpsertParticipant(participant::Model),
+    DeleteParticipant(i64),
+}
+
+// The changes are written by a single task so that they are applied in order
+pub(super) fn spawn_mirror_writer(db: Arc<DatabaseConnection>) -> Sender<MirrorOp> {
+    let (tx, mut rx) = channel::<MirrorOp>(MIRROR_QUEUE_CAPACITY);
+    tokio::spawn(async move {
+        while let Some(op) = rx.recv().await {
+            if let Err(e) = apply(&op, &db).await {
+                warn!(target: "registry", "Failed to mirror {:?} to the database: {}", op, e);
+            }
+        }
+    });
+    tx
+}
+
+async fn apply(op: &MirrorOp, db: &DatabaseConnection) -> Result<(), DbErr> {
+    match op {
+        MirrorOp::UpsertSession(model) => {
+            session::Entity::insert(session::ActiveModel::from(model.clone()).reset_all())
+                .on_conflict(
+                    OnConflict::column(session::Column::Id)
+                        .update_columns(session::Column::iter().filter(|c| !matches!(c, session::Column::Id)))
+                        .to_owned(),
+                )
+                .exec(db)
+                .await?;
+        }
+        MirrorOp::DeleteSession(id) => {
+            session::Entity::delete_by_id(*id).exec(db).await?;
+        }
+        MirrorOp::UpsertGame(model) => {
+            game::Entity::insert(game::ActiveModel::from(model.as_ref().clone()).reset_all())
+                .on_conflict(
+                    OnConflict::column(game::Column::Id)
+                        .update_columns(game::Column::iter().filter(|c| !matches!(c, game::Column::Id)))
+                        .to_owned(),
+                )
+                .exec(db)
+                .await?;
+        }
+        MirrorOp::DeleteGame(id) => {
+            game::Entity::delete_by_id(*id).exec(db).await?;
+        }
+        MirrorOp::UpsertParticipant(model) => {
+            participant::Entity::insert(participant::ActiveModel::from(model.clone()).reset_all())
+                .on_conflict(
+                    OnConflict::column(participant::Column::Id)
+                        .update_columns(
+                            participant::Column::iter().filter(|c| !matches!(c, participant::Column::Id)),
+                        )
+                        .to_owned(),
+                )
+                .exec(db)
+                .await?;
+        }
+        MirrorOp::DeleteParticipant(id) => {
+            participant::Entity::delete_by_id(*id).exec(db).await?;
+        }
+    }
+    Ok(())
+}
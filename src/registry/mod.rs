@@ -0,0 +1,104 @@
+mod games;
+mod mirror;
+mod participants;
+mod sessions;
+
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, RwLock};
+use sea_orm::DatabaseConnection;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Sender;
+use tracing::warn;
+
+use games::GameStore;
+use mirror::{spawn_mirror_writer, MirrorOp};
+use participants::ParticipantStore;
+use sessions::SessionStore;
+
+// Secondary index of a store: key -> ids of the matching entries, ordered by id
+#[derive(Debug)]
+struct MultiIndex<K> {
+    entries: HashMap<K, BTreeSet<i64>>,
+}
+
+impl<K: Eq + Hash> MultiIndex<K> {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, key: K, id: i64) {
+        self.entries.entry(key).or_default().insert(id);
+    }
+
+    fn remove(&mut self, key: &K, id: i64) {
+        if let Some(ids) = self.entries.get_mut(key) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.entries.remove(key);
+            }
+        }
+    }
+
+    fn first(&self, key: &K) -> Option<i64> {
+        self.entries.get(key).and_then(|ids| ids.first().copied())
+    }
+
+    fn all(&self, key: &K) -> Vec<i64> {
+        self.entries
+            .get(key)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+// In-memory store of the volatile state (sessions, games and participants).
+// The entries do not outlive the server, so they are not read from the database.
+// If mirroring is enabled, every change is written to the InGameSession, Game and
+// Participant tables in the background (e.g. for external dashboards).
+#[derive(Debug)]
+pub struct Registry {
+    sessions: RwLock<SessionStore>,
+    games: RwLock<GameStore>,
+    participants: RwLock<ParticipantStore>,
+    next_session_id: AtomicI64,
+    next_game_id: AtomicI64,
+    next_participant_id: AtomicI64,
+    mirror: Option<Sender<MirrorOp>>,
+}
+
+impl Registry {
+    pub fn new(mirror_db: Option<Arc<DatabaseConnection>>) -> Self {
+        Self {
+            sessions: RwLock::new(SessionStore::new()),
+            games: RwLock::new(GameStore::new()),
+            participants: RwLock::new(ParticipantStore::new()),
+            next_session_id: AtomicI64::new(1),
+            next_game_id: AtomicI64::new(1),
+            next_participant_id: AtomicI64::new(1),
+            mirror: mirror_db.map(spawn_mirror_writer),
+        }
+    }
+
+    // The registry stays authoritative, so a change is dropped rather than waited for
+    // if the database does not keep up
+    fn mirror(&self, op: MirrorOp) {
+        if let Some(mirror) = &self.mirror
+            && let Err(TrySendError::Full(op)) = mirror.try_send(op)
+        {
+            warn!(target: "registry", "Mirror queue is full, dropped {:?}", op);
+        }
+    }
+
+    // Ids are assigned on insert, unless the entry brings its own (> 0)
+    fn assign_id(counter: &AtomicI64, id: i64) -> i64 {
+        if id > 0 {
+            counter.fetch_max(id + 1, Ordering::SeqCst);
+            return id;
+        }
+        counter.fetch_add(1, Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::orm::model::participant;
+
+use super::mirror::MirrorOp;
+use super::{MultiIndex, Registry};
+
+// How long the host of a game may still report the stats of a player that left
+const DEPARTURE_TTL: Duration = Duration::from_secs(10 * 60);
+// Departures kept per persona
+const MAX_DEPARTURES_PER_PERSONA: usize = 8;
+
+// A player that left a game
+#[derive(Debug)]
+struct Departure {
+    host_persona_id: i64,
+    left_at: Instant,
+}
+
+#[derive(Debug)]
+pub(super) struct ParticipantStore {
+    by_id: BTreeMap<i64, participant::Model>,
+    by_game: MultiIndex<i64>,
+    by_persona: MultiIndex<i64>,
+    // persona id -> recent departures, oldest first
+    departures: HashMap<i64, Vec<Departure>>,
+    last_pruned_at: Instant,
+}
+
+impl ParticipantStore {
+    pub(super) fn new() -> Self {
+        Self {
+            by_id: BTreeMap::new(),
+            by_game: MultiIndex::new(),
+            by_persona: MultiIndex::new(),
+            departures: HashMap::new(),
+            last_pruned_at: Instant::now(),
+        }
+    }
+
+    fn index(&mut self, participant: &participant::Model) {
+        self.by_game.insert(participant.game_id, participant.id);
+        self.by_persona.insert(participant.persona_id, participant.id);
+    }
+
+    fn unindex(&mut self, participant: &participant::Model) {
+        self.by_game.remove(&participant.game_id, participant.id);
+        self.by_persona.remove(&participant.persona_id, participant.id);
+    }
+
+    fn get_all(&self, ids: Vec<i64>) -> Vec<participant::Model> {
+        ids.iter().filter_map(|id| self.by_id.get(id).cloned()).collect()
+    }
+
+    // Participants of the game that are in it or about to join it, and queued ones
+    fn count_by_state(&self, game_id: i64) -> (usize, usize) {
+        let participants = self.get_all(self.by_game.all(&game_id));
+        let n_queued = participants.iter().filter(|participant| participant.queue_pos > 0).count();
+        (participants.len() - n_queued, n_queued)
+    }
+
+    fn add_departure(&mut self, persona_id: i64, departure: Departure) {
+        let now = departure.left_at;
+        if now.duration_since(self.last_pruned_at) >= DEPARTURE_TTL {
+            self.departures.retain(|_, departures| {
+                departures.retain(|departure| now.duration_since(departure.left_at) < DEPARTURE_TTL);
+                !departures.is_empty()
+            });
+            self.last_pruned_at = now;
+        }
+        let departures = self.departures.entry(persona_id).or_default();
+        if departures.len() >= MAX_DEPARTURES_PER_PERSONA {
+            departures.remove(0);
+        }
+        departures.push(departure);
+    }
+}
+
+impl Registry {
+    // Adds the participant and returns it with its id
+    pub fn insert_participant(&self, mut participant: participant::Model) -> participant::Model {
+        participant.id = Self::assign_id(&self.next_participant_id, participant.id);
+        let mut store = self.participants.write().unwrap();
+        if let Some(old_participant) = store.by_id.remove(&participant.id) {
+            store.unindex(&old_participant);
+        }
+        store.index(&participant);
+        store.by_id.insert(participant.id, participant.clone());
+        drop(store);
+        self.mirror(MirrorOp::UpsertParticipant(participant.clone()));
+        participant
+    }
+
+    // Adds a player that wants to join the game. It is admitted (queue position 0) if a slot
+    // is free and nobody is queued, otherwise it is appended to the queue. Returns None if
+    // the queue is full. Decided under the write lock, so concurrent joins cannot overfill
+    // the game.
+    pub fn insert_joining_participant(
+        &self,
+        mut participant: participant::Model,
+        max_players: i32,
+        queue_length: i32,
+    ) -> Option<participant::Model> {
+        let mut store = self.participants.write().unwrap();
+        let (n_joined, n_queued) = store.count_by_state(participant.game_id);
+        participant.queue_pos = if n_joined < max_players.max(0) as usize && n_queued == 0 {
+            0
+        } else if n_queued < queue_length.max(0) as usize {
+            n_queued as i32 + 1
+        } else {
+            return None;
+        };
+        participant.id = Self::assign_id(&self.next_participant_id, participant.id);
+        store.index(&participant);
+        store.by_id.insert(participant.id, participant.clone());
+        drop(store);
+        self.mirror(MirrorOp::UpsertParticipant(participant.clone()));
+        Some(participant)
+    }
+
+    // Admits the queued player with the lowest queue position (queue position 0) if the game
+    // has a free slot. Decided under the write lock, like insert_joining_participant.
+    pub fn admit_queued_participant(
+        &self,
+        game_id: i64,
+        max_players: i32,
+    ) -> Option<participant::Model> {
+        let mut store = self.participants.write().unwrap();
+        let (n_joined, _) = store.count_by_state(game_id);
+        if n_joined >= max_players.max(0) as usize {
+            return None;
+        }
+        let mut participant = store
+            .get_all(store.by_game.all(&game_id))
+            .into_iter()
+            .filter(|participant| participant.queue_pos > 0)
+            .min_by_key(|participant| participant.queue_pos)?;
+        participant.queue_pos = 0;
+        store.by_id.insert(participant.id, participant.clone());
+        drop(store);
+        self.mirror(MirrorOp::UpsertParticipant(participant.clone()));
+        Some(participant)
+    }
+
+    // Changes the participant under the write lock, so that concurrent changes of other
+    // fields are not overwritten. Returns the changed participant, or None if the participant
+    // does not exist (anymore).
+    pub fn modify_participant(
+        &self,
+        participant_id: i64,
+        modify: impl FnOnce(&mut participant::Model),
+    ) -> Option<participant::Model> {
+        let mut store = self.participants.write().unwrap();
+        let mut participant = store.by_id.remove(&participant_id)?;
+        store.unindex(&participant);
+        modify(&mut participant);
+        participant.id = participant_id;
+        store.index(&participant);
+        store.by_id.insert(participant.id, participant.clone());
+        drop(store);
+        self.mirror(MirrorOp::UpsertParticipant(participant.clone()));
+        Some(participant)
+    }
+
+    pub fn remove_participant(&self, participant_id: i64) -> Option<participant::Model> {
+        let mut store = self.participants.write().unwrap();
+        let participant = store.by_id.remove(&participant_id)?;
+        store.unindex(&participant);
+        drop(store);
+        self.mirror(MirrorOp::DeleteParticipant(participant_id));
+        self.record_departures(std::slice::from_ref(&participant));
+        Some(participant)
+    }
+
+    // Removes all participants of the game and returns them
+    pub fn remove_participants_by_game(&self, game_id: i64) -> Vec<participant::Model> {
+        let mut store = self.participants.write().unwrap();
+        let participants = store.get_all(store.by_game.all(&game_id));
+        for participant in participants.iter() {
+            store.by_id.remove(&participant.id);
+            store.unindex(participant);
+        }
+        drop(store);
+        for participant in participants.iter() {
+            self.mirror(MirrorOp::DeleteParticipant(participant.id));
+        }
+        self.record_departures(&participants);
+        participants
+    }
+
+    // Remembers the host of the game for the players that were in it (not queued).
+    // Must be called before the game is removed.
+    fn record_departures(&self, participants: &[participant::Model]) {
+        let departures: Vec<(i64, Departure)> = participants
+            .iter()
+            .filter(|participant| participant.queue_pos == -1)
+            .filter_map(|participant| {
+                let game = self.get_game(participant.game_id)?;
+                Some((
+                    participant.persona_id,
+                    Departure {
+                        host_persona_id: game.persona_id,
+                        left_at: Instant::now(),
+                    },
+                ))
+            })
+            .collect();
+        if departures.is_empty() {
+            return;
+        }
+        let mut store = self.participants.write().unwrap();
+        for (persona_id, departure) in departures {
+            store.add_departure(persona_id, departure);
+        }
+    }
+
+    // Whether the persona is in a game of the host, or left one recently
+    pub fn has_played_with_host(&self, persona_id: i64, host_persona_id: i64) -> bool {
+        let is_participant = self
+            .get_participants_by_persona(persona_id)
+            .iter()
+            .any(|participant| {
+                self.get_game(participant.game_id)
+                    .is_some_and(|game| game.persona_id == host_persona_id)
+            });
+        if is_participant {
+            return true;
+        }
+        let store = self.participants.read().unwrap();
+        store.departures.get(&persona_id).is_some_and(|departures| {
+            departures.iter().any(|departure| {
+                departure.host_persona_id == host_persona_id
+                    && departure.left_at.elapsed() < DEPARTURE_TTL
+            })
+        })
+    }
+
+    pub fn get_participant(&self, participant_id: i64) -> Option<participant::Model> {
+        self.participants.read().unwrap().by_id.get(&participant_id).cloned()
+    }
+
+    // All participants, ordered by id
+    pub fn get_participants(&self) -> Vec<participant::Model> {
+        self.participants.read().unwrap().by_id.values().cloned().collect()
+    }
+
+    // Participants of the game, in the order they joined
+    pub fn get_participants_by_game(&self, game_id: i64) -> Vec<participant::Model> {
+        let store = self.participants.read().unwrap();
+        store.get_all(store.by_game.all(&game_id))
+    }
+
+    pub fn get_participants_by_persona(&self, persona_id: i64) -> Vec<participant::Model> {
+        let store = self.participants.read().unwrap();
+        store.get_all(store.by_persona.all(&persona_id))
+    }
+
+    pub fn get_participant_by_game_and_persona(
+        &self,
+        game_id: i64,
+        persona_id: i64,
+    ) -> Option<participant::Model> {
+        self.get_participants_by_game(game_id)
+            .into_iter()
+            .find(|participant| participant.persona_id == persona_id)
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+
+use crate::orm::model::session;
+
+use super::mirror::MirrorOp;
+use super::{MultiIndex, Registry};
+
+#[derive(Debug)]
+pub(super) struct SessionStore {
+    by_id: BTreeMap<i64, session::Model>,
+    by_user: MultiIndex<i64>,
+    by_persona: MultiIndex<i64>,
+    by_lobby_key: MultiIndex<String>,
+    by_fesl_tcp_handle: MultiIndex<String>,
+    by_theater_tcp_handle: MultiIndex<String>,
+    by_theater_udp_handle: MultiIndex<String>,
+}
+
+impl SessionStore {
+    pub(super) fn new() -> Self {
+        Self {
+            by_id: BTreeMap::new(),
+            by_user: MultiIndex::new(),
+            by_persona: MultiIndex::new(),
+            by_lobby_key: MultiIndex::new(),
+            by_fesl_tcp_handle: MultiIndex::new(),
+            by_theater_tcp_handle: MultiIndex::new(),
+            by_theater_udp_handle: MultiIndex::new(),
+        }
+    }
+
+    fn index(&mut self, session: &session::Model) {
+        self.by_user.insert(session.user_id, session.id);
+        // -1: no persona selected yet
+        if session.persona_id != -1 {
+            self.by_persona.insert(session.persona_id, session.id);
+        }
+        self.by_lobby_key.insert(session.lobby_key.clone(), session.id);
+        // Empty handles are not connected yet
+        if !session.fesl_tcp_handle.is_empty() {
+            self.by_fesl_tcp_handle.insert(session.fesl_tcp_handle.clone(), session.id);
+        }
+        if !session.theater_tcp_handle.is_empty() {
+            self.by_theater_tcp_handle.insert(session.theater_tcp_handle.clone(), session.id);
+        }
+        if !session.theater_udp_handle.is_empty() {
+            self.by_theater_udp_handle.insert(session.theater_udp_handle.clone(), session.id);
+        }
+    }
+
+    fn unindex(&mut self, session: &session::Model) {
+        self.by_user.remove(&session.user_id, session.id);
+        self.by_persona.remove(&session.persona_id, session.id);
+        self.by_lobby_key.remove(&session.lobby_key, session.id);
+        self.by_fesl_tcp_handle.remove(&session.fesl_tcp_handle, session.id);
+        self.by_theater_tcp_handle.remove(&session.theater_tcp_handle, session.id);
+        self.by_theater_udp_handle.remove(&session.theater_udp_handle, session.id);
+    }
+
+    fn get_first(&self, id: Option<i64>) -> Option<session::Model> {
+        id.and_then(|id| self.by_id.get(&id).cloned())
+    }
+
+    fn get_all(&self, ids: Vec<i64>) -> Vec<session::Model> {
+        ids.iter().filter_map(|id| self.by_id.get(id).cloned()).collect()
+    }
+}
+
+impl Registry {
+    // Adds the session (or replaces the session with the same id) and returns it with its id
+    pub fn insert_session(&self, mut session: session::Model) -> session::Model {
+        session.id = Self::assign_id(&self.next_session_id, session.id);
+        let mut store = self.sessions.write().unwrap();
+        if let Some(old_session) = store.by_id.remove(&session.id) {
+            store.unindex(&old_session);
+        }
+        store.index(&session);
+        store.by_id.insert(session.id, session.clone());
+        drop(store);
+        self.mirror(MirrorOp::UpsertSession(session.clone()));
+        session
+    }
+
+    // Changes the session under the write lock, so that concurrent changes of other
+    // fields are not overwritten. Returns the changed session, or None if the session
+    // does not exist (anymore).
+    pub fn modify_session(
+        &self,
+        session_id: i64,
+        modify: impl FnOnce(&mut session::Model),
+    ) -> Option<session::Model> {
+        let mut store = self.sessions.write().unwrap();
+        let mut session = store.by_id.remove(&session_id)?;
+        store.unindex(&session);
+        modify(&mut session);
+        session.id = session_id;
+        store.index(&session);
+        store.by_id.insert(session.id, session.clone());
+        drop(store);
+        self.mirror(MirrorOp::UpsertSession(session.clone()));
+        Some(session)
+    }
+
+    pub fn remove_session(&self, session_id: i64) -> Option<session::Model> {
+        let mut store = self.sessions.write().unwrap();
+        let session = store.by_id.remove(&session_id)?;
+        store.unindex(&session);
+        drop(store);
+        self.mirror(MirrorOp::DeleteSession(session_id));
+        Some(session)
+    }
+
+    pub fn get_session(&self, session_id: i64) -> Option<session::Model> {
+        self.sessions.read().unwrap().by_id.get(&session_id).cloned()
+    }
+
+    // All sessions, ordered by id
+    pub fn get_sessions(&self) -> Vec<session::Model> {
+        self.sessions.read().unwrap().by_id.values().cloned().collect()
+    }
+
+    pub fn get_sessions_by_user(&self, user_id: i64) -> Vec<session::Model> {
+        let store = self.sessions.read().unwrap();
+        store.get_all(store.by_user.all(&user_id))
+    }
+
+    pub fn get_session_by_persona(&self, persona_id: i64) -> Option<session::Model> {
+        let store = self.sessions.read().unwrap();
+        store.get_first(store.by_persona.first(&persona_id))
+    }
+
+    pub fn get_session_by_lobby_key(&self, lobby_key: &str) -> Option<session::Model> {
+        let store = self.sessions.read().unwrap();
+        store.get_first(store.by_lobby_key.first(&lobby_key.to_string()))
+    }
+
+    pub fn get_session_by_fesl_tcp_handle(&self, handle: &str) -> Option<session::Model> {
+        let store = self.sessions.read().unwrap();
+        store.get_first(store.by_fesl_tcp_handle.first(&handle.to_string()))
+    }
+
+    pub fn get_sessions_by_fesl_tcp_handle(&self, handle: &str) -> Vec<session::Model> {
+        let store = self.sessions.read().unwrap();
+        store.get_all(store.by_fesl_tcp_handle.all(&handle.to_string()))
+    }
+
+    pub fn get_session_by_theater_tcp_handle(&self, handle: &str) -> Option<session::Model> {
+        let store = self.sessions.read().unwrap();
+        store.get_first(store.by_theater_tcp_handle.first(&handle.to_string()))
+    }
+
+    pub fn get_session_by_theater_udp_handle(&self, handle: &str) -> Option<session::Model> {
+        let store = self.sessions.read().unwrap();
+        store.get_first(store.by_theater_udp_handle.first(&handle.to_string()))
+    }
+}
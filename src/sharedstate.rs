@@ -1,13 +1,22 @@
 use dashmap::DashMap;
 use sea_orm::{Database, DatabaseConnection};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::net::UdpSocket;
-use tracing::info;
+use tracing::{error, info, warn};
 
 use crate::client_connection::{ClientConnection, ClientConnectionDescriptor};
-use crate::orm::{
-    add_default_configuration_keys, check_config_table_exists, clear_old_db_data, create_tables,
-};
+use crate::config::{Configuration, ConsoleLinkingSection, HelloConfig};
+use crate::orm::migrations::run_migrations;
+use crate::orm::{add_default_configuration_keys, check_config_table_exists, clear_old_db_data};
+use crate::login_throttle::LoginThrottle;
+use crate::mail::Mailer;
+use crate::metrics::Metrics;
+use crate::presence::PresenceInfo;
+use crate::rate_limit::RequestLimiter;
+use crate::registry::Registry;
+use crate::utils::linking::PendingLinks;
+use crate::utils::psn::PSNTicketVerifier;
 use crate::utils::stun_turn::{STUNInfo, TURNInfo};
 
 #[derive(Debug, Clone)]
@@ -16,15 +25,42 @@ pub struct SharedState {
     pub connections: Arc<DashMap<ClientConnectionDescriptor, ClientConnection>>,
     pub udp_sockets: Arc<DashMap<u16, Arc<UdpSocket>>>,
     pub server_secret: String,
+    // Lifetime of the "remember me" tokens
+    pub remember_token_lifetime: chrono::Duration,
+    // Largest packet accepted from a client
+    pub max_frame_size: usize,
     pub stunrelay: Arc<STUNInfo>,
     pub turn: Arc<TURNInfo>,
+    // Presence of the online personas, keyed by persona id
+    pub presence: Arc<DashMap<i64, PresenceInfo>>,
+    // Held while the presence of a persona is changed and sent to its friends
+    pub presence_update_locks: Arc<DashMap<i64, Arc<tokio::sync::Mutex<()>>>>,
+    pub metrics: Arc<Metrics>,
+    // Failed logins per account and IP
+    pub login_throttle: Arc<LoginThrottle>,
+    // Requests per client IP of the rate limited routes
+    pub request_limiter: Arc<RequestLimiter>,
+    // Verification and recovery mails
+    pub mailer: Arc<Mailer>,
+    // Signature and validity checks of the PSN tickets
+    pub psn_verifier: Arc<PSNTicketVerifier>,
+    // Link codes of console identities that are not bound to an account yet
+    pub pending_links: Arc<PendingLinks>,
+    pub console_linking: Arc<ConsoleLinkingSection>,
+    // Whether the account pages (and thus the link page) are served
+    pub account_web_enabled: bool,
+    // Sessions, games and participants
+    pub registry: Arc<Registry>,
+    // Theater and messenger endpoints sent in the FSYS Hello response
+    pub hello: Arc<HelloConfig>,
+    // FESL port -> platform of the clients connecting to it
+    pub fesl_platforms: Arc<HashMap<u16, String>>,
 }
 
 impl SharedState {
     pub async fn new(
         db_connection_str: String,
-        server_secret: String,
-        init_schemas: bool,
+        configuration: &Configuration,
         set_default_values: bool,
         stunrelay: STUNInfo,
         turn: TURNInfo,
@@ -32,8 +68,26 @@ impl SharedState {
         let db = Database::connect(db_connection_str).await.unwrap();
 
         // Init DB
-        if init_schemas {
-            let _ = create_tables(&db).await;
+        if configuration.server.init_schemas {
+            match run_migrations(&db).await {
+                Ok(n_applied) => info!(target: "init", "Applied {} database migrations.", n_applied),
+                Err(e) => {
+                    error!(target: "init", "{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        // Load the keys of the PSN ticket issuers
+        let psn_verifier = match PSNTicketVerifier::new(&configuration.psn) {
+            Ok(psn_verifier) => psn_verifier,
+            Err(e) => {
+                error!(target: "init", "{}", e);
+                std::process::exit(1);
+            }
+        };
+        if psn_verifier.verify_tickets() && psn_verifier.n_issuers() == 0 {
+            warn!(target: "init", "No PSN ticket issuers configured. NuPS3Login rejects all tickets.");
         }
 
         // Wait till the Config table is created...
@@ -54,13 +108,39 @@ impl SharedState {
         // Clear old session-related data
         clear_old_db_data(&db).await;
 
+        let db = Arc::new(db);
+        let mirror_db = if configuration.server.mirror_state_to_db {
+            Some(db.clone())
+        } else {
+            None
+        };
+
         Self {
-            database: Arc::new(db),
+            database: db,
             connections: Arc::new(DashMap::new()),
             udp_sockets: Arc::new(DashMap::new()),
-            server_secret: server_secret,
+            server_secret: configuration.server.secret_key.clone(),
+            remember_token_lifetime: chrono::Duration::seconds(
+                configuration.server.remember_token_lifetime_secs as i64,
+            ),
+            max_frame_size: configuration.server.max_frame_size,
             stunrelay: Arc::new(stunrelay),
             turn: Arc::new(turn),
+            presence: Arc::new(DashMap::new()),
+            presence_update_locks: Arc::new(DashMap::new()),
+            registry: Arc::new(Registry::new(mirror_db)),
+            metrics: Arc::new(Metrics::new()),
+            login_throttle: Arc::new(LoginThrottle::new(&configuration.login_throttle)),
+            request_limiter: Arc::new(RequestLimiter::new(&configuration.rate_limit)),
+            mailer: Arc::new(Mailer::new(&configuration.mail, &configuration.account_web)),
+            psn_verifier: Arc::new(psn_verifier),
+            pending_links: Arc::new(PendingLinks::new(std::time::Duration::from_secs(
+                configuration.console_linking.link_code_lifetime_secs,
+            ))),
+            console_linking: Arc::new(configuration.console_linking.clone()),
+            account_web_enabled: configuration.account_web.enabled,
+            hello: Arc::new(configuration.hello.clone()),
+            fesl_platforms: Arc::new(configuration.get_fesl_platforms()),
         }
     }
 }
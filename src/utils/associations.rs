@@ -0,0 +1,156 @@
+use crate::orm::model::association;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use sea_orm::DatabaseConnection;
+
+pub const ASSO_TYPE_FRIENDS: &str = "PlasmaFriends";
+pub const ASSO_TYPE_BLOCK: &str = "PlasmaBlock";
+pub const ASSO_TYPE_MUTE: &str = "PlasmaMute";
+pub const ASSO_TYPE_RECENT_PLAYERS: &str = "PlasmaRecentPlayers";
+
+pub fn is_valid_asso_type(asso_type: &str) -> bool {
+    [
+        ASSO_TYPE_FRIENDS,
+        ASSO_TYPE_BLOCK,
+        ASSO_TYPE_MUTE,
+        ASSO_TYPE_RECENT_PLAYERS,
+    ]
+    .contains(&asso_type)
+}
+
+pub fn get_max_list_size(asso_type: &str) -> u64 {
+    if asso_type == ASSO_TYPE_RECENT_PLAYERS {
+        20
+    } else {
+        100
+    }
+}
+
+// All entries of the list of the owner, oldest first
+pub async fn get_association_list(
+    owner_persona_id: i64,
+    asso_type: &str,
+    db: &DatabaseConnection,
+) -> Vec<association::Model> {
+    association::Entity::find()
+        .filter(
+            Condition::all()
+                .add(association::Column::OwnerPersonaId.eq(owner_persona_id))
+                .add(association::Column::AssoType.eq(asso_type)),
+        )
+        .order_by(association::Column::CreatedAt, sea_orm::Order::Asc)
+        .all(db)
+        .await
+        .unwrap_or_default()
+}
+
+// All personas that have the member persona on their list
+pub async fn get_association_owners(
+    member_persona_id: i64,
+    asso_type: &str,
+    db: &DatabaseConnection,
+) -> Vec<i64> {
+    association::Entity::find()
+        .filter(
+            Condition::all()
+                .add(association::Column::MemberPersonaId.eq(member_persona_id))
+                .add(association::Column::AssoType.eq(asso_type)),
+        )
+        .all(db)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|asso| asso.owner_persona_id)
+        .collect()
+}
+
+pub async fn has_association(
+    owner_persona_id: i64,
+    member_persona_id: i64,
+    asso_type: &str,
+    db: &DatabaseConnection,
+) -> bool {
+    matches!(
+        association::Entity::find()
+            .filter(
+                Condition::all()
+                    .add(association::Column::OwnerPersonaId.eq(owner_persona_id))
+                    .add(association::Column::MemberPersonaId.eq(member_persona_id))
+                    .add(association::Column::AssoType.eq(asso_type)),
+            )
+            .count(db)
+            .await,
+        Ok(n_hits) if n_hits > 0
+    )
+}
+
+// Friend entries are one-sided requests until the member adds the owner to its own
+// friends list. Only mutual friends see the presence of each other.
+pub async fn get_mutual_friends(persona_id: i64, db: &DatabaseConnection) -> Vec<i64> {
+    let friend_persona_ids = get_association_owners(persona_id, ASSO_TYPE_FRIENDS, db).await;
+    if friend_persona_ids.is_empty() {
+        return Vec::new();
+    }
+    get_association_list(persona_id, ASSO_TYPE_FRIENDS, db)
+        .await
+        .into_iter()
+        .map(|db_association| db_association.member_persona_id)
+        .filter(|member_persona_id| friend_persona_ids.contains(member_persona_id))
+        .collect()
+}
+
+pub enum AddAssociationResult {
+    Added,
+    AlreadyPresent,
+    ListFull,
+    Failed,
+}
+
+// Adds the member to the list of the owner. If the list is full, the least recently
+// modified entry is dropped if roll_when_full is set.
+pub async fn add_association(
+    owner_persona_id: i64,
+    member_persona_id: i64,
+    asso_type: &str,
+    roll_when_full: bool,
+    db: &DatabaseConnection,
+) -> AddAssociationResult {
+    let db_associations = get_association_list(owner_persona_id, asso_type, db).await;
+
+    if let Some(db_association) = db_associations
+        .iter()
+        .find(|asso| asso.member_persona_id == member_persona_id)
+    {
+        // Recent players are refreshed so that they are rolled out last
+        if asso_type == ASSO_TYPE_RECENT_PLAYERS {
+            let mut db_association = db_association.clone().into_active_model();
+            db_association.created_at = Set(chrono::Utc::now());
+            let _ = db_association.update(db).await;
+        }
+        return AddAssociationResult::AlreadyPresent;
+    }
+
+    if db_associations.len() as u64 >= get_max_list_size(asso_type) {
+        if !roll_when_full {
+            return AddAssociationResult::ListFull;
+        }
+        let n_to_drop = db_associations.len() as u64 + 1 - get_max_list_size(asso_type);
+        for db_association in db_associations.iter().take(n_to_drop as usize) {
+            let _ = association::Entity::delete_by_id(db_association.id)
+                .exec(db)
+                .await;
+        }
+    }
+
+    let new_association = association::ActiveModel {
+        owner_persona_id: Set(owner_persona_id),
+        member_persona_id: Set(member_persona_id),
+        asso_type: Set(asso_type.to_string()),
+        created_at: Set(chrono::Utc::now()),
+        ..Default::default()
+    };
+    match new_association.insert(db).await {
+        Ok(_) => AddAssociationResult::Added,
+        Err(_) => AddAssociationResult::Failed,
+    }
+}
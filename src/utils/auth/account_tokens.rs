@@ -0,0 +1,94 @@
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use sea_orm::{DatabaseConnection, DbErr};
+use uuid::Uuid;
+
+use crate::orm::model::account_token;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountTokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl AccountTokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountTokenPurpose::VerifyEmail => "verify_email",
+            AccountTokenPurpose::ResetPassword => "reset_password",
+        }
+    }
+}
+
+fn token_hash(token: &str) -> String {
+    sha256::digest(token)
+}
+
+// Creates a new token and returns it. The previous tokens of the user for the same
+// purpose are replaced, so only the most recent mail is valid.
+pub async fn issue_account_token(
+    user_id: i64,
+    purpose: AccountTokenPurpose,
+    lifetime: chrono::Duration,
+    db: &DatabaseConnection,
+) -> Result<String, DbErr> {
+    revoke_account_tokens(user_id, purpose, db).await?;
+
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let current_time = chrono::Utc::now();
+    let db_token = account_token::ActiveModel {
+        user_id: Set(user_id),
+        purpose: Set(purpose.as_str().to_string()),
+        token_hash: Set(token_hash(&token)),
+        created_at: Set(current_time),
+        expires_at: Set(current_time + lifetime),
+        ..Default::default()
+    };
+    db_token.insert(db).await?;
+    Ok(token)
+}
+
+// Returns the user id of the token if it is valid, without using it up
+pub async fn check_account_token(
+    token: &str,
+    purpose: AccountTokenPurpose,
+    db: &DatabaseConnection,
+) -> Result<Option<i64>, DbErr> {
+    let db_token = account_token::Entity::find()
+        .filter(account_token::Column::TokenHash.eq(token_hash(token)))
+        .filter(account_token::Column::Purpose.eq(purpose.as_str()))
+        .filter(account_token::Column::ExpiresAt.gt(chrono::Utc::now()))
+        .one(db)
+        .await?;
+    Ok(db_token.map(|db_token| db_token.user_id))
+}
+
+// Returns the user id of the token if it is valid and deletes the token
+pub async fn consume_account_token(
+    token: &str,
+    purpose: AccountTokenPurpose,
+    db: &DatabaseConnection,
+) -> Result<Option<i64>, DbErr> {
+    let Some(user_id) = check_account_token(token, purpose, db).await? else {
+        return Ok(None);
+    };
+    // Only the request that deletes the token may use it
+    let result = account_token::Entity::delete_many()
+        .filter(account_token::Column::TokenHash.eq(token_hash(token)))
+        .exec(db)
+        .await?;
+    Ok((result.rows_affected > 0).then_some(user_id))
+}
+
+pub async fn revoke_account_tokens(
+    user_id: i64,
+    purpose: AccountTokenPurpose,
+    db: &DatabaseConnection,
+) -> Result<u64, DbErr> {
+    let result = account_token::Entity::delete_many()
+        .filter(account_token::Column::UserId.eq(user_id))
+        .filter(account_token::Column::Purpose.eq(purpose.as_str()))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected)
+}
@@ -7,46 +7,47 @@ use crate::mordorwide_errors::MWErr;
 pub enum JWTErr {
     JWTDecodeError,
     JWTEncodeError,
+    // The token is well-formed, but expired or revoked
+    TokenRevoked,
 }
 
+// The claims only reference the persisted login token; they carry no credentials
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
-    username: String,
-    hashed_password: String,
+    // Account id
+    sub: String,
+    // Id of the login token
+    jti: String,
     exp: usize,
 }
 
-pub fn get_credential_pair_from_jwt(
-    token: &String,
-    secret: &String,
-) -> Result<(String, String), MWErr> {
-    if let Ok(decoded_claim) = decode::<Claims>(
-        &token,
+// Returns the account id and the token id
+pub fn get_token_from_jwt(token: &str, secret: &str) -> Result<(i64, String), MWErr> {
+    let Ok(decoded_claim) = decode::<Claims>(
+        token,
         &DecodingKey::from_secret(secret.as_ref()),
-        &Validation::default(),
-    ) {
-        Ok((
-            decoded_claim.claims.username,
-            decoded_claim.claims.hashed_password,
-        ))
-    } else {
-        Err(MWErr::JWTError(JWTErr::JWTDecodeError))
-    }
+        &Validation::new(Algorithm::HS256),
+    ) else {
+        return Err(MWErr::JWTError(JWTErr::JWTDecodeError));
+    };
+    let Ok(user_id) = decoded_claim.claims.sub.parse::<i64>() else {
+        return Err(MWErr::JWTError(JWTErr::JWTDecodeError));
+    };
+    Ok((user_id, decoded_claim.claims.jti))
 }
 
-pub fn get_jwt_for_credentials(
-    username: &String,
-    password_hashdata: &String,
-    secret: &String,
+pub fn get_jwt_for_token(
+    user_id: i64,
+    token_id: &str,
+    expires_at: chrono::DateTime<chrono::Utc>,
+    secret: &str,
 ) -> Result<String, MWErr> {
-    // Get the secret from the database
     let header = Header::new(Algorithm::HS256); // SHA2 is fine :)
 
-    let max_exp: usize = i64::MAX as usize; // OMG, this is a long time
     let claim = Claims {
-        username: username.to_string(),
-        hashed_password: password_hashdata.to_string(),
-        exp: max_exp,
+        sub: user_id.to_string(),
+        jti: token_id.to_string(),
+        exp: expires_at.timestamp().max(0) as usize,
     };
     let Ok(token) = encode(&header, &claim, &EncodingKey::from_secret(secret.as_ref())) else {
         return Err(MWErr::JWTError(JWTErr::JWTEncodeError));
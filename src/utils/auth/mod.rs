@@ -1,3 +1,5 @@
+pub mod account_tokens;
 pub mod hashing;
 pub mod jwt;
+pub mod tokens;
 pub mod user;
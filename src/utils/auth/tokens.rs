@@ -0,0 +1,81 @@
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use sea_orm::{DatabaseConnection, DbErr};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::mordorwide_errors::MWErr;
+use crate::orm::model::login_token;
+use crate::sharedstate::SharedState;
+use crate::utils::auth::jwt::{get_jwt_for_token, get_token_from_jwt, JWTErr};
+
+// Persists a new "remember me" token of the user and returns it as JWT
+pub async fn issue_login_token(user_id: i64, sstate: &Arc<SharedState>) -> Result<String, MWErr> {
+    let current_time = chrono::Utc::now();
+    let expires_at = current_time + sstate.remember_token_lifetime;
+    let token_id = Uuid::new_v4().to_string();
+
+    // Forget the expired tokens of the user on the way
+    if login_token::Entity::delete_many()
+        .filter(login_token::Column::UserId.eq(user_id))
+        .filter(login_token::Column::ExpiresAt.lte(current_time))
+        .exec(&*sstate.database)
+        .await
+        .is_err()
+    {
+        return Err(MWErr::DBError);
+    }
+
+    let db_token = login_token::ActiveModel {
+        token_id: Set(token_id.clone()),
+        user_id: Set(user_id),
+        created_at: Set(current_time),
+        expires_at: Set(expires_at),
+        ..Default::default()
+    };
+    if db_token.insert(&*sstate.database).await.is_err() {
+        return Err(MWErr::DBError);
+    }
+
+    get_jwt_for_token(user_id, &token_id, expires_at, &sstate.server_secret)
+}
+
+// Checks the signature and that the token has neither expired nor been revoked.
+// Returns the user id and the token id.
+pub async fn validate_login_token(
+    token: &str,
+    sstate: &Arc<SharedState>,
+) -> Result<(i64, String), MWErr> {
+    let (user_id, token_id) = get_token_from_jwt(token, &sstate.server_secret)?;
+
+    let Ok(db_token) = login_token::Entity::find()
+        .filter(login_token::Column::TokenId.eq(&token_id))
+        .one(&*sstate.database)
+        .await
+    else {
+        return Err(MWErr::DBError);
+    };
+    match db_token {
+        Some(db_token) if db_token.user_id == user_id && db_token.expires_at > chrono::Utc::now() => {
+            Ok((user_id, token_id))
+        }
+        _ => Err(MWErr::JWTError(JWTErr::TokenRevoked)),
+    }
+}
+
+pub async fn revoke_login_token(token_id: &str, db: &DatabaseConnection) -> Result<u64, DbErr> {
+    let result = login_token::Entity::delete_many()
+        .filter(login_token::Column::TokenId.eq(token_id))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected)
+}
+
+// Revokes all tokens of the user, e.g. after a password change or a ban
+pub async fn revoke_login_tokens(user_id: i64, db: &DatabaseConnection) -> Result<u64, DbErr> {
+    let result = login_token::Entity::delete_many()
+        .filter(login_token::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected)
+}
@@ -1,4 +1,4 @@
-use crate::orm::model::{account, ban};
+use crate::orm::model::account;
 use crate::packet::DataPacket;
 use crate::sharedstate::SharedState;
 use chrono::NaiveDate;
@@ -11,8 +11,11 @@ use crate::mordorwide_errors::MWErr;
 // Hashing
 use crate::utils::auth::hashing::{plain_string_to_hash, verify_plain_string_for_hash};
 
-// JWT
-use crate::utils::auth::jwt::{get_credential_pair_from_jwt, JWTErr};
+// Remember me tokens
+use crate::utils::auth::tokens::validate_login_token;
+
+// Bans
+use crate::utils::bans::{ban_message, find_active_ban, BanSubject};
 
 // User (EMail) Validation / Normalization
 use crate::utils::data_validation::email::email_normalize;
@@ -24,13 +27,17 @@ pub enum UserAuthErr {
     InvalidPassword,
     AlreadyAuthenticated,
     NewUserAlreadyRegistered,
-    UserBanned,
+    // Message to show the player
+    UserBanned(String),
+    // The email address has not been verified yet (see mail.require_verification)
+    NotVerified,
 }
 
 #[derive(Debug, Clone)]
 pub enum CredentialType {
     PlainText(String, String),
-    EncryptedHashed(String, String),
+    // Validated "remember me" token (user id, token id)
+    RememberToken(i64, String),
 }
 
 pub async fn get_credentials_from_packet(
@@ -48,19 +55,11 @@ pub async fn get_credentials_from_packet(
 
         Ok(CredentialType::PlainText(username, raw_password))
     } else if packet.data.get("encryptedInfo").is_some() {
-        // Encrypted JWT-encoded credentials
+        // "Remember me" token from an earlier login -> Must not be expired or revoked
         let encrypted_info = packet.data.get("encryptedInfo").unwrap();
-        let decoded_info = get_credential_pair_from_jwt(encrypted_info, &sstate.server_secret);
-
-        if let Err(_) = decoded_info {
-            return Err(MWErr::JWTError(JWTErr::JWTDecodeError));
-        }
-
-        let (username, password) = decoded_info.unwrap();
-
-        let username = email_normalize(&username);
+        let (user_id, token_id) = validate_login_token(encrypted_info, sstate).await?;
 
-        Ok(CredentialType::EncryptedHashed(username, password))
+        Ok(CredentialType::RememberToken(user_id, token_id))
     } else {
         Err(MWErr::UserAuthError(UserAuthErr::NoCredentials))
     }
@@ -68,49 +67,42 @@ pub async fn get_credentials_from_packet(
 
 pub async fn validate_credentials(
     credentials: &CredentialType,
+    ban_subject: BanSubject,
     sstate: &Arc<SharedState>,
 ) -> Result<i64, MWErr> {
-    // Extract username first
-    let username = match credentials {
-        CredentialType::PlainText(username, _) => username,
-        CredentialType::EncryptedHashed(username, _) => username,
-    };
-
     // Check if the user exists
-    let Ok(Some(db_user)) = account::Entity::find()
-        .filter(account::Column::Email.eq(username))
-        .one(&*sstate.database)
-        .await
-    else {
+    let user_query = match credentials {
+        CredentialType::PlainText(username, _) => {
+            account::Entity::find().filter(account::Column::Email.eq(username))
+        }
+        CredentialType::RememberToken(user_id, _) => account::Entity::find_by_id(*user_id),
+    };
+    let Ok(Some(db_user)) = user_query.one(&*sstate.database).await else {
         return Err(MWErr::UserAuthError(UserAuthErr::UserNotFound));
     };
 
-    // Verify password (hashes)
+    // Verify password (hashes). The token has been validated already.
     let credentials_valid = match credentials {
-        CredentialType::PlainText(username, plain_password) => {
+        CredentialType::PlainText(_, plain_password) => {
             verify_plain_string_for_hash(plain_password, &db_user.password_hashed)
         }
-        CredentialType::EncryptedHashed(username, hashed_password) => {
-            &db_user.password_hashed == hashed_password
-        }
+        CredentialType::RememberToken(_, _) => true,
     };
 
     if !credentials_valid {
         return Err(MWErr::UserAuthError(UserAuthErr::InvalidPassword));
     }
 
-    // Check if the user is banned
-    let lowercase_username = username.to_lowercase();
-    let email_sha256hash = sha256::digest(&lowercase_username);
-    match ban::Entity::find()
-        .filter(ban::Column::EmailHash.eq(email_sha256hash))
-        .count(&*sstate.database)
-        .await
-    {
-        Ok(n_hits) if n_hits > 0 => {
-            return Err(MWErr::UserAuthError(UserAuthErr::UserBanned));
+    // Check if the user (or the device) is banned
+    let ban_subject = BanSubject {
+        email: Some(db_user.email.to_string()),
+        ..ban_subject
+    };
+    match find_active_ban(&ban_subject, &sstate.database).await {
+        Ok(Some(db_ban)) => {
+            return Err(MWErr::UserAuthError(UserAuthErr::UserBanned(ban_message(&db_ban))));
         }
-        Ok(n_hits) => {
+        Ok(None) => {
             // User is not banned
         }
         Err(_) => {
@@ -118,6 +110,10 @@ pub async fn validate_credentials(
         }
     }
 
+    if sstate.mailer.config().require_verification && !db_user.is_verified {
+        return Err(MWErr::UserAuthError(UserAuthErr::NotVerified));
+    }
+
     Ok(db_user.id)
 }
 
@@ -0,0 +1,288 @@
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use sea_orm::{DatabaseConnection, DbErr};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::client_connection::ClientConnectionDescriptor;
+use crate::handler::fesl::update_presence;
+use crate::orm::model::{account, ban, session};
+use crate::plasma_handle::close_session;
+use crate::sharedstate::SharedState;
+use crate::utils::auth::tokens::revoke_login_tokens;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BanScope {
+    Account,
+    Persona,
+    Ip,
+    Mac,
+    Console,
+}
+
+impl BanScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BanScope::Account => "account",
+            BanScope::Persona => "persona",
+            BanScope::Ip => "ip",
+            BanScope::Mac => "mac",
+            BanScope::Console => "console",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "account" => Some(BanScope::Account),
+            "persona" => Some(BanScope::Persona),
+            "ip" => Some(BanScope::Ip),
+            "mac" => Some(BanScope::Mac),
+            "console" => Some(BanScope::Console),
+            _ => None,
+        }
+    }
+}
+
+// Everything known about a player that may be banned. Unknown fields are not checked.
+#[derive(Debug, Clone, Default)]
+pub struct BanSubject {
+    pub email: Option<String>,
+    pub persona_id: Option<i64>,
+    pub ip: Option<String>,
+    pub mac_addr: Option<String>,
+    pub console_id: Option<String>,
+}
+
+impl BanSubject {
+    pub fn from_session(db_session: &session::Model) -> Self {
+        let fesl_con = ClientConnectionDescriptor::from_string(&db_session.fesl_tcp_handle);
+        Self {
+            email: None,
+            persona_id: (db_session.persona_id != -1).then_some(db_session.persona_id),
+            ip: Some(fesl_con.client_ip),
+            mac_addr: Some(db_session.mac_addr.clone()).filter(|mac_addr| !mac_addr.is_empty()),
+            console_id: Some(db_session.console_id.clone())
+                .filter(|console_id| !console_id.is_empty()),
+        }
+    }
+}
+
+// Account bans are stored by the hash of the email, so they survive the account
+pub fn email_ban_target(email: &str) -> String {
+    sha256::digest(email.to_lowercase())
+}
+
+// The MAC address is sent as "$001122aabbcc" or "00:11:22:AA:BB:CC"
+pub fn normalize_mac_addr(mac_addr: &str) -> String {
+    mac_addr
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+// Normalizes the target of a new ban. Returns None if it is not valid for the scope.
+pub fn normalize_ban_target(scope: BanScope, target: &str) -> Option<String> {
+    let target = target.trim();
+    match scope {
+        BanScope::Account => {
+            if target.len() == 64 && target.chars().all(|c| c.is_ascii_hexdigit()) {
+                Some(target.to_lowercase())
+            } else if target.contains('@') {
+                Some(email_ban_target(target))
+            } else {
+                None
+            }
+        }
+        BanScope::Persona => target.parse::<i64>().ok().map(|persona_id| persona_id.to_string()),
+        BanScope::Ip => {
+            let (addr, prefix) = match target.split_once('/') {
+                Some((addr, prefix)) => (addr, Some(prefix)),
+                None => (target, None),
+            };
+            let addr = addr.parse::<IpAddr>().ok()?;
+            let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+            match prefix {
+                Some(prefix) => {
+                    let prefix = prefix.parse::<u32>().ok().filter(|p| *p <= max_prefix)?;
+                    Some(format!("{}/{}", addr, prefix))
+                }
+                None => Some(addr.to_string()),
+            }
+        }
+        BanScope::Mac => Some(normalize_mac_addr(target)).filter(|mac_addr| mac_addr.len() == 12),
+        BanScope::Console => Some(target.to_string()).filter(|console_id| !console_id.is_empty()),
+    }
+}
+
+// Checks if the IP is the address (or in the CIDR range) of an IP ban
+fn ip_matches(ip: &str, target: &str) -> bool {
+    let Ok(ip) = ip.parse::<IpAddr>() else {
+        return false;
+    };
+    let (addr, prefix) = match target.split_once('/') {
+        Some((addr, prefix)) => (addr, prefix.parse::<u32>().ok()),
+        None => (target, None),
+    };
+    let Ok(addr) = addr.parse::<IpAddr>() else {
+        return false;
+    };
+    match (ip, addr) {
+        (IpAddr::V4(ip), IpAddr::V4(addr)) => {
+            let prefix = prefix.unwrap_or(32).min(32);
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(addr) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(addr)) => {
+            let prefix = prefix.unwrap_or(128).min(128);
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(addr) & mask
+        }
+        _ => false,
+    }
+}
+
+pub fn ban_matches(db_ban: &ban::Model, ban_subject: &BanSubject) -> bool {
+    let Some(scope) = BanScope::parse(&db_ban.scope) else {
+        return false;
+    };
+    match scope {
+        BanScope::Account => ban_subject
+            .email
+            .as_ref()
+            .is_some_and(|email| email_ban_target(email) == db_ban.target),
+        BanScope::Persona => ban_subject
+            .persona_id
+            .is_some_and(|persona_id| persona_id.to_string() == db_ban.target),
+        BanScope::Ip => ban_subject
+            .ip
+            .as_ref()
+            .is_some_and(|ip| ip_matches(ip, &db_ban.target)),
+        BanScope::Mac => ban_subject
+            .mac_addr
+            .as_ref()
+            .is_some_and(|mac_addr| normalize_mac_addr(mac_addr) == db_ban.target),
+        BanScope::Console => ban_subject
+            .console_id
+            .as_ref()
+            .is_some_and(|console_id| console_id == &db_ban.target),
+    }
+}
+
+pub fn ban_is_active(db_ban: &ban::Model) -> bool {
+    db_ban
+        .expires_at
+        .is_none_or(|expires_at| expires_at > chrono::Utc::now())
+}
+
+// The first active ban that applies to the subject.
+// The exact targets are looked up by (scope, target). IP bans may be CIDR ranges,
+// so they are loaded by scope and matched in memory.
+pub async fn find_active_ban(
+    ban_subject: &BanSubject,
+    db: &DatabaseConnection,
+) -> Result<Option<ban::Model>, DbErr> {
+    let mut exact_targets: Vec<(BanScope, String)> = Vec::new();
+    if let Some(email) = &ban_subject.email {
+        exact_targets.push((BanScope::Account, email_ban_target(email)));
+    }
+    if let Some(persona_id) = ban_subject.persona_id {
+        exact_targets.push((BanScope::Persona, persona_id.to_string()));
+    }
+    if let Some(mac_addr) = &ban_subject.mac_addr {
+        exact_targets.push((BanScope::Mac, normalize_mac_addr(mac_addr)));
+    }
+    if let Some(console_id) = &ban_subject.console_id {
+        exact_targets.push((BanScope::Console, console_id.clone()));
+    }
+    if exact_targets.is_empty() && ban_subject.ip.is_none() {
+        return Ok(None);
+    }
+
+    let mut targets = Condition::any();
+    for (scope, target) in exact_targets {
+        targets = targets.add(
+            Condition::all()
+                .add(ban::Column::Scope.eq(scope.as_str()))
+                .add(ban::Column::Target.eq(target)),
+        );
+    }
+    if ban_subject.ip.is_some() {
+        targets = targets.add(ban::Column::Scope.eq(BanScope::Ip.as_str()));
+    }
+
+    Ok(ban::Entity::find()
+        .filter(targets)
+        .filter(
+            Condition::any()
+                .add(ban::Column::ExpiresAt.is_null())
+                .add(ban::Column::ExpiresAt.gt(chrono::Utc::now())),
+        )
+        .order_by(ban::Column::Id, sea_orm::Order::Asc)
+        .all(db)
+        .await?
+        .into_iter()
+        .find(|db_ban| ban_matches(db_ban, ban_subject)))
+}
+
+// Text shown to the banned player
+pub fn ban_message(db_ban: &ban::Model) -> String {
+    let message = match db_ban.expires_at {
+        Some(expires_at) => format!("Banned until {}", expires_at.format("%Y-%m-%d %H:%M UTC")),
+        None => "Banned permanently".to_string(),
+    };
+    if db_ban.reason.is_empty() {
+        message
+    } else {
+        format!("{}: {}", message, db_ban.reason)
+    }
+}
+
+// Revokes the "remember me" tokens of a banned account
+async fn revoke_banned_login_tokens(account_id: i64, db: &DatabaseConnection) {
+    if let Err(e) = revoke_login_tokens(account_id, db).await {
+        warn!(target: "auth", "Failed to revoke the login tokens of account {}: {}", account_id, e);
+    }
+}
+
+// Disconnects the sessions the ban applies to. For account bans, the tokens of the
+// account the ban was issued for (if known) and of the disconnected accounts are
+// revoked. Returns the number of closed sessions.
+pub async fn enforce_ban(
+    db_ban: &ban::Model,
+    account_id: Option<i64>,
+    sstate: &Arc<SharedState>,
+) -> usize {
+    if !ban_is_active(db_ban) {
+        return 0;
+    }
+    let is_account_ban = db_ban.scope == BanScope::Account.as_str();
+    if is_account_ban && let Some(account_id) = account_id {
+        revoke_banned_login_tokens(account_id, &sstate.database).await;
+    }
+    let mut n_kicked = 0;
+    for db_session in sstate.registry.get_sessions() {
+        let mut ban_subject = BanSubject::from_session(&db_session);
+        if is_account_ban {
+            ban_subject.email = account::Entity::find_by_id(db_session.user_id)
+                .one(&*sstate.database)
+                .await
+                .unwrap_or(None)
+                .map(|db_account| db_account.email);
+        }
+        if !ban_matches(db_ban, &ban_subject) {
+            continue;
+        }
+        if is_account_ban && account_id != Some(db_session.user_id) {
+            revoke_banned_login_tokens(db_session.user_id, &sstate.database).await;
+        }
+        let persona_id = db_session.persona_id;
+        close_session(db_session, sstate).await;
+        if persona_id != -1 {
+            update_presence(sstate, persona_id, None).await;
+        }
+        n_kicked += 1;
+    }
+    n_kicked
+}
@@ -0,0 +1,224 @@
+use rand::RngExt;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use sea_orm::{DatabaseConnection, DbErr};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::orm::model::linked_identity;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IdentityPlatform {
+    Psn,
+    Xbl,
+}
+
+impl IdentityPlatform {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IdentityPlatform::Psn => "psn",
+            IdentityPlatform::Xbl => "xbl",
+        }
+    }
+
+    pub fn parse(platform: &str) -> Option<Self> {
+        match platform {
+            "psn" => Some(IdentityPlatform::Psn),
+            "xbl" => Some(IdentityPlatform::Xbl),
+            _ => None,
+        }
+    }
+
+    // Shown to the player
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            IdentityPlatform::Psn => "PSN",
+            IdentityPlatform::Xbl => "Xbox Live",
+        }
+    }
+
+    // Whether the login proves the identity. PSN tickets are signed, but the XUID of
+    // an Xbox Live login is sent by the client as is. A linked XUID is therefore no
+    // proof of ownership, and its persona must allow insecure logins.
+    pub fn is_verified(&self) -> bool {
+        match self {
+            IdentityPlatform::Psn => true,
+            IdentityPlatform::Xbl => false,
+        }
+    }
+}
+
+// Console identity that tried to log in without being linked
+#[derive(Debug, Clone)]
+pub struct PendingLink {
+    pub platform: IdentityPlatform,
+    pub external_id: String,
+    pub display_name: String,
+    expires_at: Instant,
+}
+
+// No 0/O and 1/I, as the code is typed in by hand
+const LINK_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const LINK_CODE_LENGTH: usize = 8;
+
+// Codes that may be pending at once. Further identities get no code until some expire.
+const MAX_PENDING_LINKS: usize = 10000;
+
+#[derive(Debug, Default)]
+struct PendingLinkStore {
+    // code -> link
+    by_code: HashMap<String, PendingLink>,
+    // (platform, external id) -> code
+    by_identity: HashMap<(IdentityPlatform, String), String>,
+}
+
+impl PendingLinkStore {
+    fn remove(&mut self, code: &str) -> Option<PendingLink> {
+        let link = self.by_code.remove(code)?;
+        self.by_identity.remove(&(link.platform, link.external_id.clone()));
+        Some(link)
+    }
+
+    fn remove_expired(&mut self, now: Instant) {
+        self.by_code.retain(|_, link| link.expires_at > now);
+        let by_code = &self.by_code;
+        self.by_identity.retain(|_, code| by_code.contains_key(code));
+    }
+}
+
+// Link codes handed out to unlinked console identities
+#[derive(Debug)]
+pub struct PendingLinks {
+    lifetime: Duration,
+    store: Mutex<PendingLinkStore>,
+}
+
+impl PendingLinks {
+    pub fn new(lifetime: Duration) -> Self {
+        Self {
+            lifetime,
+            store: Mutex::new(PendingLinkStore::default()),
+        }
+    }
+
+    // Codes are written as "ABCD-EFGH", but accepted in any case and without the dash
+    pub fn normalize_code(code: &str) -> String {
+        code.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_uppercase()
+    }
+
+    pub fn format_code(code: &str) -> String {
+        let (first, second) = code.split_at(code.len() / 2);
+        format!("{}-{}", first, second)
+    }
+
+    // The valid code of the identity, if one was issued
+    pub fn find(&self, platform: IdentityPlatform, external_id: &str) -> Option<String> {
+        let store = self.store.lock().unwrap();
+        let code = store.by_identity.get(&(platform, external_id.to_string()))?;
+        let link = store.by_code.get(code)?;
+        (link.expires_at > Instant::now()).then(|| code.clone())
+    }
+
+    // Returns the code for the identity. The code of an earlier attempt is reused
+    // while it is valid, so that repeated logins do not invalidate the shown code.
+    // Returns None if too many codes are pending.
+    pub fn issue(
+        &self,
+        platform: IdentityPlatform,
+        external_id: &str,
+        display_name: &str,
+    ) -> Option<String> {
+        let now = Instant::now();
+        let mut store = self.store.lock().unwrap();
+
+        let identity = (platform, external_id.to_string());
+        if let Some(code) = store.by_identity.get(&identity).cloned() {
+            if store.by_code.get(&code).is_some_and(|link| link.expires_at > now) {
+                return Some(code);
+            }
+            store.remove(&code);
+        }
+        if store.by_code.len() >= MAX_PENDING_LINKS {
+            store.remove_expired(now);
+            if store.by_code.len() >= MAX_PENDING_LINKS {
+                return None;
+            }
+        }
+
+        let mut rng = rand::rng();
+        let code = loop {
+            let code: String = (0..LINK_CODE_LENGTH)
+                .map(|_| LINK_CODE_ALPHABET[rng.random_range(0..LINK_CODE_ALPHABET.len())] as char)
+                .collect();
+            if !store.by_code.contains_key(&code) {
+                break code;
+            }
+        };
+        store.by_code.insert(
+            code.clone(),
+            PendingLink {
+                platform,
+                external_id: external_id.to_string(),
+                display_name: display_name.to_string(),
+                expires_at: now + self.lifetime,
+            },
+        );
+        store.by_identity.insert(identity, code.clone());
+        Some(code)
+    }
+
+    pub fn get(&self, code: &str) -> Option<PendingLink> {
+        let store = self.store.lock().unwrap();
+        let link = store.by_code.get(&Self::normalize_code(code))?;
+        (link.expires_at > Instant::now()).then(|| link.clone())
+    }
+
+    // Uses up the code
+    pub fn take(&self, code: &str) -> Option<PendingLink> {
+        let link = self.store.lock().unwrap().remove(&Self::normalize_code(code))?;
+        (link.expires_at > Instant::now()).then_some(link)
+    }
+}
+
+pub async fn find_linked_identity(
+    platform: IdentityPlatform,
+    external_id: &str,
+    db: &DatabaseConnection,
+) -> Result<Option<linked_identity::Model>, DbErr> {
+    linked_identity::Entity::find()
+        .filter(linked_identity::Column::Platform.eq(platform.as_str()))
+        .filter(linked_identity::Column::ExternalId.eq(external_id))
+        .one(db)
+        .await
+}
+
+// Binds the identity to the persona. An existing link of the identity is replaced.
+pub async fn link_identity(
+    platform: IdentityPlatform,
+    external_id: &str,
+    display_name: &str,
+    user_id: i64,
+    persona_id: i64,
+    db: &DatabaseConnection,
+) -> Result<linked_identity::Model, DbErr> {
+    linked_identity::Entity::delete_many()
+        .filter(linked_identity::Column::Platform.eq(platform.as_str()))
+        .filter(linked_identity::Column::ExternalId.eq(external_id))
+        .exec(db)
+        .await?;
+
+    let db_identity = linked_identity::ActiveModel {
+        platform: Set(platform.as_str().to_string()),
+        external_id: Set(external_id.to_string()),
+        display_name: Set(display_name.to_string()),
+        user_id: Set(user_id),
+        persona_id: Set(persona_id),
+        linked_at: Set(chrono::Utc::now()),
+        ..Default::default()
+    };
+    db_identity.insert(db).await
+}
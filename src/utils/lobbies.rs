@@ -0,0 +1,58 @@
+use crate::client_connection::ClientConnectionDescriptor;
+use crate::orm::model::{lobby, session};
+use crate::sharedstate::SharedState;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use sea_orm::DatabaseConnection;
+
+pub const PLATFORM_PC: &str = "pc";
+pub const PLATFORM_PS3: &str = "ps3";
+pub const PLATFORM_XBOX360: &str = "xbox360";
+
+pub fn is_valid_platform(platform: &str) -> bool {
+    [PLATFORM_PC, PLATFORM_PS3, PLATFORM_XBOX360].contains(&platform)
+}
+
+// The platform is derived from the FESL port the client logged in with, as configured
+// by the "platform" of the FESL services
+pub fn get_session_platform(db_session: &session::Model, sstate: &SharedState) -> Option<String> {
+    if db_session.fesl_tcp_handle.is_empty() {
+        return None;
+    }
+    let fesl_port = ClientConnectionDescriptor::from_string(&db_session.fesl_tcp_handle).host_port;
+    sstate.fesl_platforms.get(&fesl_port).cloned()
+}
+
+fn is_lobby_available(db_lobby: &lobby::Model, platform: Option<&str>) -> bool {
+    db_lobby.enabled
+        && (db_lobby.platform.is_empty() || Some(db_lobby.platform.as_str()) == platform)
+}
+
+// Enabled lobbies that are open to the platform, ordered by id
+pub async fn get_available_lobbies(
+    platform: Option<&str>,
+    db: &DatabaseConnection,
+) -> Vec<lobby::Model> {
+    lobby::Entity::find()
+        .order_by(lobby::Column::Id, sea_orm::Order::Asc)
+        .all(db)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|db_lobby| is_lobby_available(db_lobby, platform))
+        .collect()
+}
+
+pub async fn get_available_lobby(
+    lobby_id: i32,
+    platform: Option<&str>,
+    db: &DatabaseConnection,
+) -> Option<lobby::Model> {
+    let Ok(Some(db_lobby)) = lobby::Entity::find_by_id(lobby_id).one(db).await else {
+        return None;
+    };
+    if !is_lobby_available(&db_lobby, platform) {
+        return None;
+    }
+    Some(db_lobby)
+}
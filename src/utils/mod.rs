@@ -1,5 +1,10 @@
+pub mod associations;
 pub mod auth;
+pub mod bans;
 pub mod config_values;
 pub mod data_validation;
+pub mod linking;
+pub mod lobbies;
 pub mod psn;
+pub mod stats;
 pub mod stun_turn;
@@ -1,5 +1,10 @@
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Public};
+use openssl::sign::Verifier;
 use std::num::ParseIntError;
 
+use crate::config::{PsnIssuerConfig, PsnSection};
+
 // Helper to decode hex string to bytes
 pub fn dec_hex_str(s: &str) -> Result<Vec<u8>, ParseIntError> {
     (0..s.len())
@@ -283,6 +288,8 @@ impl std::fmt::Debug for PSNTicketData {
 pub struct PSNTicketSection {
     pub header: PSNTicketSectionHeader,
     pub data_entries: Vec<PSNTicketData>,
+    // Position of the section within the ticket
+    pub offset: usize,
 }
 
 impl PSNTicketSection {
@@ -314,6 +321,7 @@ impl PSNTicketSection {
         Ok(PSNTicketSection {
             header,
             data_entries,
+            offset: 0,
         })
     }
 }
@@ -322,6 +330,8 @@ impl PSNTicketSection {
 pub struct PSNTicket {
     pub header: PSNTicketHeader,
     pub sections: Vec<PSNTicketSection>,
+    // The ticket as received, for the signature check
+    pub raw: Vec<u8>,
 }
 
 impl PSNTicket {
@@ -334,14 +344,279 @@ impl PSNTicket {
         let mut offset = header.header_size as usize;
         while offset < data.len() {
             // Parse Section
-            let Ok(section) = PSNTicketSection::from_bytes(&data[offset..]) else {
+            let Ok(mut section) = PSNTicketSection::from_bytes(&data[offset..]) else {
                 return Err("Invalid PSN ticket section");
             };
+            section.offset = offset;
 
             offset += section.header.header_size as usize + section.header.length as usize;
             sections.push(section);
         }
 
-        Ok(PSNTicket { header, sections })
+        Ok(PSNTicket {
+            header,
+            sections,
+            raw: data.to_vec(),
+        })
+    }
+
+    pub fn section(&self, section_type: PSNSectionType) -> Option<&PSNTicketSection> {
+        self.sections
+            .iter()
+            .find(|section| section.header.section_type == section_type)
+    }
+
+    fn body_entry(&self, index: usize) -> Option<&PSNTicketData> {
+        self.section(PSNSectionType::Body)?.data_entries.get(index)
+    }
+
+    // Payload without the zero padding
+    fn body_string(&self, index: usize) -> Option<String> {
+        let payload = &self.body_entry(index)?.payload;
+        let end = payload.iter().rposition(|&byte| byte != 0).map_or(0, |pos| pos + 1);
+        String::from_utf8(payload[..end].to_vec()).ok()
+    }
+
+    fn body_timestamp(&self, index: usize) -> Option<chrono::DateTime<chrono::Utc>> {
+        let entry = self.body_entry(index)?;
+        if entry.data_type != PSNDataType::TimestampMS {
+            return None;
+        }
+        let millis = u64::from_be_bytes(entry.payload.as_slice().try_into().ok()?);
+        chrono::DateTime::from_timestamp_millis(i64::try_from(millis).ok()?)
+    }
+
+    // The body entries are: serial id, issuer id, issue time, expiry time, user id,
+    // online id (PSN name), region, domain, service id, status
+    pub fn issuer_id(&self) -> Option<u32> {
+        let entry = self.body_entry(1)?;
+        Some(u32::from_be_bytes(entry.payload.as_slice().try_into().ok()?))
+    }
+
+    pub fn issued_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.body_timestamp(2)
+    }
+
+    pub fn expires_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.body_timestamp(3)
+    }
+
+    // PSN account id; stays the same if the PSN name changes
+    pub fn user_id(&self) -> Option<u64> {
+        let entry = self.body_entry(4)?;
+        Some(u64::from_be_bytes(entry.payload.as_slice().try_into().ok()?))
+    }
+
+    pub fn online_id(&self) -> Option<String> {
+        self.body_string(5).filter(|online_id| !online_id.is_empty())
+    }
+
+    pub fn service_id(&self) -> Option<String> {
+        self.body_string(8)
+    }
+
+    // The footer holds the id of the signing key and the signature
+    pub fn cipher_id(&self) -> Option<&[u8]> {
+        let footer = self.section(PSNSectionType::Footer)?;
+        Some(&footer.data_entries.first()?.payload)
+    }
+
+    pub fn signature(&self) -> Option<&[u8]> {
+        let footer = self.section(PSNSectionType::Footer)?;
+        let signature = &footer.data_entries.get(1)?.payload;
+        // The DER signature may be zero-padded to a fixed size
+        if signature.len() >= 2 && signature[0] == 0x30 {
+            let der_length = signature[1] as usize + 2;
+            if der_length <= signature.len() {
+                return Some(&signature[..der_length]);
+            }
+        }
+        Some(signature)
+    }
+
+    fn signed_data(&self, signed_data: PSNSignedData) -> Option<&[u8]> {
+        match signed_data {
+            PSNSignedData::Body => {
+                let body = self.section(PSNSectionType::Body)?;
+                let end = body.offset + body.header.header_size as usize + body.header.length as usize;
+                self.raw.get(body.offset..end)
+            }
+            PSNSignedData::Ticket => {
+                let footer = self.section(PSNSectionType::Footer)?;
+                self.raw.get(..footer.offset)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PSNTicketErr {
+    Malformed,
+    NoSignature,
+    UnknownIssuer,
+    InvalidSignature,
+    NotYetValid,
+    Expired,
+    ServiceNotAllowed,
+}
+
+// The part of the ticket covered by the signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PSNSignedData {
+    // The body section (incl. its header)
+    Body,
+    // Everything before the footer section
+    Ticket,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PSNDigest {
+    Sha1,
+    Sha224,
+    Sha256,
+}
+
+impl PSNDigest {
+    fn message_digest(self) -> MessageDigest {
+        match self {
+            PSNDigest::Sha1 => MessageDigest::sha1(),
+            PSNDigest::Sha224 => MessageDigest::sha224(),
+            PSNDigest::Sha256 => MessageDigest::sha256(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PSNIssuer {
+    name: String,
+    cipher_id: Vec<u8>,
+    issuer_id: Option<u32>,
+    public_key: PKey<Public>,
+    digest: PSNDigest,
+    signed_data: PSNSignedData,
+}
+
+impl PSNIssuer {
+    fn from_config(config: &PsnIssuerConfig) -> Result<Self, String> {
+        // Either 8 hex digits or 4 characters (e.g. "RPCN")
+        let cipher_id = if config.cipher_id.len() == 8 {
+            dec_hex_str(&config.cipher_id).map_err(|_| "invalid cipher_id".to_string())?
+        } else {
+            config.cipher_id.as_bytes().to_vec()
+        };
+        let pem = match (&config.public_key, &config.public_key_path) {
+            (Some(pem), _) => pem.as_bytes().to_vec(),
+            (None, Some(path)) => std::fs::read(path)
+                .map_err(|e| format!("cannot read public key {}: {}", path, e))?,
+            (None, None) => return Err("public_key or public_key_path is required".to_string()),
+        };
+        let public_key = PKey::public_key_from_pem(&pem)
+            .map_err(|e| format!("invalid public key: {}", e))?;
+        let digest = match config.digest.as_str() {
+            "sha1" => PSNDigest::Sha1,
+            "sha224" => PSNDigest::Sha224,
+            "sha256" => PSNDigest::Sha256,
+            _ => return Err(format!("unknown digest '{}'", config.digest)),
+        };
+        let signed_data = match config.signed_data.as_str() {
+            "body" => PSNSignedData::Body,
+            "ticket" => PSNSignedData::Ticket,
+            _ => return Err(format!("unknown signed_data '{}'", config.signed_data)),
+        };
+        Ok(Self {
+            name: config.name.to_string(),
+            cipher_id,
+            issuer_id: config.issuer_id,
+            public_key,
+            digest,
+            signed_data,
+        })
+    }
+
+    fn verify_signature(&self, ticket: &PSNTicket, signature: &[u8]) -> bool {
+        let Some(signed_data) = ticket.signed_data(self.signed_data) else {
+            return false;
+        };
+        let Ok(mut verifier) = Verifier::new(self.digest.message_digest(), &self.public_key) else {
+            return false;
+        };
+        verifier.update(signed_data).is_ok() && verifier.verify(signature).unwrap_or(false)
+    }
+}
+
+// Checks the signature, validity and service of the tickets sent with NuPS3Login
+#[derive(Debug)]
+pub struct PSNTicketVerifier {
+    verify_tickets: bool,
+    max_clock_skew: chrono::Duration,
+    allowed_service_ids: Vec<String>,
+    issuers: Vec<PSNIssuer>,
+}
+
+impl PSNTicketVerifier {
+    pub fn new(config: &PsnSection) -> Result<Self, String> {
+        let mut issuers = Vec::new();
+        if config.verify_tickets {
+            for issuer_config in config.issuers.iter() {
+                let issuer = PSNIssuer::from_config(issuer_config)
+                    .map_err(|e| format!("PSN issuer '{}': {}", issuer_config.name, e))?;
+                issuers.push(issuer);
+            }
+        }
+        Ok(Self {
+            verify_tickets: config.verify_tickets,
+            max_clock_skew: chrono::Duration::seconds(config.max_clock_skew_secs as i64),
+            allowed_service_ids: config.allowed_service_ids.clone(),
+            issuers,
+        })
+    }
+
+    pub fn verify_tickets(&self) -> bool {
+        self.verify_tickets
+    }
+
+    pub fn n_issuers(&self) -> usize {
+        self.issuers.len()
+    }
+
+    // Returns the name of the issuer that signed the ticket
+    pub fn verify(&self, ticket: &PSNTicket) -> Result<&str, PSNTicketErr> {
+        if !self.verify_tickets {
+            return Ok("unverified");
+        }
+        let (Some(cipher_id), Some(signature)) = (ticket.cipher_id(), ticket.signature()) else {
+            return Err(PSNTicketErr::NoSignature);
+        };
+        let ticket_issuer_id = ticket.issuer_id();
+        let Some(issuer) = self.issuers.iter().find(|issuer| {
+            issuer.cipher_id == cipher_id
+                && issuer.issuer_id.is_none_or(|issuer_id| Some(issuer_id) == ticket_issuer_id)
+        }) else {
+            return Err(PSNTicketErr::UnknownIssuer);
+        };
+        if !issuer.verify_signature(ticket, signature) {
+            return Err(PSNTicketErr::InvalidSignature);
+        }
+
+        let (Some(issued_at), Some(expires_at)) = (ticket.issued_at(), ticket.expires_at()) else {
+            return Err(PSNTicketErr::Malformed);
+        };
+        let now = chrono::Utc::now();
+        if issued_at > now + self.max_clock_skew {
+            return Err(PSNTicketErr::NotYetValid);
+        }
+        if expires_at + self.max_clock_skew < now {
+            return Err(PSNTicketErr::Expired);
+        }
+
+        if !self.allowed_service_ids.is_empty() {
+            let Some(service_id) = ticket.service_id() else {
+                return Err(PSNTicketErr::Malformed);
+            };
+            if !self.allowed_service_ids.contains(&service_id) {
+                return Err(PSNTicketErr::ServiceNotAllowed);
+            }
+        }
+        Ok(&issuer.name)
     }
 }
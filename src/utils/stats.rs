@@ -0,0 +1,311 @@
+use crate::orm::model::{persona, stat, stat_period};
+use crate::plasma_errors::EAError;
+use crate::registry::Registry;
+use crate::utils::config_values::get_cfg_value;
+use sea_orm::entity::*;
+use sea_orm::query::*;
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbErr, TransactionError};
+use tracing::warn;
+
+// Past periods that are kept per period id; older rows are deleted on rollover
+const MAX_PAST_PERIODS: i32 = 12;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// A period id of the client and the length of its periods. Periods start at multiples
+// of the length since the Unix epoch (UTC).
+struct StatPeriod {
+    period_id: i32,
+    // 0 keeps the stats for all time
+    length_days: i64,
+}
+
+impl StatPeriod {
+    fn running_index(&self) -> Option<i64> {
+        if self.length_days == 0 {
+            return None;
+        }
+        Some(
+            chrono::Utc::now()
+                .timestamp()
+                .div_euclid(self.length_days * SECONDS_PER_DAY),
+        )
+    }
+}
+
+// Period ids from STAT_PERIODS ("period id:length in days" pairs)
+async fn get_stat_periods(db: &DatabaseConnection) -> Vec<StatPeriod> {
+    get_cfg_value("STAT_PERIODS", db)
+        .await
+        .unwrap_or("0:0".to_string())
+        .split(',')
+        .filter_map(|period| {
+            let (period_id, length_days) = period.split_once(':')?;
+            Some(StatPeriod {
+                period_id: period_id.trim().parse().ok()?,
+                length_days: length_days.trim().parse().ok().filter(|days| *days >= 0)?,
+            })
+        })
+        .collect()
+}
+
+// Moves the stats of the period id one period back per period that ended since the
+// last rollover. The running period then starts without entries.
+async fn roll_over_period(period: &StatPeriod, db: &DatabaseConnection) -> Result<(), DbErr> {
+    let Some(running_index) = period.running_index() else {
+        return Ok(());
+    };
+    let period_id = period.period_id;
+    let Some(db_period) = stat_period::Entity::find_by_id(period_id).one(db).await? else {
+        // First use of the period id, there is nothing to roll over
+        let new_period = stat_period::ActiveModel {
+            period_id: Set(period_id),
+            running_index: Set(running_index),
+        };
+        stat_period::Entity::insert(new_period)
+            .on_conflict(
+                OnConflict::column(stat_period::Column::PeriodId)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(db)
+            .await?;
+        return Ok(());
+    };
+    if db_period.running_index >= running_index {
+        return Ok(());
+    }
+    let n_ended = (running_index - db_period.running_index).min(MAX_PAST_PERIODS as i64 + 1) as i32;
+    db.transaction::<_, (), DbErr>(|txn| {
+        Box::pin(async move {
+            // Concurrent rollovers of the same period only move the stats once
+            let claim_result = stat_period::Entity::update_many()
+                .col_expr(stat_period::Column::RunningIndex, Expr::value(running_index))
+                .filter(stat_period::Column::PeriodId.eq(period_id))
+                .filter(stat_period::Column::RunningIndex.eq(db_period.running_index))
+                .exec(txn)
+                .await?;
+            if claim_result.rows_affected == 0 {
+                return Ok(());
+            }
+            stat::Entity::update_many()
+                .col_expr(
+                    stat::Column::PeriodPast,
+                    Expr::col(stat::Column::PeriodPast).add(n_ended),
+                )
+                .filter(stat::Column::PeriodId.eq(period_id))
+                .exec(txn)
+                .await?;
+            stat::Entity::delete_many()
+                .filter(stat::Column::PeriodId.eq(period_id))
+                .filter(stat::Column::PeriodPast.gt(MAX_PAST_PERIODS))
+                .exec(txn)
+                .await?;
+            Ok(())
+        })
+    })
+    .await
+    .map_err(|err| match err {
+        TransactionError::Connection(err) | TransactionError::Transaction(err) => err,
+    })
+}
+
+// Whether the period of a read is kept. Rolls the period over first, so that the read
+// sees the current entries. Past periods are not kept for the all time period ids.
+pub async fn prepare_stat_period(period_id: i32, period_past: i32, db: &DatabaseConnection) -> bool {
+    let Some(period) = get_stat_periods(db)
+        .await
+        .into_iter()
+        .find(|period| period.period_id == period_id)
+    else {
+        return false;
+    };
+    let max_period_past = if period.length_days == 0 {
+        0
+    } else {
+        MAX_PAST_PERIODS
+    };
+    if !(0..=max_period_past).contains(&period_past) {
+        return false;
+    }
+    if let Err(err) = roll_over_period(&period, db).await {
+        warn!(target: "stats", "Failed to roll over stat period {}: {}", period_id, err);
+        return false;
+    }
+    true
+}
+
+// Rolls over all period ids and returns them. Stat updates are written to the running
+// period of each of them.
+pub async fn prepare_stat_periods(db: &DatabaseConnection) -> Result<Vec<i32>, DbErr> {
+    let periods = get_stat_periods(db).await;
+    for period in periods.iter() {
+        roll_over_period(period, db).await?;
+    }
+    Ok(periods.into_iter().map(|period| period.period_id).collect())
+}
+
+fn leaderboard_condition(stat_key: &str, period_id: i32, period_past: i32) -> Condition {
+    Condition::all()
+        .add(stat::Column::StatKey.eq(stat_key))
+        .add(stat::Column::PeriodId.eq(period_id))
+        .add(stat::Column::PeriodPast.eq(period_past))
+}
+
+// Entries of the leaderboard of a stat key, best first. Ties are broken by the persona id.
+pub async fn get_leaderboard(
+    stat_key: &str,
+    period_id: i32,
+    period_past: i32,
+    ascending: bool,
+    offset: u64,
+    limit: u64,
+    db: &DatabaseConnection,
+) -> Vec<stat::Model> {
+    let value_order = if ascending {
+        sea_orm::Order::Asc
+    } else {
+        sea_orm::Order::Desc
+    };
+    stat::Entity::find()
+        .filter(leaderboard_condition(stat_key, period_id, period_past))
+        .order_by(stat::Column::Value, value_order)
+        .order_by(stat::Column::PersonaId, sea_orm::Order::Asc)
+        .offset(offset)
+        .limit(limit)
+        .all(db)
+        .await
+        .unwrap_or_default()
+}
+
+pub async fn get_stat<C: ConnectionTrait>(
+    persona_id: i64,
+    stat_key: &str,
+    period_id: i32,
+    period_past: i32,
+    db: &C,
+) -> Option<stat::Model> {
+    stat::Entity::find()
+        .filter(
+            leaderboard_condition(stat_key, period_id, period_past)
+                .add(stat::Column::PersonaId.eq(persona_id)),
+        )
+        .one(db)
+        .await
+        .unwrap_or(None)
+}
+
+// 1-based rank of the entry within its leaderboard, consistent with get_leaderboard
+pub async fn get_stat_rank(db_stat: &stat::Model, ascending: bool, db: &DatabaseConnection) -> u64 {
+    let better_value = if ascending {
+        stat::Column::Value.lt(db_stat.value)
+    } else {
+        stat::Column::Value.gt(db_stat.value)
+    };
+    let n_better = stat::Entity::find()
+        .filter(
+            leaderboard_condition(&db_stat.stat_key, db_stat.period_id, db_stat.period_past).add(
+                Condition::any().add(better_value).add(
+                    Condition::all()
+                        .add(stat::Column::Value.eq(db_stat.value))
+                        .add(stat::Column::PersonaId.lt(db_stat.persona_id)),
+                ),
+            ),
+        )
+        .count(db)
+        .await
+        .unwrap_or(0);
+    n_better + 1
+}
+
+// Stats are owned by personas, but the client addresses them by the owner id handed out
+// at login (the user id). Reads prefer the persona the user is currently logged in with.
+pub async fn resolve_owner_persona(
+    owner_id: i64,
+    registry: &Registry,
+    db: &DatabaseConnection,
+) -> Option<persona::Model> {
+    if let Some(db_session) = registry
+        .get_sessions_by_user(owner_id)
+        .into_iter()
+        .find(|db_session| db_session.persona_id != -1)
+        && let Ok(Some(db_persona)) = persona::Entity::find_by_id(db_session.persona_id)
+            .one(db)
+            .await
+    {
+        return Some(db_persona);
+    }
+    persona::Entity::find()
+        .filter(persona::Column::UserId.eq(owner_id))
+        .order_by(persona::Column::Id, sea_orm::Order::Asc)
+        .one(db)
+        .await
+        .unwrap_or(None)
+}
+
+// Persona of the owner (user) id whose stats the writer may update: the writer itself, or
+// the persona of the owner that is in a game of the writer. Hosts report at the end of the
+// round, so players that just left (and may have logged out) still count.
+pub async fn resolve_writable_persona(
+    owner_id: i64,
+    writer_persona: &persona::Model,
+    registry: &Registry,
+    db: &DatabaseConnection,
+) -> Result<persona::Model, EAError> {
+    if writer_persona.user_id == owner_id {
+        return Ok(writer_persona.clone());
+    }
+    let db_personas = persona::Entity::find()
+        .filter(persona::Column::UserId.eq(owner_id))
+        .all(db)
+        .await
+        .unwrap_or_default();
+    if db_personas.is_empty() {
+        return Err(EAError::EA_NotFound);
+    }
+    db_personas
+        .into_iter()
+        .find(|db_persona| registry.has_played_with_host(db_persona.id, writer_persona.id))
+        .ok_or(EAError::EA_AuthFail)
+}
+
+// Sets (or adds to, if relative) the value of the stat in the running period of the
+// period ids (see prepare_stat_periods)
+pub async fn update_stat<C: ConnectionTrait>(
+    persona_id: i64,
+    stat_key: &str,
+    period_ids: &[i32],
+    value: f64,
+    relative: bool,
+    db: &C,
+) -> Result<(), DbErr> {
+    for period_id in period_ids.iter().copied() {
+        match get_stat(persona_id, stat_key, period_id, 0, db).await {
+            Some(db_stat) => {
+                let new_value = if relative {
+                    db_stat.value + value
+                } else {
+                    value
+                };
+                let mut db_stat = db_stat.into_active_model();
+                db_stat.value = Set(new_value);
+                db_stat.updated_at = Set(chrono::Utc::now());
+                db_stat.update(db).await?;
+            }
+            None => {
+                let new_stat = stat::ActiveModel {
+                    persona_id: Set(persona_id),
+                    stat_key: Set(stat_key.to_string()),
+                    period_id: Set(period_id),
+                    period_past: Set(0),
+                    value: Set(value),
+                    updated_at: Set(chrono::Utc::now()),
+                    ..Default::default()
+                };
+                new_stat.insert(db).await?;
+            }
+        }
+    }
+    Ok(())
+}